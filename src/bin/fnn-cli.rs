@@ -0,0 +1,777 @@
+//! Command-line client of the fnn rpc.
+//!
+//! The requests and responses are the structs of the rpc server, and the calls are made by the
+//! clients generated from the same rpc traits, so the client can't drift from the server.
+
+use std::process::exit;
+
+use chrono::DateTime;
+use ckb_jsonrpc_types::Script;
+use clap::{Args, Parser, Subcommand};
+use fnn::fiber::{hash_algorithm::HashAlgorithm, types::Hash256, types::LockTime};
+use fnn::invoice::Currency;
+use fnn::rpc::{
+    cch::{
+        CchRpcClient, GetReceiveBtcOrderParams, ReceiveBTCResponse, ReceiveBtcParams,
+        SendBTCResponse, SendBtcParams,
+    },
+    channel::{
+        AcceptChannelParams, AccountingReportParams, AccountingSummary, AddTlcParams,
+        ChannelRpcClient, CommitmentSignedParams, ListChannelsParams, ListClosedChannelsParams,
+        OpenChannelParams, RemoveTlcParams, RemoveTlcReason, ShutdownChannelParams,
+        UpdateCommitmentFeeParams,
+    },
+    invoice::{InvoiceRpcClient, NewInvoiceParams, ParseInvoiceParams},
+    peer::{ConnectPeerParams, DisconnectPeerParams, PeerRpcClient},
+};
+use jsonrpsee::http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder};
+use serde::Serialize;
+use tentacle::{multiaddr::MultiAddr, secio::PeerId};
+
+const SHANNONS_PER_CKB: u128 = 100_000_000;
+
+#[derive(Parser)]
+#[command(
+    author,
+    version,
+    about = "Command-line client of the fnn rpc",
+    after_help = "Amounts are raw CKB shannons or UDT amounts, in decimal or 0x hex, or CKB with a ckb suffix, e.g. 100ckb or 0.5ckb."
+)]
+struct Cli {
+    /// url of the rpc service, http or https over tcp. The unix socket of the rpc service is not
+    /// supported, and https certificates are verified with the system root certificates only
+    #[arg(long, env = "FNN_RPC_URL", default_value = "http://127.0.0.1:8227")]
+    url: String,
+
+    /// token sent in the `Authorization: Bearer` header
+    #[arg(long, env = "FNN_RPC_TOKEN")]
+    token: Option<String>,
+
+    /// print the raw json response instead of the readable output
+    #[arg(long)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Open a channel with a connected peer
+    OpenChannel(OpenChannelArgs),
+    /// Accept a channel opened by a peer
+    AcceptChannel {
+        #[arg(long)]
+        temporary_channel_id: Hash256,
+        #[arg(long, value_parser = parse_amount)]
+        funding_amount: u128,
+        /// blocks to wait for the funding and closing transactions, the node config by default
+        #[arg(long)]
+        tx_confirmations: Option<u64>,
+    },
+    /// List the active channels
+    ListChannels {
+        #[arg(long)]
+        peer_id: Option<PeerId>,
+    },
+    /// List the closed channels, the latest closed first
+    ListClosedChannels {
+        #[arg(long)]
+        peer_id: Option<PeerId>,
+        /// microseconds since the UNIX epoch
+        #[arg(long)]
+        closed_after: Option<u64>,
+        /// microseconds since the UNIX epoch
+        #[arg(long)]
+        closed_before: Option<u64>,
+    },
+    /// Summarize the settled and failed tlcs and the on-chain fees
+    AccountingReport {
+        #[arg(long)]
+        channel_id: Option<Hash256>,
+        /// microseconds since the UNIX epoch
+        #[arg(long)]
+        after: Option<u64>,
+        /// microseconds since the UNIX epoch
+        #[arg(long)]
+        before: Option<u64>,
+    },
+    /// Sign the pending updates of a channel immediately
+    CommitmentSigned {
+        #[arg(long)]
+        channel_id: Hash256,
+    },
+    /// Add a tlc to a channel
+    AddTlc(AddTlcArgs),
+    /// Fulfill or fail a received tlc
+    RemoveTlc(RemoveTlcArgs),
+    /// Close a channel
+    ShutdownChannel(ShutdownChannelArgs),
+    /// Update the commitment fee rate of a channel
+    UpdateCommitmentFee {
+        #[arg(long)]
+        channel_id: Hash256,
+        /// shannons per kilo weight, estimated by the node if not set
+        #[arg(long)]
+        fee_rate: Option<u64>,
+    },
+    /// Connect to a peer
+    ConnectPeer {
+        #[arg(long)]
+        address: MultiAddr,
+    },
+    /// Disconnect from a peer
+    DisconnectPeer {
+        #[arg(long)]
+        peer_id: PeerId,
+    },
+    /// List the connected peers
+    ListPeers,
+    /// List the round trip times of the connected peers
+    ListPeerLatencies,
+    /// Create an invoice
+    NewInvoice(NewInvoiceArgs),
+    /// Decode an invoice
+    ParseInvoice {
+        #[arg(long)]
+        invoice: String,
+    },
+    /// Pay a BTC lightning invoice with CKB through the cross chain hub
+    SendBtc {
+        #[arg(long)]
+        btc_pay_req: String,
+        #[arg(long)]
+        currency: Currency,
+    },
+    /// Receive BTC in a CKB channel through the cross chain hub
+    ReceiveBtc {
+        #[arg(long)]
+        payment_hash: String,
+        #[arg(long)]
+        channel_id: Hash256,
+        #[arg(long)]
+        amount_sats: u128,
+        /// seconds
+        #[arg(long)]
+        final_tlc_expiry: u64,
+    },
+    /// Get a receive BTC order of the cross chain hub
+    GetReceiveBtcOrder {
+        #[arg(long)]
+        payment_hash: String,
+    },
+}
+
+#[derive(Args)]
+struct OpenChannelArgs {
+    #[arg(long)]
+    peer_id: PeerId,
+    #[arg(long, value_parser = parse_amount)]
+    funding_amount: u128,
+    /// json of the UDT type script, a CKB channel is opened if not set
+    #[arg(long, value_parser = parse_script)]
+    funding_udt_type_script: Option<Script>,
+    /// shannons per kilo weight
+    #[arg(long)]
+    commitment_fee_rate: Option<u64>,
+    /// shannons per kilo weight
+    #[arg(long)]
+    funding_fee_rate: Option<u64>,
+    #[arg(long, value_parser = parse_amount)]
+    max_tlc_value_in_flight: Option<u128>,
+    #[arg(long)]
+    max_num_of_accept_tlcs: Option<u64>,
+    /// blocks to wait for the funding and closing transactions, the node config by default
+    #[arg(long)]
+    tx_confirmations: Option<u64>,
+}
+
+#[derive(Args)]
+struct AddTlcArgs {
+    #[arg(long)]
+    channel_id: Hash256,
+    #[arg(long, value_parser = parse_amount)]
+    amount: u128,
+    #[arg(long)]
+    payment_hash: Hash256,
+    /// relative lock time in blocks
+    #[arg(long)]
+    expiry: u64,
+    #[arg(long, value_parser = parse_hash_algorithm)]
+    hash_algorithm: Option<HashAlgorithm>,
+}
+
+#[derive(Args)]
+struct RemoveTlcArgs {
+    #[arg(long)]
+    channel_id: Hash256,
+    #[arg(long)]
+    tlc_id: u64,
+    /// fulfill the tlc with the preimage
+    #[arg(
+        long,
+        conflicts_with = "error_code",
+        required_unless_present = "error_code"
+    )]
+    payment_preimage: Option<Hash256>,
+    /// fail the tlc with the error code
+    #[arg(long)]
+    error_code: Option<u32>,
+}
+
+#[derive(Args)]
+struct ShutdownChannelArgs {
+    #[arg(long)]
+    channel_id: Hash256,
+    /// json of the script to receive our balance
+    #[arg(long, value_parser = parse_script)]
+    close_script: Script,
+    /// broadcast the latest commitment transaction instead of closing cooperatively
+    #[arg(long)]
+    force: bool,
+    /// shannons per kilo weight
+    #[arg(long)]
+    fee_rate: Option<u64>,
+}
+
+#[derive(Args)]
+struct NewInvoiceArgs {
+    #[arg(long, value_parser = parse_amount)]
+    amount: u128,
+    #[arg(long)]
+    currency: Currency,
+    #[arg(long)]
+    payment_preimage: Hash256,
+    #[arg(long)]
+    description: Option<String>,
+    /// seconds
+    #[arg(long)]
+    expiry: Option<u64>,
+    #[arg(long)]
+    fallback_address: Option<String>,
+    #[arg(long)]
+    final_cltv: Option<u64>,
+    #[arg(long)]
+    final_htlc_timeout: Option<u64>,
+    /// json of the UDT type script, a CKB invoice is created if not set
+    #[arg(long, value_parser = parse_script)]
+    udt_type_script: Option<Script>,
+    #[arg(long, value_parser = parse_hash_algorithm)]
+    hash_algorithm: Option<HashAlgorithm>,
+}
+
+fn parse_amount(s: &str) -> Result<u128, String> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u128::from_str_radix(hex, 16).map_err(|e| e.to_string());
+    }
+    let Some(ckb) = s
+        .strip_suffix("ckb")
+        .or_else(|| s.strip_suffix("CKB"))
+        .map(str::trim)
+    else {
+        return s
+            .parse()
+            .map_err(|e: std::num::ParseIntError| e.to_string());
+    };
+    let (integer, fraction) = ckb.split_once('.').unwrap_or((ckb, ""));
+    if fraction.len() > 8 {
+        return Err("CKB has at most 8 decimal places".to_string());
+    }
+    let integer: u128 = match integer {
+        "" => 0,
+        integer => integer
+            .parse()
+            .map_err(|e: std::num::ParseIntError| e.to_string())?,
+    };
+    let fraction: u128 = match fraction {
+        "" => 0,
+        fraction => format!("{:0<8}", fraction)
+            .parse()
+            .map_err(|e: std::num::ParseIntError| e.to_string())?,
+    };
+    integer
+        .checked_mul(SHANNONS_PER_CKB)
+        .and_then(|shannons| shannons.checked_add(fraction))
+        .ok_or_else(|| "amount overflows".to_string())
+}
+
+fn parse_script(s: &str) -> Result<Script, String> {
+    serde_json::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_hash_algorithm(s: &str) -> Result<HashAlgorithm, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|e| e.to_string())
+}
+
+fn format_ckb(shannons: u128) -> String {
+    format!(
+        "{}.{:08} CKB",
+        shannons / SHANNONS_PER_CKB,
+        shannons % SHANNONS_PER_CKB
+    )
+}
+
+fn format_amount(amount: u128, udt_type_script: &Option<Script>) -> String {
+    match udt_type_script {
+        None => format_ckb(amount),
+        Some(_) => format!("{} UDT", amount),
+    }
+}
+
+fn format_time(micros: u64) -> String {
+    DateTime::from_timestamp(
+        (micros / 1_000_000) as i64,
+        (micros % 1_000_000 * 1_000) as u32,
+    )
+    .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+    .unwrap_or_else(|| micros.to_string())
+}
+
+fn format_script(script: &Option<Script>) -> String {
+    match script {
+        None => "CKB".to_string(),
+        Some(script) => serde_json::to_string(script).expect("serialize script"),
+    }
+}
+
+fn print_summary(summary: &AccountingSummary, udt_type_script: &Option<Script>) {
+    println!(
+        "  received: {}",
+        format_amount(summary.received_amount, udt_type_script)
+    );
+    println!(
+        "  sent: {}",
+        format_amount(summary.sent_amount, udt_type_script)
+    );
+    println!(
+        "  tlcs: {} settled, {} failed",
+        summary.settled_tlcs, summary.failed_tlcs
+    );
+    println!(
+        "  routing fee: {}",
+        format_amount(summary.routing_fee, udt_type_script)
+    );
+    println!(
+        "  on-chain fee: {}",
+        format_ckb(summary.on_chain_fee as u128)
+    );
+}
+
+fn print_send_btc_order(order: &SendBTCResponse) {
+    println!("payment hash: {}", order.payment_hash);
+    println!("status: {:?}", order.status);
+    println!(
+        "amount: {} sats, fee {} sats",
+        order.amount_sats, order.fee_sats
+    );
+    println!("ckb invoice: {}", order.ckb_pay_req);
+    println!("btc invoice: {}", order.btc_pay_req);
+    println!(
+        "expires at: {}",
+        format_time((order.timestamp + order.expiry) * 1_000_000)
+    );
+}
+
+fn print_receive_btc_order(order: &ReceiveBTCResponse) {
+    println!("payment hash: {}", order.payment_hash);
+    println!("status: {:?}", order.status);
+    println!(
+        "amount: {} sats, fee {} sats",
+        order.amount_sats, order.fee_sats
+    );
+    println!("channel: {:#x}", order.channel_id);
+    if let Some(tlc_id) = order.tlc_id {
+        println!("tlc: {}", tlc_id);
+    }
+    println!("btc invoice: {}", order.btc_pay_req);
+    println!(
+        "expires at: {}",
+        format_time((order.timestamp + order.expiry) * 1_000_000)
+    );
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("serialize response")
+    );
+}
+
+async fn run(client: HttpClient, command: Command, json: bool) -> Result<(), String> {
+    let error = |err: jsonrpsee::core::client::Error| err.to_string();
+    match command {
+        Command::OpenChannel(args) => {
+            let result = client
+                .open_channel(OpenChannelParams {
+                    peer_id: args.peer_id,
+                    funding_amount: args.funding_amount,
+                    funding_udt_type_script: args.funding_udt_type_script,
+                    commitment_fee_rate: args.commitment_fee_rate,
+                    funding_fee_rate: args.funding_fee_rate,
+                    max_tlc_value_in_flight: args.max_tlc_value_in_flight,
+                    max_num_of_accept_tlcs: args.max_num_of_accept_tlcs,
+                    tx_confirmations: args.tx_confirmations,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            println!("temporary channel id: {:#x}", result.temporary_channel_id);
+        }
+        Command::AcceptChannel {
+            temporary_channel_id,
+            funding_amount,
+            tx_confirmations,
+        } => {
+            let result = client
+                .accept_channel(AcceptChannelParams {
+                    temporary_channel_id,
+                    funding_amount,
+                    tx_confirmations,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            println!("channel id: {:#x}", result.channel_id);
+        }
+        Command::ListChannels { peer_id } => {
+            let result = client
+                .list_channels(ListChannelsParams { peer_id })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            for channel in result.channels {
+                let udt = &channel.funding_udt_type_script;
+                println!("{:#x}", channel.channel_id);
+                println!("  peer: {}", channel.peer_id);
+                println!("  state: {:?}", channel.state);
+                println!("  asset: {}", format_script(udt));
+                println!(
+                    "  local balance: {}",
+                    format_amount(channel.local_balance, udt)
+                );
+                println!(
+                    "  remote balance: {}",
+                    format_amount(channel.remote_balance, udt)
+                );
+                println!(
+                    "  offered tlcs: {}",
+                    format_amount(channel.sent_tlc_balance, udt)
+                );
+                println!(
+                    "  received tlcs: {}",
+                    format_amount(channel.received_tlc_balance, udt)
+                );
+                println!("  created at: {}", format_time(channel.created_at));
+            }
+        }
+        Command::ListClosedChannels {
+            peer_id,
+            closed_after,
+            closed_before,
+        } => {
+            let result = client
+                .list_closed_channels(ListClosedChannelsParams {
+                    peer_id,
+                    closed_after,
+                    closed_before,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            for channel in result.channels {
+                let udt = &channel.funding_udt_type_script;
+                println!("{:#x}", channel.channel_id);
+                println!("  peer: {}", channel.peer_id);
+                println!("  close type: {:?}", channel.close_type);
+                println!("  asset: {}", format_script(udt));
+                println!(
+                    "  local balance: {}",
+                    format_amount(channel.local_balance, udt)
+                );
+                println!(
+                    "  remote balance: {}",
+                    format_amount(channel.remote_balance, udt)
+                );
+                println!("  close fee: {}", format_ckb(channel.close_fee as u128));
+                println!("  funding fee: {}", format_ckb(channel.funding_fee as u128));
+                println!("  close tx: {:#x}", channel.close_tx_hash);
+                println!("  closed at: {}", format_time(channel.closed_at));
+            }
+        }
+        Command::AccountingReport {
+            channel_id,
+            after,
+            before,
+        } => {
+            let result = client
+                .accounting_report(AccountingReportParams {
+                    channel_id,
+                    after,
+                    before,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            for asset in &result.assets {
+                println!("asset {}", format_script(&asset.udt_type_script));
+                print_summary(&asset.summary, &asset.udt_type_script);
+            }
+            for channel in &result.channels {
+                println!("channel {:#x}", channel.channel_id);
+                print_summary(&channel.summary, &channel.udt_type_script);
+            }
+        }
+        Command::CommitmentSigned { channel_id } => client
+            .commitment_signed(CommitmentSignedParams { channel_id })
+            .await
+            .map_err(error)?,
+        Command::AddTlc(args) => {
+            let result = client
+                .add_tlc(AddTlcParams {
+                    channel_id: args.channel_id,
+                    amount: args.amount,
+                    payment_hash: args.payment_hash,
+                    expiry: LockTime::new(args.expiry),
+                    hash_algorithm: args.hash_algorithm,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            println!("tlc id: {}", result.tlc_id);
+        }
+        Command::RemoveTlc(args) => {
+            let reason = match (args.payment_preimage, args.error_code) {
+                (Some(payment_preimage), _) => {
+                    RemoveTlcReason::RemoveTlcFulfill { payment_preimage }
+                }
+                (None, Some(error_code)) => RemoveTlcReason::RemoveTlcFail { error_code },
+                (None, None) => unreachable!("required by the arguments"),
+            };
+            client
+                .remove_tlc(RemoveTlcParams {
+                    channel_id: args.channel_id,
+                    tlc_id: args.tlc_id,
+                    reason,
+                })
+                .await
+                .map_err(error)?
+        }
+        Command::ShutdownChannel(args) => client
+            .shutdown_channel(ShutdownChannelParams {
+                channel_id: args.channel_id,
+                close_script: args.close_script,
+                force: Some(args.force),
+                fee_rate: args.fee_rate,
+            })
+            .await
+            .map_err(error)?,
+        Command::UpdateCommitmentFee {
+            channel_id,
+            fee_rate,
+        } => client
+            .update_commitment_fee(UpdateCommitmentFeeParams {
+                channel_id,
+                fee_rate,
+            })
+            .await
+            .map_err(error)?,
+        Command::ConnectPeer { address } => client
+            .connect_peer(ConnectPeerParams { address })
+            .await
+            .map_err(error)?,
+        Command::DisconnectPeer { peer_id } => client
+            .disconnect_peer(DisconnectPeerParams { peer_id })
+            .await
+            .map_err(error)?,
+        Command::ListPeers => {
+            let result = client.list_peers().await.map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            for peer in result.peers {
+                println!(
+                    "{} {} {} channels{}",
+                    peer.peer_id,
+                    peer.address,
+                    peer.channel_count,
+                    if peer.is_inbound { " (inbound)" } else { "" }
+                );
+            }
+        }
+        Command::ListPeerLatencies => {
+            let result = client.list_peer_latencies().await.map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            for peer in result.peers {
+                match peer.round_trip_time_ms {
+                    Some(rtt) => println!("{} {} ms", peer.peer_id, rtt),
+                    None => println!("{} not measured yet", peer.peer_id),
+                }
+            }
+        }
+        Command::NewInvoice(args) => {
+            let result = client
+                .new_invoice(NewInvoiceParams {
+                    amount: args.amount,
+                    description: args.description,
+                    currency: args.currency,
+                    payment_preimage: args.payment_preimage,
+                    expiry: args.expiry,
+                    fallback_address: args.fallback_address,
+                    final_cltv: args.final_cltv,
+                    final_htlc_timeout: args.final_htlc_timeout,
+                    udt_type_script: args.udt_type_script,
+                    hash_algorithm: args.hash_algorithm,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            println!("{}", result.invoice_address);
+            println!("payment hash: {:#x}", result.invoice.payment_hash());
+        }
+        Command::ParseInvoice { invoice } => {
+            let result = client
+                .parse_invoice(ParseInvoiceParams { invoice })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            let invoice = result.invoice;
+            let udt_type_script = invoice.udt_type_script().cloned().map(Into::into);
+            println!("currency: {}", invoice.currency.to_string());
+            match invoice.amount() {
+                Some(amount) => println!("amount: {}", format_amount(amount, &udt_type_script)),
+                None => println!("amount: any"),
+            }
+            println!("payment hash: {:#x}", invoice.payment_hash());
+            println!("signed: {}", invoice.is_signed());
+        }
+        Command::SendBtc {
+            btc_pay_req,
+            currency,
+        } => {
+            let result = client
+                .send_btc(SendBtcParams {
+                    btc_pay_req,
+                    currency,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            print_send_btc_order(&result);
+        }
+        Command::ReceiveBtc {
+            payment_hash,
+            channel_id,
+            amount_sats,
+            final_tlc_expiry,
+        } => {
+            let result = client
+                .receive_btc(ReceiveBtcParams {
+                    payment_hash,
+                    channel_id,
+                    amount_sats,
+                    final_tlc_expiry,
+                })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            print_receive_btc_order(&result);
+        }
+        Command::GetReceiveBtcOrder { payment_hash } => {
+            let result = client
+                .get_receive_btc_order(GetReceiveBtcOrderParams { payment_hash })
+                .await
+                .map_err(error)?;
+            if json {
+                print_json(&result);
+                return Ok(());
+            }
+            print_receive_btc_order(&result);
+        }
+    }
+    Ok(())
+}
+
+fn build_client(url: &str, token: Option<&str>) -> Result<HttpClient, String> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| "invalid rpc token".to_string())?;
+        headers.insert("Authorization", value);
+    }
+    HttpClientBuilder::default()
+        .set_headers(headers)
+        .build(url)
+        .map_err(|err| format!("invalid rpc url {}: {}", url, err))
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match build_client(&cli.url, cli.token.as_deref()) {
+        Ok(client) => run(client, cli.command, cli.json).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_client, format_ckb, parse_amount};
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("100"), Ok(100));
+        assert_eq!(parse_amount("0x64"), Ok(100));
+        assert_eq!(parse_amount("1ckb"), Ok(100_000_000));
+        assert_eq!(parse_amount("61.5 CKB"), Ok(6_150_000_000));
+        assert_eq!(parse_amount("0.00000001ckb"), Ok(1));
+        assert!(parse_amount("0.000000001ckb").is_err());
+        assert!(parse_amount("1.5").is_err());
+        assert_eq!(format_ckb(6_150_000_000), "61.50000000 CKB");
+    }
+
+    #[test]
+    fn test_build_client() {
+        assert!(build_client("http://127.0.0.1:8227", Some("token")).is_ok());
+        assert!(build_client("not a url", None).is_err());
+        assert!(build_client("http://127.0.0.1:8227", Some("bad\ntoken")).is_err());
+    }
+}
@@ -6,10 +6,11 @@ use lnd_grpc_tonic_client::{
     create_invoices_client, create_router_client, invoicesrpc, lnrpc, routerrpc, InvoicesClient,
     RouterClient, Uri,
 };
-use ractor::{call, RpcReplyPort};
+use ractor::{call, OutputPort, RpcReplyPort};
 use ractor::{Actor, ActorCell, ActorProcessingErr, ActorRef};
 use serde::Deserialize;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::{select, time::sleep};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
@@ -24,7 +25,10 @@ use crate::fiber::{NetworkActorCommand, NetworkActorMessage};
 use crate::invoice::Currency;
 
 use super::error::CchDbError;
-use super::{CchConfig, CchError, CchOrderStatus, CchOrdersDb, ReceiveBTCOrder, SendBTCOrder};
+use super::{
+    CchConfig, CchError, CchOrderNotification, CchOrderStatus, CchOrdersDb, ReceiveBTCOrder,
+    SendBTCOrder,
+};
 
 pub const BTC_PAYMENT_TIMEOUT_SECONDS: i32 = 60;
 pub const DEFAULT_ORDER_EXPIRY_SECONDS: u64 = 86400; // 24 hours
@@ -35,10 +39,11 @@ pub async fn start_cch(
     token: CancellationToken,
     root_actor: ActorCell,
     network_actor: Option<ActorRef<NetworkActorMessage>>,
+    order_subscribers: Arc<OutputPort<CchOrderNotification>>,
 ) -> Result<ActorRef<CchMessage>> {
     let (actor, _handle) = Actor::spawn_linked(
         Some("cch actor".to_string()),
-        CchActor::new(config, tracker, token, network_actor),
+        CchActor::new(config, tracker, token, network_actor, order_subscribers),
         (),
         root_actor,
     )
@@ -130,6 +135,7 @@ pub struct CchActor {
     tracker: TaskTracker,
     token: CancellationToken,
     network_actor: Option<ActorRef<NetworkActorMessage>>,
+    order_subscribers: Arc<OutputPort<CchOrderNotification>>,
 }
 
 pub struct CchState {
@@ -178,7 +184,7 @@ impl Actor for CchActor {
 
         Ok(CchState {
             lnd_connection,
-            orders_db: Default::default(),
+            orders_db: CchOrdersDb::new(self.order_subscribers.clone()),
         })
     }
 
@@ -188,6 +194,7 @@ impl Actor for CchActor {
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
+        let _timer = crate::metrics::actor_message_timer("cch");
         match message {
             CchMessage::SendBTC(send_btc, port) => {
                 let result = self.send_btc(state, send_btc).await;
@@ -259,12 +266,14 @@ impl CchActor {
         tracker: TaskTracker,
         token: CancellationToken,
         network_actor: Option<ActorRef<NetworkActorMessage>>,
+        order_subscribers: Arc<OutputPort<CchOrderNotification>>,
     ) -> Self {
         Self {
             config,
             tracker,
             token,
             network_actor,
+            order_subscribers,
         }
     }
 
@@ -455,7 +464,7 @@ impl CchActor {
             order.payment_preimage = Some(preimage.clone());
 
             let message = move |rpc_reply| -> NetworkActorMessage {
-                NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+                NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                     ChannelCommandWithId {
                         channel_id,
                         command: ChannelCommand::RemoveTlc(
@@ -583,7 +592,7 @@ impl CchActor {
         if event.status == CchOrderStatus::Accepted && self.network_actor.is_some() {
             // AddTlc to initiate the CKB payment
             let message = |rpc_reply| -> NetworkActorMessage {
-                NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+                NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                     ChannelCommandWithId {
                         channel_id: order.channel_id,
                         command: ChannelCommand::AddTlc(
@@ -11,7 +11,9 @@ pub use config::{
 };
 
 mod order;
-pub use order::{CchOrderStatus, ReceiveBTCOrder, SendBTCOrder};
+pub use order::{
+    CchOrderNotification, CchOrderStatus, CchOrderType, ReceiveBTCOrder, SendBTCOrder,
+};
 
 mod orders_db;
 pub use orders_db::CchOrdersDb;
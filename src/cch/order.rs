@@ -27,6 +27,21 @@ pub enum CchOrderStatus {
     Failed = 4,
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CchOrderType {
+    SendBtc,
+    ReceiveBtc,
+}
+
+/// An order is created or its status is changed.
+#[derive(Debug, Clone)]
+pub struct CchOrderNotification {
+    pub payment_hash: String,
+    pub order_type: CchOrderType,
+    pub status: CchOrderStatus,
+}
+
 /// lnd payment is the second half of SendBTCOrder
 impl From<lnrpc::payment::PaymentStatus> for CchOrderStatus {
     fn from(status: lnrpc::payment::PaymentStatus) -> Self {
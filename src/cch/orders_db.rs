@@ -1,6 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use super::{error::CchDbError, ReceiveBTCOrder, SendBTCOrder};
+use ractor::OutputPort;
+
+use super::{
+    error::CchDbError, CchOrderNotification, CchOrderStatus, CchOrderType, ReceiveBTCOrder,
+    SendBTCOrder,
+};
 
 // TODO: persist orders
 #[derive(Default)]
@@ -8,14 +13,43 @@ pub struct CchOrdersDb {
     /// SendBTCOrder map by payment hash
     send_btc_orders: HashMap<String, SendBTCOrder>,
     receive_btc_orders: HashMap<String, ReceiveBTCOrder>,
+    /// Notified when an order is inserted or its status is changed.
+    order_subscribers: Arc<OutputPort<CchOrderNotification>>,
 }
 
 impl CchOrdersDb {
+    pub fn new(order_subscribers: Arc<OutputPort<CchOrderNotification>>) -> Self {
+        Self {
+            order_subscribers,
+            ..Default::default()
+        }
+    }
+
+    fn notify(
+        &self,
+        payment_hash: &str,
+        order_type: CchOrderType,
+        status: CchOrderStatus,
+        previous_status: Option<CchOrderStatus>,
+    ) {
+        if previous_status != Some(status) {
+            crate::metrics::update_cch_order_status(status, previous_status);
+            self.order_subscribers.send(CchOrderNotification {
+                payment_hash: payment_hash.to_string(),
+                order_type,
+                status,
+            });
+        }
+    }
+
     pub async fn insert_send_btc_order(&mut self, order: SendBTCOrder) -> Result<(), CchDbError> {
-        let key = order.payment_hash.clone();
+        let (key, status) = (order.payment_hash.clone(), order.status);
         match self.send_btc_orders.insert(key.clone(), order) {
             Some(_) => Err(CchDbError::Duplicated(key)),
-            None => Ok(()),
+            None => {
+                self.notify(&key, CchOrderType::SendBtc, status, None);
+                Ok(())
+            }
         }
     }
 
@@ -30,9 +64,12 @@ impl CchOrdersDb {
     }
 
     pub async fn update_send_btc_order(&mut self, order: SendBTCOrder) -> Result<(), CchDbError> {
-        let key = order.payment_hash.clone();
+        let (key, status) = (order.payment_hash.clone(), order.status);
         match self.send_btc_orders.insert(key.clone(), order) {
-            Some(_) => Ok(()),
+            Some(previous) => {
+                self.notify(&key, CchOrderType::SendBtc, status, Some(previous.status));
+                Ok(())
+            }
             None => Err(CchDbError::NotFound(key)),
         }
     }
@@ -41,10 +78,13 @@ impl CchOrdersDb {
         &mut self,
         order: ReceiveBTCOrder,
     ) -> Result<(), CchDbError> {
-        let key = order.payment_hash.clone();
+        let (key, status) = (order.payment_hash.clone(), order.status);
         match self.receive_btc_orders.insert(key.clone(), order) {
             Some(_) => Err(CchDbError::Duplicated(key)),
-            None => Ok(()),
+            None => {
+                self.notify(&key, CchOrderType::ReceiveBtc, status, None);
+                Ok(())
+            }
         }
     }
 
@@ -62,9 +102,17 @@ impl CchOrdersDb {
         &mut self,
         order: ReceiveBTCOrder,
     ) -> Result<(), CchDbError> {
-        let key = order.payment_hash.clone();
+        let (key, status) = (order.payment_hash.clone(), order.status);
         match self.receive_btc_orders.insert(key.clone(), order) {
-            Some(_) => Ok(()),
+            Some(previous) => {
+                self.notify(
+                    &key,
+                    CchOrderType::ReceiveBtc,
+                    status,
+                    Some(previous.status),
+                );
+                Ok(())
+            }
             None => Err(CchDbError::NotFound(key)),
         }
     }
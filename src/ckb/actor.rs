@@ -1,22 +1,72 @@
-use ckb_sdk::{CkbRpcClient, RpcError};
-use ckb_types::{core::TransactionView, packed, prelude::*};
-use ractor::{
-    concurrency::{sleep, Duration},
-    Actor, ActorProcessingErr, ActorRef, RpcReplyPort,
+use std::{collections::HashSet, sync::Arc};
+
+use ckb_jsonrpc_types::Status;
+use ckb_sdk::{
+    traits::{LiveCell, TransactionDependencyProvider},
+    RpcError,
+};
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    packed,
+};
+use ractor::{concurrency::Duration, Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
+use tokio::sync::mpsc;
+
+use crate::{
+    ckb::contracts::{get_script_by_contract, Contract},
+    signer::{FundingKeySigner, Signer},
 };
 
-use crate::ckb::contracts::{get_script_by_contract, Contract};
+use super::{
+    backend::{new_chain_backend, ChainBackend},
+    fee_estimator::{FeeEstimator, FeeRateTarget},
+    funding::FundingContext,
+    reservation::CellReservations,
+    wallet::{SendToAddressRequest, WalletBalance, WalletContext},
+    watcher::{ChainReorganized, ChainWatchEvent, ChainWatcher, WatchId, WatchTarget},
+    CkbConfig, FundingError, FundingRequest, FundingTx,
+};
 
-use super::{funding::FundingContext, CkbConfig, FundingError, FundingRequest, FundingTx};
+/// How often the chain actor checks the tip of the chain for new blocks.
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// At most this many blocks are fetched in one poll, so that other messages are not starved
+/// while the actor catches up with the chain.
+const MAX_BLOCKS_PER_POLL: usize = 100;
+/// How many blocks to wait before asking the ckb node again about the traced transactions
+/// which are not committed yet, so that rejected transactions are noticed.
+const TRACE_RECHECK_BLOCKS: u64 = 10;
+const RESERVED_CELLS_FILE_NAME: &str = "reserved_cells.json";
 
-pub struct CkbChainActor {}
+pub struct CkbChainActor {
+    signer: Arc<dyn Signer>,
+}
+
+impl CkbChainActor {
+    pub fn new(signer: Arc<dyn Signer>) -> Self {
+        Self { signer }
+    }
+}
 
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
 pub struct CkbChainState {
     config: CkbConfig,
-    secret_key: secp256k1::SecretKey,
+    backend: Arc<dyn ChainBackend>,
+    funding_signer: FundingKeySigner,
     funding_source_lock_script: packed::Script,
+    // The tip block number reported by the backend in the last poll.
+    chain_tip: u64,
+    watcher: ChainWatcher,
+    pending_traces: Vec<PendingTrace>,
+    last_trace_recheck_block: u64,
+    cell_reservations: CellReservations,
+    fee_estimator: FeeEstimator,
+}
+
+struct PendingTrace {
+    request: TraceTxRequest,
+    // The number of the block which commits the transaction on the main chain.
+    committed_block_number: Option<u64>,
+    reply_port: RpcReplyPort<Status>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,8 +84,60 @@ pub enum CkbChainMessage {
         RpcReplyPort<Result<FundingTx, FundingError>>,
     ),
     Sign(FundingTx, RpcReplyPort<Result<FundingTx, FundingError>>),
+    /// Release the cells reserved for a funding transaction which is aborted or failed.
+    ReleaseCells(Vec<packed::OutPoint>),
     SendTx(TransactionView, RpcReplyPort<Result<(), RpcError>>),
-    TraceTx(TraceTxRequest, RpcReplyPort<ckb_jsonrpc_types::Status>),
+    /// Reply once the transaction is committed with the required confirmations,
+    /// or rejected by the ckb node.
+    TraceTx(TraceTxRequest, RpcReplyPort<Status>),
+    /// Subscribe to the transactions matching the target, the events are sent to the
+    /// subscriber until it is closed or the watch is removed.
+    Watch(
+        WatchTarget,
+        mpsc::UnboundedSender<ChainWatchEvent>,
+        RpcReplyPort<WatchId>,
+    ),
+    Unwatch(WatchId),
+    /// Fetch a committed transaction, e.g. the transaction reported by a watch.
+    GetTransaction(
+        packed::Byte32,
+        RpcReplyPort<Result<TransactionView, String>>,
+    ),
+    /// Fetch the new blocks since the last poll, sent to itself periodically.
+    PollTip,
+    /// Estimate the fee rate for a kind of transaction, falls back to the configured floors
+    /// if the ckb node can't provide fee rate statistics.
+    EstimateFeeRate(FeeRateTarget, RpcReplyPort<u64>),
+    /// Wallet operations on the cells of the funding lock script.
+    GetBalance(RpcReplyPort<Result<WalletBalance, String>>),
+    ListUnspent(RpcReplyPort<Result<Vec<LiveCell>, String>>),
+    GetAddress(RpcReplyPort<Result<String, String>>),
+    SendToAddress(
+        SendToAddressRequest,
+        RpcReplyPort<Result<packed::Byte32, String>>,
+    ),
+}
+
+impl CkbChainMessage {
+    /// The label of the request in the metrics.
+    fn request_name(&self) -> &'static str {
+        match self {
+            CkbChainMessage::Fund(..) => "fund",
+            CkbChainMessage::Sign(..) => "sign",
+            CkbChainMessage::ReleaseCells(..) => "release_cells",
+            CkbChainMessage::SendTx(..) => "send_tx",
+            CkbChainMessage::TraceTx(..) => "trace_tx",
+            CkbChainMessage::Watch(..) => "watch",
+            CkbChainMessage::Unwatch(..) => "unwatch",
+            CkbChainMessage::GetTransaction(..) => "get_transaction",
+            CkbChainMessage::PollTip => "poll_tip",
+            CkbChainMessage::EstimateFeeRate(..) => "estimate_fee_rate",
+            CkbChainMessage::GetBalance(..) => "get_balance",
+            CkbChainMessage::ListUnspent(..) => "list_unspent",
+            CkbChainMessage::GetAddress(..) => "get_address",
+            CkbChainMessage::SendToAddress(..) => "send_to_address",
+        }
+    }
 }
 
 #[ractor::async_trait]
@@ -49,22 +151,55 @@ impl Actor for CkbChainActor {
         myself: ActorRef<Self::Msg>,
         config: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let secret_key = config.read_secret_key()?;
-        let secp = secp256k1::Secp256k1::new();
-        let pub_key = secret_key.public_key(&secp);
+        let cell_reservations =
+            CellReservations::load(config.base_dir().join(RESERVED_CELLS_FILE_NAME));
+        let pub_key = tokio::task::block_in_place(|| self.signer.funding_pubkey())?;
         let pub_key_hash = ckb_hash::blake2b_256(pub_key.serialize());
         let funding_source_lock_script =
             get_script_by_contract(Contract::Secp256k1Lock, &pub_key_hash[0..20]);
+        let funding_signer = FundingKeySigner::new(
+            self.signer.clone(),
+            pub_key_hash[0..20]
+                .try_into()
+                .expect("20 bytes pubkey hash"),
+        );
         tracing::info!(
             "[{}] funding lock args: {}",
             myself.get_name().unwrap_or_default(),
             funding_source_lock_script.args()
         );
 
+        let backend = new_chain_backend(&config);
+        // The cells of the funding lock script may be created long before.
+        if let Err(err) = tokio::task::block_in_place(|| {
+            backend.subscribe_scripts(vec![funding_source_lock_script.clone()], 0)
+        }) {
+            tracing::error!(
+                "[{}] subscribe funding lock script failed: {:?}",
+                myself.get_name().unwrap_or_default(),
+                err
+            );
+        }
+
+        myself.send_interval(TIP_POLL_INTERVAL, || CkbChainMessage::PollTip);
+
+        let fee_estimator = FeeEstimator::new(
+            backend.clone(),
+            config.min_fee_rate(),
+            config.min_commitment_fee_rate(),
+            config.max_commitment_fee_rate(),
+        );
         Ok(CkbChainState {
             config,
-            secret_key,
+            backend,
+            funding_signer,
             funding_source_lock_script,
+            chain_tip: 0,
+            watcher: ChainWatcher::new(),
+            pending_traces: vec![],
+            last_trace_recheck_block: 0,
+            cell_reservations,
+            fee_estimator,
         })
     }
 
@@ -74,26 +209,41 @@ impl Actor for CkbChainActor {
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        use CkbChainMessage::{Fund, SendTx, Sign, TraceTx};
+        use CkbChainMessage::{
+            EstimateFeeRate, Fund, GetAddress, GetBalance, GetTransaction, ListUnspent, PollTip,
+            ReleaseCells, SendToAddress, SendTx, Sign, TraceTx, Unwatch, Watch,
+        };
+        let _timer = crate::metrics::ckb_chain_request_timer(message.request_name());
         match message {
             Fund(tx, request, reply_port) => {
                 let context = state.build_funding_context(&request);
                 if !reply_port.is_closed() {
-                    tokio::task::block_in_place(move || {
-                        let result = tx.fulfill(request, context);
-                        if !reply_port.is_closed() {
-                            // ignore error
-                            let _ = reply_port.send(result);
-                        }
-                    });
+                    let original_inputs: HashSet<packed::OutPoint> = tx
+                        .as_ref()
+                        .map(|tx| tx.input_pts_iter().collect())
+                        .unwrap_or_default();
+                    let result = tokio::task::block_in_place(move || tx.fulfill(request, context));
+                    // Reserve the cells we just picked, until the funding transaction is
+                    // committed or failed.
+                    if let Some(tx) = result.as_ref().ok().and_then(|tx| tx.as_ref()) {
+                        state.cell_reservations.reserve(
+                            tx.input_pts_iter()
+                                .filter(|out_point| !original_inputs.contains(out_point)),
+                        );
+                    }
+                    if !reply_port.is_closed() {
+                        // ignore error
+                        let _ = reply_port.send(result);
+                    }
                 }
             }
+            ReleaseCells(out_points) => state.cell_reservations.release(out_points.iter()),
             Sign(tx, reply_port) => {
                 if !reply_port.is_closed() {
-                    let secret_key = state.secret_key;
-                    let rpc_url = state.config.rpc_url.clone();
+                    let signer = state.funding_signer.clone();
+                    let backend = state.backend.clone();
                     tokio::task::block_in_place(move || {
-                        let result = tx.sign(secret_key, rpc_url);
+                        let result = tx.sign(signer, backend.as_ref());
                         if !reply_port.is_closed() {
                             // ignore error
                             let _ = reply_port.send(result);
@@ -102,10 +252,9 @@ impl Actor for CkbChainActor {
                 }
             }
             SendTx(tx, reply_port) => {
-                let rpc_url = state.config.rpc_url.clone();
+                let backend = state.backend.clone();
                 tokio::task::block_in_place(move || {
-                    let ckb_client = CkbRpcClient::new(&rpc_url);
-                    let result = match ckb_client.send_transaction(tx.data().into(), None) {
+                    let result = match backend.send_transaction(&tx) {
                         Ok(_) => Ok(()),
                         Err(err) => {
                             //FIXME(yukang): RBF or duplicated transaction handling
@@ -138,76 +287,107 @@ impl Actor for CkbChainActor {
                     }
                 });
             }
-            TraceTx(
-                TraceTxRequest {
-                    tx_hash,
-                    confirmations,
-                },
-                reply_port,
-            ) => {
+            TraceTx(request, reply_port) => {
                 tracing::info!(
                     "[{}] trace transaction {} with {} confs",
                     myself.get_name().unwrap_or_default(),
-                    tx_hash,
-                    confirmations
+                    request.tx_hash,
+                    request.confirmations
                 );
-                // TODO: Need a better way to trace the transaction.
-                while !reply_port.is_closed() {
-                    let actor_name = myself.get_name().unwrap_or_default();
-                    let rpc_url = state.config.rpc_url.clone();
-                    let tx_hash = tx_hash.clone();
-                    let status = tokio::task::block_in_place(move || {
-                        let ckb_client = CkbRpcClient::new(&rpc_url);
-                        match ckb_client.get_transaction_status(tx_hash.unpack()) {
-                            Ok(resp) => match resp.tx_status.status {
-                                ckb_jsonrpc_types::Status::Committed => {
-                                    match ckb_client.get_tip_block_number() {
-                                        Ok(tip_number) => {
-                                            let tip_number: u64 = tip_number.into();
-                                            let commit_number: u64 = resp
-                                                .tx_status
-                                                .block_number
-                                                .unwrap_or_default()
-                                                .into();
-                                            (tip_number >= commit_number + confirmations)
-                                                .then_some(ckb_jsonrpc_types::Status::Committed)
-                                        }
-                                        Err(err) => {
-                                            tracing::error!(
-                                                "[{}] get tip block number failed: {:?}",
-                                                actor_name,
-                                                err
-                                            );
-                                            None
-                                        }
-                                    }
-                                }
-                                ckb_jsonrpc_types::Status::Rejected => {
-                                    Some(ckb_jsonrpc_types::Status::Rejected)
-                                }
-                                _ => None,
-                            },
-                            Err(err) => {
-                                tracing::error!(
-                                    "[{}] get transaction status failed: {:?}",
-                                    actor_name,
-                                    err
-                                );
-                                None
-                            }
-                        }
-                    });
-                    match status {
-                        Some(status) => {
-                            if !reply_port.is_closed() {
-                                // ignore error
-                                let _ = reply_port.send(status);
-                            }
-                            return Ok(());
-                        }
-                        None => sleep(Duration::from_secs(5)).await,
+                let actor_name = myself.get_name().unwrap_or_default();
+                tokio::task::block_in_place(|| state.trace_tx(request, reply_port, &actor_name));
+            }
+            Watch(target, subscriber, reply_port) => {
+                if let WatchTarget::LockScript(script) = &target {
+                    let backend = state.backend.clone();
+                    let script = script.clone();
+                    let from_block = state.chain_tip;
+                    if let Err(err) = tokio::task::block_in_place(move || {
+                        backend.subscribe_scripts(vec![script], from_block)
+                    }) {
+                        tracing::error!(
+                            "[{}] subscribe lock script failed: {:?}",
+                            myself.get_name().unwrap_or_default(),
+                            err
+                        );
+                    }
+                }
+                let watch_id = state.watcher.add_watch(target, subscriber);
+                if !reply_port.is_closed() {
+                    // ignore error
+                    let _ = reply_port.send(watch_id);
+                }
+            }
+            Unwatch(watch_id) => state.watcher.remove_watch(watch_id),
+            GetTransaction(tx_hash, reply_port) => {
+                let backend = state.backend.clone();
+                tokio::task::block_in_place(move || {
+                    let result = backend
+                        .tx_dep_provider()
+                        .get_transaction(&tx_hash)
+                        .map_err(|err| err.to_string());
+                    // ignore error
+                    let _ = reply_port.send(result);
+                });
+            }
+            PollTip => {
+                let actor_name = myself.get_name().unwrap_or_default();
+                tokio::task::block_in_place(|| state.poll_tip(&actor_name));
+            }
+            EstimateFeeRate(target, reply_port) => {
+                let fee_rate = tokio::task::block_in_place(|| state.fee_estimator.estimate(target));
+                // ignore error
+                let _ = reply_port.send(fee_rate);
+            }
+            GetBalance(reply_port) => {
+                let context = state.build_wallet_context();
+                tokio::task::block_in_place(move || {
+                    let result = context.get_balance().map_err(|err| err.to_string());
+                    // ignore error
+                    let _ = reply_port.send(result);
+                });
+            }
+            ListUnspent(reply_port) => {
+                let context = state.build_wallet_context();
+                tokio::task::block_in_place(move || {
+                    let result = context.list_unspent().map_err(|err| err.to_string());
+                    // ignore error
+                    let _ = reply_port.send(result);
+                });
+            }
+            GetAddress(reply_port) => {
+                let context = state.build_wallet_context();
+                tokio::task::block_in_place(move || {
+                    let result = context
+                        .address()
+                        .map(|address| address.to_string())
+                        .map_err(|err| err.to_string());
+                    // ignore error
+                    let _ = reply_port.send(result);
+                });
+            }
+            SendToAddress(request, reply_port) => {
+                let context = state.build_wallet_context();
+                let result = tokio::task::block_in_place(move || context.send_to_address(request));
+                match &result {
+                    Ok(tx) => {
+                        tracing::info!(
+                            "[{}] wallet transaction {} sent",
+                            myself.get_name().unwrap_or_default(),
+                            tx.hash()
+                        );
+                        // Keep the fundings and the wallet off the inputs until the
+                        // transaction is committed.
+                        state.cell_reservations.reserve(tx.input_pts_iter());
                     }
+                    Err(err) => tracing::error!(
+                        "[{}] send to address failed: {}",
+                        myself.get_name().unwrap_or_default(),
+                        err
+                    ),
                 }
+                // ignore error
+                let _ = reply_port.send(result.map(|tx| tx.hash()).map_err(|err| err.to_string()));
             }
         }
         Ok(())
@@ -217,31 +397,267 @@ impl Actor for CkbChainActor {
 impl CkbChainState {
     fn build_funding_context(&self, request: &FundingRequest) -> FundingContext {
         FundingContext {
-            secret_key: self.secret_key,
-            rpc_url: self.config.rpc_url.clone(),
+            backend: self.backend.clone(),
             funding_source_lock_script: self.funding_source_lock_script.clone(),
             funding_cell_lock_script: request.script.clone(),
+            reserved_cells: self.cell_reservations.reserved_cells(),
+        }
+    }
+
+    fn build_wallet_context(&self) -> WalletContext {
+        WalletContext {
+            signer: self.funding_signer.clone(),
+            backend: self.backend.clone(),
+            lock_script: self.funding_source_lock_script.clone(),
+            reserved_cells: self.cell_reservations.reserved_cells(),
+        }
+    }
+
+    fn trace_tx(
+        &mut self,
+        request: TraceTxRequest,
+        reply_port: RpcReplyPort<Status>,
+        actor_name: &str,
+    ) {
+        // The transaction may have been committed before the watcher starts following the chain,
+        // so ask the backend once, after that the watcher tells when the transaction is committed.
+        let committed_block_number = match self.backend.get_transaction_status(&request.tx_hash) {
+            Ok(resp) => match resp.status {
+                Status::Rejected => {
+                    // ignore error
+                    let _ = reply_port.send(Status::Rejected);
+                    return;
+                }
+                Status::Committed => resp.block_number,
+                _ => None,
+            },
+            Err(err) => {
+                tracing::error!("[{}] get transaction status failed: {:?}", actor_name, err);
+                None
+            }
+        };
+        self.pending_traces.push(PendingTrace {
+            request,
+            committed_block_number,
+            reply_port,
+        });
+        self.reply_confirmed_traces();
+    }
+
+    fn poll_tip(&mut self, actor_name: &str) {
+        let backend = self.backend.clone();
+        let chain_tip = match backend.get_tip_block_number() {
+            Ok(tip_number) => tip_number,
+            Err(err) => {
+                tracing::error!("[{}] get tip block number failed: {:?}", actor_name, err);
+                return;
+            }
+        };
+        self.chain_tip = chain_tip;
+
+        // Without full blocks, the committed transactions are only found by checking their status.
+        let max_blocks = if backend.has_full_blocks() {
+            MAX_BLOCKS_PER_POLL
+        } else {
+            0
+        };
+        // The chain has switched to a shorter fork. The watched blocks above the new tip are
+        // never replaced by the blocks of the longer chain, and the watched block at the new
+        // tip may be on the old fork too.
+        if max_blocks > 0 && matches!(self.watcher.tip(), Some((number, _)) if number > chain_tip) {
+            while matches!(self.watcher.tip(), Some((number, _)) if number > chain_tip) {
+                self.detach_tip(actor_name);
+            }
+            if let Some((number, hash)) = self.watcher.tip() {
+                match backend.get_block_by_number(number) {
+                    Ok(Some(block)) if block.hash() == hash => {}
+                    Ok(_) => self.detach_tip(actor_name),
+                    Err(err) => {
+                        tracing::error!("[{}] get block {} failed: {:?}", actor_name, number, err);
+                        return;
+                    }
+                }
+            }
+        }
+        for _ in 0..max_blocks {
+            // Start following the chain from the current tip.
+            let next_number = match self.watcher.tip() {
+                Some((number, _)) => number + 1,
+                None => chain_tip,
+            };
+            if next_number > chain_tip {
+                break;
+            }
+            let block: BlockView = match backend.get_block_by_number(next_number) {
+                Ok(Some(block)) => block,
+                // The chain has switched to a shorter fork since we got the tip.
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::error!(
+                        "[{}] get block {} failed: {:?}",
+                        actor_name,
+                        next_number,
+                        err
+                    );
+                    break;
+                }
+            };
+            match self.watcher.attach_block(&block) {
+                Ok(attached) => {
+                    if !self.cell_reservations.is_empty() {
+                        // Spent cells can never be picked again.
+                        let spent = block
+                            .transactions()
+                            .into_iter()
+                            .flat_map(|tx| tx.input_pts_iter())
+                            .collect::<Vec<_>>();
+                        self.cell_reservations.release(spent.iter());
+                    }
+                    for trace in self.pending_traces.iter_mut() {
+                        if attached.tx_hashes.contains(&trace.request.tx_hash) {
+                            trace.committed_block_number = Some(attached.number);
+                        }
+                    }
+                }
+                Err(ChainReorganized) => self.detach_tip(actor_name),
+            }
+        }
+
+        if !backend.has_full_blocks() {
+            self.poll_watches(actor_name);
+        }
+
+        self.pending_traces
+            .retain(|trace| !trace.reply_port.is_closed());
+        if !backend.has_full_blocks()
+            || chain_tip >= self.last_trace_recheck_block + TRACE_RECHECK_BLOCKS
+        {
+            self.last_trace_recheck_block = chain_tip;
+            self.recheck_pending_traces(actor_name);
+        }
+        self.reply_confirmed_traces();
+    }
+
+    fn detach_tip(&mut self, actor_name: &str) {
+        if let Some(detached) = self.watcher.detach_tip() {
+            tracing::warn!(
+                "[{}] block {} ({}) detached by chain reorganization",
+                actor_name,
+                detached.number,
+                detached.hash
+            );
+            for trace in self.pending_traces.iter_mut() {
+                if detached.tx_hashes.contains(&trace.request.tx_hash) {
+                    trace.committed_block_number = None;
+                }
+            }
+        }
+        if self.watcher.tip().is_none() {
+            tracing::warn!(
+                "[{}] chain reorganization is deeper than the watched blocks, restart from the tip",
+                actor_name
+            );
+        }
+    }
+
+    // Without full blocks, the backend searches the transactions of the watches.
+    fn poll_watches(&mut self, actor_name: &str) {
+        let backend = self.backend.clone();
+        for (watch_id, target) in self.watcher.watch_targets() {
+            match backend.find_watched_transactions(&target) {
+                Ok(found) => {
+                    self.watcher
+                        .update_found_transactions(watch_id, found, self.chain_tip)
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "[{}] find the transactions of watch {} failed: {:?}",
+                        actor_name,
+                        watch_id,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    // Rejected transactions never show up in blocks, ask the backend about them.
+    fn recheck_pending_traces(&mut self, actor_name: &str) {
+        let mut i = 0;
+        while i < self.pending_traces.len() {
+            let trace = &mut self.pending_traces[i];
+            if trace.committed_block_number.is_some() {
+                i += 1;
+                continue;
+            }
+            match self.backend.get_transaction_status(&trace.request.tx_hash) {
+                Ok(resp) if resp.status == Status::Rejected => {
+                    let trace = self.pending_traces.remove(i);
+                    // ignore error
+                    let _ = trace.reply_port.send(Status::Rejected);
+                    continue;
+                }
+                Ok(resp) if resp.status == Status::Committed => {
+                    trace.committed_block_number = resp.block_number;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::error!("[{}] get transaction status failed: {:?}", actor_name, err);
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn reply_confirmed_traces(&mut self) {
+        let tip_number = self
+            .watcher
+            .tip()
+            .map(|(number, _)| number)
+            .unwrap_or(self.chain_tip);
+        let (confirmed, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_traces)
+            .into_iter()
+            .partition(|trace| {
+                trace
+                    .committed_block_number
+                    .is_some_and(|number| tip_number >= number + trace.request.confirmations)
+            });
+        self.pending_traces = pending;
+        for trace in confirmed {
+            // ignore error
+            let _ = trace.reply_port.send(Status::Committed);
         }
     }
 }
 
 #[cfg(test)]
-pub use test_utils::{submit_tx, trace_tx, trace_tx_hash, MockChainActor};
+pub use test_utils::{submit_tx, trace_tx, trace_tx_hash, MockChainActor, MockChainBackend};
 
 #[cfg(test)]
 mod test_utils {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, sync::RwLock};
 
     use anyhow::anyhow;
+    use ckb_sdk::{
+        traits::{
+            CellCollector, HeaderDepResolver, OffchainCellCollector, OffchainHeaderDepResolver,
+            OffchainTransactionDependencyProvider, TransactionDependencyProvider,
+        },
+        NetworkType, RpcError,
+    };
     use ckb_types::{
-        core::TransactionView,
-        packed::{CellOutput, OutPoint},
+        core::{BlockView, TransactionView},
+        packed::{CellOutput, OutPoint, Script},
         prelude::{Builder, Entity, Pack, PackVec, Unpack},
     };
 
     use crate::ckb::TraceTxRequest;
+    use crate::fiber::channel::{DEFAULT_COMMITMENT_FEE_RATE, DEFAULT_FEE_RATE};
 
-    use super::super::contracts::MockContext;
+    use super::super::{
+        contracts::MockContext, watcher::ChainWatcher, ChainBackend, FeeRateTarget,
+        TransactionStatus,
+    };
     use super::CkbChainMessage;
 
     use ckb_types::packed::Byte32;
@@ -255,10 +671,180 @@ mod test_utils {
         Consumed,
     }
 
-    pub struct MockChainActorState {
-        ctx: MockContext,
-        tx_status: HashMap<Byte32, ckb_jsonrpc_types::Status>,
+    #[derive(Debug, Default)]
+    struct MockChainData {
+        tx_status: HashMap<Byte32, TransactionStatus>,
         cell_status: HashMap<OutPoint, CellStatus>,
+        blocks: Vec<BlockView>,
+    }
+
+    /// A chain backend which verifies transactions with the contracts in `MockContext`.
+    /// Every committed transaction gets a block of its own.
+    #[derive(Debug, Default)]
+    pub struct MockChainBackend {
+        ctx: MockContext,
+        data: RwLock<MockChainData>,
+    }
+
+    impl MockChainBackend {
+        fn verify_tx(
+            &self,
+            data: &mut MockChainData,
+            tx: &TransactionView,
+        ) -> Result<(), RpcError> {
+            const MAX_CYCLES: u64 = 100_000_000;
+            // Mark the inputs as consumed
+            for input in tx.input_pts_iter() {
+                match data.cell_status.entry(input.clone()) {
+                    std::collections::hash_map::Entry::Occupied(mut entry) => {
+                        if *entry.get() == CellStatus::Consumed {
+                            return Err(RpcError::Other(anyhow!(
+                                "Cell {:?} already consumed",
+                                &input
+                            )));
+                        }
+                        *entry.get_mut() = CellStatus::Consumed;
+                    }
+                    std::collections::hash_map::Entry::Vacant(entry) => {
+                        debug!("Consuming cell {:?}", &input);
+                        entry.insert(CellStatus::Consumed);
+                    }
+                }
+            }
+            let mut context = self.ctx.write();
+            match context.verify_tx(tx, MAX_CYCLES) {
+                Ok(c) => {
+                    debug!("Verified transaction: {:?} with {} CPU cycles", tx, c);
+                    // Also save the outputs to the context, so that we can refer to
+                    // these out points later.
+                    for outpoint in tx.output_pts().into_iter() {
+                        let index: u32 = outpoint.index().unpack();
+                        let index = index as usize;
+                        let cell = tx.outputs().get(index).unwrap();
+                        let data = tx.outputs_data().get(index).unwrap();
+                        debug!(
+                            "Creating cell with outpoint: {:?}, cell: {:?}, data: {:?}",
+                            outpoint, cell, data
+                        );
+                        context.create_cell_with_out_point(outpoint.clone(), cell, data.as_bytes());
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(RpcError::Other(anyhow!(
+                    "Failed to verify transaction: {:?}, error: {:?}",
+                    tx,
+                    e
+                ))),
+            }
+        }
+    }
+
+    impl ChainBackend for MockChainBackend {
+        fn send_transaction(&self, tx: &TransactionView) -> Result<(), RpcError> {
+            let mut data = self.data.write().unwrap();
+            let result = self.verify_tx(&mut data, tx);
+            let status = match result {
+                Ok(()) => {
+                    let (number, parent_hash) = data
+                        .blocks
+                        .last()
+                        .map(|block| (block.number() + 1, block.hash()))
+                        .unwrap_or_default();
+                    let block = BlockView::new_advanced_builder()
+                        .number(number.pack())
+                        .parent_hash(parent_hash)
+                        .transaction(tx.clone())
+                        .build();
+                    data.blocks.push(block);
+                    TransactionStatus {
+                        status: ckb_jsonrpc_types::Status::Committed,
+                        block_number: Some(number),
+                    }
+                }
+                Err(_) => TransactionStatus {
+                    status: ckb_jsonrpc_types::Status::Rejected,
+                    block_number: None,
+                },
+            };
+            data.tx_status.insert(tx.hash(), status);
+            result
+        }
+
+        fn get_transaction_status(&self, tx_hash: &Byte32) -> Result<TransactionStatus, RpcError> {
+            Ok(self
+                .data
+                .read()
+                .unwrap()
+                .tx_status
+                .get(tx_hash)
+                .cloned()
+                .unwrap_or(TransactionStatus {
+                    status: ckb_jsonrpc_types::Status::Unknown,
+                    block_number: None,
+                }))
+        }
+
+        fn get_tip_block_number(&self) -> Result<u64, RpcError> {
+            Ok(self
+                .data
+                .read()
+                .unwrap()
+                .blocks
+                .last()
+                .map(|block| block.number())
+                .unwrap_or_default())
+        }
+
+        fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, RpcError> {
+            Ok(self
+                .data
+                .read()
+                .unwrap()
+                .blocks
+                .get(number as usize)
+                .cloned())
+        }
+
+        fn get_genesis_block(&self) -> Result<BlockView, RpcError> {
+            Err(RpcError::Other(anyhow!(
+                "the mock chain has no genesis block"
+            )))
+        }
+
+        fn network_type(&self) -> Result<NetworkType, RpcError> {
+            Ok(NetworkType::Dev)
+        }
+
+        fn get_median_fee_rate(&self) -> Result<Option<u64>, RpcError> {
+            Ok(None)
+        }
+
+        fn subscribe_scripts(
+            &self,
+            _scripts: Vec<Script>,
+            _from_block: u64,
+        ) -> Result<(), RpcError> {
+            Ok(())
+        }
+
+        // The mock chain does not index cells, headers or transactions, callers see empty
+        // results instead of panicking the chain actor.
+        fn cell_collector(&self) -> Box<dyn CellCollector> {
+            Box::<OffchainCellCollector>::default()
+        }
+
+        fn header_dep_resolver(&self) -> Box<dyn HeaderDepResolver> {
+            Box::<OffchainHeaderDepResolver>::default()
+        }
+
+        fn tx_dep_provider(&self) -> Box<dyn TransactionDependencyProvider> {
+            Box::<OffchainTransactionDependencyProvider>::default()
+        }
+    }
+
+    pub struct MockChainActorState {
+        backend: MockChainBackend,
+        watcher: ChainWatcher,
     }
 
     impl Default for MockChainActorState {
@@ -270,9 +856,8 @@ mod test_utils {
     impl MockChainActorState {
         pub fn new() -> Self {
             Self {
-                ctx: MockContext::new(),
-                tx_status: HashMap::new(),
-                cell_status: HashMap::new(),
+                backend: MockChainBackend::default(),
+                watcher: ChainWatcher::new(),
             }
         }
     }
@@ -402,64 +987,16 @@ mod test_utils {
                     }
                 }
                 SendTx(tx, reply_port) => {
-                    const MAX_CYCLES: u64 = 100_000_000;
-                    let mut context = state.ctx.write();
-                    let mut f = || {
-                        // Mark the inputs as consumed
-                        for input in tx.input_pts_iter() {
-                            match state.cell_status.entry(input.clone()) {
-                                std::collections::hash_map::Entry::Occupied(mut entry) => {
-                                    if *entry.get() == CellStatus::Consumed {
-                                        return (
-                                            ckb_jsonrpc_types::Status::Rejected,
-                                            Err(ckb_sdk::RpcError::Other(anyhow!(
-                                                "Cell {:?} already consumed",
-                                                &input
-                                            ))),
-                                        );
-                                    }
-                                    *entry.get_mut() = CellStatus::Consumed;
-                                }
-                                std::collections::hash_map::Entry::Vacant(entry) => {
-                                    debug!("Consuming cell {:?}", &input);
-                                    entry.insert(CellStatus::Consumed);
-                                }
-                            }
-                        }
-                        match context.verify_tx(&tx, MAX_CYCLES) {
-                            Ok(c) => {
-                                debug!("Verified transaction: {:?} with {} CPU cycles", tx, c);
-                                // Also save the outputs to the context, so that we can refer to
-                                // these out points later.
-                                for outpoint in tx.output_pts().into_iter() {
-                                    let index: u32 = outpoint.index().unpack();
-                                    let index = index as usize;
-                                    let cell = tx.outputs().get(index).unwrap();
-                                    let data = tx.outputs_data().get(index).unwrap();
-                                    debug!(
-                                        "Creating cell with outpoint: {:?}, cell: {:?}, data: {:?}",
-                                        outpoint, cell, data
-                                    );
-                                    context.create_cell_with_out_point(
-                                        outpoint.clone(),
-                                        cell,
-                                        data.as_bytes(),
-                                    );
-                                }
-                                (ckb_jsonrpc_types::Status::Committed, Ok(()))
-                            }
-                            Err(e) => (
-                                ckb_jsonrpc_types::Status::Rejected,
-                                Err(ckb_sdk::RpcError::Other(anyhow!(
-                                    "Failed to verify transaction: {:?}, error: {:?}",
-                                    tx,
-                                    e
-                                ))),
-                            ),
-                        }
-                    };
-                    let (status, result) = f();
-                    state.tx_status.insert(tx.hash(), status);
+                    let result = state.backend.send_transaction(&tx);
+                    if result.is_ok() {
+                        let block = state
+                            .backend
+                            .get_tip_block_number()
+                            .and_then(|number| state.backend.get_block_by_number(number))
+                            .expect("get tip block")
+                            .expect("tip block exists");
+                        state.watcher.attach_block(&block).expect("attach block");
+                    }
                     if let Err(e) = reply_port.send(result) {
                         error!(
                             "[{}] send reply failed: {:?}",
@@ -470,9 +1007,9 @@ mod test_utils {
                 }
                 TraceTx(tx, reply_port) => {
                     let status = state
-                        .tx_status
-                        .get(&tx.tx_hash)
-                        .cloned()
+                        .backend
+                        .get_transaction_status(&tx.tx_hash)
+                        .map(|status| status.status)
                         .unwrap_or(ckb_jsonrpc_types::Status::Unknown);
                     debug!(
                         "Tracing transaction: {:?}, status: {:?}",
@@ -486,6 +1023,52 @@ mod test_utils {
                         );
                     }
                 }
+                Watch(target, subscriber, reply_port) => {
+                    let watch_id = state.watcher.add_watch(target, subscriber);
+                    if let Err(e) = reply_port.send(watch_id) {
+                        error!(
+                            "[{}] send reply failed: {:?}",
+                            myself.get_name().unwrap_or_default(),
+                            e
+                        );
+                    }
+                }
+                Unwatch(watch_id) => state.watcher.remove_watch(watch_id),
+                // Blocks are produced as soon as transactions are sent.
+                PollTip => {}
+                ReleaseCells(_) => {}
+                EstimateFeeRate(target, reply_port) => {
+                    let fee_rate = match target {
+                        FeeRateTarget::Funding | FeeRateTarget::Closing => DEFAULT_FEE_RATE,
+                        FeeRateTarget::Commitment => DEFAULT_COMMITMENT_FEE_RATE,
+                    };
+                    if let Err(e) = reply_port.send(fee_rate) {
+                        error!(
+                            "[{}] send reply failed: {:?}",
+                            myself.get_name().unwrap_or_default(),
+                            e
+                        );
+                    }
+                }
+                // The mock chain does not index cells, the reply ports are dropped.
+                GetBalance(_) | ListUnspent(_) | GetAddress(_) | SendToAddress(..) => {
+                    error!("Wallet is not supported by the mock chain actor");
+                }
+                GetTransaction(tx_hash, reply_port) => {
+                    let tip = state.backend.get_tip_block_number().unwrap_or_default();
+                    let tx = (0..=tip)
+                        .filter_map(|number| state.backend.get_block_by_number(number).ok()?)
+                        .flat_map(|block| block.transactions())
+                        .find(|tx| tx.hash() == tx_hash)
+                        .ok_or_else(|| format!("transaction {:?} not found", tx_hash));
+                    if let Err(e) = reply_port.send(tx) {
+                        error!(
+                            "[{}] send reply failed: {:?}",
+                            myself.get_name().unwrap_or_default(),
+                            e
+                        );
+                    }
+                }
             }
             Ok(())
         }
@@ -541,6 +1124,7 @@ mod test {
     use ractor::{Actor, ActorRef};
 
     use super::super::contracts::{get_cell_deps_by_contracts, get_script_by_contract, Contract};
+    use super::super::{ChainWatchEvent, WatchTarget};
     use super::test_utils::submit_tx;
     use super::CkbChainMessage;
 
@@ -718,4 +1302,33 @@ mod test {
             .build();
         assert_eq!(submit_tx(actor, tx).await, Status::Rejected);
     }
+
+    #[tokio::test]
+    async fn test_watch_lock_script() {
+        let actor = create_mock_chain_actor().await;
+        let lock = get_script_by_contract(Contract::AlwaysSuccess, &b"watched"[..]);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let watch_id = ractor::call!(actor, |reply| CkbChainMessage::Watch(
+            WatchTarget::LockScript(lock.clone()),
+            sender,
+            reply
+        ))
+        .expect("chain actor alive");
+
+        let tx = TransactionView::new_advanced_builder()
+            .output(CellOutput::new_builder().lock(lock).build())
+            .output_data(Default::default())
+            .build();
+        assert_eq!(
+            submit_tx(actor.clone(), tx.clone()).await,
+            Status::Committed
+        );
+        match receiver.recv().await {
+            Some(ChainWatchEvent::Attached(watch_match)) => {
+                assert_eq!(watch_match.watch_id, watch_id);
+                assert_eq!(watch_match.tx_hash, tx.hash());
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
 }
@@ -0,0 +1,91 @@
+use ckb_sdk::{
+    traits::{
+        CellCollector, DefaultCellCollector, DefaultHeaderDepResolver,
+        DefaultTransactionDependencyProvider, HeaderDepResolver, TransactionDependencyProvider,
+    },
+    CkbRpcClient, NetworkType, RpcError,
+};
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    packed::{Byte32, Script},
+    prelude::*,
+};
+
+use super::{ChainBackend, TransactionStatus};
+
+/// A CKB full node with the indexer enabled.
+#[derive(Clone, Debug)]
+pub struct FullNodeBackend {
+    rpc_url: String,
+}
+
+impl FullNodeBackend {
+    pub fn new(rpc_url: String) -> Self {
+        Self { rpc_url }
+    }
+
+    fn client(&self) -> CkbRpcClient {
+        CkbRpcClient::new(&self.rpc_url)
+    }
+}
+
+impl ChainBackend for FullNodeBackend {
+    fn send_transaction(&self, tx: &TransactionView) -> Result<(), RpcError> {
+        self.client().send_transaction(tx.data().into(), None)?;
+        Ok(())
+    }
+
+    fn get_transaction_status(&self, tx_hash: &Byte32) -> Result<TransactionStatus, RpcError> {
+        let resp = self.client().get_transaction_status(tx_hash.unpack())?;
+        Ok(TransactionStatus {
+            status: resp.tx_status.status,
+            block_number: resp.tx_status.block_number.map(Into::into),
+        })
+    }
+
+    fn get_tip_block_number(&self) -> Result<u64, RpcError> {
+        Ok(self.client().get_tip_block_number()?.into())
+    }
+
+    fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, RpcError> {
+        Ok(self
+            .client()
+            .get_block_by_number(number.into())?
+            .map(Into::into))
+    }
+
+    fn get_genesis_block(&self) -> Result<BlockView, RpcError> {
+        self.get_block_by_number(0)?
+            .ok_or_else(|| RpcError::Other(anyhow::anyhow!("genesis block not found")))
+    }
+
+    fn network_type(&self) -> Result<NetworkType, RpcError> {
+        let chain = self.client().get_blockchain_info()?.chain;
+        // Chains with custom names are dev chains.
+        Ok(NetworkType::from_raw_str(&chain).unwrap_or(NetworkType::Dev))
+    }
+
+    fn get_median_fee_rate(&self) -> Result<Option<u64>, RpcError> {
+        Ok(self
+            .client()
+            .get_fee_rate_statistics(None)?
+            .map(|statistics| statistics.median.value()))
+    }
+
+    // The indexer of the full node indexes all the scripts.
+    fn subscribe_scripts(&self, _scripts: Vec<Script>, _from_block: u64) -> Result<(), RpcError> {
+        Ok(())
+    }
+
+    fn cell_collector(&self) -> Box<dyn CellCollector> {
+        Box::new(DefaultCellCollector::new(&self.rpc_url))
+    }
+
+    fn header_dep_resolver(&self) -> Box<dyn HeaderDepResolver> {
+        Box::new(DefaultHeaderDepResolver::new(&self.rpc_url))
+    }
+
+    fn tx_dep_provider(&self) -> Box<dyn TransactionDependencyProvider> {
+        Box::new(DefaultTransactionDependencyProvider::new(&self.rpc_url, 10))
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use ckb_jsonrpc_types::Status;
+use ckb_sdk::{
+    rpc::ckb_light_client::{
+        FetchStatus, Order, ScriptStatus, ScriptType, SearchKey, SetScriptsCommand, Tx,
+    },
+    traits::{
+        CellCollector, HeaderDepResolver, LightClientCellCollector, LightClientHeaderDepResolver,
+        LightClientTransactionDependencyProvider, TransactionDependencyProvider,
+    },
+    LightClientRpcClient, NetworkType, RpcError,
+};
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    h256,
+    packed::{self, Byte32, OutPoint, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{ChainBackend, CommittedTransaction, TransactionStatus, WatchTarget};
+
+const MAINNET_GENESIS_HASH: H256 =
+    h256!("0x92b197aa1fba0f63633922c61c92375c9c074a93e85963554f5499fe1450d0e5");
+const TESTNET_GENESIS_HASH: H256 =
+    h256!("0x10639e0895502b5688a6be8cf69460d76541bfa4821629d86d62ba0aae3f9606");
+
+// The cells of a watched lock script are rarely touched, only the latest transactions of the
+// script are searched.
+const MAX_SCRIPT_TRANSACTIONS: u32 = 100;
+
+/// A CKB light client. It only syncs the transactions of the subscribed scripts, so it
+/// can't serve full blocks. The transactions of the watches are searched in the subscribed
+/// scripts instead, and a transaction is known to be rejected only once one of its inputs
+/// is spent by another transaction.
+#[derive(Clone, Debug)]
+pub struct LightClientBackend {
+    rpc_url: String,
+    // The transactions sent through this backend which are not committed yet.
+    sent_transactions: Arc<Mutex<HashMap<Byte32, TransactionView>>>,
+    // The lock scripts of the cells whose spending transactions are searched, the scripts are
+    // subscribed from the blocks of the cells.
+    cell_locks: Arc<Mutex<HashMap<OutPoint, Script>>>,
+}
+
+impl LightClientBackend {
+    pub fn new(rpc_url: String) -> Self {
+        Self {
+            rpc_url,
+            sent_transactions: Default::default(),
+            cell_locks: Default::default(),
+        }
+    }
+
+    fn client(&self) -> LightClientRpcClient {
+        LightClientRpcClient::new(&self.rpc_url)
+    }
+
+    // Transactions of other scripts are fetched from the peers on demand, `None` until they are
+    // fetched.
+    fn get_committed_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<Option<CommittedTransaction>, RpcError> {
+        match self.client().fetch_transaction(tx_hash.unpack())? {
+            FetchStatus::Fetched { data } => Ok(Some(CommittedTransaction {
+                tx_hash: tx_hash.clone(),
+                block_number: data.header.inner.number.into(),
+                block_hash: data.header.hash.pack(),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    // The lock script of the cell, which is subscribed from the block of the cell so that the
+    // transactions spending it are synced. `None` until the transaction of the cell is fetched.
+    fn subscribe_cell(&self, out_point: &OutPoint) -> Result<Option<Script>, RpcError> {
+        if let Some(lock) = self.cell_locks.lock().unwrap().get(out_point) {
+            return Ok(Some(lock.clone()));
+        }
+        let FetchStatus::Fetched { data } = self
+            .client()
+            .fetch_transaction(out_point.tx_hash().unpack())?
+        else {
+            return Ok(None);
+        };
+        let index: u32 = out_point.index().unpack();
+        let lock: Script = match data.transaction.inner.outputs.get(index as usize) {
+            Some(output) => output.lock.clone().into(),
+            None => {
+                return Err(RpcError::Other(anyhow::anyhow!(
+                    "cell {} does not exist",
+                    out_point
+                )))
+            }
+        };
+        self.subscribe_scripts(vec![lock.clone()], data.header.inner.number.into())?;
+        self.cell_locks
+            .lock()
+            .unwrap()
+            .insert(out_point.clone(), lock.clone());
+        Ok(Some(lock))
+    }
+
+    // The latest synced transactions which have a cell of the subscribed lock script in their
+    // inputs or outputs.
+    fn get_script_transactions(&self, lock: &Script) -> Result<Vec<TransactionView>, RpcError> {
+        let search_key = SearchKey {
+            script: lock.clone().into(),
+            script_type: ScriptType::Lock,
+            filter: None,
+            with_data: None,
+            group_by_transaction: Some(true),
+        };
+        let txs = self.client().get_transactions(
+            search_key,
+            Order::Desc,
+            MAX_SCRIPT_TRANSACTIONS.into(),
+            None,
+        )?;
+        Ok(txs
+            .objects
+            .into_iter()
+            .map(|tx| {
+                let tx = match tx {
+                    Tx::Grouped(tx) => tx.transaction,
+                    Tx::Ungrouped(tx) => tx.transaction,
+                };
+                packed::Transaction::from(tx.inner).into_view()
+            })
+            .collect())
+    }
+
+    fn find_spending_transactions(
+        &self,
+        out_point: &OutPoint,
+    ) -> Result<Vec<CommittedTransaction>, RpcError> {
+        let Some(lock) = self.subscribe_cell(out_point)? else {
+            return Ok(vec![]);
+        };
+        let mut found = vec![];
+        for tx in self.get_script_transactions(&lock)? {
+            if tx.input_pts_iter().any(|x| &x == out_point) {
+                found.extend(self.get_committed_transaction(&tx.hash())?);
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl ChainBackend for LightClientBackend {
+    fn send_transaction(&self, tx: &TransactionView) -> Result<(), RpcError> {
+        self.client().send_transaction(tx.data().into())?;
+        self.sent_transactions
+            .lock()
+            .unwrap()
+            .insert(tx.hash(), tx.clone());
+        Ok(())
+    }
+
+    fn get_transaction_status(&self, tx_hash: &Byte32) -> Result<TransactionStatus, RpcError> {
+        if let Some(committed) = self.get_committed_transaction(tx_hash)? {
+            self.sent_transactions.lock().unwrap().remove(tx_hash);
+            return Ok(TransactionStatus {
+                status: Status::Committed,
+                block_number: Some(committed.block_number),
+            });
+        }
+        // The peers don't tell the light client about the rejected transactions. A sent
+        // transaction can never be committed once one of its inputs is spent by another one.
+        let sent_tx = self.sent_transactions.lock().unwrap().get(tx_hash).cloned();
+        if let Some(tx) = sent_tx {
+            for out_point in tx.input_pts_iter() {
+                if self
+                    .find_spending_transactions(&out_point)?
+                    .iter()
+                    .any(|x| &x.tx_hash != tx_hash)
+                {
+                    self.sent_transactions.lock().unwrap().remove(tx_hash);
+                    return Ok(TransactionStatus {
+                        status: Status::Rejected,
+                        block_number: None,
+                    });
+                }
+            }
+        }
+        Ok(TransactionStatus {
+            status: Status::Unknown,
+            block_number: None,
+        })
+    }
+
+    fn get_tip_block_number(&self) -> Result<u64, RpcError> {
+        Ok(self.client().get_tip_header()?.inner.number.into())
+    }
+
+    fn has_full_blocks(&self) -> bool {
+        false
+    }
+
+    fn find_watched_transactions(
+        &self,
+        target: &WatchTarget,
+    ) -> Result<Vec<CommittedTransaction>, RpcError> {
+        match target {
+            WatchTarget::Transaction(tx_hash) => Ok(self
+                .get_committed_transaction(tx_hash)?
+                .into_iter()
+                .collect()),
+            WatchTarget::OutPoint(out_point) => self.find_spending_transactions(out_point),
+            // The script is subscribed when the watch is added.
+            WatchTarget::LockScript(script) => {
+                let mut found = vec![];
+                for tx in self.get_script_transactions(script)? {
+                    if tx.outputs().into_iter().any(|x| &x.lock() == script) {
+                        found.extend(self.get_committed_transaction(&tx.hash())?);
+                    }
+                }
+                Ok(found)
+            }
+        }
+    }
+
+    fn get_block_by_number(&self, _number: u64) -> Result<Option<BlockView>, RpcError> {
+        Ok(None)
+    }
+
+    fn get_genesis_block(&self) -> Result<BlockView, RpcError> {
+        Ok(self.client().get_genesis_block()?.into())
+    }
+
+    fn network_type(&self) -> Result<NetworkType, RpcError> {
+        let genesis_hash = self.client().get_genesis_block()?.header.hash;
+        let network_type = if genesis_hash == MAINNET_GENESIS_HASH {
+            NetworkType::Mainnet
+        } else if genesis_hash == TESTNET_GENESIS_HASH {
+            NetworkType::Testnet
+        } else {
+            NetworkType::Dev
+        };
+        Ok(network_type)
+    }
+
+    fn get_median_fee_rate(&self) -> Result<Option<u64>, RpcError> {
+        Ok(None)
+    }
+
+    fn subscribe_scripts(&self, scripts: Vec<Script>, from_block: u64) -> Result<(), RpcError> {
+        // Don't sync the scripts subscribed before all over again.
+        let subscribed = self.client().get_scripts()?;
+        let scripts: Vec<_> = scripts
+            .into_iter()
+            .filter(|script| {
+                let script: ckb_jsonrpc_types::Script = script.clone().into();
+                !subscribed.iter().any(|status| status.script == script)
+            })
+            .map(|script| ScriptStatus {
+                script: script.into(),
+                script_type: ScriptType::Lock,
+                block_number: from_block.into(),
+            })
+            .collect();
+        if scripts.is_empty() {
+            return Ok(());
+        }
+        self.client()
+            .set_scripts(scripts, Some(SetScriptsCommand::Partial))
+    }
+
+    fn cell_collector(&self) -> Box<dyn CellCollector> {
+        Box::new(LightClientCellCollector::new(&self.rpc_url))
+    }
+
+    fn header_dep_resolver(&self) -> Box<dyn HeaderDepResolver> {
+        Box::new(LightClientHeaderDepResolver::new(&self.rpc_url))
+    }
+
+    fn tx_dep_provider(&self) -> Box<dyn TransactionDependencyProvider> {
+        Box::new(LightClientTransactionDependencyProvider::new(&self.rpc_url))
+    }
+}
@@ -0,0 +1,92 @@
+mod full_node;
+mod light_client;
+
+use std::{fmt::Debug, sync::Arc};
+
+use ckb_jsonrpc_types::Status;
+use ckb_sdk::{
+    traits::{
+        CellCollector, CellCollectorError, CellQueryOptions, HeaderDepResolver, LiveCell,
+        TransactionDependencyProvider,
+    },
+    NetworkType, RpcError,
+};
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    packed::{Byte32, Script},
+};
+
+pub use full_node::FullNodeBackend;
+pub use light_client::LightClientBackend;
+
+use super::{
+    watcher::{CommittedTransaction, WatchTarget},
+    CkbConfig,
+};
+
+/// The status of a transaction as seen by the chain backend.
+#[derive(Clone, Debug)]
+pub struct TransactionStatus {
+    pub status: Status,
+    /// The number of the block which commits the transaction.
+    pub block_number: Option<u64>,
+}
+
+/// Everything the chain actor needs from the CKB chain. All the methods block on the
+/// network, call them in `tokio::task::block_in_place`.
+pub trait ChainBackend: Debug + Send + Sync {
+    fn send_transaction(&self, tx: &TransactionView) -> Result<(), RpcError>;
+
+    fn get_transaction_status(&self, tx_hash: &Byte32) -> Result<TransactionStatus, RpcError>;
+
+    fn get_tip_block_number(&self) -> Result<u64, RpcError>;
+
+    /// Whether `get_block_by_number` returns blocks with all their transactions. Without
+    /// full blocks, the chain actor can't follow the chain block by block, so the transactions
+    /// of the watches are found by `find_watched_transactions`, and the committed transactions
+    /// by checking their status.
+    fn has_full_blocks(&self) -> bool {
+        true
+    }
+
+    /// Find the committed transactions matching the watch target, only called if the backend
+    /// doesn't have full blocks.
+    fn find_watched_transactions(
+        &self,
+        _target: &WatchTarget,
+    ) -> Result<Vec<CommittedTransaction>, RpcError> {
+        Ok(vec![])
+    }
+
+    fn get_block_by_number(&self, number: u64) -> Result<Option<BlockView>, RpcError>;
+
+    fn get_genesis_block(&self) -> Result<BlockView, RpcError>;
+
+    fn network_type(&self) -> Result<NetworkType, RpcError>;
+
+    /// The median fee rate of the recent blocks, `None` if the backend has no statistics.
+    fn get_median_fee_rate(&self) -> Result<Option<u64>, RpcError>;
+
+    /// Make sure the cells and transactions of the lock scripts since `from_block` can be
+    /// queried from the backend.
+    fn subscribe_scripts(&self, scripts: Vec<Script>, from_block: u64) -> Result<(), RpcError>;
+
+    fn cell_collector(&self) -> Box<dyn CellCollector>;
+
+    fn header_dep_resolver(&self) -> Box<dyn HeaderDepResolver>;
+
+    fn tx_dep_provider(&self) -> Box<dyn TransactionDependencyProvider>;
+
+    fn collect_cells(&self, query: &CellQueryOptions) -> Result<Vec<LiveCell>, CellCollectorError> {
+        let (cells, _) = self.cell_collector().collect_live_cells(query, false)?;
+        Ok(cells)
+    }
+}
+
+/// The light client is used if its rpc url is configured, otherwise the full node.
+pub fn new_chain_backend(config: &CkbConfig) -> Arc<dyn ChainBackend> {
+    match config.light_client_rpc_url.as_ref() {
+        Some(url) => Arc::new(LightClientBackend::new(url.clone())),
+        None => Arc::new(FullNodeBackend::new(config.rpc_url.clone())),
+    }
+}
@@ -6,6 +6,9 @@ use std::{
     io::{ErrorKind, Read},
     path::PathBuf,
 };
+use zeroize::Zeroizing;
+
+use crate::keystore;
 
 use std::str::FromStr;
 
@@ -21,8 +24,13 @@ use clap_serde_derive::clap::{self};
 use molecule::prelude::Entity;
 use serde::Deserialize;
 
+use crate::fiber::channel::{DEFAULT_COMMITMENT_FEE_RATE, DEFAULT_FEE_RATE};
+
 pub const DEFAULT_CKB_BASE_DIR_NAME: &str = "ckb";
 const DEFAULT_CKB_NODE_RPC_URL: &str = "http://127.0.0.1:8114";
+// The commitment fee is reserved from the balances for the lifetime of the channel, so a
+// burst of the fee rates on chain shouldn't lock up the funds.
+const DEFAULT_MAX_COMMITMENT_FEE_RATE: u64 = 100 * DEFAULT_COMMITMENT_FEE_RATE;
 
 #[derive(ClapSerde, Debug, Clone)]
 pub struct CkbConfig {
@@ -44,6 +52,14 @@ pub struct CkbConfig {
     )]
     pub rpc_url: String,
 
+    #[arg(
+        name = "CKB_LIGHT_CLIENT_RPC_URL",
+        long = "ckb-light-client-rpc-url",
+        env,
+        help = "rpc url to connect the ckb light client, the full node is not used if it is set"
+    )]
+    pub light_client_rpc_url: Option<String>,
+
     #[arg(
         name = "CKB_UDT_WHITELIST",
         long = "ckb-udt-whitelist",
@@ -51,6 +67,30 @@ pub struct CkbConfig {
         help = "a list of supported UDT scripts"
     )]
     pub udt_whitelist: Option<UdtCfgInfos>,
+
+    #[arg(
+        name = "CKB_MIN_FEE_RATE",
+        long = "ckb-min-fee-rate",
+        env,
+        help = format!("the lowest fee rate for funding and closing transactions, in shannons per kilo-weight [default: {}]", DEFAULT_FEE_RATE)
+    )]
+    pub min_fee_rate: Option<u64>,
+
+    #[arg(
+        name = "CKB_MIN_COMMITMENT_FEE_RATE",
+        long = "ckb-min-commitment-fee-rate",
+        env,
+        help = format!("the lowest fee rate for commitment transactions, in shannons per kilo-weight [default: {}]", DEFAULT_COMMITMENT_FEE_RATE)
+    )]
+    pub min_commitment_fee_rate: Option<u64>,
+
+    #[arg(
+        name = "CKB_MAX_COMMITMENT_FEE_RATE",
+        long = "ckb-max-commitment-fee-rate",
+        env,
+        help = format!("the highest estimated fee rate for commitment transactions, in shannons per kilo-weight [default: {}]", DEFAULT_MAX_COMMITMENT_FEE_RATE)
+    )]
+    pub max_commitment_fee_rate: Option<u64>,
 }
 
 impl CkbConfig {
@@ -58,6 +98,26 @@ impl CkbConfig {
         self.base_dir.as_ref().expect("have set base dir")
     }
 
+    /// Fee rates lower than the defaults are rejected by the peers, so the floors can only
+    /// be raised.
+    pub fn min_fee_rate(&self) -> u64 {
+        self.min_fee_rate
+            .unwrap_or(DEFAULT_FEE_RATE)
+            .max(DEFAULT_FEE_RATE)
+    }
+
+    pub fn min_commitment_fee_rate(&self) -> u64 {
+        self.min_commitment_fee_rate
+            .unwrap_or(DEFAULT_COMMITMENT_FEE_RATE)
+            .max(DEFAULT_COMMITMENT_FEE_RATE)
+    }
+
+    pub fn max_commitment_fee_rate(&self) -> u64 {
+        self.max_commitment_fee_rate
+            .unwrap_or(DEFAULT_MAX_COMMITMENT_FEE_RATE)
+            .max(self.min_commitment_fee_rate())
+    }
+
     pub fn create_base_dir(&self) -> crate::Result<()> {
         if !self.base_dir().exists() {
             std::fs::create_dir_all(self.base_dir()).map_err(Into::into)
@@ -66,7 +126,12 @@ impl CkbConfig {
         }
     }
 
-    // TODO: Use keystore and password to read secret key and add an RPC method to authorize the secret key access.
+    pub fn secret_key_exists(&self) -> bool {
+        self.base_dir().join("key").exists()
+    }
+
+    /// Read the funding key, which is decrypted with the passphrase of the node if it is
+    /// stored in a keystore, see [`crate::keystore`].
     pub fn read_secret_key(&self) -> crate::Result<SecretKey> {
         self.create_base_dir()?;
         let path = self.base_dir().join("key");
@@ -95,11 +160,18 @@ impl CkbConfig {
             warn(!file.metadata()?.permissions().readonly(), "readonly");
         }
 
-        let mut key_hex: String = Default::default();
-        file.read_to_string(&mut key_hex)?;
-        let key_bin = hex::decode(key_hex.trim())
-            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "invalid secret key data"))?;
-        SecretKey::from_slice(&key_bin).map_err(|_| {
+        let mut content = Zeroizing::new(Vec::new());
+        file.read_to_end(&mut content)?;
+        // The key is either a hex string or a keystore encrypted by a passphrase.
+        let key_bin = keystore::decode_key_file(&path, &content, |content| {
+            let mut key_bin = Zeroizing::new([0u8; 32]);
+            let key_hex = std::str::from_utf8(content).unwrap_or_default().trim();
+            hex::decode_to_slice(key_hex, key_bin.as_mut_slice()).map_err(|_| {
+                std::io::Error::new(ErrorKind::InvalidData, "invalid secret key data")
+            })?;
+            Ok(key_bin)
+        })?;
+        SecretKey::from_slice(key_bin.as_slice()).map_err(|_| {
             std::io::Error::new(ErrorKind::InvalidData, "invalid secret key data").into()
         })
     }
@@ -528,7 +528,7 @@ pub fn get_cell_deps_by_contracts(contracts: Vec<Contract>) -> CellDepVec {
     init_contracts_context(None, None).get_cell_deps(contracts)
 }
 
-fn get_udt_info(script: &Script) -> Option<&UdtArgInfo> {
+pub(crate) fn get_udt_info(script: &Script) -> Option<&UdtArgInfo> {
     init_contracts_context(None, None).get_udt_info(script)
 }
 
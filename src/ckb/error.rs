@@ -1,4 +1,6 @@
-use ckb_sdk::{tx_builder::TxBuilderError, unlock::UnlockError, RpcError};
+use ckb_sdk::{
+    traits::CellCollectorError, tx_builder::TxBuilderError, unlock::UnlockError, RpcError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -21,6 +23,27 @@ pub enum FundingError {
     InvalidChannel,
 }
 
+#[derive(Error, Debug)]
+pub enum WalletError {
+    #[error("Failed to call CKB node RPC: {0}")]
+    CkbRpcError(#[from] RpcError),
+
+    #[error("Failed to collect cells: {0}")]
+    CellCollectorError(#[from] CellCollectorError),
+
+    #[error("Failed to build CKB tx: {0}")]
+    CkbTxBuilderError(#[from] TxBuilderError),
+
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+
+    #[error("Invalid chain: {0}")]
+    InvalidChain(String),
+}
+
 #[derive(Error, Debug)]
 pub enum CkbChainError {
     #[error("Funding error: {0}")]
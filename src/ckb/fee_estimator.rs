@@ -0,0 +1,122 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, warn};
+
+use super::ChainBackend;
+
+/// The fee rate statistics of the ckb node are fetched again after this time.
+const FEE_RATE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The kinds of transactions a fee rate is estimated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FeeRateTarget {
+    Funding,
+    Commitment,
+    Closing,
+}
+
+/// Estimate fee rates (shannons per kilo-weight) from the median fee rate of the recent
+/// blocks reported by the chain backend. The estimates never go below the configured floors,
+/// which are also used when the backend can't provide the statistics, and the commitment fee
+/// rates never go above the configured maximum.
+#[derive(Debug)]
+pub struct FeeEstimator {
+    backend: Arc<dyn ChainBackend>,
+    min_fee_rate: u64,
+    min_commitment_fee_rate: u64,
+    max_commitment_fee_rate: u64,
+    // The median fee rate and when it was fetched.
+    cached: Option<(Instant, Option<u64>)>,
+}
+
+impl FeeEstimator {
+    pub fn new(
+        backend: Arc<dyn ChainBackend>,
+        min_fee_rate: u64,
+        min_commitment_fee_rate: u64,
+        max_commitment_fee_rate: u64,
+    ) -> Self {
+        Self {
+            backend,
+            min_fee_rate,
+            min_commitment_fee_rate,
+            max_commitment_fee_rate,
+            cached: None,
+        }
+    }
+
+    pub fn estimate(&mut self, target: FeeRateTarget) -> u64 {
+        let median = match self.cached {
+            Some((fetched_at, median)) if fetched_at.elapsed() < FEE_RATE_CACHE_TTL => median,
+            _ => {
+                let median = self.fetch_median_fee_rate();
+                self.cached = Some((Instant::now(), median));
+                median
+            }
+        };
+        let fee_rate = self.apply_bounds(target, median);
+        debug!(
+            "Estimated {:?} fee rate {} from median {:?}",
+            target, fee_rate, median
+        );
+        fee_rate
+    }
+
+    fn apply_bounds(&self, target: FeeRateTarget, median: Option<u64>) -> u64 {
+        let fee_rate = median.unwrap_or_default();
+        match target {
+            FeeRateTarget::Funding | FeeRateTarget::Closing => fee_rate.max(self.min_fee_rate),
+            FeeRateTarget::Commitment => fee_rate
+                .min(self.max_commitment_fee_rate)
+                .max(self.min_commitment_fee_rate),
+        }
+    }
+
+    fn fetch_median_fee_rate(&self) -> Option<u64> {
+        match self.backend.get_median_fee_rate() {
+            Ok(median) => median,
+            Err(err) => {
+                warn!("Failed to get fee rate statistics: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::super::FullNodeBackend;
+    use super::{FeeEstimator, FeeRateTarget};
+
+    #[test]
+    fn test_fee_rate_bounds() {
+        let backend = Arc::new(FullNodeBackend::new("http://127.0.0.1:8114".to_string()));
+        let estimator = FeeEstimator::new(backend, 1000, 2000, 5000);
+        assert_eq!(estimator.apply_bounds(FeeRateTarget::Funding, None), 1000);
+        assert_eq!(
+            estimator.apply_bounds(FeeRateTarget::Closing, Some(500)),
+            1000
+        );
+        assert_eq!(
+            estimator.apply_bounds(FeeRateTarget::Commitment, Some(1500)),
+            2000
+        );
+        assert_eq!(
+            estimator.apply_bounds(FeeRateTarget::Funding, Some(3000)),
+            3000
+        );
+        assert_eq!(
+            estimator.apply_bounds(FeeRateTarget::Commitment, Some(8000)),
+            5000
+        );
+        assert_eq!(
+            estimator.apply_bounds(FeeRateTarget::Funding, Some(8000)),
+            8000
+        );
+    }
+}
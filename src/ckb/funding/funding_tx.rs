@@ -1,20 +1,21 @@
-use super::super::FundingError;
-use crate::{ckb::contracts::get_udt_cell_deps, fiber::serde_utils::EntityHex};
+use super::super::{ChainBackend, FundingError};
+use crate::{
+    ckb::contracts::get_udt_cell_deps, fiber::serde_utils::EntityHex, signer::FundingKeySigner,
+};
 use anyhow::anyhow;
 use ckb_sdk::{
     constants::SIGHASH_TYPE_HASH,
     rpc::ckb_indexer::SearchMode,
     traits::{
-        CellCollector, CellDepResolver, CellQueryOptions, DefaultCellCollector,
-        DefaultCellDepResolver, DefaultHeaderDepResolver, DefaultTransactionDependencyProvider,
+        CellCollector, CellDepResolver, CellQueryOptions, DefaultCellDepResolver,
         HeaderDepResolver, SecpCkbRawKeySigner, TransactionDependencyProvider, ValueRangeOption,
     },
     tx_builder::{unlock_tx, CapacityBalancer, TxBuilder, TxBuilderError},
     unlock::{ScriptUnlocker, SecpSighashUnlocker},
-    CkbRpcClient, ScriptId,
+    ScriptId,
 };
 use ckb_types::{
-    core::{BlockView, Capacity, TransactionView},
+    core::{Capacity, TransactionView},
     packed::{self, Bytes, CellInput, CellOutput, Script, Transaction},
     prelude::*,
 };
@@ -24,7 +25,10 @@ use molecule::{
 };
 use serde::Deserialize;
 use serde_with::serde_as;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use tracing::{debug, warn};
 
 /// Funding transaction wrapper.
@@ -69,13 +73,13 @@ pub struct FundingRequest {
     pub remote_reserved_ckb_amount: u64,
 }
 
-// TODO: trace locked cells
 #[derive(Clone, Debug)]
 pub struct FundingContext {
-    pub secret_key: secp256k1::SecretKey,
-    pub rpc_url: String,
+    pub backend: Arc<dyn ChainBackend>,
     pub funding_source_lock_script: packed::Script,
     pub funding_cell_lock_script: packed::Script,
+    /// Cells reserved by other fundings, which must not be used as inputs.
+    pub reserved_cells: Vec<packed::OutPoint>,
 }
 
 #[allow(dead_code)]
@@ -303,21 +307,29 @@ impl FundingTxBuilder {
             self.request.funding_fee_rate,
         );
 
-        let ckb_client = CkbRpcClient::new(&self.context.rpc_url);
+        let backend = self.context.backend.clone();
         let cell_dep_resolver = {
-            let genesis_block = ckb_client.get_block_by_number(0.into()).unwrap().unwrap();
-            DefaultCellDepResolver::from_genesis(&BlockView::from(genesis_block)).unwrap()
+            let genesis_block = backend.get_genesis_block()?;
+            DefaultCellDepResolver::from_genesis(&genesis_block).unwrap()
         };
 
-        let header_dep_resolver = DefaultHeaderDepResolver::new(&self.context.rpc_url);
-        let mut cell_collector = DefaultCellCollector::new(&self.context.rpc_url);
-        let tx_dep_provider = DefaultTransactionDependencyProvider::new(&self.context.rpc_url, 10);
+        let header_dep_resolver = backend.header_dep_resolver();
+        let mut cell_collector = backend.cell_collector();
+        if !self.context.reserved_cells.is_empty() {
+            let tip_block_number = backend.get_tip_block_number()?;
+            for out_point in self.context.reserved_cells.iter() {
+                cell_collector
+                    .lock_cell(out_point.clone(), tip_block_number)
+                    .map_err(|err| TxBuilderError::Other(err.into()))?;
+            }
+        }
+        let tx_dep_provider = backend.tx_dep_provider();
 
         let (tx, _) = self.build_unlocked(
-            &mut cell_collector,
+            cell_collector.as_mut(),
             &cell_dep_resolver,
-            &header_dep_resolver,
-            &tx_dep_provider,
+            header_dep_resolver.as_ref(),
+            tx_dep_provider.as_ref(),
             &balancer,
             &unlockers,
         )?;
@@ -362,21 +374,9 @@ impl FundingTx {
 
     pub fn sign(
         mut self,
-        secret_key: secp256k1::SecretKey,
-        rpc_url: String,
+        signer: FundingKeySigner,
+        backend: &dyn ChainBackend,
     ) -> Result<Self, FundingError> {
-        // Convert between different versions of secp256k1.
-        // This app requires 0.28 because of:
-        // ```
-        // #[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
-        // pub struct Signature(pub Secp256k1Signature);
-        // ```
-        //
-        // However, ckb-sdk-rust still uses 0.24.
-        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![std::str::FromStr::from_str(
-            hex::encode(secret_key.as_ref()).as_ref(),
-        )
-        .unwrap()]);
         let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
         let sighash_script_id = ScriptId::new_type(SIGHASH_TYPE_HASH.clone());
         let mut unlockers = HashMap::default();
@@ -385,9 +385,9 @@ impl FundingTx {
             Box::new(sighash_unlocker) as Box<dyn ScriptUnlocker>,
         );
         let tx = self.take().ok_or(FundingError::AbsentTx)?;
-        let tx_dep_provider = DefaultTransactionDependencyProvider::new(&rpc_url, 10);
+        let tx_dep_provider = backend.tx_dep_provider();
 
-        let (tx, _) = unlock_tx(tx, &tx_dep_provider, &unlockers)?;
+        let (tx, _) = unlock_tx(tx, tx_dep_provider.as_ref(), &unlockers)?;
         self.update_for_self(tx)?;
         Ok(self)
     }
@@ -1,13 +1,24 @@
 mod actor;
+mod backend;
 mod config;
 mod error;
+mod fee_estimator;
 mod funding;
+mod reservation;
+mod wallet;
+mod watcher;
 
 pub use actor::{CkbChainActor, CkbChainMessage, TraceTxRequest};
+pub use backend::{ChainBackend, FullNodeBackend, LightClientBackend, TransactionStatus};
 pub use config::{CkbConfig, DEFAULT_CKB_BASE_DIR_NAME};
-pub use error::{CkbChainError, FundingError};
+pub use error::{CkbChainError, FundingError, WalletError};
+pub use fee_estimator::FeeRateTarget;
 pub use funding::{FundingRequest, FundingTx};
+pub use wallet::{SendToAddressRequest, UdtBalance, WalletBalance};
+pub use watcher::{
+    ChainWatchEvent, CommittedTransaction, WatchId, WatchMatch, WatchTarget, REORG_WINDOW_BLOCKS,
+};
 
 #[cfg(test)]
-pub use actor::{submit_tx, trace_tx, trace_tx_hash, MockChainActor};
+pub use actor::{submit_tx, trace_tx, trace_tx_hash, MockChainActor, MockChainBackend};
 pub mod contracts;
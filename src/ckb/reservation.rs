@@ -0,0 +1,148 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ckb_types::packed::OutPoint;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use tracing::{error, warn};
+
+use crate::fiber::serde_utils::EntityHex;
+
+/// A reservation is dropped if the cell is still not spent after this time, so that cells
+/// of a funding which silently went away are not locked forever.
+pub const CELL_RESERVATION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CellReservation {
+    #[serde_as(as = "EntityHex")]
+    out_point: OutPoint,
+    // Milliseconds since UNIX epoch.
+    reserved_at: u64,
+}
+
+/// Cells picked as inputs of funding transactions and wallet transactions which are not
+/// committed yet. They are excluded from coin selection, so that concurrent fundings don't
+/// spend the same cells.
+/// The registry is saved to a file, so the reservations survive restarts.
+#[derive(Debug)]
+pub struct CellReservations {
+    path: PathBuf,
+    cells: HashMap<OutPoint, u64>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Duration since unix epoch")
+        .as_millis() as u64
+}
+
+impl CellReservations {
+    pub fn load(path: PathBuf) -> Self {
+        let cells = match std::fs::read(&path) {
+            Ok(content) => match serde_json::from_slice::<Vec<CellReservation>>(&content) {
+                Ok(reservations) => reservations
+                    .into_iter()
+                    .map(|r| (r.out_point, r.reserved_at))
+                    .collect(),
+                Err(err) => {
+                    warn!("Ignore invalid cell reservations file {:?}: {}", &path, err);
+                    HashMap::new()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                warn!("Failed to read cell reservations file {:?}: {}", &path, err);
+                HashMap::new()
+            }
+        };
+        Self { path, cells }
+    }
+
+    /// The cells which should not be picked by coin selection.
+    pub fn reserved_cells(&self) -> Vec<OutPoint> {
+        let now = now_millis();
+        self.cells
+            .iter()
+            .filter(|(_, reserved_at)| !is_expired(**reserved_at, now))
+            .map(|(out_point, _)| out_point.clone())
+            .collect()
+    }
+
+    pub fn reserve(&mut self, out_points: impl IntoIterator<Item = OutPoint>) {
+        let now = now_millis();
+        self.cells
+            .retain(|_, reserved_at| !is_expired(*reserved_at, now));
+        for out_point in out_points {
+            self.cells.insert(out_point, now);
+        }
+        self.save();
+    }
+
+    pub fn release<'a>(&mut self, out_points: impl IntoIterator<Item = &'a OutPoint>) {
+        let len = self.cells.len();
+        for out_point in out_points {
+            self.cells.remove(out_point);
+        }
+        if self.cells.len() != len {
+            self.save();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    fn save(&self) {
+        let reservations = self
+            .cells
+            .iter()
+            .map(|(out_point, reserved_at)| CellReservation {
+                out_point: out_point.clone(),
+                reserved_at: *reserved_at,
+            })
+            .collect::<Vec<_>>();
+        let content = serde_json::to_vec(&reservations).expect("serialize cell reservations");
+        // Write to a temporary file first, so that a crash never leaves a truncated file.
+        let tmp_path = self.path.with_extension("tmp");
+        if let Err(err) =
+            std::fs::write(&tmp_path, content).and_then(|_| std::fs::rename(&tmp_path, &self.path))
+        {
+            error!(
+                "Failed to save cell reservations to {:?}: {}",
+                &self.path, err
+            );
+        }
+    }
+}
+
+fn is_expired(reserved_at: u64, now: u64) -> bool {
+    now.saturating_sub(reserved_at) > CELL_RESERVATION_TIMEOUT.as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use ckb_types::packed::OutPoint;
+
+    use super::CellReservations;
+
+    #[test]
+    fn test_cell_reservations_persist() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("reserved_cells.json");
+        let cell1 = OutPoint::new(Default::default(), 0);
+        let cell2 = OutPoint::new(Default::default(), 1);
+
+        let mut reservations = CellReservations::load(path.clone());
+        assert!(reservations.is_empty());
+        reservations.reserve(vec![cell1.clone(), cell2.clone()]);
+        reservations.release(&[cell1.clone()]);
+
+        let reservations = CellReservations::load(path);
+        assert_eq!(reservations.reserved_cells(), vec![cell2]);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use ckb_sdk::{
+    constants::SIGHASH_TYPE_HASH,
+    rpc::ckb_indexer::SearchMode,
+    traits::{CellQueryOptions, DefaultCellDepResolver, LiveCell},
+    tx_builder::{transfer::CapacityTransferBuilder, CapacityBalancer, TxBuilder},
+    unlock::{ScriptUnlocker, SecpSighashUnlocker},
+    Address, AddressPayload, NetworkType, ScriptId,
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionView},
+    packed::{self, CellOutput, Script},
+    prelude::*,
+};
+
+use crate::signer::FundingKeySigner;
+
+use super::{contracts::get_udt_info, ChainBackend, WalletError};
+
+/// Everything needed to query and spend the cells of the funding key.
+#[derive(Clone, Debug)]
+pub struct WalletContext {
+    pub signer: FundingKeySigner,
+    pub backend: Arc<dyn ChainBackend>,
+    pub lock_script: Script,
+    /// Cells reserved by pending fundings, which must not be spent by the wallet.
+    pub reserved_cells: Vec<packed::OutPoint>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WalletBalance {
+    /// Capacity of the plain cells, i.e. cells without type script and data.
+    pub ckb: u64,
+    /// Balances of the whitelisted UDTs.
+    pub udts: Vec<UdtBalance>,
+}
+
+#[derive(Clone, Debug)]
+pub struct UdtBalance {
+    pub name: String,
+    pub type_script: Script,
+    pub amount: u128,
+    /// Capacity occupied by the UDT cells.
+    pub capacity: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SendToAddressRequest {
+    pub address: String,
+    /// Capacity of the output cell, in shannons.
+    pub amount: u64,
+    pub fee_rate: u64,
+}
+
+impl WalletContext {
+    /// The address of the funding lock script on the chain of the ckb node.
+    pub fn address(&self) -> Result<Address, WalletError> {
+        Ok(Address::new(
+            self.network_type()?,
+            AddressPayload::from(self.lock_script.clone()),
+            true,
+        ))
+    }
+
+    pub fn list_unspent(&self) -> Result<Vec<LiveCell>, WalletError> {
+        let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
+        query.script_search_mode = Some(SearchMode::Exact);
+        // Collect all the cells instead of stopping at the first one.
+        query.min_total_capacity = u64::MAX;
+        Ok(self.backend.collect_cells(&query)?)
+    }
+
+    pub fn get_balance(&self) -> Result<WalletBalance, WalletError> {
+        let mut balance = WalletBalance::default();
+        let mut udts: HashMap<Script, UdtBalance> = HashMap::new();
+        for cell in self.list_unspent()? {
+            let capacity: u64 = cell.output.capacity().unpack();
+            match cell.output.type_().to_opt() {
+                None if cell.output_data.is_empty() => balance.ckb += capacity,
+                Some(type_script) if cell.output_data.len() >= 16 => {
+                    let Some(udt_info) = get_udt_info(&type_script) else {
+                        continue;
+                    };
+                    let mut amount_bytes = [0u8; 16];
+                    amount_bytes.copy_from_slice(&cell.output_data.as_ref()[0..16]);
+                    let udt = udts.entry(type_script.clone()).or_insert(UdtBalance {
+                        name: udt_info.name.clone(),
+                        type_script,
+                        amount: 0,
+                        capacity: 0,
+                    });
+                    udt.amount += u128::from_le_bytes(amount_bytes);
+                    udt.capacity += capacity;
+                }
+                _ => {}
+            }
+        }
+        balance.udts = udts.into_values().collect();
+        balance.udts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(balance)
+    }
+
+    /// Send CKB to the address, the change goes back to the funding lock script.
+    /// Returns the transaction sent to the ckb node.
+    pub fn send_to_address(
+        &self,
+        request: SendToAddressRequest,
+    ) -> Result<TransactionView, WalletError> {
+        let address = Address::from_str(&request.address).map_err(WalletError::InvalidAddress)?;
+        let network_type = self.network_type()?;
+        if address.network() != network_type {
+            return Err(WalletError::InvalidAddress(format!(
+                "expect an address of {}, got {}",
+                network_type.to_str(),
+                address.network().to_str()
+            )));
+        }
+        let output = CellOutput::new_builder()
+            .capacity(request.amount.pack())
+            .lock(Script::from(&address))
+            .build();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::zero())
+            .expect("capacity overflow")
+            .as_u64();
+        if request.amount < occupied_capacity {
+            return Err(WalletError::InvalidAmount(format!(
+                "the output needs at least {} shannons, got {}",
+                occupied_capacity, request.amount
+            )));
+        }
+
+        let sighash_unlocker = SecpSighashUnlocker::from(Box::new(self.signer.clone()) as Box<_>);
+        let sighash_script_id = ScriptId::new_type(SIGHASH_TYPE_HASH.clone());
+        let mut unlockers = HashMap::default();
+        unlockers.insert(
+            sighash_script_id,
+            Box::new(sighash_unlocker) as Box<dyn ScriptUnlocker>,
+        );
+
+        let placeholder_witness = packed::WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let balancer = CapacityBalancer::new_simple(
+            self.lock_script.clone(),
+            placeholder_witness,
+            request.fee_rate,
+        );
+
+        let genesis_block = self.backend.get_genesis_block()?;
+        let cell_dep_resolver = DefaultCellDepResolver::from_genesis(&genesis_block)
+            .map_err(|err| WalletError::InvalidChain(err.to_string()))?;
+        let header_dep_resolver = self.backend.header_dep_resolver();
+        let mut cell_collector = self.backend.cell_collector();
+        if !self.reserved_cells.is_empty() {
+            let tip_block_number = self.backend.get_tip_block_number()?;
+            for out_point in self.reserved_cells.iter() {
+                cell_collector.lock_cell(out_point.clone(), tip_block_number)?;
+            }
+        }
+        let tx_dep_provider = self.backend.tx_dep_provider();
+
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        let (tx, _) = builder.build_unlocked(
+            cell_collector.as_mut(),
+            &cell_dep_resolver,
+            header_dep_resolver.as_ref(),
+            tx_dep_provider.as_ref(),
+            &balancer,
+            &unlockers,
+        )?;
+        self.backend.send_transaction(&tx)?;
+        Ok(tx)
+    }
+
+    fn network_type(&self) -> Result<NetworkType, WalletError> {
+        Ok(self.backend.network_type()?)
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::{HashMap, VecDeque};
+
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    packed,
+    prelude::*,
+};
+use tokio::sync::mpsc;
+
+/// How many recently attached blocks the watcher remembers. A chain reorganization deeper
+/// than this cannot be rolled back block by block, the watcher restarts from the new tip.
+/// Watches on transactions are closed once their blocks are out of this window.
+pub const REORG_WINDOW_BLOCKS: usize = 64;
+
+pub type WatchId = u64;
+
+/// Something on chain a subscriber is interested in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchTarget {
+    /// The transaction with this hash.
+    Transaction(packed::Byte32),
+    /// Any transaction which consumes this outpoint.
+    OutPoint(packed::OutPoint),
+    /// Any transaction which creates a cell locked by this script.
+    LockScript(packed::Script),
+}
+
+impl WatchTarget {
+    pub fn matches(&self, tx: &TransactionView) -> bool {
+        match self {
+            WatchTarget::Transaction(tx_hash) => &tx.hash() == tx_hash,
+            WatchTarget::OutPoint(out_point) => tx.input_pts_iter().any(|x| &x == out_point),
+            WatchTarget::LockScript(script) => {
+                tx.outputs().into_iter().any(|x| &x.lock() == script)
+            }
+        }
+    }
+}
+
+/// A transaction in a block which matches a watch target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchMatch {
+    pub watch_id: WatchId,
+    pub target: WatchTarget,
+    pub tx_hash: packed::Byte32,
+    pub block_number: u64,
+    pub block_hash: packed::Byte32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainWatchEvent {
+    /// The matching transaction has been committed in a block on the main chain.
+    Attached(WatchMatch),
+    /// The block containing the matching transaction has left the main chain because of
+    /// a chain reorganization. An `Attached` event follows if the transaction is committed
+    /// again in the new fork.
+    Detached(WatchMatch),
+}
+
+/// A committed transaction found by a chain backend which can't feed the blocks to the
+/// watcher, see [`ChainWatcher::update_found_transactions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommittedTransaction {
+    pub tx_hash: packed::Byte32,
+    pub block_number: u64,
+    pub block_hash: packed::Byte32,
+}
+
+/// Returned by [`ChainWatcher::attach_block`] when the block does not extend the current tip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainReorganized;
+
+/// A block attached by the watcher, with the transactions in it.
+#[derive(Debug, Clone)]
+pub struct WatchedBlock {
+    pub number: u64,
+    pub hash: packed::Byte32,
+    pub tx_hashes: Vec<packed::Byte32>,
+    matches: Vec<WatchMatch>,
+}
+
+struct Watch {
+    target: WatchTarget,
+    subscriber: mpsc::UnboundedSender<ChainWatchEvent>,
+}
+
+/// Follows the chain tip block by block and notifies the subscribers of matching transactions.
+/// The watcher itself does no IO, blocks are fed by the chain actor.
+#[derive(Default)]
+pub struct ChainWatcher {
+    next_watch_id: WatchId,
+    watches: HashMap<WatchId, Watch>,
+    recent_blocks: VecDeque<WatchedBlock>,
+    // The matches of the transactions found by the backend, see `update_found_transactions`.
+    found_matches: HashMap<WatchId, Vec<WatchMatch>>,
+}
+
+impl std::fmt::Debug for ChainWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChainWatcher")
+            .field("watches", &self.watches.len())
+            .field("tip", &self.tip())
+            .finish()
+    }
+}
+
+impl ChainWatcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number and hash of the last attached block.
+    pub fn tip(&self) -> Option<(u64, packed::Byte32)> {
+        self.recent_blocks
+            .back()
+            .map(|block| (block.number, block.hash.clone()))
+    }
+
+    /// Start watching the target. If the target is a transaction which is already in one of
+    /// the recent blocks, no `Attached` event is sent, but the subscriber is still notified when
+    /// the block is detached.
+    pub fn add_watch(
+        &mut self,
+        target: WatchTarget,
+        subscriber: mpsc::UnboundedSender<ChainWatchEvent>,
+    ) -> WatchId {
+        let watch_id = self.next_watch_id;
+        self.next_watch_id += 1;
+        if let WatchTarget::Transaction(tx_hash) = &target {
+            if let Some(block) = self
+                .recent_blocks
+                .iter_mut()
+                .find(|block| block.tx_hashes.contains(tx_hash))
+            {
+                block.matches.push(WatchMatch {
+                    watch_id,
+                    target: target.clone(),
+                    tx_hash: tx_hash.clone(),
+                    block_number: block.number,
+                    block_hash: block.hash.clone(),
+                });
+            }
+        }
+        self.watches.insert(watch_id, Watch { target, subscriber });
+        watch_id
+    }
+
+    pub fn remove_watch(&mut self, watch_id: WatchId) {
+        self.watches.remove(&watch_id);
+        self.found_matches.remove(&watch_id);
+    }
+
+    pub fn watch_targets(&self) -> Vec<(WatchId, WatchTarget)> {
+        self.watches
+            .iter()
+            .map(|(watch_id, watch)| (*watch_id, watch.target.clone()))
+            .collect()
+    }
+
+    /// Update the transactions matching the watch, which are found by a chain backend without
+    /// full blocks. The transactions not found before are notified as `Attached`, and the ones
+    /// no longer found, e.g. because of a chain reorganization, as `Detached`. A watch on a
+    /// transaction is closed once the transaction is deeper than the reorg window.
+    pub fn update_found_transactions(
+        &mut self,
+        watch_id: WatchId,
+        found: Vec<CommittedTransaction>,
+        tip_number: u64,
+    ) {
+        let Some(watch) = self.watches.get(&watch_id) else {
+            return;
+        };
+        let target = watch.target.clone();
+        let matches: Vec<_> = found
+            .into_iter()
+            .map(|tx| WatchMatch {
+                watch_id,
+                target: target.clone(),
+                tx_hash: tx.tx_hash,
+                block_number: tx.block_number,
+                block_hash: tx.block_hash,
+            })
+            .collect();
+        let previous = self.found_matches.remove(&watch_id).unwrap_or_default();
+        for watch_match in previous.iter().filter(|x| !matches.contains(x)) {
+            self.notify(ChainWatchEvent::Detached(watch_match.clone()));
+        }
+        for watch_match in matches.iter().filter(|x| !previous.contains(x)) {
+            self.notify(ChainWatchEvent::Attached(watch_match.clone()));
+        }
+
+        let finalized = matches!(target, WatchTarget::Transaction(_))
+            && matches
+                .iter()
+                .any(|x| tip_number >= x.block_number + REORG_WINDOW_BLOCKS as u64);
+        if finalized {
+            self.remove_watch(watch_id);
+        } else if self.watches.contains_key(&watch_id) {
+            self.found_matches.insert(watch_id, matches);
+        }
+    }
+
+    /// Attach a block on top of the current tip, and notify the subscribers of the matching
+    /// transactions. Returns `ChainReorganized` if the parent of the block is not the current
+    /// tip, in which case the caller should detach the tip and retry.
+    pub fn attach_block(&mut self, block: &BlockView) -> Result<&WatchedBlock, ChainReorganized> {
+        if let Some((number, hash)) = self.tip() {
+            if block.number() != number + 1 || block.parent_hash() != hash {
+                return Err(ChainReorganized);
+            }
+        }
+
+        let mut matches = vec![];
+        for tx in block.transactions() {
+            for (watch_id, watch) in self.watches.iter() {
+                if watch.target.matches(&tx) {
+                    matches.push(WatchMatch {
+                        watch_id: *watch_id,
+                        target: watch.target.clone(),
+                        tx_hash: tx.hash(),
+                        block_number: block.number(),
+                        block_hash: block.hash(),
+                    });
+                }
+            }
+        }
+        for watch_match in matches.iter() {
+            self.notify(ChainWatchEvent::Attached(watch_match.clone()));
+        }
+
+        if self.recent_blocks.len() >= REORG_WINDOW_BLOCKS {
+            let finalized = self.recent_blocks.pop_front().expect("window is not empty");
+            // A committed transaction deeper than the window can no longer leave the chain,
+            // close the watch so that the subscriber knows it can stop waiting.
+            for watch_match in finalized.matches {
+                if matches!(watch_match.target, WatchTarget::Transaction(_)) {
+                    self.remove_watch(watch_match.watch_id);
+                }
+            }
+        }
+        self.recent_blocks.push_back(WatchedBlock {
+            number: block.number(),
+            hash: block.hash(),
+            tx_hashes: block.tx_hashes().to_vec(),
+            matches,
+        });
+        Ok(self.recent_blocks.back().expect("block just attached"))
+    }
+
+    /// Detach the tip block, and notify the subscribers of the transactions in it.
+    /// Returns `None` if there are no more blocks in the reorg window.
+    pub fn detach_tip(&mut self) -> Option<WatchedBlock> {
+        let block = self.recent_blocks.pop_back()?;
+        for watch_match in block.matches.iter() {
+            self.notify(ChainWatchEvent::Detached(watch_match.clone()));
+        }
+        Some(block)
+    }
+
+    fn notify(&mut self, event: ChainWatchEvent) {
+        let watch_id = match &event {
+            ChainWatchEvent::Attached(x) | ChainWatchEvent::Detached(x) => x.watch_id,
+        };
+        let closed = match self.watches.get(&watch_id) {
+            Some(watch) => watch.subscriber.send(event).is_err(),
+            None => false,
+        };
+        // The subscriber has gone away, there is no need to keep watching.
+        if closed {
+            self.watches.remove(&watch_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ckb_types::{
+        core::{BlockView, TransactionView},
+        packed::{CellInput, CellOutput, OutPoint, Script},
+        prelude::*,
+    };
+    use tokio::sync::mpsc;
+
+    use super::{
+        ChainReorganized, ChainWatchEvent, ChainWatcher, CommittedTransaction, WatchTarget,
+        REORG_WINDOW_BLOCKS,
+    };
+
+    fn build_block(parent: Option<&BlockView>, txs: Vec<TransactionView>) -> BlockView {
+        let (number, parent_hash) = parent
+            .map(|x| (x.number() + 1, x.hash()))
+            .unwrap_or_default();
+        BlockView::new_advanced_builder()
+            .number(number.pack())
+            .parent_hash(parent_hash)
+            .transactions(txs)
+            .build()
+    }
+
+    fn build_tx(lock_args: &[u8], input: Option<OutPoint>) -> TransactionView {
+        let output = CellOutput::new_builder()
+            .lock(Script::new_builder().args(lock_args.pack()).build())
+            .build();
+        let mut builder = TransactionView::new_advanced_builder()
+            .output(output)
+            .output_data(Default::default());
+        if let Some(input) = input {
+            builder = builder.input(CellInput::new(input, 0));
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_watch_tx_outpoint_and_lock_script() {
+        let mut watcher = ChainWatcher::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        let tx1 = build_tx(b"alice", None);
+        let tx2 = build_tx(b"bob", Some(OutPoint::new(tx1.hash(), 0)));
+        let tx_watch = watcher.add_watch(WatchTarget::Transaction(tx1.hash()), sender.clone());
+        let out_point_watch = watcher.add_watch(
+            WatchTarget::OutPoint(OutPoint::new(tx1.hash(), 0)),
+            sender.clone(),
+        );
+        let lock_watch = watcher.add_watch(
+            WatchTarget::LockScript(Script::new_builder().args(b"bob".pack()).build()),
+            sender,
+        );
+
+        let genesis = build_block(None, vec![]);
+        let block1 = build_block(Some(&genesis), vec![tx1.clone()]);
+        let block2 = build_block(Some(&block1), vec![tx2.clone()]);
+        for block in [&genesis, &block1, &block2] {
+            watcher.attach_block(block).expect("attach block");
+        }
+
+        let mut events = vec![];
+        while let Ok(ChainWatchEvent::Attached(watch_match)) = receiver.try_recv() {
+            events.push((watch_match.watch_id, watch_match.tx_hash));
+        }
+        events.sort_by_key(|(watch_id, _)| *watch_id);
+        assert_eq!(
+            events,
+            vec![
+                (tx_watch, tx1.hash()),
+                (out_point_watch, tx2.hash()),
+                (lock_watch, tx2.hash())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watch_chain_reorganization() {
+        let mut watcher = ChainWatcher::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let tx = build_tx(b"alice", None);
+        watcher.add_watch(WatchTarget::Transaction(tx.hash()), sender);
+
+        let genesis = build_block(None, vec![]);
+        let block1 = build_block(Some(&genesis), vec![tx.clone()]);
+        watcher.attach_block(&genesis).expect("attach genesis");
+        watcher.attach_block(&block1).expect("attach block");
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Attached(x)) if x.block_hash == block1.hash()
+        ));
+
+        // A competing block at the same height does not extend the tip.
+        let fork1 = build_block(Some(&genesis), vec![]);
+        assert_eq!(
+            watcher.attach_block(&fork1).map(|_| ()),
+            Err(ChainReorganized)
+        );
+        let detached = watcher.detach_tip().expect("detach tip");
+        assert_eq!(detached.hash, block1.hash());
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Detached(x)) if x.tx_hash == tx.hash()
+        ));
+
+        watcher.attach_block(&fork1).expect("attach fork");
+        assert_eq!(watcher.tip(), Some((1, fork1.hash())));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_watch_committed_transaction() {
+        let mut watcher = ChainWatcher::new();
+        let tx = build_tx(b"alice", None);
+        let genesis = build_block(None, vec![]);
+        let mut tip = build_block(Some(&genesis), vec![tx.clone()]);
+        watcher.attach_block(&genesis).expect("attach genesis");
+        watcher.attach_block(&tip).expect("attach block");
+
+        // The transaction is already committed, only the reorg is notified.
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        watcher.add_watch(WatchTarget::Transaction(tx.hash()), sender);
+        assert!(receiver.try_recv().is_err());
+        watcher.detach_tip().expect("detach tip");
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Detached(x)) if x.tx_hash == tx.hash()
+        ));
+
+        // The watch is closed once the transaction is buried deep enough.
+        watcher.attach_block(&tip).expect("attach block");
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Attached(_))
+        ));
+        for _ in 0..REORG_WINDOW_BLOCKS {
+            tip = build_block(Some(&tip), vec![]);
+            watcher.attach_block(&tip).expect("attach block");
+        }
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_update_found_transactions() {
+        let mut watcher = ChainWatcher::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let tx = build_tx(b"alice", None);
+        let watch_id = watcher.add_watch(WatchTarget::Transaction(tx.hash()), sender);
+        let committed = CommittedTransaction {
+            tx_hash: tx.hash(),
+            block_number: 10,
+            block_hash: build_block(None, vec![tx.clone()]).hash(),
+        };
+
+        watcher.update_found_transactions(watch_id, vec![committed.clone()], 10);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Attached(x)) if x.tx_hash == tx.hash() && x.block_number == 10
+        ));
+        // Found again, nothing new.
+        watcher.update_found_transactions(watch_id, vec![committed.clone()], 11);
+        assert!(receiver.try_recv().is_err());
+
+        // Reorganized out of the chain.
+        watcher.update_found_transactions(watch_id, vec![], 12);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Detached(x)) if x.tx_hash == tx.hash()
+        ));
+
+        // Committed again and buried deep enough, the watch is closed.
+        watcher.update_found_transactions(
+            watch_id,
+            vec![committed],
+            10 + REORG_WINDOW_BLOCKS as u64,
+        );
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ChainWatchEvent::Attached(_))
+        ));
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+}
@@ -204,6 +204,10 @@ impl Config {
         );
 
         let fiber = services.contains(&Service::FIBER).then_some(fiber);
+        if let Some(Err(err)) = fiber.as_ref().map(FiberConfig::validate) {
+            error!("Invalid fiber config: {}", err);
+            print_help_and_exit(1)
+        }
         let ldk = services.contains(&Service::LDK).then_some(ldk);
         let cch = services.contains(&Service::CCH).then_some(cch);
         let rpc = services.contains(&Service::RPC).then_some(rpc);
@@ -12,6 +12,7 @@ use crate::{
 };
 
 use crate::invoice::InvoiceError;
+use crate::store::StoreError;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -37,6 +38,8 @@ pub enum Error {
     FundingError(#[from] FundingError),
     #[error("InvalidParameter: {0}")]
     InvalidParameter(String),
+    #[error("Store error: {0}")]
+    StoreError(#[from] StoreError),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
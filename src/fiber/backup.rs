@@ -0,0 +1,528 @@
+//! Static channel backups.
+//!
+//! The backup records what is needed to get the funds back from the open channels if the store
+//! is lost: the peer of each channel, the funding outpoint and the key id of the channel keys
+//! in the node signer. It is encrypted with a key derived from the node key, so the node
+//! restored from the same mnemonic can read it.
+//!
+//! A backup can't restore the channels, since the latest commitment state is not in it. The
+//! recovering node asks the peers to force close the channels, see
+//! [`crate::fiber::channel::ChannelActorState::force_close`], and sweeps its immediately
+//! spendable outputs of the commitment transactions.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use aes::Aes128;
+use ckb_hash::{blake2b_256, new_blake2b};
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, FeeRate, TransactionBuilder, TransactionView},
+    packed::{CellInput, OutPoint, Script, WitnessArgs},
+    prelude::{AsTransactionBuilder, Builder, Entity, Pack, Unpack},
+};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tentacle::{multiaddr::Multiaddr, secio::PeerId};
+use thiserror::Error;
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+
+use crate::{
+    ckb::contracts::{get_cell_deps, get_script_by_contract, Contract},
+    keystore,
+    signer::{Signer, SignerError},
+};
+
+use super::{
+    channel::derive_payment_pubkey, key::blake2b_hash_with_salt, serde_utils::EntityHex,
+    types::Hash256, KeyPair,
+};
+
+pub const CHANNEL_BACKUP_FILE_NAME: &str = "channel_backup";
+
+const BACKUP_VERSION: u8 = 1;
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+// The commitment transaction signed by us may be one commitment behind or ahead of the version
+// in its witness, search the payment keys of the nearby commitment numbers.
+const SWEEP_COMMITMENT_NUMBER_WINDOW: u64 = 2;
+// The length of the empty witness args before the version in the witness of a funding cell.
+const FUNDING_CELL_WITNESS_VERSION_OFFSET: usize = 16;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+#[derive(Error, Debug)]
+pub enum ChannelBackupError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unsupported channel backup version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid channel backup: {0}")]
+    InvalidData(String),
+    #[error("The channel backup is not encrypted by the key of this node")]
+    WrongKey,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelBackup {
+    pub channel_id: Hash256,
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    // Only the address we have dialed, the address of an inbound connection can't be dialed.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub peer_address: Option<Multiaddr>,
+    #[serde_as(as = "EntityHex")]
+    pub funding_out_point: OutPoint,
+    #[serde_as(as = "Option<EntityHex>")]
+    pub funding_udt_type_script: Option<Script>,
+    pub key_id: Hash256,
+}
+
+/// The key which encrypts the channel backup of the node.
+pub fn channel_backup_key(node_key: &KeyPair) -> Zeroizing<[u8; 32]> {
+    Zeroizing::new(blake2b_hash_with_salt(
+        node_key.as_ref(),
+        b"FIBER_CHANNEL_BACKUP_KEY",
+    ))
+}
+
+// The first half of the key encrypts the backup, the second half authenticates it.
+fn compute_mac(key: &[u8; 32], iv: &[u8], ciphertext: &[u8]) -> [u8; MAC_LEN] {
+    blake2b_256([&key[16..32], iv, ciphertext].concat())
+}
+
+pub fn encrypt_channel_backup(key: &[u8; 32], channels: &[ChannelBackup]) -> Vec<u8> {
+    let iv: [u8; IV_LEN] = thread_rng().gen();
+    let mut ciphertext = serde_json::to_vec(channels).expect("serialize channel backup");
+    Aes128Ctr::new(key[0..16].into(), iv.as_slice().into()).apply_keystream(&mut ciphertext);
+    let mac = compute_mac(key, &iv, &ciphertext);
+    [
+        &[BACKUP_VERSION],
+        iv.as_slice(),
+        &ciphertext,
+        mac.as_slice(),
+    ]
+    .concat()
+}
+
+pub fn decrypt_channel_backup(
+    key: &[u8; 32],
+    data: &[u8],
+) -> Result<Vec<ChannelBackup>, ChannelBackupError> {
+    let (&version, data) = data
+        .split_first()
+        .ok_or_else(|| ChannelBackupError::InvalidData("empty file".to_string()))?;
+    if version != BACKUP_VERSION {
+        return Err(ChannelBackupError::UnsupportedVersion(version));
+    }
+    if data.len() < IV_LEN + MAC_LEN {
+        return Err(ChannelBackupError::InvalidData("too short".to_string()));
+    }
+    let (iv, data) = data.split_at(IV_LEN);
+    let (ciphertext, mac) = data.split_at(data.len() - MAC_LEN);
+    if compute_mac(key, iv, ciphertext).as_slice() != mac {
+        return Err(ChannelBackupError::WrongKey);
+    }
+    let mut plaintext = ciphertext.to_vec();
+    Aes128Ctr::new(key[0..16].into(), iv.into()).apply_keystream(&mut plaintext);
+    serde_json::from_slice(&plaintext)
+        .map_err(|err| ChannelBackupError::InvalidData(err.to_string()))
+}
+
+pub fn read_channel_backup(
+    path: &Path,
+    key: &[u8; 32],
+) -> Result<Vec<ChannelBackup>, ChannelBackupError> {
+    decrypt_channel_backup(key, &fs::read(path)?)
+}
+
+/// The static channel backup file of the node, rewritten whenever a channel is opened or closed.
+pub struct ChannelBackupFile {
+    path: PathBuf,
+    key: Zeroizing<[u8; 32]>,
+    // The channels in the file. The peer addresses are kept while the peers are not connected.
+    channels: Vec<ChannelBackup>,
+}
+
+impl ChannelBackupFile {
+    pub fn open(path: PathBuf, key: Zeroizing<[u8; 32]>) -> Self {
+        let channels = match read_channel_backup(&path, &key) {
+            Ok(channels) => channels,
+            Err(ChannelBackupError::Io(err)) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => {
+                warn!(
+                    "Failed to read the channel backup {:?}, it will be overwritten: {}",
+                    &path, err
+                );
+                vec![]
+            }
+        };
+        Self {
+            path,
+            key,
+            channels,
+        }
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Replace the channels in the backup, the file is only written if they have changed.
+    pub fn write(&mut self, mut channels: Vec<ChannelBackup>) -> io::Result<()> {
+        for channel in channels.iter_mut() {
+            if channel.peer_address.is_none() {
+                channel.peer_address = self
+                    .channels
+                    .iter()
+                    .find(|old| old.peer_id == channel.peer_id && old.peer_address.is_some())
+                    .and_then(|old| old.peer_address.clone());
+            }
+        }
+        if channels == self.channels && self.path.exists() {
+            return Ok(());
+        }
+        debug!(
+            "Writing {} channels to the channel backup {:?}",
+            channels.len(),
+            &self.path
+        );
+        keystore::write_private_file(&self.path, &encrypt_channel_backup(&self.key, &channels))?;
+        self.channels = channels;
+        Ok(())
+    }
+}
+
+fn get_sighash_all_message(tx: &TransactionView, witness: &WitnessArgs) -> [u8; 32] {
+    let witness = witness.as_bytes();
+    let mut hasher = new_blake2b();
+    hasher.update(tx.hash().as_slice());
+    hasher.update(&(witness.len() as u64).to_le_bytes());
+    hasher.update(&witness);
+    let mut message = [0u8; 32];
+    hasher.finalize(&mut message);
+    message
+}
+
+/// The sweep transactions send the outputs to the secp256k1 lock of the node funding key.
+fn get_sweep_lock(signer: &dyn Signer) -> Result<Script, SignerError> {
+    let funding_pubkey = signer.funding_pubkey()?;
+    Ok(get_script_by_contract(
+        Contract::Secp256k1Lock,
+        &blake2b_256(funding_pubkey.serialize())[0..20],
+    ))
+}
+
+fn get_placeholder_witness() -> WitnessArgs {
+    WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build()
+}
+
+/// Find the output of the commitment transaction locked by our payment keys, returns the index
+/// of the output and the commitment number of the payment key.
+fn find_payment_output(
+    signer: &dyn Signer,
+    channel: &ChannelBackup,
+    commitment_tx: &TransactionView,
+) -> Result<Option<(usize, u64)>, SignerError> {
+    let version = match commitment_tx.witnesses().get(0).and_then(|witness| {
+        let witness = witness.raw_data();
+        witness
+            .get(FUNDING_CELL_WITNESS_VERSION_OFFSET..FUNDING_CELL_WITNESS_VERSION_OFFSET + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("8 bytes version")))
+    }) {
+        Some(version) => version,
+        None => return Ok(None),
+    };
+    let payment_base_key = signer
+        .channel_base_public_keys(channel.key_id)?
+        .payment_base_key;
+
+    for commitment_number in version.saturating_sub(SWEEP_COMMITMENT_NUMBER_WINDOW)
+        ..=version.saturating_add(SWEEP_COMMITMENT_NUMBER_WINDOW)
+    {
+        let commitment_point = signer.commitment_point(channel.key_id, commitment_number)?;
+        let payment_key = derive_payment_pubkey(&payment_base_key, &commitment_point);
+        let lock = get_script_by_contract(
+            Contract::Secp256k1Lock,
+            &blake2b_256(payment_key.serialize())[0..20],
+        );
+        if let Some(index) = commitment_tx
+            .outputs()
+            .into_iter()
+            .position(|output| output.lock() == lock)
+        {
+            return Ok(Some((index, commitment_number)));
+        }
+    }
+    Ok(None)
+}
+
+/// Check that `sweep_tx` only moves our immediately spendable output of the commitment
+/// transaction `commitment_tx`, which must spend the funding cell of the channel, to the funding
+/// lock of the node. Returns the commitment number of the payment key and the message to sign.
+///
+/// The signer checks the transaction before signing it with a payment key, so that the payment
+/// keys can't be used to sign anything else.
+pub fn check_sweep_transaction(
+    signer: &dyn Signer,
+    channel: &ChannelBackup,
+    commitment_tx: &TransactionView,
+    sweep_tx: &TransactionView,
+) -> Result<(u64, [u8; 32]), SignerError> {
+    let refuse = |reason: &str| Err(SignerError::Refused(reason.to_string()));
+    if !commitment_tx
+        .input_pts_iter()
+        .any(|out_point| out_point == channel.funding_out_point)
+    {
+        return refuse("the commitment transaction doesn't spend the funding cell");
+    }
+    let (index, commitment_number) = match find_payment_output(signer, channel, commitment_tx)? {
+        Some(found) => found,
+        None => return refuse("no output of the commitment transaction is ours"),
+    };
+    let (output, data) = commitment_tx
+        .output_with_data(index)
+        .expect("output exists");
+    if sweep_tx.inputs().len() != 1
+        || sweep_tx
+            .inputs()
+            .get(0)
+            .expect("one input")
+            .previous_output()
+            != OutPoint::new(commitment_tx.hash(), index as u32)
+    {
+        return refuse("the sweep transaction must only spend our output");
+    }
+    if sweep_tx.outputs().len() != 1 || sweep_tx.outputs_data().len() != 1 {
+        return refuse("the sweep transaction must have exactly one output");
+    }
+    let (sweep_output, sweep_data) = sweep_tx.output_with_data(0).expect("one output");
+    if sweep_output.lock() != get_sweep_lock(signer)? {
+        return refuse("the sweep transaction must pay to the funding lock");
+    }
+    if sweep_output.type_() != output.type_() || sweep_data != data {
+        return refuse("the sweep transaction must keep the type and data of the output");
+    }
+    let capacity: u64 = output.capacity().unpack();
+    let sweep_capacity: u64 = sweep_output.capacity().unpack();
+    if sweep_capacity > capacity {
+        return refuse("the sweep transaction creates capacity");
+    }
+    Ok((
+        commitment_number,
+        get_sighash_all_message(sweep_tx, &get_placeholder_witness()),
+    ))
+}
+
+/// Build a transaction which sends our immediately spendable output of the commitment
+/// transaction `commitment_tx` to the funding lock of the node. Returns `None` if no output of
+/// the transaction is locked by our payment keys, e.g. the channel is closed cooperatively, or
+/// the output can't pay the fee.
+pub fn build_sweep_transaction(
+    signer: &dyn Signer,
+    channel: &ChannelBackup,
+    commitment_tx: &TransactionView,
+    fee_rate: u64,
+) -> Result<Option<TransactionView>, SignerError> {
+    let index = match find_payment_output(signer, channel, commitment_tx)? {
+        Some((index, _)) => index,
+        None => return Ok(None),
+    };
+
+    let (output, data) = commitment_tx
+        .output_with_data(index)
+        .expect("output exists");
+    let placeholder_witness = get_placeholder_witness();
+    let output = output.as_builder().lock(get_sweep_lock(signer)?).build();
+    let tx = TransactionBuilder::default()
+        .cell_deps(get_cell_deps(
+            vec![Contract::Secp256k1Lock],
+            &output.type_().to_opt(),
+        ))
+        .input(
+            CellInput::new_builder()
+                .previous_output(OutPoint::new(commitment_tx.hash(), index as u32))
+                .build(),
+        )
+        .output(output.clone())
+        .output_data(data.pack())
+        .witness(placeholder_witness.as_bytes().pack())
+        .build();
+
+    let fee = FeeRate::from_u64(fee_rate)
+        .fee(tx.data().serialized_size_in_block() as u64)
+        .as_u64();
+    let capacity: u64 = output.capacity().unpack();
+    let occupied_capacity = output
+        .occupied_capacity(Capacity::bytes(data.len()).expect("capacity"))
+        .expect("capacity")
+        .as_u64();
+    if capacity < occupied_capacity + fee {
+        warn!(
+            "Output {} of commitment transaction {:?} can't pay the fee {} of the sweep transaction",
+            index,
+            commitment_tx.hash(),
+            fee
+        );
+        return Ok(None);
+    }
+    let tx = tx
+        .as_advanced_builder()
+        .set_outputs(vec![output
+            .as_builder()
+            .capacity((capacity - fee).pack())
+            .build()])
+        .build();
+
+    let signature = signer.sign_sweep_transaction(channel, commitment_tx, &tx)?;
+    let witness = placeholder_witness
+        .as_builder()
+        .lock(Some(Bytes::from(signature.to_vec())).pack())
+        .build();
+    Ok(Some(
+        tx.as_advanced_builder()
+            .set_witnesses(vec![witness.as_bytes().pack()])
+            .build(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use ckb_hash::blake2b_256;
+    use ckb_types::{
+        bytes::Bytes,
+        core::TransactionBuilder,
+        packed::{CellInput, CellOutput, OutPoint, WitnessArgs},
+        prelude::{AsTransactionBuilder, Builder, Entity, Pack, Unpack},
+    };
+    use secp256k1::{
+        ecdsa::{RecoverableSignature, RecoveryId},
+        Message, Secp256k1,
+    };
+    use tentacle::secio::PeerId;
+
+    use crate::{
+        ckb::contracts::{get_script_by_contract, Contract},
+        fiber::{channel::derive_payment_pubkey, types::Privkey},
+        signer::{LocalSigner, Signer, SignerError},
+    };
+
+    use super::{
+        build_sweep_transaction, decrypt_channel_backup, encrypt_channel_backup,
+        get_sighash_all_message, ChannelBackup, ChannelBackupError,
+    };
+
+    fn channel_backup() -> ChannelBackup {
+        ChannelBackup {
+            channel_id: [1u8; 32].into(),
+            peer_id: PeerId::random(),
+            peer_address: Some("/ip4/127.0.0.1/tcp/8228".parse().expect("valid address")),
+            funding_out_point: OutPoint::new([2u8; 32].pack(), 0),
+            funding_udt_type_script: None,
+            key_id: [3u8; 32].into(),
+        }
+    }
+
+    #[test]
+    fn test_channel_backup_roundtrip() {
+        let channels = vec![channel_backup()];
+        let data = encrypt_channel_backup(&[4u8; 32], &channels);
+        assert_eq!(decrypt_channel_backup(&[4u8; 32], &data).unwrap(), channels);
+        assert!(matches!(
+            decrypt_channel_backup(&[5u8; 32], &data),
+            Err(ChannelBackupError::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn test_sweep_our_output_of_commitment_transaction() {
+        let signer = LocalSigner::new(Privkey::from(&[6u8; 32]).into(), [7u8; 32]);
+        let channel = channel_backup();
+        let payment_base_key = signer
+            .channel_base_public_keys(channel.key_id)
+            .unwrap()
+            .payment_base_key;
+        let commitment_point = signer.commitment_point(channel.key_id, 5).unwrap();
+        let payment_key = derive_payment_pubkey(&payment_base_key, &commitment_point);
+        let capacity = 1000u64 * 100_000_000;
+        let empty_witness_args = [16, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0];
+        let commitment_tx = TransactionBuilder::default()
+            .input(
+                CellInput::new_builder()
+                    .previous_output(channel.funding_out_point.clone())
+                    .build(),
+            )
+            .output(
+                CellOutput::new_builder()
+                    .capacity(capacity.pack())
+                    .lock(get_script_by_contract(
+                        Contract::Secp256k1Lock,
+                        &blake2b_256(payment_key.serialize())[0..20],
+                    ))
+                    .build(),
+            )
+            .output_data(Bytes::new().pack())
+            .witness(
+                [empty_witness_args.as_slice(), &5u64.to_le_bytes()]
+                    .concat()
+                    .pack(),
+            )
+            .build();
+
+        let tx = build_sweep_transaction(&signer, &channel, &commitment_tx, 1000)
+            .unwrap()
+            .expect("sweep transaction");
+        assert_eq!(
+            tx.inputs().get(0).unwrap().previous_output(),
+            OutPoint::new(commitment_tx.hash(), 0)
+        );
+        let output = tx.outputs().get(0).unwrap();
+        let funding_pubkey = signer.funding_pubkey().unwrap();
+        assert_eq!(
+            output.lock(),
+            get_script_by_contract(
+                Contract::Secp256k1Lock,
+                &blake2b_256(funding_pubkey.serialize())[0..20],
+            )
+        );
+        assert!(Unpack::<u64>::unpack(&output.capacity()) < capacity);
+
+        // The signer refuses to send the output anywhere else.
+        let stolen_tx = tx
+            .as_advanced_builder()
+            .set_outputs(vec![output
+                .as_builder()
+                .lock(get_script_by_contract(Contract::Secp256k1Lock, &[8u8; 20]))
+                .build()])
+            .build();
+        assert!(matches!(
+            signer.sign_sweep_transaction(&channel, &commitment_tx, &stolen_tx),
+            Err(SignerError::Refused(_))
+        ));
+
+        let witness = WitnessArgs::from_slice(&tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+        let signature = witness.lock().to_opt().unwrap().raw_data();
+        let placeholder_witness = witness
+            .as_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let message = get_sighash_all_message(&tx, &placeholder_witness);
+        let signature = RecoverableSignature::from_compact(
+            &signature[0..64],
+            RecoveryId::from_i32(signature[64] as i32).unwrap(),
+        )
+        .unwrap();
+        let pubkey = Secp256k1::new()
+            .recover_ecdsa(&Message::from_digest(message), &signature)
+            .unwrap();
+        assert_eq!(pubkey, payment_key.0);
+    }
+}
@@ -28,11 +28,10 @@ use thiserror::Error;
 use tokio::sync::oneshot;
 
 use std::{
-    borrow::Borrow,
     collections::BTreeMap,
     fmt::Debug,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
@@ -46,6 +45,8 @@ use crate::{
         network::emit_service_event,
         types::Shutdown,
     },
+    signer::{Signer, SignerError},
+    store::StoreError,
     NetworkServiceEvent,
 };
 
@@ -54,12 +55,13 @@ use super::{
     fee::{calculate_shutdown_tx_fee, default_minimal_ckb_amount},
     hash_algorithm::HashAlgorithm,
     key::blake2b_hash_with_salt,
-    network::FiberMessageWithPeerId,
+    network::{FiberMessageWithPeerId, PeerNotification},
     serde_utils::EntityHex,
     types::{
         AcceptChannel, AddTlc, ChannelReady, ClosingSigned, CommitmentSigned, FiberMessage,
         Hash256, LockTime, OpenChannel, Privkey, Pubkey, ReestablishChannel, RemoveTlc,
         RemoveTlcFulfill, RemoveTlcReason, RevokeAndAck, TxCollaborationMsg, TxComplete, TxUpdate,
+        UpdateFee,
     },
     NetworkActorCommand, NetworkActorEvent, NetworkActorMessage,
 };
@@ -76,6 +78,7 @@ pub const FUNDING_CELL_WITNESS_LEN: usize = 16 + 8 + 36 + 32 + 64;
 pub const INITIAL_COMMITMENT_NUMBER: u64 = 0;
 
 const ASSUME_NETWORK_ACTOR_ALIVE: &str = "network actor must be alive";
+const ASSUME_SIGNER_ATTACHED: &str = "signer must be attached to the channel";
 
 pub enum ChannelActorMessage {
     /// Command are the messages that are sent to the channel actor to perform some action.
@@ -83,8 +86,27 @@ pub enum ChannelActorMessage {
     Command(ChannelCommand),
     /// Some system events associated to a channel, such as the funding transaction confirmed.
     Event(ChannelEvent),
-    /// PeerMessage are the messages sent from the peer.
-    PeerMessage(FiberMessage),
+    /// PeerMessage are the messages sent from the peer, along with the id of the sender.
+    PeerMessage(PeerId, FiberMessage),
+}
+
+// Build the messages with these functions, which count the messages in the mailboxes of the
+// channel actors, see `crate::metrics::actor_message_sent`.
+impl ChannelActorMessage {
+    pub fn new_command(command: ChannelCommand) -> Self {
+        crate::metrics::actor_message_sent("channel");
+        Self::Command(command)
+    }
+
+    pub fn new_event(event: ChannelEvent) -> Self {
+        crate::metrics::actor_message_sent("channel");
+        Self::Event(event)
+    }
+
+    pub fn new_peer_message(peer_id: PeerId, message: FiberMessage) -> Self {
+        crate::metrics::actor_message_sent("channel");
+        Self::PeerMessage(peer_id, message)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -102,12 +124,13 @@ pub struct TlcNotification {
 #[derive(Debug)]
 pub enum ChannelCommand {
     TxCollaborationCommand(TxCollaborationCommand),
-    // TODO: maybe we should automatically send commitment_signed message after receiving
-    // tx_complete event.
+    // Sign all the pending updates right away. Updates are normally signed automatically
+    // in batches (see `CommitmentBatchConfig`), so this command is optional.
     CommitmentSigned(),
     AddTlc(AddTlcCommand, RpcReplyPort<Result<AddTlcResponse, String>>),
     RemoveTlc(RemoveTlcCommand, RpcReplyPort<Result<(), String>>),
     Shutdown(ShutdownCommand, RpcReplyPort<Result<(), String>>),
+    UpdateCommitmentFee(UpdateCommitmentFeeCommand, RpcReplyPort<Result<(), String>>),
 }
 
 #[derive(Debug)]
@@ -138,6 +161,11 @@ pub struct ShutdownCommand {
     pub force: bool,
 }
 
+#[derive(Debug)]
+pub struct UpdateCommitmentFeeCommand {
+    pub fee_rate: u64,
+}
+
 fn get_random_preimage() -> Hash256 {
     let mut preimage = [0u8; 32];
     preimage.copy_from_slice(&rand::random::<[u8; 32]>());
@@ -172,6 +200,7 @@ pub struct OpenChannelParameter {
     pub funding_fee_rate: Option<u64>,
     pub max_tlc_value_in_flight: Option<u128>,
     pub max_num_of_accept_tlcs: Option<u64>,
+    pub tx_confirmations: Option<u64>,
 }
 
 pub struct AcceptChannelParameter {
@@ -180,6 +209,7 @@ pub struct AcceptChannelParameter {
     pub seed: [u8; 32],
     pub open_channel: OpenChannel,
     pub channel_id_sender: Option<oneshot::Sender<Hash256>>,
+    pub tx_confirmations: Option<u64>,
 }
 
 pub enum ChannelInitializationParameter {
@@ -196,10 +226,31 @@ pub enum ChannelInitializationParameter {
     ReestablishChannel(Hash256),
 }
 
+/// A tlc is added to a channel, by us or by the peer.
+#[derive(Clone, Debug)]
+pub struct AddedTlcNotification {
+    pub channel_id: Hash256,
+    pub tlc: TLC,
+    pub udt_type_script: Option<Script>,
+}
+
+/// The state of a channel is changed.
+#[derive(Clone, Debug)]
+pub struct ChannelStateNotification {
+    pub channel_id: Hash256,
+    pub peer_id: PeerId,
+    pub state: ChannelState,
+}
+
 #[derive(Clone)]
 pub struct ChannelSubscribers {
     pub pending_received_tlcs_subscribers: Arc<OutputPort<TlcNotification>>,
     pub settled_tlcs_subscribers: Arc<OutputPort<TlcNotification>>,
+    pub channel_state_subscribers: Arc<OutputPort<ChannelStateNotification>>,
+    pub added_tlcs_subscribers: Arc<OutputPort<AddedTlcNotification>>,
+    pub removed_tlcs_subscribers: Arc<OutputPort<TlcRecord>>,
+    // Not sent by the channel actors, but by the consumer of the `NetworkServiceEvent`s.
+    pub peer_subscribers: Arc<OutputPort<PeerNotification>>,
 }
 
 impl Default for ChannelSubscribers {
@@ -207,15 +258,33 @@ impl Default for ChannelSubscribers {
         Self {
             pending_received_tlcs_subscribers: Arc::new(OutputPort::default()),
             settled_tlcs_subscribers: Arc::new(OutputPort::default()),
+            channel_state_subscribers: Arc::new(OutputPort::default()),
+            added_tlcs_subscribers: Arc::new(OutputPort::default()),
+            removed_tlcs_subscribers: Arc::new(OutputPort::default()),
+            peer_subscribers: Arc::new(OutputPort::default()),
         }
     }
 }
 
+// The batch timer stops retrying to sign the pending updates after this many failures.
+const MAX_COMMITMENT_BATCH_RETRIES: usize = 10;
+
+/// TLC and fee updates are not signed one by one. They are batched and a commitment_signed
+/// message is sent once `max_updates` updates are pending, or `interval` after the first
+/// pending update, whichever comes first.
+#[derive(Clone, Copy, Debug)]
+pub struct CommitmentBatchConfig {
+    pub interval: Duration,
+    pub max_updates: usize,
+}
+
 pub struct ChannelActor<S> {
     peer_id: PeerId,
     network: ActorRef<NetworkActorMessage>,
     store: S,
     subscribers: ChannelSubscribers,
+    commitment_batch_config: CommitmentBatchConfig,
+    signer: Arc<dyn Signer>,
 }
 
 impl<S: ChannelActorStateStore> ChannelActor<S> {
@@ -224,12 +293,16 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         network: ActorRef<NetworkActorMessage>,
         store: S,
         subscribers: ChannelSubscribers,
+        commitment_batch_config: CommitmentBatchConfig,
+        signer: Arc<dyn Signer>,
     ) -> Self {
         Self {
             peer_id,
             network,
             store,
             subscribers,
+            commitment_batch_config,
+            signer,
         }
     }
 
@@ -384,6 +457,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
 
                 let tlc = state.create_inbounding_tlc(add_tlc)?;
                 state.insert_tlc(tlc)?;
+                self.notify_added_tlc(state, tlc);
                 if let Some(ref udt_type_script) = state.funding_udt_type_script {
                     self.subscribers
                         .pending_received_tlcs_subscribers
@@ -505,10 +579,40 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                 state.handle_reestablish_channel_message(reestablish_channel, &self.network)?;
                 Ok(())
             }
-            FiberMessage::TxAbort(_) | FiberMessage::TxInitRBF(_) | FiberMessage::TxAckRBF(_) => {
+            FiberMessage::UpdateFee(update_fee) => {
+                state.handle_update_fee_message(update_fee)?;
+                Ok(())
+            }
+            FiberMessage::TxAbort(tx_abort) => {
+                if !state.is_funding_unsigned() {
+                    warn!(
+                        "Ignoring TxAbort of channel {:?} in state {:?}",
+                        state.get_id(),
+                        &state.state
+                    );
+                    return Ok(());
+                }
+                warn!(
+                    "Funding of channel {:?} is aborted by the peer: {}",
+                    state.get_id(),
+                    String::from_utf8_lossy(&tx_abort.message)
+                );
+                state.abort_funding(&self.network);
+                Ok(())
+            }
+            FiberMessage::TxInitRBF(_) | FiberMessage::TxAckRBF(_) => {
                 warn!("Received unsupported message: {:?}", &message);
                 Ok(())
             }
+            FiberMessage::Ping(_) | FiberMessage::Pong(_) => {
+                // Liveness messages are handled by the network actor and should never
+                // be routed to a channel actor.
+                warn!(
+                    "Received peer-level message in channel actor: {:?}",
+                    &message
+                );
+                Ok(())
+            }
         }
     }
 
@@ -581,20 +685,22 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
             msg: _,
             version,
         } = state.build_and_sign_commitment_tx()?;
+        state.record_pending_fee_rate_commitment(true);
         debug!(
             "Built and signed commitment tx #{}: transaction: ({:?}), partial signature: {:?}",
             version, &tx, &signature
         );
 
+        let next_local_nonce = state.get_next_local_nonce()?;
         debug!(
             "Sending next local nonce {:?} (previous nonce {:?})",
-            state.get_next_local_nonce(),
-            state.get_local_nonce().borrow()
+            &next_local_nonce,
+            state.get_local_nonce()?
         );
         let commitment_signed = CommitmentSigned {
             channel_id: state.get_id(),
             partial_signature: signature,
-            next_local_nonce: state.get_next_local_nonce(),
+            next_local_nonce,
         };
         debug!(
             "Sending built commitment_signed message: {:?}",
@@ -608,6 +714,8 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                 }),
             ))
             .expect(ASSUME_NETWORK_ACTOR_ALIVE);
+        state.pending_commitment_updates = 0;
+        state.commitment_batch_retries = 0;
         self.network
             .send_message(NetworkActorMessage::new_event(
                 NetworkActorEvent::NetworkServiceEvent(NetworkServiceEvent::LocalCommitmentSigned(
@@ -634,8 +742,52 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         Ok(())
     }
 
+    // Record a new update which needs to be signed. A timer is started for the first update,
+    // and the pending updates are signed immediately if the batch is full. If the signing
+    // fails, the timer retries it up to `MAX_COMMITMENT_BATCH_RETRIES` times, and is started
+    // again by the next update.
+    fn add_pending_commitment_update(
+        &self,
+        myself: &ActorRef<ChannelActorMessage>,
+        state: &mut ChannelActorState,
+    ) -> ProcessingChannelResult {
+        state.pending_commitment_updates += 1;
+        if state.pending_commitment_updates == 1
+            || state.commitment_batch_retries >= MAX_COMMITMENT_BATCH_RETRIES
+        {
+            state.commitment_batch_retries = 0;
+            self.start_commitment_batch_timer(myself);
+        }
+        if state.pending_commitment_updates >= self.commitment_batch_config.max_updates {
+            debug!(
+                "{} updates pending, signing commitment transaction now",
+                state.pending_commitment_updates
+            );
+            return self.handle_commitment_signed_command(state);
+        }
+        Ok(())
+    }
+
+    fn start_commitment_batch_timer(&self, myself: &ActorRef<ChannelActorMessage>) {
+        let myself = myself.clone();
+        let interval = self.commitment_batch_config.interval;
+        tokio::spawn(async move {
+            tokio::time::sleep(interval).await;
+            // The actor may have stopped before the timer fires.
+            if myself
+                .send_message(ChannelActorMessage::new_event(
+                    ChannelEvent::CommitmentBatchTimeout,
+                ))
+                .is_err()
+            {
+                crate::metrics::actor_message_dropped("channel");
+            }
+        });
+    }
+
     pub fn handle_add_tlc_command(
         &self,
+        myself: &ActorRef<ChannelActorMessage>,
         state: &mut ChannelActorState,
         command: AddTlcCommand,
     ) -> Result<u64, ProcessingChannelError> {
@@ -643,6 +795,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         state.check_for_tlc_update(Some(command.amount))?;
         let tlc = state.create_outbounding_tlc(command);
         state.insert_tlc(tlc)?;
+        self.notify_added_tlc(state, tlc);
 
         debug!("Inserted tlc into channel state: {:?}", &tlc);
         // TODO: Note that since message sending is async,
@@ -670,12 +823,48 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
             ))
             .expect(ASSUME_NETWORK_ACTOR_ALIVE);
 
-        self.handle_commitment_signed_command(state)?;
+        self.add_pending_commitment_update(myself, state)?;
         Ok(tlc.id.into())
     }
 
+    fn notify_added_tlc(&self, state: &ChannelActorState, tlc: TLC) {
+        self.subscribers
+            .added_tlcs_subscribers
+            .send(AddedTlcNotification {
+                channel_id: state.get_id(),
+                tlc,
+                udt_type_script: state.funding_udt_type_script.clone(),
+            });
+    }
+
+    fn publish_state_notifications(&self, state: &mut ChannelActorState) {
+        for notification in std::mem::take(&mut state.state_notifications) {
+            self.subscribers
+                .channel_state_subscribers
+                .send(notification);
+        }
+    }
+
+    // Save the records of the tlcs whose removals are committed, they are published once the
+    // state is saved. Saving a record again overwrites the previous one.
+    fn save_committed_tlc_records(&self, state: &mut ChannelActorState) -> Vec<TlcRecord> {
+        let records = std::mem::take(&mut state.committed_tlc_records);
+        for record in &records {
+            if let Err(err) = self.store.insert_tlc_record(record.clone()) {
+                error!(
+                    "Failed to save the removed tlc {:?} of channel {:?}: {}",
+                    record.tlc_id,
+                    state.get_id(),
+                    err
+                );
+            }
+        }
+        records
+    }
+
     pub fn handle_remove_tlc_command(
         &self,
+        myself: &ActorRef<ChannelActorMessage>,
         state: &mut ChannelActorState,
         command: RemoveTlcCommand,
     ) -> ProcessingChannelResult {
@@ -703,7 +892,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
             state.to_remote_amount
         );
         state.maybe_transition_to_shutdown(&self.network)?;
-        self.handle_commitment_signed_command(state)?;
+        self.add_pending_commitment_update(myself, state)?;
         Ok(())
     }
 
@@ -741,24 +930,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         state.check_shutdown_fee_rate(command.fee_rate, &command.close_script)?;
 
         if command.force {
-            if let Some(transaction) = &state.latest_commitment_transaction {
-                self.network
-                    .send_message(NetworkActorMessage::new_event(
-                        NetworkActorEvent::CommitmentTransactionPending(
-                            transaction.clone(),
-                            state.get_id(),
-                        ),
-                    ))
-                    .expect(ASSUME_NETWORK_ACTOR_ALIVE);
-
-                state.update_state(ChannelState::ShuttingDown(
-                    ShuttingDownFlags::WAITING_COMMITMENT_CONFIRMATION,
-                ));
-            } else {
-                return Err(ProcessingChannelError::InvalidState(
-                    "Force shutdown without a valid commitment transaction".to_string(),
-                ));
-            }
+            state.force_close(&self.network)?;
         } else {
             self.network
                 .send_message(NetworkActorMessage::new_command(
@@ -789,6 +961,41 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         Ok(())
     }
 
+    pub fn handle_update_commitment_fee_command(
+        &self,
+        myself: &ActorRef<ChannelActorMessage>,
+        state: &mut ChannelActorState,
+        command: UpdateCommitmentFeeCommand,
+    ) -> ProcessingChannelResult {
+        debug!("Handling update commitment fee command: {:?}", &command);
+        // Only the opener updates the fee rate like the funder in BOLT 2, so that the two
+        // parties never propose different fee rates at the same time.
+        if state.is_acceptor {
+            return Err(ProcessingChannelError::InvalidState(
+                "Only the channel opener can update the commitment fee rate".to_string(),
+            ));
+        }
+        state.check_for_commitment_fee_update(command.fee_rate)?;
+        state.pending_commitment_fee_rate = Some(command.fee_rate);
+
+        self.network
+            .send_message(NetworkActorMessage::new_command(
+                NetworkActorCommand::SendFiberMessage(FiberMessageWithPeerId {
+                    peer_id: self.peer_id.clone(),
+                    message: FiberMessage::UpdateFee(UpdateFee {
+                        channel_id: state.get_id(),
+                        fee_rate: command.fee_rate,
+                    }),
+                }),
+            ))
+            .expect(ASSUME_NETWORK_ACTOR_ALIVE);
+
+        // The new fee rate only takes effect after the commitment transaction
+        // signed with it is revoked and acked by both parties.
+        self.add_pending_commitment_update(myself, state)?;
+        Ok(())
+    }
+
     // This is the dual of `handle_tx_collaboration_msg`. Any logic error here is likely
     // to present in the other function as well.
     pub fn handle_tx_collaboration_command(
@@ -890,6 +1097,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
 
     pub fn handle_command(
         &self,
+        myself: &ActorRef<ChannelActorMessage>,
         state: &mut ChannelActorState,
         command: ChannelCommand,
     ) -> Result<(), ProcessingChannelError> {
@@ -899,7 +1107,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
             }
             ChannelCommand::CommitmentSigned() => self.handle_commitment_signed_command(state),
             ChannelCommand::AddTlc(command, reply) => {
-                match self.handle_add_tlc_command(state, command) {
+                match self.handle_add_tlc_command(myself, state, command) {
                     Ok(tlc_id) => {
                         let _ = reply.send(Ok(AddTlcResponse { tlc_id }));
                         Ok(())
@@ -911,7 +1119,7 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                 }
             }
             ChannelCommand::RemoveTlc(command, reply) => {
-                match self.handle_remove_tlc_command(state, command) {
+                match self.handle_remove_tlc_command(myself, state, command) {
                     Ok(_) => {
                         let _ = reply.send(Ok(()));
                         Ok(())
@@ -936,6 +1144,18 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                     }
                 }
             }
+            ChannelCommand::UpdateCommitmentFee(command, reply) => {
+                match self.handle_update_commitment_fee_command(myself, state, command) {
+                    Ok(_) => {
+                        let _ = reply.send(Ok(()));
+                        Ok(())
+                    }
+                    Err(err) => {
+                        let _ = reply.send(Err(err.to_string()));
+                        Err(err)
+                    }
+                }
+            }
         }
     }
 
@@ -948,6 +1168,16 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
         match event {
             ChannelEvent::FundingTransactionConfirmed => {
                 let flags = match state.state {
+                    ChannelState::AwaitingChannelReady(flags)
+                        if flags.contains(AwaitingChannelReadyFlags::FUNDING_TX_ORPHANED) =>
+                    {
+                        info!(
+                            "Funding transaction of channel {:?} is confirmed again",
+                            state.get_id()
+                        );
+                        state.update_state(ChannelState::ChannelReady());
+                        return Ok(());
+                    }
                     ChannelState::AwaitingChannelReady(flags) => flags,
                     ChannelState::AwaitingTxSignatures(f)
                         if f.contains(AwaitingTxSignaturesFlags::TX_SIGNATURES_SENT) =>
@@ -975,6 +1205,24 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                     state.on_channel_ready(&self.network);
                 }
             }
+            ChannelEvent::FundingTransactionOrphaned => match state.state {
+                ChannelState::ChannelReady() => {
+                    warn!(
+                        "Funding transaction of channel {:?} is orphaned, waiting for it to be confirmed again",
+                        state.get_id()
+                    );
+                    state.update_state(ChannelState::AwaitingChannelReady(
+                        AwaitingChannelReadyFlags::FUNDING_TX_ORPHANED,
+                    ));
+                }
+                _ => {
+                    error!(
+                        "Funding transaction of channel {:?} is orphaned in state {:?}",
+                        state.get_id(),
+                        &state.state
+                    );
+                }
+            },
             ChannelEvent::CommitmentTransactionConfirmed => {
                 match state.state {
                     ChannelState::ShuttingDown(flags)
@@ -989,11 +1237,60 @@ impl<S: ChannelActorStateStore> ChannelActor<S> {
                 debug!("Channel closed with uncooperative close");
             }
             ChannelEvent::PeerDisconnected => {
-                myself.stop(Some("PeerDisconnected".to_string()));
+                // A funding can't be resumed after reconnecting, see
+                // `handle_reestablish_channel_message`, the actor is stopped once the aborted
+                // funding is saved.
+                if state.is_funding_unsigned() {
+                    state.abort_funding(&self.network);
+                } else {
+                    myself.stop(Some("PeerDisconnected".to_string()));
+                }
             }
             ChannelEvent::ClosingTransactionConfirmed => {
                 myself.stop(Some("ChannelClosed".to_string()));
             }
+            ChannelEvent::ClosedByPeer => {
+                state.update_state(ChannelState::Closed(CloseFlags::UNCOOPERATIVE));
+                debug!("Channel closed by a commitment transaction of the peer");
+                myself.stop(Some("ChannelClosed".to_string()));
+            }
+            ChannelEvent::CommitmentBatchTimeout => {
+                // The updates loaded with the channel are signed after it is reestablished.
+                if state.reestablishing {
+                    self.start_commitment_batch_timer(myself);
+                    return Ok(());
+                }
+                // The pending updates may have already been signed because the batch
+                // is full or a commitment_signed command is received.
+                if state.pending_commitment_updates > 0 {
+                    if let Err(err) = self.handle_commitment_signed_command(state) {
+                        // The updates are still pending, try again later (e.g. the remote
+                        // signer may be reachable again), unless the channel can't be
+                        // updated anymore.
+                        let can_sign = match state.state {
+                            ChannelState::ChannelReady() => true,
+                            ChannelState::ShuttingDown(flags) => {
+                                flags.contains(ShuttingDownFlags::AWAITING_PENDING_TLCS)
+                            }
+                            _ => false,
+                        };
+                        state.commitment_batch_retries += 1;
+                        if !can_sign {
+                            state.pending_commitment_updates = 0;
+                        } else if state.commitment_batch_retries < MAX_COMMITMENT_BATCH_RETRIES {
+                            self.start_commitment_batch_timer(myself);
+                        } else {
+                            error!(
+                                "Giving up signing {} pending updates of channel {:?} after {} retries",
+                                state.pending_commitment_updates,
+                                state.get_id(),
+                                state.commitment_batch_retries
+                            );
+                        }
+                        return Err(err);
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -1042,6 +1339,7 @@ where
                 seed,
                 open_channel,
                 channel_id_sender,
+                tx_confirmations,
             }) => {
                 let peer_id = self.peer_id.clone();
                 debug!(
@@ -1081,7 +1379,7 @@ where
                     *commitment_fee_rate,
                     *funding_fee_rate,
                     funding_udt_type_script.clone(),
-                    &seed,
+                    ChannelSigner::new(seed.into(), self.signer.clone()),
                     peer_id.clone(),
                     *funding_amount,
                     *reserved_ckb_amount,
@@ -1092,7 +1390,8 @@ where
                     *second_per_commitment_point,
                     *max_tlc_value_in_flight,
                     *max_num_of_accept_tlcs,
-                );
+                )?;
+                state.tx_confirmations = tx_confirmations;
 
                 state.check_ckb_params(vec![
                     "local_reserved_ckb_amount",
@@ -1103,6 +1402,7 @@ where
                 ])?;
 
                 let commitment_number = INITIAL_COMMITMENT_NUMBER;
+                let local_pubkeys = &state.get_local_channel_parameters().pubkeys;
 
                 let accept_channel = AcceptChannel {
                     channel_id: *channel_id,
@@ -1111,19 +1411,19 @@ where
                     max_tlc_value_in_flight: DEFAULT_MAX_TLC_VALUE_IN_FLIGHT,
                     max_num_of_accept_tlcs: DEFAULT_MAX_NUM_OF_ACCEPT_TLCS,
                     to_local_delay: *to_local_delay,
-                    funding_pubkey: state.signer.funding_key.pubkey(),
-                    revocation_basepoint: state.signer.revocation_base_key.pubkey(),
-                    payment_basepoint: state.signer.payment_key.pubkey(),
+                    funding_pubkey: local_pubkeys.funding_pubkey,
+                    revocation_basepoint: local_pubkeys.revocation_base_key,
+                    payment_basepoint: local_pubkeys.payment_base_key,
                     min_tlc_value: DEFAULT_MIN_TLC_VALUE,
-                    delayed_payment_basepoint: state.signer.delayed_payment_base_key.pubkey(),
-                    tlc_basepoint: state.signer.tlc_base_key.pubkey(),
+                    delayed_payment_basepoint: local_pubkeys.delayed_payment_base_key,
+                    tlc_basepoint: local_pubkeys.tlc_base_key,
                     first_per_commitment_point: state
                         .signer
-                        .get_commitment_point(commitment_number),
+                        .get_commitment_point(commitment_number)?,
                     second_per_commitment_point: state
                         .signer
-                        .get_commitment_point(commitment_number + 1),
-                    next_local_nonce: state.get_local_musig2_pubnonce(),
+                        .get_commitment_point(commitment_number + 1)?,
+                    next_local_nonce: state.get_local_nonce()?,
                 };
 
                 let command = FiberMessageWithPeerId {
@@ -1163,6 +1463,7 @@ where
                 funding_fee_rate,
                 max_num_of_accept_tlcs,
                 max_tlc_value_in_flight,
+                tx_confirmations,
             }) => {
                 let peer_id = self.peer_id.clone();
                 info!("Trying to open a channel to {:?}", &peer_id);
@@ -1175,7 +1476,7 @@ where
                     self.get_funding_and_reserved_amount(funding_amount, &funding_udt_type_script)?;
 
                 let mut channel = ChannelActorState::new_outbound_channel(
-                    &seed,
+                    ChannelSigner::new(seed.into(), self.signer.clone()),
                     self.peer_id.clone(),
                     funding_amount,
                     reserved_ckb_amount,
@@ -1185,7 +1486,8 @@ where
                     max_tlc_value_in_flight.unwrap_or(DEFAULT_MAX_TLC_VALUE_IN_FLIGHT),
                     max_num_of_accept_tlcs.unwrap_or(DEFAULT_MAX_NUM_OF_ACCEPT_TLCS),
                     LockTime::new(DEFAULT_TO_LOCAL_DELAY_BLOCKS),
-                );
+                )?;
+                channel.tx_confirmations = tx_confirmations;
 
                 channel.check_ckb_params(vec![
                     "commitment_fee_rate",
@@ -1210,10 +1512,10 @@ where
                     channel_flags: 0,
                     first_per_commitment_point: channel
                         .signer
-                        .get_commitment_point(commitment_number),
+                        .get_commitment_point(commitment_number)?,
                     second_per_commitment_point: channel
                         .signer
-                        .get_commitment_point(commitment_number + 1),
+                        .get_commitment_point(commitment_number + 1)?,
                     funding_pubkey: channel
                         .get_local_channel_parameters()
                         .pubkeys
@@ -1231,7 +1533,7 @@ where
                         .pubkeys
                         .delayed_payment_base_key,
                     tlc_basepoint: channel.get_local_channel_parameters().pubkeys.tlc_base_key,
-                    next_local_nonce: channel.get_local_musig2_pubnonce(),
+                    next_local_nonce: channel.get_local_nonce()?,
                 });
 
                 debug!(
@@ -1285,9 +1587,18 @@ where
             ChannelInitializationParameter::ReestablishChannel(channel_id) => {
                 let mut channel = self
                     .store
-                    .get_channel_actor_state(&channel_id)
-                    .expect("channel should exist");
+                    .get_channel_actor_state(&channel_id)?
+                    .ok_or_else(|| {
+                        ProcessingChannelError::InvalidParameter(format!(
+                            "Channel {:?} not found in the store",
+                            channel_id
+                        ))
+                    })?;
+                channel.signer.attach(self.signer.clone());
                 channel.reestablishing = true;
+                if channel.pending_commitment_updates > 0 {
+                    self.start_commitment_batch_timer(&myself);
+                }
 
                 let reestablish_channel = ReestablishChannel {
                     channel_id,
@@ -1326,9 +1637,20 @@ where
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
+        crate::metrics::actor_message_received("channel");
+        let _timer = crate::metrics::actor_message_timer("channel");
         match message {
-            ChannelActorMessage::PeerMessage(message) => {
-                if state.reestablishing {
+            ChannelActorMessage::PeerMessage(peer_id, message) => {
+                // Any connected peer can send a message with the id of this channel, only the
+                // messages from the peer of the channel are processed.
+                if peer_id != state.peer_id {
+                    warn!(
+                        "Ignoring message for channel {:?} from peer {:?} which is not its peer: {:?}",
+                        state.get_id(),
+                        &peer_id,
+                        message
+                    );
+                } else if state.reestablishing {
                     match message {
                         FiberMessage::ReestablishChannel(reestablish_channel) => {
                             state.handle_reestablish_channel_message(
@@ -1345,7 +1667,7 @@ where
                 }
             }
             ChannelActorMessage::Command(command) => {
-                if let Err(err) = self.handle_command(state, command) {
+                if let Err(err) = self.handle_command(&myself, state, command) {
                     error!("Error while processing channel command: {:?}", err);
                 }
             }
@@ -1356,11 +1678,55 @@ where
             }
         }
 
-        self.store.insert_channel_actor_state(state.clone());
+        let tlc_records = self.save_committed_tlc_records(state);
+        // The messages sent above may have revealed the secrets of the revoked commitments, the
+        // actor can't go on with a state which is not saved, stop it by returning the error.
+        if let Err(err) = self.store.insert_channel_actor_state(state.clone()) {
+            error!(
+                "Failed to save the state of channel {:?}, stopping the channel actor: {}",
+                state.get_id(),
+                err
+            );
+            return Err(Box::new(err));
+        }
+        if state.state == ChannelState::Closed(CloseFlags::FUNDING_ABORTED) {
+            myself.stop(Some("FundingAborted".to_string()));
+        }
+        for record in tlc_records {
+            self.subscribers.removed_tlcs_subscribers.send(record);
+        }
+        self.publish_state_notifications(state);
+        Ok(())
+    }
+
+    async fn post_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        // The state of a new channel is set in `pre_start`.
+        self.publish_state_notifications(state);
+        Ok(())
+    }
+
+    async fn post_stop(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        // The actor is also stopped when the node shuts down, a funding in progress is kept
+        // then, the reservations of its cells expire by themselves if it is never resumed.
+        self.publish_state_notifications(state);
         Ok(())
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingFeeRateCommitments {
+    pub local: Option<u64>,
+    pub remote: Option<u64>,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CommitmentNumbers {
     pub local: u64,
@@ -1495,8 +1861,6 @@ pub struct ChannelActorState {
     // An inbound channel is one where the counterparty is the funder of the channel.
     pub is_acceptor: bool,
 
-    // TODO: consider transaction fee while building the commitment transaction.
-
     // The invariant here is that the sum of `to_local_amount` and `to_remote_amount`
     // should be equal to the total amount of the channel.
     // The changes of both `to_local_amount` and `to_remote_amount`
@@ -1516,6 +1880,8 @@ pub struct ChannelActorState {
     // TLC operations will not affect these two amounts, only used to keep the commitment transactions
     // to be valid, so that any party can close the channel at any time.
     // Note: the values are different for the UDT scenario
+    // The commitment transaction fee is paid with the reserved ckb amount of the broadcaster first,
+    // the rest is deducted from its balance, see `get_commitment_fee_paid_by_balance`.
     pub local_reserved_ckb_amount: u64,
     pub remote_reserved_ckb_amount: u64,
 
@@ -1523,12 +1889,37 @@ pub struct ChannelActorState {
     // The side who want to submit the commitment transaction will pay fee
     pub commitment_fee_rate: u64,
 
+    // A new commitment fee rate proposed by an UpdateFee message (sent or received).
+    // It is used to build the commitment transactions from then on, and replaces
+    // `commitment_fee_rate` once a commitment transaction built with it is revoked.
+    pub pending_commitment_fee_rate: Option<u64>,
+
+    // The numbers of the first local and remote commitment transactions built with
+    // `pending_commitment_fee_rate`.
+    #[serde(default)]
+    pub pending_commitment_fee_rate_commitments: PendingFeeRateCommitments,
+
+    // The records of the tlcs whose removals are committed by the last revoke_and_ack
+    // message, they are saved and published by the channel actor.
+    #[serde(skip)]
+    pub committed_tlc_records: Vec<TlcRecord>,
+
+    // The state changes not published yet, they are published by the channel actor once the
+    // state is saved.
+    #[serde(skip)]
+    pub state_notifications: Vec<ChannelStateNotification>,
+
     // The fee rate used for funding transaction, the initiator may set it as `funding_fee_rate` option,
     // if it's not set, DEFAULT_FEE_RATE will be used as default value, two sides will use the same fee rate
     pub funding_fee_rate: u64,
 
+    // The confirmations required for the funding and closing transactions of this channel,
+    // the configured `tx_confirmations` is used if it's not set.
+    #[serde(default)]
+    pub tx_confirmations: Option<u64>,
+
     // Signer is used to sign the commitment transactions.
-    pub signer: InMemorySigner,
+    pub signer: ChannelSigner,
 
     // Cached channel parameter for easier of access.
     pub local_channel_parameters: ChannelParametersOneParty,
@@ -1580,23 +1971,108 @@ pub struct ChannelActorState {
     // A flag to indicate whether the channel is reestablishing, we won't process any messages until the channel is reestablished.
     pub reestablishing: bool,
 
+    // The number of updates (AddTlc, RemoveTlc and UpdateFee) we sent which are not signed yet.
+    // It is saved so that the batch timer is started again when the channel is loaded, the
+    // updates may not be resent while reestablishing the channel if the peer has received them.
+    #[serde(default)]
+    pub pending_commitment_updates: usize,
+
+    // The number of times in a row the batch timer failed to sign the pending updates.
+    #[serde(skip)]
+    pub commitment_batch_retries: usize,
+
     // A redundant field to record the total amount of the channel.
-    // Used only for debugging purposes.
-    #[cfg(debug_assertions)]
+    // Used only for debugging purposes, it is kept in release builds as well so that
+    // the stored channel states don't depend on the build profile. The JSON states saved by
+    // release builds don't have it, it is filled by the store migration.
+    #[serde(default)]
     pub total_amount: u128,
 
     pub created_at: SystemTime,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
-pub struct ClosedChannel {}
+/// How a channel is closed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelCloseType {
+    /// Both parties signed a closing transaction.
+    Cooperative,
+    /// We broadcast our latest commitment transaction.
+    LocalForce,
+    /// The peer broadcast its latest commitment transaction.
+    RemoteForce,
+    /// The peer broadcast a revoked commitment transaction.
+    Revoked,
+}
+
+/// The archive record of a closed channel, kept for accounting after the channel is closed.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClosedChannel {
+    pub channel_id: Hash256,
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    #[serde_as(as = "Option<EntityHex>")]
+    pub funding_udt_type_script: Option<Script>,
+    pub funding_tx_hash: Hash256,
+    pub close_tx_hash: Hash256,
+    pub close_type: ChannelCloseType,
+    // The balances of the last commitment, in CKB shannons or UDT amount.
+    pub local_balance: u128,
+    pub remote_balance: u128,
+    // The fee of the closing or commitment transaction, in shannons.
+    pub close_fee: u64,
+    // Our share of the fee of the funding transaction, in proportion to the capacity of the
+    // inputs we put in it, in shannons.
+    pub funding_fee: u64,
+    // In microseconds since the UNIX epoch.
+    pub created_at: u64,
+    pub closed_at: u64,
+}
+
+/// Whether a tlc was offered by the peer or by us.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlcDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// How a tlc is removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlcStatus {
+    Settled,
+    Failed,
+}
+
+/// The record of a removed tlc, kept for accounting.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TlcRecord {
+    pub channel_id: Hash256,
+    pub tlc_id: u64,
+    pub direction: TlcDirection,
+    pub status: TlcStatus,
+    pub payment_hash: Hash256,
+    // In CKB shannons or UDT amount.
+    pub amount: u128,
+    #[serde_as(as = "Option<EntityHex>")]
+    pub udt_type_script: Option<Script>,
+    // In microseconds since the UNIX epoch.
+    pub removed_at: u64,
+}
 
 #[derive(Debug)]
 pub enum ChannelEvent {
     PeerDisconnected,
     FundingTransactionConfirmed,
+    FundingTransactionOrphaned,
     CommitmentTransactionConfirmed,
     ClosingTransactionConfirmed,
+    // The peer has closed the channel by a commitment transaction spending the funding cell.
+    ClosedByPeer,
+    CommitmentBatchTimeout,
 }
 
 pub type ProcessingChannelResult = Result<(), ProcessingChannelError>;
@@ -1615,6 +2091,10 @@ pub enum ProcessingChannelError {
     Musig2VerifyError(#[from] VerifyError),
     #[error("Musig2 SigningError: {0}")]
     Musig2SigningError(#[from] SigningError),
+    #[error("Signer error: {0}")]
+    SignerError(#[from] SignerError),
+    #[error("Store error: {0}")]
+    StoreError(#[from] StoreError),
 }
 
 bitflags! {
@@ -1658,6 +2138,10 @@ bitflags! {
         const OUR_CHANNEL_READY = 1;
         const THEIR_CHANNEL_READY = 1 << 1;
         const CHANNEL_READY = AwaitingChannelReadyFlags::OUR_CHANNEL_READY.bits() | AwaitingChannelReadyFlags::THEIR_CHANNEL_READY.bits();
+        /// Indicates that the channel was ready, but the funding transaction has left the chain
+        /// because of a chain reorganization. The channel is ready again once the funding
+        /// transaction is confirmed again, without exchanging `channel_ready` messages.
+        const FUNDING_TX_ORPHANED = 1 << 2;
     }
 
     #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -1683,6 +2167,8 @@ bitflags! {
         const COOPERATIVE = 1;
         /// Indicates that channel is closed uncooperatively, initiated by one party forcely.
         const UNCOOPERATIVE = 1 << 1;
+        /// Indicates that the funding is aborted before the funding transaction is signed.
+        const FUNDING_ABORTED = 1 << 2;
     }
 }
 
@@ -1696,12 +2182,7 @@ enum CommitmentSignedFlags {
     ChannelReady(),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(
-    rename_all = "SCREAMING_SNAKE_CASE",
-    tag = "state_name",
-    content = "state_flags"
-)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ChannelState {
     /// We are negotiating the parameters required for the channel prior to funding it.
     NegotiatingFunding(NegotiatingFundingFlags),
@@ -1731,6 +2212,61 @@ impl ChannelState {
     }
 }
 
+// The JSON layout of `ChannelState` is adjacently tagged, which binary formats such as the
+// store encoding can't decode, they use the default layout of enums instead.
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
+#[serde(
+    remote = "ChannelState",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    tag = "state_name",
+    content = "state_flags"
+)]
+enum JsonChannelState {
+    NegotiatingFunding(NegotiatingFundingFlags),
+    CollaboratingFundingTx(CollaboratingFundingTxFlags),
+    SigningCommitment(SigningCommitmentFlags),
+    AwaitingTxSignatures(AwaitingTxSignaturesFlags),
+    AwaitingChannelReady(AwaitingChannelReadyFlags),
+    ChannelReady(),
+    ShuttingDown(ShuttingDownFlags),
+    Closed(CloseFlags),
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ChannelState")]
+enum StoredChannelState {
+    NegotiatingFunding(NegotiatingFundingFlags),
+    CollaboratingFundingTx(CollaboratingFundingTxFlags),
+    SigningCommitment(SigningCommitmentFlags),
+    AwaitingTxSignatures(AwaitingTxSignaturesFlags),
+    AwaitingChannelReady(AwaitingChannelReadyFlags),
+    ChannelReady(),
+    ShuttingDown(ShuttingDownFlags),
+    Closed(CloseFlags),
+}
+
+impl Serialize for ChannelState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            JsonChannelState::serialize(self, serializer)
+        } else {
+            StoredChannelState::serialize(self, serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChannelState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            JsonChannelState::deserialize(deserializer)
+        } else {
+            StoredChannelState::deserialize(deserializer)
+        }
+    }
+}
+
 pub fn new_channel_id_from_seed(seed: &[u8]) -> Hash256 {
     blake2b_256(seed).into()
 }
@@ -1781,7 +2317,7 @@ impl ChannelActorState {
         commitment_fee_rate: u64,
         funding_fee_rate: u64,
         funding_udt_type_script: Option<Script>,
-        seed: &[u8],
+        signer: ChannelSigner,
         peer_id: PeerId,
         remote_value: u128,
         remote_reserved_ckb_amount: u64,
@@ -1792,9 +2328,8 @@ impl ChannelActorState {
         second_commitment_point: Pubkey,
         max_tlc_value_in_flight: u128,
         max_num_of_accept_tlcs: u64,
-    ) -> Self {
-        let signer = InMemorySigner::generate_from_seed(seed);
-        let local_base_pubkeys = signer.get_base_public_keys();
+    ) -> Result<Self, ProcessingChannelError> {
+        let local_base_pubkeys = signer.get_base_public_keys()?;
 
         let channel_id = derive_channel_id_from_revocation_keys(
             &local_base_pubkeys.revocation_base_key,
@@ -1806,7 +2341,7 @@ impl ChannelActorState {
             &channel_id, &temp_channel_id,
         );
 
-        Self {
+        Ok(Self {
             state: ChannelState::NegotiatingFunding(NegotiatingFundingFlags::THEIR_INIT_SENT),
             peer_id,
             funding_tx: None,
@@ -1815,7 +2350,12 @@ impl ChannelActorState {
             to_local_amount: local_value,
             to_remote_amount: remote_value,
             commitment_fee_rate,
+            pending_commitment_fee_rate: None,
+            pending_commitment_fee_rate_commitments: Default::default(),
+            committed_tlc_records: vec![],
+            state_notifications: vec![],
             funding_fee_rate,
+            tx_confirmations: None,
             id: channel_id,
             tlc_ids: Default::default(),
             tlcs: Default::default(),
@@ -1844,15 +2384,16 @@ impl ChannelActorState {
             max_num_of_accept_tlcs,
 
             reestablishing: false,
-            #[cfg(debug_assertions)]
+            pending_commitment_updates: 0,
+            commitment_batch_retries: 0,
             total_amount: local_value + remote_value,
             created_at: SystemTime::now(),
-        }
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
     pub fn new_outbound_channel(
-        seed: &[u8],
+        signer: ChannelSigner,
         peer_id: PeerId,
         value: u128,
         local_reserved_ckb_amount: u64,
@@ -1862,12 +2403,11 @@ impl ChannelActorState {
         max_tlc_value_in_flight: u128,
         max_num_of_accept_tlcs: u64,
         to_local_delay: LockTime,
-    ) -> Self {
-        let signer = InMemorySigner::generate_from_seed(seed);
-        let local_pubkeys = signer.get_base_public_keys();
+    ) -> Result<Self, ProcessingChannelError> {
+        let local_pubkeys = signer.get_base_public_keys()?;
         let temp_channel_id =
             derive_temp_channel_id_from_revocation_key(&local_pubkeys.revocation_base_key);
-        Self {
+        Ok(Self {
             state: ChannelState::NegotiatingFunding(NegotiatingFundingFlags::empty()),
             peer_id,
             funding_tx: None,
@@ -1876,7 +2416,12 @@ impl ChannelActorState {
             to_local_amount: value,
             to_remote_amount: 0,
             commitment_fee_rate,
+            pending_commitment_fee_rate: None,
+            pending_commitment_fee_rate_commitments: Default::default(),
+            committed_tlc_records: vec![],
+            state_notifications: vec![],
             funding_fee_rate,
+            tx_confirmations: None,
             id: temp_channel_id,
             tlc_ids: Default::default(),
             tlcs: Default::default(),
@@ -1902,10 +2447,11 @@ impl ChannelActorState {
             latest_commitment_transaction: None,
 
             reestablishing: false,
+            pending_commitment_updates: 0,
+            commitment_batch_retries: 0,
             created_at: SystemTime::now(),
-            #[cfg(debug_assertions)]
             total_amount: value,
-        }
+        })
     }
 
     fn check_reserved_ckb_amount(
@@ -2017,15 +2563,153 @@ impl ChannelActorState {
         Ok(())
     }
 
-    pub fn get_local_balance(&self) -> u128 {
-        self.to_local_amount
+    // The fee rate used to build new commitment transactions, which is the proposed
+    // fee rate of an UpdateFee message if it is not committed yet.
+    pub fn get_commitment_fee_rate(&self) -> u64 {
+        self.pending_commitment_fee_rate
+            .unwrap_or(self.commitment_fee_rate)
     }
 
-    pub fn get_remote_balance(&self) -> u128 {
-        self.to_remote_amount
+    // The fee of a commitment transaction, which is computed from the size of
+    // the commitment transaction and the given fee rate.
+    pub fn get_commitment_tx_fee(&self, fee_rate: u64) -> u64 {
+        calculate_commitment_tx_fee(fee_rate, &self.funding_udt_type_script)
     }
 
-    pub fn get_sent_tlc_balance(&self) -> u128 {
+    // The commitment transaction fee is paid by the side who want to submit the commitment transaction.
+    // The part of reserved ckb amount above the occupied capacity is used to pay the fee first,
+    // and this function returns the rest of the fee, which must be paid with the balance of that side.
+    // The balance of UDT channels can't be used to pay the fee, so the rest of the fee
+    // for UDT channels is checked while updating commitment fee rate.
+    fn get_commitment_fee_paid_by_balance(&self, local: bool, fee_rate: u64) -> u128 {
+        if self.funding_udt_type_script.is_some() {
+            return 0;
+        }
+        let reserved_ckb_amount = if local {
+            self.local_reserved_ckb_amount
+        } else {
+            self.remote_reserved_ckb_amount
+        };
+        self.get_commitment_tx_fee(fee_rate)
+            .saturating_sub(reserved_ckb_amount.saturating_sub(MIN_OCCUPIED_CAPACITY))
+            as u128
+    }
+
+    pub fn check_for_commitment_fee_update(&self, fee_rate: u64) -> ProcessingChannelResult {
+        if !matches!(self.state, ChannelState::ChannelReady()) {
+            return Err(ProcessingChannelError::InvalidState(format!(
+                "Invalid state {:?} for updating commitment fee rate",
+                self.state
+            )));
+        }
+        if let Some(pending_fee_rate) = self.pending_commitment_fee_rate {
+            return Err(ProcessingChannelError::InvalidState(format!(
+                "Previous commitment fee rate update to {} is not committed yet",
+                pending_fee_rate
+            )));
+        }
+        if fee_rate < DEFAULT_COMMITMENT_FEE_RATE {
+            return Err(ProcessingChannelError::InvalidParameter(format!(
+                "Commitment fee rate is less than {}",
+                DEFAULT_COMMITMENT_FEE_RATE,
+            )));
+        }
+
+        // Both parties may broadcast their commitment transactions, and each pays
+        // the fee with its own reserved ckb amount. The new fee rate must pass the check
+        // of the channel parameters in `check_reserved_ckb_amount` too, which requires the
+        // reserved ckb amount to cover twice the commitment fee, otherwise a fee update
+        // could bring the channel to a fee rate it can't be opened with.
+        let commitment_fee = self.get_commitment_tx_fee(fee_rate);
+        let expected_minimal_reserved_ckb_amount = commitment_fee * 2;
+        for (local, reserved_ckb_amount) in [
+            (true, self.local_reserved_ckb_amount),
+            (false, self.remote_reserved_ckb_amount),
+        ] {
+            let side = if local { "local" } else { "remote" };
+            if reserved_ckb_amount < expected_minimal_reserved_ckb_amount {
+                return Err(ProcessingChannelError::InvalidParameter(format!(
+                    "Commitment fee rate {} requires {} reserved ckb amount to be larger than {}, but it is {}",
+                    fee_rate, side, expected_minimal_reserved_ckb_amount, reserved_ckb_amount
+                )));
+            }
+
+            if self.funding_udt_type_script.is_some() {
+                // The balance of UDT channels is not ckb, the fee can only be paid
+                // with the reserved ckb amount.
+                if reserved_ckb_amount < commitment_fee + MIN_UDT_OCCUPIED_CAPACITY {
+                    return Err(ProcessingChannelError::InvalidParameter(format!(
+                        "Commitment fee rate {} requires {} reserved ckb amount to be larger than {}, but it is {}",
+                        fee_rate, side, commitment_fee + MIN_UDT_OCCUPIED_CAPACITY, reserved_ckb_amount
+                    )));
+                }
+            } else {
+                let available_balance = if local {
+                    self.to_local_amount - self.get_sent_tlc_balance()
+                } else {
+                    self.to_remote_amount - self.get_received_tlc_balance()
+                };
+                let fee_paid_by_balance = self.get_commitment_fee_paid_by_balance(local, fee_rate);
+                if fee_paid_by_balance > available_balance {
+                    return Err(ProcessingChannelError::InvalidParameter(format!(
+                        "Commitment fee rate {} requires {} balance to pay fee {}, but the available balance is {}",
+                        fee_rate, side, fee_paid_by_balance, available_balance
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Record the number of the commitment transaction just built with the pending fee rate,
+    // if it is the first one of this side.
+    fn record_pending_fee_rate_commitment(&mut self, local: bool) {
+        if self.pending_commitment_fee_rate.is_none() {
+            return;
+        }
+        let number = self.get_current_commitment_number(local);
+        let commitments = &mut self.pending_commitment_fee_rate_commitments;
+        let recorded = if local {
+            &mut commitments.local
+        } else {
+            &mut commitments.remote
+        };
+        recorded.get_or_insert(number);
+    }
+
+    pub fn handle_update_fee_message(&mut self, update_fee: UpdateFee) -> ProcessingChannelResult {
+        if !self.is_acceptor {
+            return Err(ProcessingChannelError::InvalidParameter(format!(
+                "Only the channel opener can update the commitment fee rate, but received {:?} from the acceptor",
+                update_fee
+            )));
+        }
+        // The opener resends the fee rate on reestablishing the channel, it may be
+        // received or even committed already.
+        if self.get_commitment_fee_rate() == update_fee.fee_rate {
+            return Err(ProcessingChannelError::RepeatedProcessing(format!(
+                "update_fee message {:?}",
+                update_fee
+            )));
+        }
+        self.check_for_commitment_fee_update(update_fee.fee_rate)?;
+        debug!(
+            "Received update_fee message, commitment fee rate will be updated from {} to {}",
+            self.commitment_fee_rate, update_fee.fee_rate
+        );
+        self.pending_commitment_fee_rate = Some(update_fee.fee_rate);
+        Ok(())
+    }
+
+    pub fn get_local_balance(&self) -> u128 {
+        self.to_local_amount
+    }
+
+    pub fn get_remote_balance(&self) -> u128 {
+        self.to_remote_amount
+    }
+
+    pub fn get_sent_tlc_balance(&self) -> u128 {
         self.get_active_offered_tlcs(true)
             .map(|tlc| tlc.tlc.amount)
             .sum::<u128>()
@@ -2044,27 +2728,153 @@ impl ChannelActorState {
             .as_micros() as u64
     }
 
+    /// The archive record of the channel closed by the transaction, `None` if the channel
+    /// is not funded.
+    pub fn to_closed_channel(
+        &self,
+        close_type: ChannelCloseType,
+        close_tx: &TransactionView,
+        funding_fee: u64,
+    ) -> Option<ClosedChannel> {
+        let funding_tx = self.funding_tx.as_ref()?;
+        let funding_capacity: u64 = funding_tx.raw().outputs().get(0)?.capacity().unpack();
+        let outputs_capacity = close_tx.outputs_capacity().ok()?.as_u64();
+        Some(ClosedChannel {
+            channel_id: self.get_id(),
+            peer_id: self.peer_id.clone(),
+            funding_udt_type_script: self.funding_udt_type_script.clone(),
+            funding_tx_hash: funding_tx.calc_tx_hash().into(),
+            close_tx_hash: close_tx.hash().into(),
+            close_type,
+            local_balance: self.get_local_balance(),
+            remote_balance: self.get_remote_balance(),
+            close_fee: funding_capacity.saturating_sub(outputs_capacity),
+            funding_fee,
+            created_at: self.get_created_at_in_microseconds(),
+            closed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64,
+        })
+    }
+
+    /// How the channel is closed by the transaction spending its funding cell. The version
+    /// signed in the funding cell witness tells the closing transaction from the commitment
+    /// transactions, and which commitment transaction has been revoked.
+    pub fn get_close_type(&self, close_tx: &TransactionView) -> ChannelCloseType {
+        let version = close_tx
+            .witnesses()
+            .get(0)
+            .and_then(|witness| {
+                let witness = witness.raw_data();
+                let bytes: [u8; 8] = witness.get(16..24)?.try_into().ok()?;
+                Some(u64::from_le_bytes(bytes))
+            })
+            .unwrap_or_default();
+        if version == u64::MAX {
+            return ChannelCloseType::Cooperative;
+        }
+        let is_local_commitment = self
+            .latest_commitment_transaction
+            .as_ref()
+            .is_some_and(|tx| tx.calc_tx_hash() == close_tx.hash());
+        if is_local_commitment {
+            ChannelCloseType::LocalForce
+        } else if version.saturating_add(1) < self.get_local_commitment_number() {
+            // The peer has revoked all the commitment transactions before the last two we
+            // signed for it.
+            ChannelCloseType::Revoked
+        } else {
+            ChannelCloseType::RemoteForce
+        }
+    }
+
+    pub fn to_tlc_record(&self, tlc: &TLC, reason: RemoveTlcReason) -> TlcRecord {
+        TlcRecord {
+            channel_id: self.get_id(),
+            tlc_id: tlc.get_id(),
+            direction: if tlc.is_received() {
+                TlcDirection::Incoming
+            } else {
+                TlcDirection::Outgoing
+            },
+            status: match reason {
+                RemoveTlcReason::RemoveTlcFulfill(_) => TlcStatus::Settled,
+                RemoveTlcReason::RemoveTlcFail(_) => TlcStatus::Failed,
+            },
+            payment_hash: tlc.payment_hash,
+            amount: tlc.amount,
+            udt_type_script: self.funding_udt_type_script.clone(),
+            removed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64,
+        }
+    }
+
     pub fn is_closed(&self) -> bool {
         self.state.is_closed()
     }
 
+    // Whether the funding can still be aborted, i.e. we haven't sent our signatures of the
+    // funding transaction, so the peer can't broadcast it.
+    fn is_funding_unsigned(&self) -> bool {
+        match self.state {
+            ChannelState::NegotiatingFunding(_)
+            | ChannelState::CollaboratingFundingTx(_)
+            | ChannelState::SigningCommitment(_) => true,
+            ChannelState::AwaitingTxSignatures(flags) => {
+                !flags.contains(AwaitingTxSignaturesFlags::OUR_TX_SIGNATURES_SENT)
+            }
+            _ => false,
+        }
+    }
+
+    // Give up the funding, the cells we put in the funding transaction are released.
+    fn abort_funding(&mut self, network: &ActorRef<NetworkActorMessage>) {
+        if let Some(funding_tx) = self.funding_tx.clone() {
+            // The network actor may be stopping with the node, the reservations of the cells
+            // expire by themselves then.
+            if network
+                .send_message(NetworkActorMessage::new_event(
+                    NetworkActorEvent::FundingTransactionAborted(self.get_id(), funding_tx),
+                ))
+                .is_err()
+            {
+                crate::metrics::actor_message_dropped("network");
+            }
+        }
+        self.update_state(ChannelState::Closed(CloseFlags::FUNDING_ABORTED));
+    }
+
     fn update_state(&mut self, new_state: ChannelState) {
         debug!(
             "Updating channel state from {:?} to {:?}",
             &self.state, &new_state
         );
+        if self.state != new_state {
+            self.state_notifications.push(ChannelStateNotification {
+                channel_id: self.get_id(),
+                peer_id: self.peer_id.clone(),
+                state: new_state,
+            });
+        }
         self.state = new_state;
     }
 
     // Send RevokeAndAck message to the counterparty, and update the
     // channel state accordingly.
-    fn send_revoke_and_ack_message(&mut self, network: &ActorRef<NetworkActorMessage>) {
+    fn send_revoke_and_ack_message(
+        &mut self,
+        network: &ActorRef<NetworkActorMessage>,
+    ) -> ProcessingChannelResult {
         // Now we should revoke previous transation by revealing preimage.
-        let (commitment_number, commitment_secret) = self.get_previous_local_commitment_secret();
-        // Note that we must update channel state here to update commitment number,
-        // so that next step will obtain the correct commitmen point.
+        let (commitment_number, commitment_secret) = self.get_previous_local_commitment_secret()?;
+        // The commitment point of the commitment number after the update below, it is obtained
+        // first so that the state is not updated if the signer fails.
+        let point = self.get_local_commitment_point(self.get_remote_commitment_number() + 1)?;
+        // Note that we must update channel state here to update commitment number.
         self.update_state_on_raa_msg(false);
-        let point = self.get_current_local_commitment_point();
 
         debug!(
             "Sending commitment secret {:?} for commitment number {} and new commitment point {:?}",
@@ -2084,6 +2894,7 @@ impl ChannelActorState {
                 }),
             ))
             .expect(ASSUME_NETWORK_ACTOR_ALIVE);
+        Ok(())
     }
 
     // After sending or receiving a RevokeAndAck message, all messages before
@@ -2093,10 +2904,7 @@ impl ChannelActorState {
     // This may fill in the creation_confirmed_at and removal_confirmed_at fields
     // of the tlcs. And update the to_local_amount and to_remote_amount.
     fn update_state_on_raa_msg(&mut self, is_received: bool) {
-        #[cfg(debug_assertions)]
-        {
-            self.total_amount = self.to_local_amount + self.to_remote_amount;
-        }
+        self.total_amount = self.to_local_amount + self.to_remote_amount;
 
         if is_received {
             self.increment_local_commitment_number();
@@ -2114,6 +2922,7 @@ impl ChannelActorState {
         debug!("Updating local state on revoke_and_ack message {}, current commitment number: {:?}, to_local_amount: {}, to_remote_amount: {}",
             if is_received { "received" } else { "sent" }, commitment_numbers, to_local_amount, to_remote_amount);
 
+        let mut committed_removals = vec![];
         self.tlcs.values_mut().for_each(|tlc| {
             if tlc.removal_confirmed_at.is_some() {
                 return;
@@ -2131,6 +2940,7 @@ impl ChannelActorState {
             match (tlc.removed_at, tlc.removal_confirmed_at) {
                 (Some((_removed_at, reason)), None) => {
                     tlc.removal_confirmed_at = Some(commitment_numbers);
+                    committed_removals.push((tlc.tlc, reason));
                      match reason {
                         RemoveTlcReason::RemoveTlcFulfill(_)  => {
                             if tlc.is_offered(){
@@ -2166,12 +2976,31 @@ impl ChannelActorState {
         });
         self.to_local_amount = to_local_amount;
         self.to_remote_amount = to_remote_amount;
-        debug!("Updated local state on revoke_and_ack message {}: current commitment number: {:?}, to_local_amount: {}, to_remote_amount: {}",
-        if is_received { "received" } else { "sent" }, commitment_numbers, to_local_amount, to_remote_amount);
-        #[cfg(debug_assertions)]
+        for (tlc, reason) in committed_removals {
+            let record = self.to_tlc_record(&tlc, reason);
+            self.committed_tlc_records.push(record);
+        }
+        // The commitment transactions signed before the UpdateFee message don't have the
+        // new fee rate, revoking them doesn't commit it.
+        let PendingFeeRateCommitments { local, remote } =
+            self.pending_commitment_fee_rate_commitments;
+        let is_revoked =
+            |number: Option<u64>, current: u64| matches!(number, Some(n) if n < current);
+        if is_revoked(local, self.get_local_commitment_number())
+            || is_revoked(remote, self.get_remote_commitment_number())
         {
-            self.total_amount = self.to_local_amount + self.to_remote_amount;
+            if let Some(fee_rate) = self.pending_commitment_fee_rate.take() {
+                debug!(
+                    "Updating commitment fee rate from {} to {} on revoke_and_ack message",
+                    self.commitment_fee_rate, fee_rate
+                );
+                self.commitment_fee_rate = fee_rate;
+            }
+            self.pending_commitment_fee_rate_commitments = Default::default();
         }
+        debug!("Updated local state on revoke_and_ack message {}: current commitment number: {:?}, to_local_amount: {}, to_remote_amount: {}",
+        if is_received { "received" } else { "sent" }, commitment_numbers, to_local_amount, to_remote_amount);
+        self.total_amount = self.to_local_amount + self.to_remote_amount;
     }
 }
 
@@ -2181,22 +3010,16 @@ impl ChannelActorState {
         self.id
     }
 
-    pub fn get_local_secnonce(&self) -> SecNonce {
-        self.signer
-            .derive_musig2_nonce(self.get_local_commitment_number())
-    }
-
-    pub fn get_local_nonce(&self) -> impl Borrow<PubNonce> {
-        self.get_local_secnonce().public_nonce()
-    }
-
-    pub fn get_next_local_secnonce(&self) -> SecNonce {
-        self.signer
-            .derive_musig2_nonce(self.get_next_commitment_number(true))
+    pub fn get_local_nonce(&self) -> Result<PubNonce, ProcessingChannelError> {
+        Ok(self
+            .signer
+            .get_musig2_pubnonce(self.get_local_commitment_number())?)
     }
 
-    pub fn get_next_local_nonce(&self) -> PubNonce {
-        self.get_next_local_secnonce().public_nonce()
+    pub fn get_next_local_nonce(&self) -> Result<PubNonce, ProcessingChannelError> {
+        Ok(self
+            .signer
+            .get_musig2_pubnonce(self.get_next_commitment_number(true))?)
     }
 
     pub fn get_remote_nonce(&self) -> &PubNonce {
@@ -2230,6 +3053,9 @@ impl ChannelActorState {
             self.get_local_commitment_number() + 1
         );
         self.commitment_numbers.increment_local();
+        crate::metrics::COMMITMENT_UPDATES
+            .with_label_values(&["local"])
+            .inc();
     }
 
     pub fn increment_remote_commitment_number(&mut self) {
@@ -2239,6 +3065,9 @@ impl ChannelActorState {
             self.get_remote_commitment_number() + 1
         );
         self.commitment_numbers.increment_remote();
+        crate::metrics::COMMITMENT_UPDATES
+            .with_label_values(&["remote"])
+            .inc();
     }
 
     pub fn get_current_commitment_number(&self, local: bool) -> u64 {
@@ -2304,13 +3133,17 @@ impl ChannelActorState {
             let sent_tlc_value = self.get_sent_tlc_balance();
             debug!("Value of local sent tlcs: {}", sent_tlc_value);
             debug_assert!(self.to_local_amount >= sent_tlc_value);
-            // TODO: handle transaction fee here.
-            if sent_tlc_value + tlc.amount > self.to_local_amount {
+            // We must still be able to pay the fee of our commitment transaction
+            // even if all the offered tlcs are fulfilled.
+            let commitment_fee =
+                self.get_commitment_fee_paid_by_balance(true, self.get_commitment_fee_rate());
+            if sent_tlc_value + tlc.amount + commitment_fee > self.to_local_amount {
                 return Err(ProcessingChannelError::InvalidParameter(format!(
-                    "Adding tlc {:?} with amount {} exceeds local balance {}",
+                    "Adding tlc {:?} with amount {} exceeds local balance {} (commitment fee {} excluded)",
                     tlc.id,
                     tlc.amount,
-                    self.to_local_amount - sent_tlc_value
+                    self.to_local_amount.saturating_sub(sent_tlc_value + commitment_fee),
+                    commitment_fee
                 )));
             }
         } else {
@@ -2319,13 +3152,17 @@ impl ChannelActorState {
             let received_tlc_value = self.get_received_tlc_balance();
             debug!("Value of remote received tlcs: {}", received_tlc_value);
             debug_assert!(self.to_remote_amount >= received_tlc_value);
-            // TODO: handle transaction fee here.
-            if received_tlc_value + tlc.amount > self.to_remote_amount {
+            // The remote must still be able to pay the fee of its commitment transaction
+            // even if all the tlcs it offered are fulfilled.
+            let commitment_fee =
+                self.get_commitment_fee_paid_by_balance(false, self.get_commitment_fee_rate());
+            if received_tlc_value + tlc.amount + commitment_fee > self.to_remote_amount {
                 return Err(ProcessingChannelError::InvalidParameter(format!(
-                    "Adding tlc {:?} with amount {} exceeds remote balance {}",
+                    "Adding tlc {:?} with amount {} exceeds remote balance {} (commitment fee {} excluded)",
                     tlc.id,
                     tlc.amount,
-                    self.to_remote_amount - received_tlc_value
+                    self.to_remote_amount.saturating_sub(received_tlc_value + commitment_fee),
+                    commitment_fee
                 )));
             }
         }
@@ -2444,13 +3281,16 @@ impl ChannelActorState {
             .expect("Counterparty shutdown script is present")
     }
 
-    fn get_local_commitment_point(&self, commitment_number: u64) -> Pubkey {
-        let commitment_point = self.signer.get_commitment_point(commitment_number);
+    fn get_local_commitment_point(
+        &self,
+        commitment_number: u64,
+    ) -> Result<Pubkey, ProcessingChannelError> {
+        let commitment_point = self.signer.get_commitment_point(commitment_number)?;
         debug!(
             "Obtained {}th local commitment point: {:?}",
             commitment_number, commitment_point
         );
-        commitment_point
+        Ok(commitment_point)
     }
 
     /// Get the counterparty commitment point for the given commitment number.
@@ -2475,14 +3315,12 @@ impl ChannelActorState {
         )
     }
 
-    fn get_previous_local_commitment_secret(&self) -> (u64, [u8; 32]) {
+    fn get_previous_local_commitment_secret(
+        &self,
+    ) -> Result<(u64, [u8; 32]), ProcessingChannelError> {
         let commitment_number = self.get_remote_commitment_number() - 1;
-        let secret = self.signer.get_commitment_secret(commitment_number);
-        (commitment_number, secret)
-    }
-
-    fn get_current_local_commitment_point(&self) -> Pubkey {
-        self.get_local_commitment_point(self.get_remote_commitment_number())
+        let secret = self.signer.get_commitment_secret(commitment_number)?;
+        Ok((commitment_number, secret))
     }
 
     pub fn get_funding_lock_script_xonly(&self) -> [u8; 32] {
@@ -2535,27 +3373,17 @@ impl ChannelActorState {
         get_script_by_contract(Contract::Secp256k1Lock, &pub_key_hash[0..20])
     }
 
-    pub fn get_local_musig2_secnonce(&self) -> SecNonce {
-        self.signer
-            .derive_musig2_nonce(self.get_local_commitment_number())
-    }
-
-    pub fn get_local_musig2_pubnonce(&self) -> PubNonce {
-        self.get_local_musig2_secnonce().public_nonce()
-    }
-
-    pub fn get_musig2_agg_pubnonce(&self) -> AggNonce {
-        let local_nonce = self.get_local_nonce();
-        let local_nonce = local_nonce.borrow();
+    pub fn get_musig2_agg_pubnonce(&self) -> Result<AggNonce, ProcessingChannelError> {
+        let local_nonce = self.get_local_nonce()?;
         let remote_nonce = self.get_remote_nonce();
-        let nonces = self.order_things_for_musig2(local_nonce, remote_nonce);
+        let nonces = self.order_things_for_musig2(&local_nonce, remote_nonce);
         debug!(
             "Got agg nonces {:?} from peer {:?}: {:?}",
             AggNonce::sum(nonces),
             &self.peer_id,
             nonces
         );
-        AggNonce::sum(nonces)
+        Ok(AggNonce::sum(nonces))
     }
 
     // The parameter `local_commitment` indicates whether we are building a local or remote
@@ -2634,6 +3462,14 @@ impl ChannelActorState {
         })
     }
 
+    /// The offered and received tlcs which are not removed yet.
+    pub fn get_tlcs_in_flight(&self) -> impl Iterator<Item = &TLC> {
+        self.tlcs
+            .values()
+            .filter(|info| info.removed_at.is_none())
+            .map(|info| &info.tlc)
+    }
+
     // The parameter local indicates whether we are interested in the value sent by the local party.
     fn get_tlc_value_received_from_remote(&self, local_commitment: bool) -> u128 {
         if local_commitment {
@@ -2653,7 +3489,11 @@ impl ChannelActorState {
     // The offerer who offered this tlc will have the first pubkey, and the receiver
     // will have the second pubkey.
     // This tlc must have valid local_committed_at and remote_committed_at fields.
-    pub fn get_tlc_pubkeys(&self, tlc: &DetailedTLCInfo, local: bool) -> (Pubkey, Pubkey) {
+    pub fn get_tlc_pubkeys(
+        &self,
+        tlc: &DetailedTLCInfo,
+        local: bool,
+    ) -> Result<(Pubkey, Pubkey), ProcessingChannelError> {
         debug!("Getting tlc pubkeys for tlc: {:?}", tlc);
         let is_offered = tlc.tlc.is_offered();
         let CommitmentNumbers {
@@ -2666,7 +3506,7 @@ impl ChannelActorState {
         );
         let local_pubkey = derive_tlc_pubkey(
             &self.get_local_channel_parameters().pubkeys.tlc_base_key,
-            &self.get_local_commitment_point(remote_commitment_number),
+            &self.get_local_commitment_point(remote_commitment_number)?,
         );
         let remote_pubkey = derive_tlc_pubkey(
             &self.get_remote_channel_parameters().pubkeys.tlc_base_key,
@@ -2674,43 +3514,48 @@ impl ChannelActorState {
         );
 
         if is_offered {
-            (local_pubkey, remote_pubkey)
+            Ok((local_pubkey, remote_pubkey))
         } else {
-            (remote_pubkey, local_pubkey)
+            Ok((remote_pubkey, local_pubkey))
         }
     }
 
     pub fn get_active_received_tlc_with_pubkeys(
         &self,
         local: bool,
-    ) -> impl Iterator<Item = (&DetailedTLCInfo, Pubkey, Pubkey)> {
+    ) -> impl Iterator<Item = Result<(&DetailedTLCInfo, Pubkey, Pubkey), ProcessingChannelError>>
+    {
         self.get_active_received_tlcs(local).map(move |tlc| {
-            let (k1, k2) = self.get_tlc_pubkeys(tlc, local);
-            (tlc, k1, k2)
+            let (k1, k2) = self.get_tlc_pubkeys(tlc, local)?;
+            Ok((tlc, k1, k2))
         })
     }
 
     pub fn get_active_offered_tlc_with_pubkeys(
         &self,
         local: bool,
-    ) -> impl Iterator<Item = (&DetailedTLCInfo, Pubkey, Pubkey)> {
+    ) -> impl Iterator<Item = Result<(&DetailedTLCInfo, Pubkey, Pubkey), ProcessingChannelError>>
+    {
         self.get_active_offered_tlcs(local).map(move |tlc| {
-            let (k1, k2) = self.get_tlc_pubkeys(tlc, local);
-            (tlc, k1, k2)
+            let (k1, k2) = self.get_tlc_pubkeys(tlc, local)?;
+            Ok((tlc, k1, k2))
         })
     }
 
-    pub fn get_witness_args_for_active_tlcs(&self, local: bool) -> Vec<u8> {
+    pub fn get_witness_args_for_active_tlcs(
+        &self,
+        local: bool,
+    ) -> Result<Vec<u8>, ProcessingChannelError> {
         // Build a sorted array of TLC so that both party can generate the same commitment transaction.
         debug!("All tlcs: {:?}", self.tlcs);
         let tlcs = {
             let (mut received_tlcs, mut offered_tlcs) = (
                 self.get_active_received_tlc_with_pubkeys(local)
-                    .map(|(tlc, local, remote)| (*tlc, local, remote))
-                    .collect::<Vec<_>>(),
+                    .map(|tlc| tlc.map(|(tlc, local, remote)| (*tlc, local, remote)))
+                    .collect::<Result<Vec<_>, _>>()?,
                 self.get_active_offered_tlc_with_pubkeys(local)
-                    .map(|(tlc, local, remote)| (*tlc, local, remote))
-                    .collect::<Vec<_>>(),
+                    .map(|tlc| tlc.map(|(tlc, local, remote)| (*tlc, local, remote)))
+                    .collect::<Result<Vec<_>, _>>()?,
             );
             debug!("Received tlcs: {:?}", &received_tlcs);
             debug!("Offered tlcs: {:?}", &offered_tlcs);
@@ -2728,7 +3573,8 @@ impl ChannelActorState {
             [a, b].concat()
         };
         debug!("Sorted tlcs: {:?}", &tlcs);
-        tlcs.iter()
+        Ok(tlcs
+            .iter()
             .flat_map(|(tlc, local, remote)| {
                 [
                     vec![tlc.tlc.get_htlc_type()],
@@ -2743,7 +3589,7 @@ impl ChannelActorState {
                 ]
                 .concat()
             })
-            .collect()
+            .collect())
     }
 
     fn any_tlc_pending(&self) -> bool {
@@ -2865,25 +3711,31 @@ impl ChannelActorState {
     }
 }
 
-impl From<&ChannelActorState> for Musig2SignContext {
-    fn from(value: &ChannelActorState) -> Self {
-        Musig2SignContext {
-            key_agg_ctx: value.get_musig2_agg_context(),
-            agg_nonce: value.get_musig2_agg_pubnonce(),
-            seckey: value.signer.funding_key,
-            secnonce: value.get_local_musig2_secnonce(),
-        }
+impl TryFrom<&ChannelActorState> for Musig2SignContext {
+    type Error = ProcessingChannelError;
+
+    fn try_from(value: &ChannelActorState) -> Result<Self, Self::Error> {
+        let local_pubkey = value.get_local_channel_parameters().pubkeys.funding_pubkey;
+        let remote_pubkey = value.get_remote_channel_parameters().pubkeys.funding_pubkey;
+        Ok(Musig2SignContext {
+            signer: value.signer.clone(),
+            commitment_number: value.get_local_commitment_number(),
+            pubkeys: value.order_things_for_musig2(local_pubkey, remote_pubkey),
+            agg_nonce: value.get_musig2_agg_pubnonce()?,
+        })
     }
 }
 
-impl From<&ChannelActorState> for Musig2VerifyContext {
-    fn from(value: &ChannelActorState) -> Self {
-        Musig2VerifyContext {
+impl TryFrom<&ChannelActorState> for Musig2VerifyContext {
+    type Error = ProcessingChannelError;
+
+    fn try_from(value: &ChannelActorState) -> Result<Self, Self::Error> {
+        Ok(Musig2VerifyContext {
             key_agg_ctx: value.get_musig2_agg_context(),
-            agg_nonce: value.get_musig2_agg_pubnonce(),
+            agg_nonce: value.get_musig2_agg_pubnonce()?,
             pubkey: *value.get_remote_funding_pubkey(),
             pubnonce: value.get_remote_nonce().clone(),
-        }
+        })
     }
 }
 
@@ -2922,7 +3774,7 @@ impl ChannelActorState {
             version
         );
 
-        let verify_ctx = Musig2VerifyContext::from(self);
+        let verify_ctx = Musig2VerifyContext::try_from(self)?;
 
         let signature = aggregate_partial_signatures_for_msg(
             message.as_slice(),
@@ -2946,7 +3798,7 @@ impl ChannelActorState {
             hex::encode(tx.msg.as_slice()),
             tx.version
         );
-        let sign_ctx = Musig2SignContext::from(self);
+        let sign_ctx = Musig2SignContext::try_from(self)?;
         let signature2 = sign_ctx.sign(tx.msg.as_slice())?;
 
         self.aggregate_partial_signatures_to_consume_funding_cell(
@@ -2984,7 +3836,7 @@ impl ChannelActorState {
         ));
 
         let (shutdown_tx, message) = self.build_shutdown_tx()?;
-        let sign_ctx = Musig2SignContext::from(&*self);
+        let sign_ctx = Musig2SignContext::try_from(&*self)?;
 
         // Create our shutdown signature if we haven't already.
         let local_shutdown_signature = match self.local_shutdown_signature {
@@ -3074,10 +3926,7 @@ impl ChannelActorState {
         self.to_remote_amount = accept_channel.funding_amount;
         self.remote_reserved_ckb_amount = accept_channel.reserved_ckb_amount;
 
-        #[cfg(debug_assertions)]
-        {
-            self.total_amount = self.to_local_amount + self.to_remote_amount;
-        }
+        self.total_amount = self.to_local_amount + self.to_remote_amount;
 
         self.remote_nonce = Some(accept_channel.next_local_nonce.clone());
         let remote_pubkeys = (&accept_channel).into();
@@ -3260,6 +4109,7 @@ impl ChannelActorState {
         };
 
         let tx = self.verify_and_complete_tx(commitment_signed.partial_signature)?;
+        self.record_pending_fee_rate_commitment(false);
         // This is the commitment transaction that both parties signed,
         // can be broadcasted to the network if necessary
 
@@ -3303,7 +4153,7 @@ impl ChannelActorState {
                 self.maybe_transition_to_tx_signatures(flags, network)?;
             }
             CommitmentSignedFlags::ChannelReady() | CommitmentSignedFlags::PendingShutdown(_) => {
-                self.send_revoke_and_ack_message(network);
+                self.send_revoke_and_ack_message(network)?;
                 match flags {
                     CommitmentSignedFlags::ChannelReady() => {}
                     CommitmentSignedFlags::PendingShutdown(_) => {
@@ -3463,7 +4313,7 @@ impl ChannelActorState {
                 commitment_number, per_commitment_key, per_commitment_point
             )));
         }
-        let witnesses = self.get_previous_local_commitment_witnesses();
+        let witnesses = self.get_previous_local_commitment_witnesses()?;
         let hash = blake2b_256(&witnesses);
         let script_args: &[u8] = &hash[..20];
         debug!(
@@ -3505,6 +4355,28 @@ impl ChannelActorState {
         Ok(())
     }
 
+    /// Close the channel unilaterally by broadcasting the latest commitment transaction.
+    pub fn force_close(
+        &mut self,
+        network: &ActorRef<NetworkActorMessage>,
+    ) -> ProcessingChannelResult {
+        let transaction = self.latest_commitment_transaction.clone().ok_or_else(|| {
+            ProcessingChannelError::InvalidState(
+                "Force shutdown without a valid commitment transaction".to_string(),
+            )
+        })?;
+        network
+            .send_message(NetworkActorMessage::new_event(
+                NetworkActorEvent::CommitmentTransactionPending(transaction, self.get_id()),
+            ))
+            .expect(ASSUME_NETWORK_ACTOR_ALIVE);
+
+        self.update_state(ChannelState::ShuttingDown(
+            ShuttingDownFlags::WAITING_COMMITMENT_CONFIRMATION,
+        ));
+        Ok(())
+    }
+
     fn handle_reestablish_channel_message(
         &mut self,
         reestablish_channel: ReestablishChannel,
@@ -3523,6 +4395,25 @@ impl ChannelActorState {
             ChannelState::ChannelReady() => {
                 let expected_local_commitment_number = self.get_local_commitment_number();
                 let acutal_local_commitment_number = reestablish_channel.remote_commitment_number;
+                // The peer has lost its channel state and is recovering from a static channel
+                // backup, which sends zero commitment numbers. It can't continue the channel,
+                // close it so that the peer can sweep its outputs. Other mismatches of the
+                // commitment numbers are only logged below, a peer can't make us broadcast
+                // the commitment transaction by just sending smaller numbers.
+                let is_recovering = reestablish_channel.local_commitment_number
+                    == INITIAL_COMMITMENT_NUMBER
+                    && reestablish_channel.remote_commitment_number == INITIAL_COMMITMENT_NUMBER;
+                if is_recovering
+                    && (expected_local_commitment_number != INITIAL_COMMITMENT_NUMBER
+                        || self.get_remote_commitment_number() != INITIAL_COMMITMENT_NUMBER)
+                {
+                    warn!(
+                        "Peer {:?} has lost the state of channel {:?}, force closing it",
+                        &self.peer_id,
+                        self.get_id()
+                    );
+                    return self.force_close(network);
+                }
                 if acutal_local_commitment_number == expected_local_commitment_number {
                     // resend AddTlc, RemoveTlc and CommitmentSigned messages if needed
                     let mut need_resend_commitment_signed = false;
@@ -3574,6 +4465,29 @@ impl ChannelActorState {
                             }
                         }
                     }
+                    // Resend the fee rate update if the peer hasn't acked the commitment
+                    // transaction signed with it.
+                    if let Some(fee_rate) = self.pending_commitment_fee_rate {
+                        let signed_at = self.pending_commitment_fee_rate_commitments.local;
+                        if !self.is_acceptor
+                            && signed_at
+                                .map_or(true, |number| number >= acutal_local_commitment_number)
+                        {
+                            network
+                                .send_message(NetworkActorMessage::new_command(
+                                    NetworkActorCommand::SendFiberMessage(FiberMessageWithPeerId {
+                                        peer_id: self.peer_id.clone(),
+                                        message: FiberMessage::UpdateFee(UpdateFee {
+                                            channel_id: self.get_id(),
+                                            fee_rate,
+                                        }),
+                                    }),
+                                ))
+                                .expect(ASSUME_NETWORK_ACTOR_ALIVE);
+
+                            need_resend_commitment_signed = true;
+                        }
+                    }
                     if need_resend_commitment_signed {
                         debug!("Resend CommitmentSigned message");
                         network
@@ -3603,7 +4517,7 @@ impl ChannelActorState {
                     // Resetting our remote commitment number to the actual remote commitment number
                     // and resend the RevokeAndAck message.
                     self.set_remote_commitment_number(acutal_remote_commitment_number);
-                    self.send_revoke_and_ack_message(network);
+                    self.send_revoke_and_ack_message(network)?;
                 } else {
                     // unreachable state, just log an error for potential bugs
                     error!(
@@ -3930,7 +4844,10 @@ impl ChannelActorState {
     // and the second element is the message to be signed by the each party,
     // so as to consume the funding cell. The last element is the witnesses for the
     // commitment transaction.
-    pub fn build_commitment_tx(&self, local: bool) -> (TransactionView, [u8; 32], Vec<u8>) {
+    pub fn build_commitment_tx(
+        &self,
+        local: bool,
+    ) -> Result<(TransactionView, [u8; 32], Vec<u8>), ProcessingChannelError> {
         let version = self.get_current_commitment_number(local);
         debug!(
             "Building {} commitment transaction #{} with local commtiment number {} and remote commitment number {}",
@@ -3947,7 +4864,7 @@ impl ChannelActorState {
         );
 
         let (outputs, outputs_data, witnesses) =
-            self.build_commitment_transaction_parameters(local);
+            self.build_commitment_transaction_parameters(local)?;
         debug!(
             "Building {} commitment transaction #{}'s outputs: {:?}",
             if local { "local" } else { "remote" },
@@ -3963,14 +4880,14 @@ impl ChannelActorState {
             if local { "local" } else { "remote" }, version,
             &tx, hex::encode(message.as_slice()), hex::encode(&witnesses)
         );
-        (tx, message, witnesses)
+        Ok((tx, message, witnesses))
     }
 
     fn build_commitment_transaction_witnesses(
         &self,
         local: bool,
         commitment_number: u64,
-    ) -> (Vec<u8>, [u8; 20]) {
+    ) -> Result<(Vec<u8>, [u8; 20]), ProcessingChannelError> {
         debug!(
             "Building {} commitment transaction #{}'s witnesses",
             if local { "local" } else { "remote" },
@@ -3997,7 +4914,7 @@ impl ChannelActorState {
                 )
             } else {
                 (
-                    self.get_local_commitment_point(commitment_number),
+                    self.get_local_commitment_point(commitment_number)?,
                     self.get_local_channel_parameters().selected_contest_delay,
                     self.get_local_channel_parameters()
                         .delayed_payment_base_key(),
@@ -4036,7 +4953,7 @@ impl ChannelActorState {
             (Since::from(delayed_epoch).value()).to_le_bytes().to_vec(),
             delayed_payment_key_hash[..20].to_vec(),
             revocation_key_hash[..20].to_vec(),
-            self.get_witness_args_for_active_tlcs(local),
+            self.get_witness_args_for_active_tlcs(local)?,
         ]
         .concat();
         let hash = blake2b_256(&witnesses)[..20].try_into().unwrap();
@@ -4048,13 +4965,13 @@ impl ChannelActorState {
             hex::encode(&witnesses),
             hex::encode(&hash)
         );
-        (witnesses, hash)
+        Ok((witnesses, hash))
     }
 
     fn get_current_commitment_transaction_witnesses_with_hash(
         &self,
         local: bool,
-    ) -> (Vec<u8>, [u8; 20]) {
+    ) -> Result<(Vec<u8>, [u8; 20]), ProcessingChannelError> {
         let commitment_number = if local {
             self.get_local_commitment_number()
         } else {
@@ -4063,13 +4980,17 @@ impl ChannelActorState {
         self.build_commitment_transaction_witnesses(local, commitment_number)
     }
 
-    fn get_local_commitment_witnesses(&self, commitment_number: u64) -> Vec<u8> {
+    fn get_local_commitment_witnesses(
+        &self,
+        commitment_number: u64,
+    ) -> Result<Vec<u8>, ProcessingChannelError> {
         debug_assert!(commitment_number < self.get_local_commitment_number());
-        self.build_commitment_transaction_witnesses(true, commitment_number)
-            .0
+        Ok(self
+            .build_commitment_transaction_witnesses(true, commitment_number)?
+            .0)
     }
 
-    fn get_previous_local_commitment_witnesses(&self) -> Vec<u8> {
+    fn get_previous_local_commitment_witnesses(&self) -> Result<Vec<u8>, ProcessingChannelError> {
         self.get_local_commitment_witnesses(self.get_local_commitment_number() - 1)
     }
 
@@ -4079,14 +5000,11 @@ impl ChannelActorState {
     fn build_commitment_transaction_parameters(
         &self,
         local: bool,
-    ) -> (Vec<CellOutput>, Vec<Bytes>, Vec<u8>) {
-        #[cfg(debug_assertions)]
-        {
-            debug_assert_eq!(
-                self.total_amount,
-                self.to_local_amount + self.to_remote_amount
-            );
-        }
+    ) -> Result<(Vec<CellOutput>, Vec<Bytes>, Vec<u8>), ProcessingChannelError> {
+        debug_assert_eq!(
+            self.total_amount,
+            self.to_local_amount + self.to_remote_amount
+        );
 
         // The time_locked_value is amount of assets locked by commitment-lock.
         // Our value is always time-locked. Additionally, we need to add the value of
@@ -4127,7 +5045,7 @@ impl ChannelActorState {
                 (
                     // Note that we're building a remote commitment transaction, so we need to use
                     // the remote commitment number.
-                    self.get_local_commitment_point(self.get_remote_commitment_number()),
+                    self.get_local_commitment_point(self.get_remote_commitment_number())?,
                     self.get_local_channel_parameters().payment_base_key(),
                 )
             };
@@ -4135,7 +5053,7 @@ impl ChannelActorState {
         };
 
         let (witnesses, script_args) =
-            self.get_current_commitment_transaction_witnesses_with_hash(local);
+            self.get_current_commitment_transaction_witnesses_with_hash(local)?;
 
         let immediate_secp256k1_lock_script = get_script_by_contract(
             Contract::Secp256k1Lock,
@@ -4143,8 +5061,7 @@ impl ChannelActorState {
         );
         let commitment_lock_script = get_script_by_contract(Contract::CommitmentLock, &script_args);
 
-        let commitment_tx_fee =
-            calculate_commitment_tx_fee(self.commitment_fee_rate, &self.funding_udt_type_script);
+        let commitment_tx_fee = self.get_commitment_tx_fee(self.get_commitment_fee_rate());
         debug!("debug commitment_fee: {:?}", commitment_tx_fee);
 
         if let Some(udt_type_script) = &self.funding_udt_type_script {
@@ -4160,9 +5077,17 @@ impl ChannelActorState {
                 )
             };
 
-            // NOTE: we have already make sure the reserved_ckb_amount will enough for commitment tx fee
+            // The fee rate updates are checked against the reserved ckb amounts, but the peer
+            // may still have sent a fee rate which its reserved ckb amount can't pay.
             // commitment tx fee is paid by the side who want to submit the commitment transaction
-            time_locked_ckb_amount -= commitment_tx_fee;
+            time_locked_ckb_amount = time_locked_ckb_amount
+                .checked_sub(commitment_tx_fee)
+                .ok_or_else(|| {
+                    ProcessingChannelError::InvalidState(format!(
+                        "Reserved ckb amount {} is not enough to pay commitment tx fee {}",
+                        time_locked_ckb_amount, commitment_tx_fee
+                    ))
+                })?;
 
             let immediate_output_data = immediately_spendable_value.to_le_bytes().pack();
             let immediate_output = CellOutput::new_builder()
@@ -4180,12 +5105,19 @@ impl ChannelActorState {
 
             let outputs = vec![immediate_output, commitment_lock_output];
             let outputs_data = vec![immediate_output_data, commitment_lock_output_data];
-            (outputs, outputs_data, witnesses)
+            Ok((outputs, outputs_data, witnesses))
         } else {
             let commitment_tx_fee = commitment_tx_fee as u128;
 
             // commitment tx fee is paid by the side who want to submit the commitment transaction
-            time_locked_value -= commitment_tx_fee;
+            time_locked_value = time_locked_value
+                .checked_sub(commitment_tx_fee)
+                .ok_or_else(|| {
+                    ProcessingChannelError::InvalidState(format!(
+                        "Balance {} is not enough to pay commitment tx fee {}",
+                        time_locked_value, commitment_tx_fee
+                    ))
+                })?;
 
             let outputs = vec![
                 CellOutput::new_builder()
@@ -4198,7 +5130,7 @@ impl ChannelActorState {
                     .build(),
             ];
             let outputs_data = vec![Bytes::default(); outputs.len()];
-            (outputs, outputs_data, witnesses)
+            Ok((outputs, outputs_data, witnesses))
         }
     }
 
@@ -4206,9 +5138,9 @@ impl ChannelActorState {
         &self,
         signature: PartialSignature,
     ) -> Result<PartiallySignedCommitmentTransaction, ProcessingChannelError> {
-        let verify_ctx = Musig2VerifyContext::from(self);
+        let verify_ctx = Musig2VerifyContext::try_from(self)?;
 
-        let (tx, msg, witnesses) = self.build_commitment_tx(false);
+        let (tx, msg, witnesses) = self.build_commitment_tx(false)?;
         debug!(
             "Verifying partial signature ({:?}) of commitment tx ({:?}) message {:?}",
             &signature,
@@ -4228,9 +5160,9 @@ impl ChannelActorState {
     pub fn build_and_sign_commitment_tx(
         &self,
     ) -> Result<PartiallySignedCommitmentTransaction, ProcessingChannelError> {
-        let sign_ctx = Musig2SignContext::from(self);
+        let sign_ctx = Musig2SignContext::try_from(self)?;
 
-        let (tx, msg, witnesses) = self.build_commitment_tx(true);
+        let (tx, msg, witnesses) = self.build_commitment_tx(true)?;
 
         debug!(
             "Signing commitment tx with message {:?}",
@@ -4269,27 +5201,42 @@ impl ChannelActorState {
 }
 
 pub trait ChannelActorStateStore {
-    fn get_channel_actor_state(&self, id: &Hash256) -> Option<ChannelActorState>;
-    fn insert_channel_actor_state(&self, state: ChannelActorState);
-    fn delete_channel_actor_state(&self, id: &Hash256);
-    fn get_channel_ids_by_peer(&self, peer_id: &PeerId) -> Vec<Hash256>;
-    fn get_active_channel_ids_by_peer(&self, peer_id: &PeerId) -> Vec<Hash256> {
-        self.get_channel_ids_by_peer(peer_id)
-            .into_iter()
-            .filter(
-                |id| matches!(self.get_channel_actor_state(id), Some(state) if !state.is_closed()),
-            )
-            .collect()
+    fn get_channel_actor_state(
+        &self,
+        id: &Hash256,
+    ) -> Result<Option<ChannelActorState>, StoreError>;
+    fn insert_channel_actor_state(&self, state: ChannelActorState) -> Result<(), StoreError>;
+    fn delete_channel_actor_state(&self, id: &Hash256) -> Result<(), StoreError>;
+    fn get_channel_ids_by_peer(&self, peer_id: &PeerId) -> Result<Vec<Hash256>, StoreError>;
+    fn get_active_channel_ids_by_peer(&self, peer_id: &PeerId) -> Result<Vec<Hash256>, StoreError> {
+        let mut channel_ids = vec![];
+        for id in self.get_channel_ids_by_peer(peer_id)? {
+            if matches!(self.get_channel_actor_state(&id)?, Some(state) if !state.is_closed()) {
+                channel_ids.push(id);
+            }
+        }
+        Ok(channel_ids)
     }
-    fn get_channel_states(&self, peer_id: Option<PeerId>) -> Vec<(PeerId, Hash256, ChannelState)>;
+    fn get_channel_states(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<(PeerId, Hash256, ChannelState)>, StoreError>;
+    fn insert_closed_channel(&self, channel: ClosedChannel) -> Result<(), StoreError>;
+    fn get_closed_channels(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<ClosedChannel>, StoreError>;
+    fn insert_tlc_record(&self, record: TlcRecord) -> Result<(), StoreError>;
+    fn get_tlc_records(&self, channel_id: Option<Hash256>) -> Result<Vec<TlcRecord>, StoreError>;
     fn get_active_channel_states(
         &self,
         peer_id: Option<PeerId>,
-    ) -> Vec<(PeerId, Hash256, ChannelState)> {
-        self.get_channel_states(peer_id)
+    ) -> Result<Vec<(PeerId, Hash256, ChannelState)>, StoreError> {
+        Ok(self
+            .get_channel_states(peer_id)?
             .into_iter()
             .filter(|(_, _, state)| !state.is_closed())
-            .collect()
+            .collect())
     }
 }
 
@@ -4354,17 +5301,6 @@ pub struct Musig2VerifyContext {
     pub pubnonce: PubNonce,
 }
 
-impl From<Musig2SignContext> for Musig2VerifyContext {
-    fn from(value: Musig2SignContext) -> Self {
-        Musig2VerifyContext {
-            key_agg_ctx: value.key_agg_ctx,
-            agg_nonce: value.agg_nonce,
-            pubkey: value.seckey.pubkey(),
-            pubnonce: value.secnonce.public_nonce(),
-        }
-    }
-}
-
 impl Musig2VerifyContext {
     pub fn verify(&self, signature: PartialSignature, message: &[u8]) -> ProcessingChannelResult {
         let result = verify_partial(
@@ -4389,26 +5325,24 @@ impl Musig2VerifyContext {
 
 #[derive(Debug, Clone)]
 pub struct Musig2SignContext {
-    key_agg_ctx: KeyAggContext,
+    signer: ChannelSigner,
+    commitment_number: u64,
+    pubkeys: [Pubkey; 2],
     agg_nonce: AggNonce,
-    seckey: Privkey,
-    secnonce: SecNonce,
 }
 
 impl Musig2SignContext {
     pub fn sign(self, message: &[u8]) -> Result<PartialSignature, ProcessingChannelError> {
         debug!(
-            "Musig2 signing partial message {:?} with nonce {:?} (public nonce: {:?}), agg nonce {:?}",
+            "Musig2 signing partial message {:?} with nonce of commitment number {}, agg nonce {:?}",
             hex::encode(message),
-            self.secnonce,
-            self.secnonce.public_nonce(),
+            self.commitment_number,
             &self.agg_nonce
         );
-        Ok(sign_partial(
-            &self.key_agg_ctx,
-            self.seckey,
-            self.secnonce,
-            &self.agg_nonce,
+        Ok(self.signer.sign_musig2_partial(
+            self.commitment_number,
+            self.pubkeys,
+            self.agg_nonce,
             message,
         )?)
     }
@@ -4723,7 +5657,7 @@ impl InMemorySigner {
         }
     }
 
-    fn get_base_public_keys(&self) -> ChannelBasePublicKeys {
+    pub fn get_base_public_keys(&self) -> ChannelBasePublicKeys {
         ChannelBasePublicKeys {
             funding_pubkey: self.funding_key.pubkey(),
             revocation_base_key: self.revocation_base_key.pubkey(),
@@ -4778,6 +5712,225 @@ impl InMemorySigner {
         );
         SecNonce::build(seckey.as_ref()).build()
     }
+
+    pub fn sign_musig2_partial(
+        &self,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        message: &[u8],
+    ) -> Result<PartialSignature, SignerError> {
+        let key_agg_ctx = KeyAggContext::new(pubkeys)?;
+        let secnonce = self.derive_musig2_nonce(commitment_number);
+        Ok(sign_partial(
+            &key_agg_ctx,
+            self.funding_key,
+            secnonce,
+            &agg_nonce,
+            message,
+        )?)
+    }
+}
+
+// The nonce of a commitment number signs the commitment transactions of both parties, which
+// gives the peer two equations of the two secret nonces and the funding key. A third message
+// signed with the nonce would reveal the funding key.
+pub(crate) const MAX_MUSIG2_NONCE_USES: usize = 2;
+
+/// A message signed with the musig2 nonce of a commitment number, see
+/// [`ChannelSigner::sign_musig2_partial`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Musig2NonceUse {
+    commitment_number: u64,
+    // The hash of the aggregated nonce and the message.
+    digest: [u8; 32],
+}
+
+/// The keys of a channel, kept by the [`Signer`] of the node. Only the key id is saved with
+/// the channel state, the signer is attached again when the state is loaded from the store.
+#[derive(Clone, Debug)]
+pub struct ChannelSigner {
+    key_id: Hash256,
+    // Channels created before the signer was introduced keep their keys in the state.
+    legacy: Option<InMemorySigner>,
+    signer: Option<Arc<dyn Signer>>,
+    // The messages signed with the nonces of the latest commitment number, saved with the
+    // state so that a nonce is never used for more messages than the protocol requires.
+    nonce_uses: Arc<Mutex<Vec<Musig2NonceUse>>>,
+}
+
+// The JSON layout of the channel states saved before the signer was introduced, which have
+// the keys of `InMemorySigner` inlined. Binary formats can't flatten fields, so the keys are
+// nested there, see `StoredChannelSigner`.
+#[derive(Serialize, Deserialize)]
+struct JsonChannelSigner {
+    #[serde(default)]
+    key_id: Hash256,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    legacy: Option<InMemorySigner>,
+    #[serde(default)]
+    nonce_uses: Vec<Musig2NonceUse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChannelSigner {
+    key_id: Hash256,
+    legacy: Option<InMemorySigner>,
+    #[serde(default)]
+    nonce_uses: Vec<Musig2NonceUse>,
+}
+
+impl Serialize for ChannelSigner {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (key_id, legacy) = (self.key_id, self.legacy.clone());
+        let nonce_uses = self.nonce_uses.lock().expect("lock nonce uses").clone();
+        if serializer.is_human_readable() {
+            JsonChannelSigner {
+                key_id,
+                legacy,
+                nonce_uses,
+            }
+            .serialize(serializer)
+        } else {
+            StoredChannelSigner {
+                key_id,
+                legacy,
+                nonce_uses,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChannelSigner {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (key_id, legacy, nonce_uses) = if deserializer.is_human_readable() {
+            let JsonChannelSigner {
+                key_id,
+                legacy,
+                nonce_uses,
+            } = JsonChannelSigner::deserialize(deserializer)?;
+            (key_id, legacy, nonce_uses)
+        } else {
+            let StoredChannelSigner {
+                key_id,
+                legacy,
+                nonce_uses,
+            } = StoredChannelSigner::deserialize(deserializer)?;
+            (key_id, legacy, nonce_uses)
+        };
+        Ok(Self {
+            key_id,
+            legacy,
+            signer: None,
+            nonce_uses: Arc::new(Mutex::new(nonce_uses)),
+        })
+    }
+}
+
+impl ChannelSigner {
+    pub fn new(key_id: Hash256, signer: Arc<dyn Signer>) -> Self {
+        Self {
+            key_id,
+            legacy: None,
+            signer: Some(signer),
+            nonce_uses: Default::default(),
+        }
+    }
+
+    pub fn attach(&mut self, signer: Arc<dyn Signer>) {
+        self.signer = Some(signer);
+    }
+
+    /// The key id of the channel keys in the node signer, `None` if the keys are kept in the
+    /// channel state, such channels can't be restored from a static channel backup.
+    pub fn key_id(&self) -> Option<Hash256> {
+        self.legacy.is_none().then_some(self.key_id)
+    }
+
+    fn signer(&self) -> &dyn Signer {
+        self.signer.as_deref().expect(ASSUME_SIGNER_ATTACHED)
+    }
+
+    pub fn get_base_public_keys(&self) -> Result<ChannelBasePublicKeys, SignerError> {
+        match &self.legacy {
+            Some(keys) => Ok(keys.get_base_public_keys()),
+            None => self.signer().channel_base_public_keys(self.key_id),
+        }
+    }
+
+    pub fn get_commitment_point(&self, commitment_number: u64) -> Result<Pubkey, SignerError> {
+        match &self.legacy {
+            Some(keys) => Ok(keys.get_commitment_point(commitment_number)),
+            None => self
+                .signer()
+                .commitment_point(self.key_id, commitment_number),
+        }
+    }
+
+    pub fn get_commitment_secret(&self, commitment_number: u64) -> Result<[u8; 32], SignerError> {
+        match &self.legacy {
+            Some(keys) => Ok(keys.get_commitment_secret(commitment_number)),
+            None => self
+                .signer()
+                .commitment_secret(self.key_id, commitment_number),
+        }
+    }
+
+    pub fn get_musig2_pubnonce(&self, commitment_number: u64) -> Result<PubNonce, SignerError> {
+        match &self.legacy {
+            Some(keys) => Ok(keys.derive_musig2_nonce(commitment_number).public_nonce()),
+            None => self
+                .signer()
+                .musig2_pubnonce(self.key_id, commitment_number),
+        }
+    }
+
+    /// Sign the message with the nonce of the commitment number. Signing the same message
+    /// again returns the same signature, but a nonce never signs more than
+    /// [`MAX_MUSIG2_NONCE_USES`] different messages.
+    pub fn sign_musig2_partial(
+        &self,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        message: &[u8],
+    ) -> Result<PartialSignature, SignerError> {
+        let digest = blake2b_256([agg_nonce.serialize().as_slice(), message].concat());
+        let mut nonce_uses = self.nonce_uses.lock().expect("lock nonce uses");
+        // The nonces of the previous commitment numbers are never used again.
+        nonce_uses.retain(|nonce_use| nonce_use.commitment_number >= commitment_number);
+        let used = nonce_uses
+            .iter()
+            .filter(|nonce_use| nonce_use.commitment_number == commitment_number)
+            .map(|nonce_use| nonce_use.digest)
+            .collect::<Vec<_>>();
+        if !used.contains(&digest) {
+            if used.len() >= MAX_MUSIG2_NONCE_USES {
+                return Err(SignerError::Refused(format!(
+                    "the nonce of commitment number {} has already signed {} messages",
+                    commitment_number,
+                    used.len()
+                )));
+            }
+            nonce_uses.push(Musig2NonceUse {
+                commitment_number,
+                digest,
+            });
+        }
+        drop(nonce_uses);
+
+        match &self.legacy {
+            Some(keys) => keys.sign_musig2_partial(commitment_number, pubkeys, agg_nonce, message),
+            None => self.signer().musig2_partial_sign(
+                self.key_id,
+                commitment_number,
+                pubkeys,
+                agg_nonce,
+                message,
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -4787,25 +5940,35 @@ mod tests {
         fiber::{
             channel::{
                 derive_revocation_pubkey, AddTlcCommand, ChannelCommand, ChannelCommandWithId,
-                RemoveTlcCommand, ShutdownCommand, DEFAULT_COMMITMENT_FEE_RATE,
+                RemoveTlcCommand, ShutdownCommand, UpdateCommitmentFeeCommand,
+                DEFAULT_COMMITMENT_FEE_RATE,
             },
+            config::{CKB_SHANNONS, DEFAULT_CHANNEL_MINIMAL_CKB_AMOUNT},
+            fee::commitment_tx_size,
             hash_algorithm::HashAlgorithm,
             network::{AcceptChannelCommand, OpenChannelCommand},
             test_utils::NetworkNode,
             types::{Hash256, LockTime, RemoveTlcFulfill, RemoveTlcReason},
-            NetworkActorCommand, NetworkActorMessage,
+            NetworkActorCommand, NetworkActorEvent, NetworkActorMessage,
         },
         NetworkServiceEvent,
     };
 
-    use super::{super::types::Privkey, derive_private_key, derive_tlc_pubkey, InMemorySigner};
+    use super::{
+        super::types::Privkey, derive_private_key, derive_tlc_pubkey, ChannelActorState,
+        ChannelActorStateStore, ChannelCloseType, ChannelSigner, InMemorySigner,
+        ProcessingChannelError, TlcDirection, TlcStatus, UpdateFee,
+    };
+    use crate::signer::{LocalSigner, SignerError};
     use ckb_jsonrpc_types::Status;
     use ckb_types::{
         core::FeeRate,
-        packed::{Bytes, CellInput, CellOutput, Script, Transaction},
+        packed::{Bytes, CellInput, CellOutput, OutPoint, Script, Transaction},
         prelude::{AsTransactionBuilder, Builder, Entity, Pack, PackVec},
     };
+    use musig2::{AggNonce, SecNonce};
     use ractor::call;
+    use std::sync::Arc;
     use tracing::debug;
 
     #[test]
@@ -4818,12 +5981,45 @@ mod tests {
     }
 
     #[test]
-    fn test_derive_private_and_public_tlc_keys() {
-        let privkey = Privkey::from(&[1; 32]);
-        let per_commitment_point = Privkey::from(&[2; 32]).pubkey();
-        let derived_privkey = derive_private_key(&privkey, &per_commitment_point);
-        let derived_pubkey = derive_tlc_pubkey(&privkey.pubkey(), &per_commitment_point);
-        assert_eq!(derived_privkey.pubkey(), derived_pubkey);
+    fn test_musig2_nonce_signs_limited_messages() {
+        let signer = ChannelSigner::new(
+            Hash256::from([1u8; 32]),
+            Arc::new(LocalSigner::new(
+                Privkey::from(&[2u8; 32]).into(),
+                [3u8; 32],
+            )),
+        );
+        let local_pubkey = signer.get_base_public_keys().unwrap().funding_pubkey;
+        let pubkeys = [local_pubkey, Privkey::from(&[4u8; 32]).pubkey()];
+        let remote_nonce = SecNonce::build([5u8; 32]).build().public_nonce();
+        let agg_nonce = AggNonce::sum([signer.get_musig2_pubnonce(5).unwrap(), remote_nonce]);
+        let sign = |commitment_number, message: &[u8]| {
+            signer.sign_musig2_partial(commitment_number, pubkeys, agg_nonce.clone(), message)
+        };
+
+        let signature = sign(5, b"remote commitment").unwrap();
+        assert_eq!(sign(5, b"remote commitment").unwrap(), signature);
+        assert!(sign(5, b"local commitment").is_ok());
+        assert!(matches!(
+            sign(5, b"another message"),
+            Err(SignerError::Refused(_))
+        ));
+        // The uses survive a restart.
+        let signer: ChannelSigner =
+            serde_json::from_str(&serde_json::to_string(&signer).unwrap()).unwrap();
+        assert!(matches!(
+            signer.sign_musig2_partial(5, pubkeys, agg_nonce.clone(), b"another message"),
+            Err(SignerError::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn test_derive_private_and_public_tlc_keys() {
+        let privkey = Privkey::from(&[1; 32]);
+        let per_commitment_point = Privkey::from(&[2; 32]).pubkey();
+        let derived_privkey = derive_private_key(&privkey, &per_commitment_point);
+        let derived_pubkey = derive_tlc_pubkey(&privkey.pubkey(), &per_commitment_point);
+        assert_eq!(derived_privkey.pubkey(), derived_pubkey);
     }
 
     #[test]
@@ -4847,7 +6043,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: 100000000000,
@@ -4856,6 +6052,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -4886,7 +6083,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: 100000000000,
@@ -4895,6 +6092,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -4915,10 +6113,11 @@ mod tests {
             .await;
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: DEFAULT_CHANNEL_MINIMAL_CKB_AMOUNT as u128,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -4939,7 +6138,7 @@ mod tests {
         let node_b_funidng_amount = 6200000000;
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: node_a_funding_amount,
@@ -4948,6 +6147,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -4967,10 +6167,11 @@ mod tests {
             })
             .await;
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: node_b_funidng_amount,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5015,7 +6216,7 @@ mod tests {
         let tlc_amount = 1000000000;
 
         let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::AddTlc(
@@ -5055,7 +6256,7 @@ mod tests {
             .await;
 
         let remove_tlc_result = call!(node_b.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::RemoveTlc(
@@ -5124,7 +6325,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: node_a_funding_amount,
@@ -5133,6 +6334,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5152,10 +6354,11 @@ mod tests {
             })
             .await;
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: node_b_funding_amount,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5213,7 +6416,7 @@ mod tests {
         let tlc_amount = 1000000000;
 
         let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::AddTlc(
@@ -5238,7 +6441,7 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
         call!(node_b.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::RemoveTlc(
@@ -5257,7 +6460,7 @@ mod tests {
         .expect("successfully removed tlc");
 
         let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::AddTlc(
@@ -5282,7 +6485,7 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
         let remove_tlc_result = call!(node_b.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::RemoveTlc(
@@ -5303,6 +6506,470 @@ mod tests {
         assert!(remove_tlc_result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_tlc_records_saved_after_removal_committed() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 6200000000;
+
+        let (node_a, node_b, new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        let preimage = [2; 32];
+        let digest = HashAlgorithm::CkbHash.hash(preimage);
+        let tlc_amount = 1000000000;
+        let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::AddTlc(
+                        AddTlcCommand {
+                            amount: tlc_amount,
+                            hash_algorithm: HashAlgorithm::CkbHash,
+                            payment_hash: Some(digest.into()),
+                            expiry: LockTime::new(100),
+                            preimage: None,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive")
+        .expect("successfully added tlc");
+
+        // Wait for the AddTlc to be committed by both parties.
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        call!(node_b.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::RemoveTlc(
+                        RemoveTlcCommand {
+                            id: add_tlc_result.tlc_id,
+                            reason: RemoveTlcReason::RemoveTlcFulfill(RemoveTlcFulfill {
+                                payment_preimage: preimage.into(),
+                            }),
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_b alive")
+        .expect("successfully removed tlc");
+        // The removal is not committed until the commitment of the batch is revoked.
+        assert_eq!(node_b.store.get_tlc_records(None).unwrap(), vec![]);
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        for (node, direction) in [
+            (&node_a, TlcDirection::Outgoing),
+            (&node_b, TlcDirection::Incoming),
+        ] {
+            let records = node.store.get_tlc_records(Some(new_channel_id)).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].tlc_id, add_tlc_result.tlc_id);
+            assert_eq!(records[0].direction, direction);
+            assert_eq!(records[0].status, TlcStatus::Settled);
+            assert_eq!(records[0].payment_hash, Hash256::from(digest));
+            assert_eq!(records[0].amount, tlc_amount);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_tlc_signs_commitment_automatically() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 6200000000;
+
+        let (node_a, mut node_b, new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::AddTlc(
+                        AddTlcCommand {
+                            amount: 1000000000,
+                            hash_algorithm: HashAlgorithm::CkbHash,
+                            payment_hash: None,
+                            expiry: LockTime::new(100),
+                            preimage: None,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive")
+        .expect("successfully added tlc");
+
+        // No commitment_signed command is sent, the pending update should be signed
+        // once the batch timer expires.
+        node_b
+            .expect_event(|event| match event {
+                NetworkServiceEvent::RemoteCommitmentSigned(peer_id, channel_id, _, _) => {
+                    assert_eq!(peer_id, &node_a.peer_id);
+                    assert_eq!(channel_id, &new_channel_id);
+                    true
+                }
+                _ => false,
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_list_peers_with_channel() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 6200000000;
+
+        let (node_a, node_b, _new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        // node_a connects to node_b.
+        for (node, peer, is_inbound) in [(&node_a, &node_b, false), (&node_b, &node_a, true)] {
+            let peers = call!(node.network_actor, |rpc_reply| {
+                NetworkActorMessage::new_command(NetworkActorCommand::ListPeers(rpc_reply))
+            })
+            .expect("node alive")
+            .expect("list peers");
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].peer_id, peer.peer_id);
+            assert_eq!(peers[0].is_inbound, is_inbound);
+            assert_eq!(peers[0].channel_count, 1);
+            if !is_inbound {
+                assert!(peers[0]
+                    .address
+                    .to_string()
+                    .starts_with(&peer.listening_addr.to_string()));
+            }
+
+            let node_info = call!(node.network_actor, |rpc_reply| {
+                NetworkActorMessage::new_command(NetworkActorCommand::NodeInfo(rpc_reply))
+            })
+            .expect("node alive")
+            .expect("get node info");
+            assert_eq!(node_info.peer_id, node.peer_id);
+            assert_eq!(node_info.peer_count, 1);
+            assert_eq!(node_info.channel_count, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_funding_transaction_orphaned() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 6200000000;
+
+        let (node_a, _node_b, new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        // The network actor only uses the outpoint to find the channel again.
+        let outpoint = OutPoint::default();
+        node_a
+            .network_actor
+            .send_message(NetworkActorMessage::new_event(
+                NetworkActorEvent::FundingTransactionOrphaned(outpoint.clone(), new_channel_id),
+            ))
+            .expect("node_a alive");
+        // The channel is not usable until the funding transaction is confirmed again.
+        let result = call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::AddTlc(
+                        AddTlcCommand {
+                            amount: 1000000000,
+                            hash_algorithm: HashAlgorithm::CkbHash,
+                            payment_hash: None,
+                            expiry: LockTime::new(100),
+                            preimage: None,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        });
+        assert!(result.expect("node_a alive").is_err());
+
+        node_a
+            .network_actor
+            .send_message(NetworkActorMessage::new_event(
+                NetworkActorEvent::FundingTransactionConfirmed(outpoint),
+            ))
+            .expect("node_a alive");
+        call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::AddTlc(
+                        AddTlcCommand {
+                            amount: 1000000000,
+                            hash_algorithm: HashAlgorithm::CkbHash,
+                            payment_hash: None,
+                            expiry: LockTime::new(100),
+                            preimage: None,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive")
+        .expect("successfully added tlc");
+    }
+
+    #[test]
+    fn test_pending_fee_rate_committed_with_its_commitment() {
+        let signer = LocalSigner::new(Privkey::from(&[2u8; 32]).into(), [3u8; 32]);
+        let mut state = ChannelActorState::new_outbound_channel(
+            ChannelSigner::new(Hash256::from([1u8; 32]), Arc::new(signer)),
+            tentacle::secio::PeerId::random(),
+            1000,
+            100,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            None,
+            u128::MAX,
+            10,
+            LockTime::new(100),
+        )
+        .unwrap();
+        let fee_rate = DEFAULT_COMMITMENT_FEE_RATE * 2;
+        state.pending_commitment_fee_rate = Some(fee_rate);
+
+        // The remote commitment being revoked was signed before the fee update.
+        state.update_state_on_raa_msg(false);
+        assert_eq!(state.commitment_fee_rate, DEFAULT_COMMITMENT_FEE_RATE);
+
+        state.record_pending_fee_rate_commitment(true);
+        state.update_state_on_raa_msg(false);
+        assert_eq!(state.pending_commitment_fee_rate, Some(fee_rate));
+
+        state.update_state_on_raa_msg(true);
+        assert_eq!(state.commitment_fee_rate, fee_rate);
+        assert_eq!(state.pending_commitment_fee_rate, None);
+        assert_eq!(
+            state.pending_commitment_fee_rate_commitments,
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn test_update_fee_only_from_opener() {
+        let signer = LocalSigner::new(Privkey::from(&[2u8; 32]).into(), [3u8; 32]);
+        let mut state = ChannelActorState::new_outbound_channel(
+            ChannelSigner::new(Hash256::from([1u8; 32]), Arc::new(signer)),
+            tentacle::secio::PeerId::random(),
+            1000,
+            100,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            None,
+            u128::MAX,
+            10,
+            LockTime::new(100),
+        )
+        .unwrap();
+        let update_fee = UpdateFee {
+            channel_id: state.get_id(),
+            fee_rate: DEFAULT_COMMITMENT_FEE_RATE * 2,
+        };
+        assert!(matches!(
+            state.handle_update_fee_message(update_fee),
+            Err(ProcessingChannelError::InvalidParameter(_))
+        ));
+
+        // The opener resends the pending fee rate on reestablishing the channel.
+        state.is_acceptor = true;
+        state.pending_commitment_fee_rate = Some(update_fee.fee_rate);
+        assert!(matches!(
+            state.handle_update_fee_message(update_fee),
+            Err(ProcessingChannelError::RepeatedProcessing(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_close_type() {
+        let signer = LocalSigner::new(Privkey::from(&[2u8; 32]).into(), [3u8; 32]);
+        let mut state = ChannelActorState::new_outbound_channel(
+            ChannelSigner::new(Hash256::from([1u8; 32]), Arc::new(signer)),
+            tentacle::secio::PeerId::random(),
+            1000,
+            100,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            DEFAULT_COMMITMENT_FEE_RATE,
+            None,
+            u128::MAX,
+            10,
+            LockTime::new(100),
+        )
+        .unwrap();
+        state.commitment_numbers.local = 5;
+        let close_tx = |version: u64, output_capacity: u64| {
+            let mut witness = vec![16, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0];
+            witness.extend_from_slice(&version.to_le_bytes());
+            Transaction::default()
+                .as_advanced_builder()
+                .output(
+                    CellOutput::new_builder()
+                        .capacity(output_capacity.pack())
+                        .build(),
+                )
+                .output_data(Bytes::default())
+                .witness(witness.pack())
+                .build()
+        };
+
+        assert_eq!(
+            state.get_close_type(&close_tx(u64::MAX, 0)),
+            ChannelCloseType::Cooperative
+        );
+        let local_commitment_tx = close_tx(3, 0);
+        state.latest_commitment_transaction = Some(local_commitment_tx.data());
+        assert_eq!(
+            state.get_close_type(&local_commitment_tx),
+            ChannelCloseType::LocalForce
+        );
+        // The peer may broadcast either of the last two commitment transactions we signed.
+        assert_eq!(
+            state.get_close_type(&close_tx(4, 1)),
+            ChannelCloseType::RemoteForce
+        );
+        assert_eq!(
+            state.get_close_type(&close_tx(5, 1)),
+            ChannelCloseType::RemoteForce
+        );
+        assert_eq!(
+            state.get_close_type(&close_tx(3, 1)),
+            ChannelCloseType::Revoked
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_commitment_fee() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 6200000000;
+
+        let (mut node_a, mut node_b, new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::UpdateCommitmentFee(
+                        UpdateCommitmentFeeCommand {
+                            fee_rate: DEFAULT_COMMITMENT_FEE_RATE * 2,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive")
+        .expect("successfully updated commitment fee");
+
+        node_b
+            .expect_event(|event| match event {
+                NetworkServiceEvent::RemoteCommitmentSigned(peer_id, channel_id, _, _) => {
+                    assert_eq!(peer_id, &node_a.peer_id);
+                    assert_eq!(channel_id, &new_channel_id);
+                    true
+                }
+                _ => false,
+            })
+            .await;
+
+        node_a
+            .expect_event(|event| match event {
+                NetworkServiceEvent::RevokeAndAckReceived(peer_id, channel_id, ..) => {
+                    assert_eq!(peer_id, &node_b.peer_id);
+                    assert_eq!(channel_id, &new_channel_id);
+                    true
+                }
+                _ => false,
+            })
+            .await;
+
+        // The reserved ckb amount is not enough to pay the commitment fee with this rate.
+        let update_fee_result = call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::UpdateCommitmentFee(
+                        UpdateCommitmentFeeCommand {
+                            fee_rate: 100_000_000_000,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive");
+        assert!(update_fee_result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_tlc_reserves_commitment_fee() {
+        let node_a_funding_amount = 100000000000;
+        let node_b_funding_amount = 100000000000;
+
+        let (mut node_a, _node_b, new_channel_id) =
+            create_nodes_with_established_channel(node_a_funding_amount, node_b_funding_amount)
+                .await;
+
+        // A fee rate with which the commitment fee is 10 CKB, which is much more than
+        // the reserved ckb amount can pay.
+        let fee_rate = 10 * CKB_SHANNONS * 1000 / commitment_tx_size(&None, None) as u64;
+        call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::UpdateCommitmentFee(
+                        UpdateCommitmentFeeCommand { fee_rate },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive")
+        .expect("successfully updated commitment fee");
+
+        node_a
+            .expect_event(|event| {
+                matches!(event, NetworkServiceEvent::RevokeAndAckReceived(_, channel_id, ..) if channel_id == &new_channel_id)
+            })
+            .await;
+
+        // All the local balance is offered, nothing is left to pay the commitment fee.
+        let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: new_channel_id,
+                    command: ChannelCommand::AddTlc(
+                        AddTlcCommand {
+                            amount: node_a_funding_amount
+                                - DEFAULT_CHANNEL_MINIMAL_CKB_AMOUNT as u128,
+                            hash_algorithm: HashAlgorithm::CkbHash,
+                            payment_hash: None,
+                            expiry: LockTime::new(100),
+                            preimage: None,
+                        },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        })
+        .expect("node_a alive");
+        assert!(add_tlc_result.is_err());
+    }
+
     #[tokio::test]
     async fn test_remove_tlc_with_wrong_hash_algorithm() {
         let supported_algorithms = HashAlgorithm::supported_algorithms();
@@ -5329,7 +6996,7 @@ mod tests {
         let tlc_amount = 1000000000;
 
         let add_tlc_result = call!(node_a.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::AddTlc(
@@ -5354,7 +7021,7 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
         call!(node_b.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::RemoveTlc(
@@ -5374,7 +7041,7 @@ mod tests {
 
         let fee_rate = FeeRate::from_u64(DEFAULT_COMMITMENT_FEE_RATE);
         call!(node_b.network_actor, |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::Shutdown(
@@ -5452,7 +7119,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: 100000000000,
@@ -5461,6 +7128,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5480,10 +7148,11 @@ mod tests {
             })
             .await;
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: 6200000000,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5540,7 +7209,7 @@ mod tests {
 
         let _ = node_a
             .network_actor
-            .send_message(NetworkActorMessage::Command(
+            .send_message(NetworkActorMessage::new_command(
                 NetworkActorCommand::ControlFiberChannel(ChannelCommandWithId {
                     channel_id: new_channel_id,
                     command: ChannelCommand::CommitmentSigned(),
@@ -5630,7 +7299,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: 100000000000,
@@ -5639,6 +7308,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5658,10 +7328,11 @@ mod tests {
             })
             .await;
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: 6200000000,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5750,7 +7421,7 @@ mod tests {
             .unwrap();
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: node_b.peer_id.clone(),
                     funding_amount: 100000000000,
@@ -5759,6 +7430,7 @@ mod tests {
                     funding_fee_rate: None,
                     max_num_of_accept_tlcs: None,
                     max_tlc_value_in_flight: None,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
@@ -5779,10 +7451,11 @@ mod tests {
             .await;
 
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: open_channel_result.channel_id,
                     funding_amount: 6200000000,
+                    tx_confirmations: None,
                 },
                 rpc_reply,
             ))
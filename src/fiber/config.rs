@@ -1,12 +1,15 @@
-use crate::Result;
+use crate::{
+    mnemonic::{self, NodeSeed},
+    Result,
+};
 use ckb_sdk::NetworkType;
 use clap::ValueEnum;
 use clap_serde_derive::{
     clap::{self},
     ClapSerde,
 };
-use serde::Deserialize;
-use std::{fs, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, time::Duration};
 
 pub const CKB_SHANNONS: u64 = 100_000_000; // 1 CKB = 10 ^ 8 shannons
 pub const DEFAULT_MIN_INBOUND_LIQUIDITY: u64 = 100 * CKB_SHANNONS; // 100 CKB for minimal inbound liquidity
@@ -30,6 +33,21 @@ pub const DEFAULT_UDT_MINIMAL_CKB_AMOUNT: u64 =
 pub const DEFAULT_CHANNEL_MIN_AUTO_CKB_AMOUNT: u64 =
     DEFAULT_MIN_INBOUND_LIQUIDITY + MIN_OCCUPIED_CAPACITY + DEFAULT_MIN_SHUTDOWN_FEE;
 
+/// Send a ping to every connected peer every 30 seconds by default.
+pub const DEFAULT_PING_INTERVAL_SECONDS: u64 = 30;
+
+/// Disconnect a peer if it hasn't answered our ping within 60 seconds by default.
+pub const DEFAULT_PING_TIMEOUT_SECONDS: u64 = 60;
+
+/// Sign pending channel updates at most 100 milliseconds after the first update by default.
+pub const DEFAULT_COMMITMENT_BATCH_INTERVAL_MILLIS: u64 = 100;
+
+/// Sign pending channel updates right away once there are 10 of them by default.
+pub const DEFAULT_COMMITMENT_BATCH_MAX_UPDATES: usize = 10;
+
+/// Consider funding and closing transactions confirmed once they are 4 blocks deep by default.
+pub const DEFAULT_TX_CONFIRMATIONS: u64 = 4;
+
 // See comment in `LdkConfig` for why do we need to specify both name and long,
 // and prefix them with `ckb-`/`CKB_`.
 #[derive(ClapSerde, Debug, Clone)]
@@ -83,6 +101,87 @@ pub struct FiberConfig {
         help = "whether to accept open channel requests with ckb funding amount automatically, unit: shannons [default: 6200000000 shannons], if this is set to zero, it means to disable auto accept"
     )]
     pub auto_accept_channel_ckb_funding_amount: Option<u64>,
+
+    /// interval between two pings sent to a connected peer, unit: seconds [default: 30]
+    #[arg(
+        name = "FIBER_PING_INTERVAL_SECONDS",
+        long = "fiber-ping-interval-seconds",
+        env,
+        help = "interval between two pings sent to a connected peer, unit: seconds [default: 30]"
+    )]
+    pub ping_interval_seconds: Option<u64>,
+
+    /// a peer is disconnected if it doesn't reply to our ping within this time, unit: seconds [default: 60]
+    #[arg(
+        name = "FIBER_PING_TIMEOUT_SECONDS",
+        long = "fiber-ping-timeout-seconds",
+        env,
+        help = "a peer is disconnected if it doesn't reply to our ping within this time, unit: seconds [default: 60]"
+    )]
+    pub ping_timeout_seconds: Option<u64>,
+
+    /// pending channel updates are signed at most this time after the first update, unit: milliseconds [default: 100]
+    #[arg(
+        name = "FIBER_COMMITMENT_BATCH_INTERVAL_MILLIS",
+        long = "fiber-commitment-batch-interval-millis",
+        env,
+        help = "pending channel updates are signed at most this time after the first update, unit: milliseconds [default: 100]"
+    )]
+    pub commitment_batch_interval_millis: Option<u64>,
+
+    /// pending channel updates are signed right away once there are this many of them [default: 10]
+    #[arg(
+        name = "FIBER_COMMITMENT_BATCH_MAX_UPDATES",
+        long = "fiber-commitment-batch-max-updates",
+        env,
+        help = "pending channel updates are signed right away once there are this many of them [default: 10]"
+    )]
+    pub commitment_batch_max_updates: Option<usize>,
+
+    /// funding and closing transactions of channels are considered confirmed once they are this many blocks deep, unless the channel is opened or accepted with its own confirmations [default: 4]
+    #[arg(
+        name = "FIBER_TX_CONFIRMATIONS",
+        long = "fiber-tx-confirmations",
+        env,
+        help = "funding and closing transactions of channels are considered confirmed once they are this many blocks deep, unless the channel is opened or accepted with its own confirmations [default: 4]"
+    )]
+    pub tx_confirmations: Option<u64>,
+
+    /// path of the unix socket of an external signer, the funding key and the channel keys are kept in memory if not set
+    #[arg(
+        name = "FIBER_SIGNER_SOCKET",
+        long = "fiber-signer-socket",
+        env,
+        help = "path of the unix socket of an external signer, the funding key and the channel keys are kept in memory if not set"
+    )]
+    pub signer_socket: Option<PathBuf>,
+
+    /// path of a static channel backup to recover from, the peers of the channels missing from the store are asked to force close them and our outputs are swept
+    #[arg(
+        name = "FIBER_RECOVER_CHANNEL_BACKUP",
+        long = "fiber-recover-channel-backup",
+        env,
+        help = "path of a static channel backup to recover from, the peers of the channels missing from the store are asked to force close them and our outputs are swept"
+    )]
+    pub recover_channel_backup: Option<PathBuf>,
+
+    /// path of a store checkpoint to restore at startup, the current store is kept as a backup next to it and the checkpoint is renamed to `<path>.restored`, so it is only restored once. The restore is refused if a channel in the current store is ahead of the checkpoint
+    #[arg(
+        name = "FIBER_RESTORE_STORE_CHECKPOINT",
+        long = "fiber-restore-store-checkpoint",
+        env,
+        help = "path of a store checkpoint to restore at startup, the current store is kept as a backup next to it and the checkpoint is renamed to `<path>.restored`, so it is only restored once. The restore is refused if a channel in the current store is ahead of the checkpoint"
+    )]
+    pub restore_store_checkpoint: Option<PathBuf>,
+
+    /// address to serve the prometheus metrics on `/metrics`, the metrics are not served if not set
+    #[arg(
+        name = "FIBER_METRICS_LISTENING_ADDR",
+        long = "fiber-metrics-listening-addr",
+        env,
+        help = "address to serve the prometheus metrics on `/metrics`, the metrics are not served if not set"
+    )]
+    pub metrics_listening_addr: Option<String>,
 }
 
 impl FiberConfig {
@@ -98,9 +197,28 @@ impl FiberConfig {
         }
     }
 
+    /// The node key is derived from the mnemonic, unless the node was created with a
+    /// standalone key file `sk` before the mnemonic was introduced.
     pub fn read_or_generate_secret_key(&self) -> Result<super::KeyPair> {
         self.create_base_dir()?;
-        super::key::KeyPair::read_or_generate(&self.base_dir().join("sk")).map_err(Into::into)
+        match self.read_or_generate_node_seed()? {
+            Some(seed) => super::KeyPair::try_from(seed.node_key().as_slice()).map_err(Into::into),
+            None => super::key::KeyPair::read_or_generate(&self.base_dir().join("sk"))
+                .map_err(Into::into),
+        }
+    }
+
+    /// Read the mnemonic of the node, a new one is generated for new nodes. Returns `None` for
+    /// the nodes which only have the legacy key file `sk`.
+    pub fn read_or_generate_node_seed(&self) -> Result<Option<NodeSeed>> {
+        self.create_base_dir()?;
+        let generate = !self.base_dir().join("sk").exists();
+        mnemonic::read_or_generate(&self.base_dir().join("mnemonic"), generate).map_err(Into::into)
+    }
+
+    pub fn channel_backup_path(&self) -> PathBuf {
+        self.base_dir()
+            .join(super::backup::CHANNEL_BACKUP_FILE_NAME)
     }
 
     pub fn store_path(&self) -> PathBuf {
@@ -120,11 +238,52 @@ impl FiberConfig {
         self.auto_accept_channel_ckb_funding_amount
             .unwrap_or(DEFAULT_CHANNEL_MINIMAL_CKB_AMOUNT)
     }
+
+    /// Check the values which can't be used, e.g. a zero interval makes the timers panic.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.ping_interval_seconds == Some(0) {
+            return Err("ping_interval_seconds must be greater than 0".to_string());
+        }
+        if self.ping_timeout_seconds == Some(0) {
+            return Err("ping_timeout_seconds must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs(
+            self.ping_interval_seconds
+                .unwrap_or(DEFAULT_PING_INTERVAL_SECONDS),
+        )
+    }
+
+    pub fn ping_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.ping_timeout_seconds
+                .unwrap_or(DEFAULT_PING_TIMEOUT_SECONDS),
+        )
+    }
+
+    pub fn commitment_batch_interval(&self) -> Duration {
+        Duration::from_millis(
+            self.commitment_batch_interval_millis
+                .unwrap_or(DEFAULT_COMMITMENT_BATCH_INTERVAL_MILLIS),
+        )
+    }
+
+    pub fn commitment_batch_max_updates(&self) -> usize {
+        self.commitment_batch_max_updates
+            .unwrap_or(DEFAULT_COMMITMENT_BATCH_MAX_UPDATES)
+    }
+
+    pub fn tx_confirmations(&self) -> u64 {
+        self.tx_confirmations.unwrap_or(DEFAULT_TX_CONFIRMATIONS)
+    }
 }
 
 // Basically ckb_sdk::types::NetworkType. But we added a `Mocknet` variant.
 // And we can't use `ckb_sdk::types::NetworkType` directly because it is not `ValueEnum`.
-#[derive(Debug, Clone, Copy, ValueEnum, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, PartialEq, Eq)]
 pub enum CkbNetwork {
     Mocknet,
     Mainnet,
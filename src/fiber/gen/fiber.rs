@@ -8946,6 +8946,735 @@ impl molecule::prelude::Builder for ReestablishChannelBuilder {
     }
 }
 #[derive(Clone)]
+pub struct Ping(molecule::bytes::Bytes);
+impl ::core::fmt::LowerHex for Ping {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl ::core::fmt::Debug for Ping {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl ::core::fmt::Display for Ping {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "nonce", self.nonce())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl ::core::default::Default for Ping {
+    fn default() -> Self {
+        let v = molecule::bytes::Bytes::from_static(&Self::DEFAULT_VALUE);
+        Ping::new_unchecked(v)
+    }
+}
+impl Ping {
+    const DEFAULT_VALUE: [u8; 16] = [16, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    pub const FIELD_COUNT: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn nonce(&self) -> Uint64 {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[8..]) as usize;
+            Uint64::new_unchecked(self.0.slice(start..end))
+        } else {
+            Uint64::new_unchecked(self.0.slice(start..))
+        }
+    }
+    pub fn as_reader<'r>(&'r self) -> PingReader<'r> {
+        PingReader::new_unchecked(self.as_slice())
+    }
+}
+impl molecule::prelude::Entity for Ping {
+    type Builder = PingBuilder;
+    const NAME: &'static str = "Ping";
+    fn new_unchecked(data: molecule::bytes::Bytes) -> Self {
+        Ping(data)
+    }
+    fn as_bytes(&self) -> molecule::bytes::Bytes {
+        self.0.clone()
+    }
+    fn as_slice(&self) -> &[u8] {
+        &self.0[..]
+    }
+    fn from_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        PingReader::from_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn from_compatible_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        PingReader::from_compatible_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn new_builder() -> Self::Builder {
+        ::core::default::Default::default()
+    }
+    fn as_builder(self) -> Self::Builder {
+        Self::new_builder().nonce(self.nonce())
+    }
+}
+#[derive(Clone, Copy)]
+pub struct PingReader<'r>(&'r [u8]);
+impl<'r> ::core::fmt::LowerHex for PingReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl<'r> ::core::fmt::Debug for PingReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl<'r> ::core::fmt::Display for PingReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "nonce", self.nonce())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl<'r> PingReader<'r> {
+    pub const FIELD_COUNT: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn nonce(&self) -> Uint64Reader<'r> {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[8..]) as usize;
+            Uint64Reader::new_unchecked(&self.as_slice()[start..end])
+        } else {
+            Uint64Reader::new_unchecked(&self.as_slice()[start..])
+        }
+    }
+}
+impl<'r> molecule::prelude::Reader<'r> for PingReader<'r> {
+    type Entity = Ping;
+    const NAME: &'static str = "PingReader";
+    fn to_entity(&self) -> Self::Entity {
+        Self::Entity::new_unchecked(self.as_slice().to_owned().into())
+    }
+    fn new_unchecked(slice: &'r [u8]) -> Self {
+        PingReader(slice)
+    }
+    fn as_slice(&self) -> &'r [u8] {
+        self.0
+    }
+    fn verify(slice: &[u8], compatible: bool) -> molecule::error::VerificationResult<()> {
+        use molecule::verification_error as ve;
+        let slice_len = slice.len();
+        if slice_len < molecule::NUMBER_SIZE {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE, slice_len);
+        }
+        let total_size = molecule::unpack_number(slice) as usize;
+        if slice_len != total_size {
+            return ve!(Self, TotalSizeNotMatch, total_size, slice_len);
+        }
+        if slice_len < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE * 2, slice_len);
+        }
+        let offset_first = molecule::unpack_number(&slice[molecule::NUMBER_SIZE..]) as usize;
+        if offset_first % molecule::NUMBER_SIZE != 0 || offset_first < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        if slice_len < offset_first {
+            return ve!(Self, HeaderIsBroken, offset_first, slice_len);
+        }
+        let field_count = offset_first / molecule::NUMBER_SIZE - 1;
+        if field_count < Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        } else if !compatible && field_count > Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        };
+        let mut offsets: Vec<usize> = slice[molecule::NUMBER_SIZE..offset_first]
+            .chunks_exact(molecule::NUMBER_SIZE)
+            .map(|x| molecule::unpack_number(x) as usize)
+            .collect();
+        offsets.push(total_size);
+        if offsets.windows(2).any(|i| i[0] > i[1]) {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        Uint64Reader::verify(&slice[offsets[0]..offsets[1]], compatible)?;
+        Ok(())
+    }
+}
+#[derive(Clone, Debug, Default)]
+pub struct PingBuilder {
+    pub(crate) nonce: Uint64,
+}
+impl PingBuilder {
+    pub const FIELD_COUNT: usize = 1;
+    pub fn nonce(mut self, v: Uint64) -> Self {
+        self.nonce = v;
+        self
+    }
+}
+impl molecule::prelude::Builder for PingBuilder {
+    type Entity = Ping;
+    const NAME: &'static str = "PingBuilder";
+    fn expected_length(&self) -> usize {
+        molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1) + self.nonce.as_slice().len()
+    }
+    fn write<W: molecule::io::Write>(&self, writer: &mut W) -> molecule::io::Result<()> {
+        let mut total_size = molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1);
+        let mut offsets = Vec::with_capacity(Self::FIELD_COUNT);
+        offsets.push(total_size);
+        total_size += self.nonce.as_slice().len();
+        writer.write_all(&molecule::pack_number(total_size as molecule::Number))?;
+        for offset in offsets.into_iter() {
+            writer.write_all(&molecule::pack_number(offset as molecule::Number))?;
+        }
+        writer.write_all(self.nonce.as_slice())?;
+        Ok(())
+    }
+    fn build(&self) -> Self::Entity {
+        let mut inner = Vec::with_capacity(self.expected_length());
+        self.write(&mut inner)
+            .unwrap_or_else(|_| panic!("{} build should be ok", Self::NAME));
+        Ping::new_unchecked(inner.into())
+    }
+}
+#[derive(Clone)]
+pub struct Pong(molecule::bytes::Bytes);
+impl ::core::fmt::LowerHex for Pong {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl ::core::fmt::Debug for Pong {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl ::core::fmt::Display for Pong {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "nonce", self.nonce())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl ::core::default::Default for Pong {
+    fn default() -> Self {
+        let v = molecule::bytes::Bytes::from_static(&Self::DEFAULT_VALUE);
+        Pong::new_unchecked(v)
+    }
+}
+impl Pong {
+    const DEFAULT_VALUE: [u8; 16] = [16, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    pub const FIELD_COUNT: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn nonce(&self) -> Uint64 {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[8..]) as usize;
+            Uint64::new_unchecked(self.0.slice(start..end))
+        } else {
+            Uint64::new_unchecked(self.0.slice(start..))
+        }
+    }
+    pub fn as_reader<'r>(&'r self) -> PongReader<'r> {
+        PongReader::new_unchecked(self.as_slice())
+    }
+}
+impl molecule::prelude::Entity for Pong {
+    type Builder = PongBuilder;
+    const NAME: &'static str = "Pong";
+    fn new_unchecked(data: molecule::bytes::Bytes) -> Self {
+        Pong(data)
+    }
+    fn as_bytes(&self) -> molecule::bytes::Bytes {
+        self.0.clone()
+    }
+    fn as_slice(&self) -> &[u8] {
+        &self.0[..]
+    }
+    fn from_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        PongReader::from_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn from_compatible_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        PongReader::from_compatible_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn new_builder() -> Self::Builder {
+        ::core::default::Default::default()
+    }
+    fn as_builder(self) -> Self::Builder {
+        Self::new_builder().nonce(self.nonce())
+    }
+}
+#[derive(Clone, Copy)]
+pub struct PongReader<'r>(&'r [u8]);
+impl<'r> ::core::fmt::LowerHex for PongReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl<'r> ::core::fmt::Debug for PongReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl<'r> ::core::fmt::Display for PongReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "nonce", self.nonce())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl<'r> PongReader<'r> {
+    pub const FIELD_COUNT: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn nonce(&self) -> Uint64Reader<'r> {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[8..]) as usize;
+            Uint64Reader::new_unchecked(&self.as_slice()[start..end])
+        } else {
+            Uint64Reader::new_unchecked(&self.as_slice()[start..])
+        }
+    }
+}
+impl<'r> molecule::prelude::Reader<'r> for PongReader<'r> {
+    type Entity = Pong;
+    const NAME: &'static str = "PongReader";
+    fn to_entity(&self) -> Self::Entity {
+        Self::Entity::new_unchecked(self.as_slice().to_owned().into())
+    }
+    fn new_unchecked(slice: &'r [u8]) -> Self {
+        PongReader(slice)
+    }
+    fn as_slice(&self) -> &'r [u8] {
+        self.0
+    }
+    fn verify(slice: &[u8], compatible: bool) -> molecule::error::VerificationResult<()> {
+        use molecule::verification_error as ve;
+        let slice_len = slice.len();
+        if slice_len < molecule::NUMBER_SIZE {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE, slice_len);
+        }
+        let total_size = molecule::unpack_number(slice) as usize;
+        if slice_len != total_size {
+            return ve!(Self, TotalSizeNotMatch, total_size, slice_len);
+        }
+        if slice_len < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE * 2, slice_len);
+        }
+        let offset_first = molecule::unpack_number(&slice[molecule::NUMBER_SIZE..]) as usize;
+        if offset_first % molecule::NUMBER_SIZE != 0 || offset_first < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        if slice_len < offset_first {
+            return ve!(Self, HeaderIsBroken, offset_first, slice_len);
+        }
+        let field_count = offset_first / molecule::NUMBER_SIZE - 1;
+        if field_count < Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        } else if !compatible && field_count > Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        };
+        let mut offsets: Vec<usize> = slice[molecule::NUMBER_SIZE..offset_first]
+            .chunks_exact(molecule::NUMBER_SIZE)
+            .map(|x| molecule::unpack_number(x) as usize)
+            .collect();
+        offsets.push(total_size);
+        if offsets.windows(2).any(|i| i[0] > i[1]) {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        Uint64Reader::verify(&slice[offsets[0]..offsets[1]], compatible)?;
+        Ok(())
+    }
+}
+#[derive(Clone, Debug, Default)]
+pub struct PongBuilder {
+    pub(crate) nonce: Uint64,
+}
+impl PongBuilder {
+    pub const FIELD_COUNT: usize = 1;
+    pub fn nonce(mut self, v: Uint64) -> Self {
+        self.nonce = v;
+        self
+    }
+}
+impl molecule::prelude::Builder for PongBuilder {
+    type Entity = Pong;
+    const NAME: &'static str = "PongBuilder";
+    fn expected_length(&self) -> usize {
+        molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1) + self.nonce.as_slice().len()
+    }
+    fn write<W: molecule::io::Write>(&self, writer: &mut W) -> molecule::io::Result<()> {
+        let mut total_size = molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1);
+        let mut offsets = Vec::with_capacity(Self::FIELD_COUNT);
+        offsets.push(total_size);
+        total_size += self.nonce.as_slice().len();
+        writer.write_all(&molecule::pack_number(total_size as molecule::Number))?;
+        for offset in offsets.into_iter() {
+            writer.write_all(&molecule::pack_number(offset as molecule::Number))?;
+        }
+        writer.write_all(self.nonce.as_slice())?;
+        Ok(())
+    }
+    fn build(&self) -> Self::Entity {
+        let mut inner = Vec::with_capacity(self.expected_length());
+        self.write(&mut inner)
+            .unwrap_or_else(|_| panic!("{} build should be ok", Self::NAME));
+        Pong::new_unchecked(inner.into())
+    }
+}
+#[derive(Clone)]
+pub struct UpdateFee(molecule::bytes::Bytes);
+impl ::core::fmt::LowerHex for UpdateFee {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl ::core::fmt::Debug for UpdateFee {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl ::core::fmt::Display for UpdateFee {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "channel_id", self.channel_id())?;
+        write!(f, ", {}: {}", "fee_rate", self.fee_rate())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl ::core::default::Default for UpdateFee {
+    fn default() -> Self {
+        let v = molecule::bytes::Bytes::from_static(&Self::DEFAULT_VALUE);
+        UpdateFee::new_unchecked(v)
+    }
+}
+impl UpdateFee {
+    const DEFAULT_VALUE: [u8; 52] = [
+        52, 0, 0, 0, 12, 0, 0, 0, 44, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    pub const FIELD_COUNT: usize = 2;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn channel_id(&self) -> Byte32 {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        let end = molecule::unpack_number(&slice[8..]) as usize;
+        Byte32::new_unchecked(self.0.slice(start..end))
+    }
+    pub fn fee_rate(&self) -> Uint64 {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[8..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[12..]) as usize;
+            Uint64::new_unchecked(self.0.slice(start..end))
+        } else {
+            Uint64::new_unchecked(self.0.slice(start..))
+        }
+    }
+    pub fn as_reader<'r>(&'r self) -> UpdateFeeReader<'r> {
+        UpdateFeeReader::new_unchecked(self.as_slice())
+    }
+}
+impl molecule::prelude::Entity for UpdateFee {
+    type Builder = UpdateFeeBuilder;
+    const NAME: &'static str = "UpdateFee";
+    fn new_unchecked(data: molecule::bytes::Bytes) -> Self {
+        UpdateFee(data)
+    }
+    fn as_bytes(&self) -> molecule::bytes::Bytes {
+        self.0.clone()
+    }
+    fn as_slice(&self) -> &[u8] {
+        &self.0[..]
+    }
+    fn from_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        UpdateFeeReader::from_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn from_compatible_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        UpdateFeeReader::from_compatible_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn new_builder() -> Self::Builder {
+        ::core::default::Default::default()
+    }
+    fn as_builder(self) -> Self::Builder {
+        Self::new_builder()
+            .channel_id(self.channel_id())
+            .fee_rate(self.fee_rate())
+    }
+}
+#[derive(Clone, Copy)]
+pub struct UpdateFeeReader<'r>(&'r [u8]);
+impl<'r> ::core::fmt::LowerHex for UpdateFeeReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl<'r> ::core::fmt::Debug for UpdateFeeReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl<'r> ::core::fmt::Display for UpdateFeeReader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{} {{ ", Self::NAME)?;
+        write!(f, "{}: {}", "channel_id", self.channel_id())?;
+        write!(f, ", {}: {}", "fee_rate", self.fee_rate())?;
+        let extra_count = self.count_extra_fields();
+        if extra_count != 0 {
+            write!(f, ", .. ({} fields)", extra_count)?;
+        }
+        write!(f, " }}")
+    }
+}
+impl<'r> UpdateFeeReader<'r> {
+    pub const FIELD_COUNT: usize = 2;
+    pub fn total_size(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn field_count(&self) -> usize {
+        if self.total_size() == molecule::NUMBER_SIZE {
+            0
+        } else {
+            (molecule::unpack_number(&self.as_slice()[molecule::NUMBER_SIZE..]) as usize / 4) - 1
+        }
+    }
+    pub fn count_extra_fields(&self) -> usize {
+        self.field_count() - Self::FIELD_COUNT
+    }
+    pub fn has_extra_fields(&self) -> bool {
+        Self::FIELD_COUNT != self.field_count()
+    }
+    pub fn channel_id(&self) -> Byte32Reader<'r> {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[4..]) as usize;
+        let end = molecule::unpack_number(&slice[8..]) as usize;
+        Byte32Reader::new_unchecked(&self.as_slice()[start..end])
+    }
+    pub fn fee_rate(&self) -> Uint64Reader<'r> {
+        let slice = self.as_slice();
+        let start = molecule::unpack_number(&slice[8..]) as usize;
+        if self.has_extra_fields() {
+            let end = molecule::unpack_number(&slice[12..]) as usize;
+            Uint64Reader::new_unchecked(&self.as_slice()[start..end])
+        } else {
+            Uint64Reader::new_unchecked(&self.as_slice()[start..])
+        }
+    }
+}
+impl<'r> molecule::prelude::Reader<'r> for UpdateFeeReader<'r> {
+    type Entity = UpdateFee;
+    const NAME: &'static str = "UpdateFeeReader";
+    fn to_entity(&self) -> Self::Entity {
+        Self::Entity::new_unchecked(self.as_slice().to_owned().into())
+    }
+    fn new_unchecked(slice: &'r [u8]) -> Self {
+        UpdateFeeReader(slice)
+    }
+    fn as_slice(&self) -> &'r [u8] {
+        self.0
+    }
+    fn verify(slice: &[u8], compatible: bool) -> molecule::error::VerificationResult<()> {
+        use molecule::verification_error as ve;
+        let slice_len = slice.len();
+        if slice_len < molecule::NUMBER_SIZE {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE, slice_len);
+        }
+        let total_size = molecule::unpack_number(slice) as usize;
+        if slice_len != total_size {
+            return ve!(Self, TotalSizeNotMatch, total_size, slice_len);
+        }
+        if slice_len < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE * 2, slice_len);
+        }
+        let offset_first = molecule::unpack_number(&slice[molecule::NUMBER_SIZE..]) as usize;
+        if offset_first % molecule::NUMBER_SIZE != 0 || offset_first < molecule::NUMBER_SIZE * 2 {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        if slice_len < offset_first {
+            return ve!(Self, HeaderIsBroken, offset_first, slice_len);
+        }
+        let field_count = offset_first / molecule::NUMBER_SIZE - 1;
+        if field_count < Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        } else if !compatible && field_count > Self::FIELD_COUNT {
+            return ve!(Self, FieldCountNotMatch, Self::FIELD_COUNT, field_count);
+        };
+        let mut offsets: Vec<usize> = slice[molecule::NUMBER_SIZE..offset_first]
+            .chunks_exact(molecule::NUMBER_SIZE)
+            .map(|x| molecule::unpack_number(x) as usize)
+            .collect();
+        offsets.push(total_size);
+        if offsets.windows(2).any(|i| i[0] > i[1]) {
+            return ve!(Self, OffsetsNotMatch);
+        }
+        Byte32Reader::verify(&slice[offsets[0]..offsets[1]], compatible)?;
+        Uint64Reader::verify(&slice[offsets[1]..offsets[2]], compatible)?;
+        Ok(())
+    }
+}
+#[derive(Clone, Debug, Default)]
+pub struct UpdateFeeBuilder {
+    pub(crate) channel_id: Byte32,
+    pub(crate) fee_rate: Uint64,
+}
+impl UpdateFeeBuilder {
+    pub const FIELD_COUNT: usize = 2;
+    pub fn channel_id(mut self, v: Byte32) -> Self {
+        self.channel_id = v;
+        self
+    }
+    pub fn fee_rate(mut self, v: Uint64) -> Self {
+        self.fee_rate = v;
+        self
+    }
+}
+impl molecule::prelude::Builder for UpdateFeeBuilder {
+    type Entity = UpdateFee;
+    const NAME: &'static str = "UpdateFeeBuilder";
+    fn expected_length(&self) -> usize {
+        molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1)
+            + self.channel_id.as_slice().len()
+            + self.fee_rate.as_slice().len()
+    }
+    fn write<W: molecule::io::Write>(&self, writer: &mut W) -> molecule::io::Result<()> {
+        let mut total_size = molecule::NUMBER_SIZE * (Self::FIELD_COUNT + 1);
+        let mut offsets = Vec::with_capacity(Self::FIELD_COUNT);
+        offsets.push(total_size);
+        total_size += self.channel_id.as_slice().len();
+        offsets.push(total_size);
+        total_size += self.fee_rate.as_slice().len();
+        writer.write_all(&molecule::pack_number(total_size as molecule::Number))?;
+        for offset in offsets.into_iter() {
+            writer.write_all(&molecule::pack_number(offset as molecule::Number))?;
+        }
+        writer.write_all(self.channel_id.as_slice())?;
+        writer.write_all(self.fee_rate.as_slice())?;
+        Ok(())
+    }
+    fn build(&self) -> Self::Entity {
+        let mut inner = Vec::with_capacity(self.expected_length());
+        self.write(&mut inner)
+            .unwrap_or_else(|_| panic!("{} build should be ok", Self::NAME));
+        UpdateFee::new_unchecked(inner.into())
+    }
+}
+#[derive(Clone)]
 pub struct FiberMessage(molecule::bytes::Bytes);
 impl ::core::fmt::LowerHex for FiberMessage {
     fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -8996,7 +9725,7 @@ impl FiberMessage {
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         0, 0, 0, 0, 0, 0, 0, 0, 0,
     ];
-    pub const ITEMS_COUNT: usize = 16;
+    pub const ITEMS_COUNT: usize = 19;
     pub fn item_id(&self) -> molecule::Number {
         molecule::unpack_number(self.as_slice())
     }
@@ -9019,6 +9748,9 @@ impl FiberMessage {
             13 => Shutdown::new_unchecked(inner).into(),
             14 => ClosingSigned::new_unchecked(inner).into(),
             15 => ReestablishChannel::new_unchecked(inner).into(),
+            16 => Ping::new_unchecked(inner).into(),
+            17 => Pong::new_unchecked(inner).into(),
+            18 => UpdateFee::new_unchecked(inner).into(),
             _ => panic!("{}: invalid data", Self::NAME),
         }
     }
@@ -9075,7 +9807,7 @@ impl<'r> ::core::fmt::Display for FiberMessageReader<'r> {
     }
 }
 impl<'r> FiberMessageReader<'r> {
-    pub const ITEMS_COUNT: usize = 16;
+    pub const ITEMS_COUNT: usize = 19;
     pub fn item_id(&self) -> molecule::Number {
         molecule::unpack_number(self.as_slice())
     }
@@ -9098,6 +9830,9 @@ impl<'r> FiberMessageReader<'r> {
             13 => ShutdownReader::new_unchecked(inner).into(),
             14 => ClosingSignedReader::new_unchecked(inner).into(),
             15 => ReestablishChannelReader::new_unchecked(inner).into(),
+            16 => PingReader::new_unchecked(inner).into(),
+            17 => PongReader::new_unchecked(inner).into(),
+            18 => UpdateFeeReader::new_unchecked(inner).into(),
             _ => panic!("{}: invalid data", Self::NAME),
         }
     }
@@ -9139,6 +9874,9 @@ impl<'r> molecule::prelude::Reader<'r> for FiberMessageReader<'r> {
             13 => ShutdownReader::verify(inner_slice, compatible),
             14 => ClosingSignedReader::verify(inner_slice, compatible),
             15 => ReestablishChannelReader::verify(inner_slice, compatible),
+            16 => PingReader::verify(inner_slice, compatible),
+            17 => PongReader::verify(inner_slice, compatible),
+            18 => UpdateFeeReader::verify(inner_slice, compatible),
             _ => ve!(Self, UnknownItem, Self::ITEMS_COUNT, item_id),
         }?;
         Ok(())
@@ -9147,7 +9885,7 @@ impl<'r> molecule::prelude::Reader<'r> for FiberMessageReader<'r> {
 #[derive(Clone, Debug, Default)]
 pub struct FiberMessageBuilder(pub(crate) FiberMessageUnion);
 impl FiberMessageBuilder {
-    pub const ITEMS_COUNT: usize = 16;
+    pub const ITEMS_COUNT: usize = 19;
     pub fn set<I>(mut self, v: I) -> Self
     where
         I: ::core::convert::Into<FiberMessageUnion>,
@@ -9191,6 +9929,9 @@ pub enum FiberMessageUnion {
     Shutdown(Shutdown),
     ClosingSigned(ClosingSigned),
     ReestablishChannel(ReestablishChannel),
+    Ping(Ping),
+    Pong(Pong),
+    UpdateFee(UpdateFee),
 }
 #[derive(Debug, Clone, Copy)]
 pub enum FiberMessageUnionReader<'r> {
@@ -9210,6 +9951,9 @@ pub enum FiberMessageUnionReader<'r> {
     Shutdown(ShutdownReader<'r>),
     ClosingSigned(ClosingSignedReader<'r>),
     ReestablishChannel(ReestablishChannelReader<'r>),
+    Ping(PingReader<'r>),
+    Pong(PongReader<'r>),
+    UpdateFee(UpdateFeeReader<'r>),
 }
 impl ::core::default::Default for FiberMessageUnion {
     fn default() -> Self {
@@ -9267,6 +10011,15 @@ impl ::core::fmt::Display for FiberMessageUnion {
             FiberMessageUnion::ReestablishChannel(ref item) => {
                 write!(f, "{}::{}({})", Self::NAME, ReestablishChannel::NAME, item)
             }
+            FiberMessageUnion::Ping(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Ping::NAME, item)
+            }
+            FiberMessageUnion::Pong(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Pong::NAME, item)
+            }
+            FiberMessageUnion::UpdateFee(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, UpdateFee::NAME, item)
+            }
         }
     }
 }
@@ -9321,6 +10074,15 @@ impl<'r> ::core::fmt::Display for FiberMessageUnionReader<'r> {
             FiberMessageUnionReader::ReestablishChannel(ref item) => {
                 write!(f, "{}::{}({})", Self::NAME, ReestablishChannel::NAME, item)
             }
+            FiberMessageUnionReader::Ping(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Ping::NAME, item)
+            }
+            FiberMessageUnionReader::Pong(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Pong::NAME, item)
+            }
+            FiberMessageUnionReader::UpdateFee(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, UpdateFee::NAME, item)
+            }
         }
     }
 }
@@ -9343,6 +10105,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(ref item) => write!(f, "{}", item),
             FiberMessageUnion::ClosingSigned(ref item) => write!(f, "{}", item),
             FiberMessageUnion::ReestablishChannel(ref item) => write!(f, "{}", item),
+            FiberMessageUnion::Ping(ref item) => write!(f, "{}", item),
+            FiberMessageUnion::Pong(ref item) => write!(f, "{}", item),
+            FiberMessageUnion::UpdateFee(ref item) => write!(f, "{}", item),
         }
     }
 }
@@ -9365,6 +10130,9 @@ impl<'r> FiberMessageUnionReader<'r> {
             FiberMessageUnionReader::Shutdown(ref item) => write!(f, "{}", item),
             FiberMessageUnionReader::ClosingSigned(ref item) => write!(f, "{}", item),
             FiberMessageUnionReader::ReestablishChannel(ref item) => write!(f, "{}", item),
+            FiberMessageUnionReader::Ping(ref item) => write!(f, "{}", item),
+            FiberMessageUnionReader::Pong(ref item) => write!(f, "{}", item),
+            FiberMessageUnionReader::UpdateFee(ref item) => write!(f, "{}", item),
         }
     }
 }
@@ -9448,6 +10216,21 @@ impl ::core::convert::From<ReestablishChannel> for FiberMessageUnion {
         FiberMessageUnion::ReestablishChannel(item)
     }
 }
+impl ::core::convert::From<Ping> for FiberMessageUnion {
+    fn from(item: Ping) -> Self {
+        FiberMessageUnion::Ping(item)
+    }
+}
+impl ::core::convert::From<Pong> for FiberMessageUnion {
+    fn from(item: Pong) -> Self {
+        FiberMessageUnion::Pong(item)
+    }
+}
+impl ::core::convert::From<UpdateFee> for FiberMessageUnion {
+    fn from(item: UpdateFee) -> Self {
+        FiberMessageUnion::UpdateFee(item)
+    }
+}
 impl<'r> ::core::convert::From<OpenChannelReader<'r>> for FiberMessageUnionReader<'r> {
     fn from(item: OpenChannelReader<'r>) -> Self {
         FiberMessageUnionReader::OpenChannel(item)
@@ -9528,6 +10311,21 @@ impl<'r> ::core::convert::From<ReestablishChannelReader<'r>> for FiberMessageUni
         FiberMessageUnionReader::ReestablishChannel(item)
     }
 }
+impl<'r> ::core::convert::From<PingReader<'r>> for FiberMessageUnionReader<'r> {
+    fn from(item: PingReader<'r>) -> Self {
+        FiberMessageUnionReader::Ping(item)
+    }
+}
+impl<'r> ::core::convert::From<PongReader<'r>> for FiberMessageUnionReader<'r> {
+    fn from(item: PongReader<'r>) -> Self {
+        FiberMessageUnionReader::Pong(item)
+    }
+}
+impl<'r> ::core::convert::From<UpdateFeeReader<'r>> for FiberMessageUnionReader<'r> {
+    fn from(item: UpdateFeeReader<'r>) -> Self {
+        FiberMessageUnionReader::UpdateFee(item)
+    }
+}
 impl FiberMessageUnion {
     pub const NAME: &'static str = "FiberMessageUnion";
     pub fn as_bytes(&self) -> molecule::bytes::Bytes {
@@ -9548,6 +10346,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(item) => item.as_bytes(),
             FiberMessageUnion::ClosingSigned(item) => item.as_bytes(),
             FiberMessageUnion::ReestablishChannel(item) => item.as_bytes(),
+            FiberMessageUnion::Ping(item) => item.as_bytes(),
+            FiberMessageUnion::Pong(item) => item.as_bytes(),
+            FiberMessageUnion::UpdateFee(item) => item.as_bytes(),
         }
     }
     pub fn as_slice(&self) -> &[u8] {
@@ -9568,6 +10369,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(item) => item.as_slice(),
             FiberMessageUnion::ClosingSigned(item) => item.as_slice(),
             FiberMessageUnion::ReestablishChannel(item) => item.as_slice(),
+            FiberMessageUnion::Ping(item) => item.as_slice(),
+            FiberMessageUnion::Pong(item) => item.as_slice(),
+            FiberMessageUnion::UpdateFee(item) => item.as_slice(),
         }
     }
     pub fn item_id(&self) -> molecule::Number {
@@ -9588,6 +10392,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(_) => 13,
             FiberMessageUnion::ClosingSigned(_) => 14,
             FiberMessageUnion::ReestablishChannel(_) => 15,
+            FiberMessageUnion::Ping(_) => 16,
+            FiberMessageUnion::Pong(_) => 17,
+            FiberMessageUnion::UpdateFee(_) => 18,
         }
     }
     pub fn item_name(&self) -> &str {
@@ -9608,6 +10415,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(_) => "Shutdown",
             FiberMessageUnion::ClosingSigned(_) => "ClosingSigned",
             FiberMessageUnion::ReestablishChannel(_) => "ReestablishChannel",
+            FiberMessageUnion::Ping(_) => "Ping",
+            FiberMessageUnion::Pong(_) => "Pong",
+            FiberMessageUnion::UpdateFee(_) => "UpdateFee",
         }
     }
     pub fn as_reader<'r>(&'r self) -> FiberMessageUnionReader<'r> {
@@ -9628,6 +10438,9 @@ impl FiberMessageUnion {
             FiberMessageUnion::Shutdown(item) => item.as_reader().into(),
             FiberMessageUnion::ClosingSigned(item) => item.as_reader().into(),
             FiberMessageUnion::ReestablishChannel(item) => item.as_reader().into(),
+            FiberMessageUnion::Ping(item) => item.as_reader().into(),
+            FiberMessageUnion::Pong(item) => item.as_reader().into(),
+            FiberMessageUnion::UpdateFee(item) => item.as_reader().into(),
         }
     }
 }
@@ -9651,6 +10464,9 @@ impl<'r> FiberMessageUnionReader<'r> {
             FiberMessageUnionReader::Shutdown(item) => item.as_slice(),
             FiberMessageUnionReader::ClosingSigned(item) => item.as_slice(),
             FiberMessageUnionReader::ReestablishChannel(item) => item.as_slice(),
+            FiberMessageUnionReader::Ping(item) => item.as_slice(),
+            FiberMessageUnionReader::Pong(item) => item.as_slice(),
+            FiberMessageUnionReader::UpdateFee(item) => item.as_slice(),
         }
     }
     pub fn item_id(&self) -> molecule::Number {
@@ -9671,6 +10487,9 @@ impl<'r> FiberMessageUnionReader<'r> {
             FiberMessageUnionReader::Shutdown(_) => 13,
             FiberMessageUnionReader::ClosingSigned(_) => 14,
             FiberMessageUnionReader::ReestablishChannel(_) => 15,
+            FiberMessageUnionReader::Ping(_) => 16,
+            FiberMessageUnionReader::Pong(_) => 17,
+            FiberMessageUnionReader::UpdateFee(_) => 18,
         }
     }
     pub fn item_name(&self) -> &str {
@@ -9691,6 +10510,9 @@ impl<'r> FiberMessageUnionReader<'r> {
             FiberMessageUnionReader::Shutdown(_) => "Shutdown",
             FiberMessageUnionReader::ClosingSigned(_) => "ClosingSigned",
             FiberMessageUnionReader::ReestablishChannel(_) => "ReestablishChannel",
+            FiberMessageUnionReader::Ping(_) => "Ping",
+            FiberMessageUnionReader::Pong(_) => "Pong",
+            FiberMessageUnionReader::UpdateFee(_) => "UpdateFee",
         }
     }
 }
@@ -9774,3 +10596,18 @@ impl From<ReestablishChannel> for FiberMessage {
         Self::new_builder().set(value).build()
     }
 }
+impl From<Ping> for FiberMessage {
+    fn from(value: Ping) -> Self {
+        Self::new_builder().set(value).build()
+    }
+}
+impl From<Pong> for FiberMessage {
+    fn from(value: Pong) -> Self {
+        Self::new_builder().set(value).build()
+    }
+}
+impl From<UpdateFee> for FiberMessage {
+    fn from(value: UpdateFee) -> Self {
+        Self::new_builder().set(value).build()
+    }
+}
@@ -1,16 +1,19 @@
 use ckb_hash::new_blake2b;
 use std::{fs, path::Path};
 use tracing::warn;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::keystore;
 
-// TODO: we need to securely erase the key.
 // We wrap the key in a struct to obtain create a function to obtain secret entropy from this key.
 // Unfortunately, SecioKeyPair does not allow us to obtain the secret key from the key pair.
+// The key is erased from memory when the struct is dropped.
 pub struct KeyPair([u8; 32]);
 
 use tentacle::secio::SecioKeyPair;
 
 use rand::{thread_rng, Rng};
-use std::io::{Error, ErrorKind, Read, Write};
+use std::io::{Error, ErrorKind, Read};
 
 impl KeyPair {
     pub fn generate_random_key() -> Self {
@@ -34,25 +37,9 @@ impl KeyPair {
         }
     }
 
+    /// Write the key encrypted if a passphrase is set, see [`crate::keystore`].
     pub fn write_to_file(&self, path: &Path) -> Result<(), Error> {
-        fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(path)
-            .and_then(|mut file| {
-                file.write_all(self.as_ref())?;
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    file.set_permissions(fs::Permissions::from_mode(0o400))
-                }
-                #[cfg(not(unix))]
-                {
-                    let mut permissions = file.metadata()?.permissions();
-                    permissions.set_readonly(true);
-                    file.set_permissions(permissions)
-                }
-            })
+        keystore::write_key_file(path, &self.0, &self.0)
     }
 
     pub fn read_from_file(path: &Path) -> Result<Option<Self>, Error> {
@@ -66,6 +53,12 @@ impl AsRef<[u8]> for KeyPair {
     }
 }
 
+impl Drop for KeyPair {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl From<KeyPair> for SecioKeyPair {
     fn from(val: KeyPair) -> Self {
         SecioKeyPair::secp256k1_raw_key(val.0).expect("key must have been validated")
@@ -120,12 +113,14 @@ pub(crate) fn read_secret_key(path: &Path) -> Result<Option<KeyPair>, Error> {
     {
         warn(!file.metadata()?.permissions().readonly(), "readonly");
     }
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).and_then(|_read_size| {
-        KeyPair::try_from(buf.as_slice())
-            .map(Some)
+    let mut buf = Zeroizing::new(Vec::new());
+    file.read_to_end(&mut buf)?;
+    let key = keystore::decode_key_file(path, &buf, |content| {
+        KeyPair::try_from(content)
+            .map(|key| Zeroizing::new(key.0))
             .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid secret key data"))
-    })
+    })?;
+    KeyPair::try_from(key.as_slice()).map(Some)
 }
 
 pub(crate) fn blake2b_hash_with_salt(data: &[u8], salt: &[u8]) -> [u8; 32] {
@@ -15,6 +15,8 @@ pub mod gen;
 
 pub mod channel;
 
+pub mod backup;
+
 pub mod types;
 
 pub mod hash_algorithm;
@@ -1,14 +1,16 @@
+use ckb_hash::blake2b_256;
 use ckb_jsonrpc_types::Status;
 use ckb_types::core::TransactionView;
 use ckb_types::packed::{Byte32, OutPoint, Script, Transaction};
 use ckb_types::prelude::{IntoTransactionView, Pack, Unpack};
 use ractor::{
-    async_trait as rasync_trait, call_t, Actor, ActorCell, ActorProcessingErr, ActorRef, RactorErr,
-    RpcReplyPort, SupervisionEvent,
+    async_trait as rasync_trait, call, call_t, Actor, ActorCell, ActorProcessingErr, ActorRef,
+    RactorErr, RpcReplyPort, SupervisionEvent,
 };
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tentacle::{
     async_trait,
     builder::{MetaBuilder, ServiceBuilder},
@@ -19,7 +21,7 @@ use tentacle::{
     secio::PeerId,
     secio::SecioKeyPair,
     service::{
-        ProtocolHandle, ProtocolMeta, ServiceAsyncControl, ServiceError, ServiceEvent,
+        ProtocolHandle, ProtocolMeta, ServiceAsyncControl, ServiceError, ServiceEvent, SessionType,
         TargetProtocol,
     },
     traits::{ServiceHandle, ServiceProtocol},
@@ -29,21 +31,36 @@ use tokio::sync::{mpsc, oneshot};
 use tokio_util::task::TaskTracker;
 use tracing::{debug, error, info, warn};
 
+use super::backup::{
+    build_sweep_transaction, channel_backup_key, read_channel_backup, ChannelBackup,
+    ChannelBackupFile,
+};
 use super::channel::{
-    AcceptChannelParameter, ChannelActor, ChannelActorMessage, ChannelActorStateStore,
-    ChannelCommand, ChannelCommandWithId, ChannelEvent, ChannelInitializationParameter,
-    ChannelSubscribers, OpenChannelParameter, ProcessingChannelError, ProcessingChannelResult,
-    DEFAULT_COMMITMENT_FEE_RATE, DEFAULT_FEE_RATE,
+    AcceptChannelParameter, AwaitingChannelReadyFlags, ChannelActor, ChannelActorMessage,
+    ChannelActorStateStore, ChannelCloseType, ChannelCommand, ChannelCommandWithId, ChannelEvent,
+    ChannelInitializationParameter, ChannelState, ChannelStateNotification, ChannelSubscribers,
+    CloseFlags, ClosedChannel, CommitmentBatchConfig, OpenChannelParameter, ProcessingChannelError,
+    ProcessingChannelResult, DEFAULT_COMMITMENT_FEE_RATE, DEFAULT_FEE_RATE,
 };
+use super::config::CkbNetwork;
 use super::fee::{calculate_commitment_tx_fee, default_minimal_ckb_amount};
 use super::key::blake2b_hash_with_salt;
-use super::types::{FiberMessage, Hash256, OpenChannel, Privkey, Pubkey};
+use super::types::{
+    FiberMessage, Hash256, OpenChannel, Ping, Pong, Privkey, Pubkey, ReestablishChannel,
+};
 use super::FiberConfig;
 
-use crate::ckb::contracts::{check_udt_script, is_udt_type_auto_accept};
-use crate::ckb::{CkbChainMessage, FundingRequest, FundingTx, TraceTxRequest};
+use crate::ckb::contracts::{
+    check_udt_script, get_script_by_contract, is_udt_type_auto_accept, Contract,
+};
+use crate::ckb::{
+    ChainWatchEvent, CkbChainMessage, FeeRateTarget, FundingRequest, FundingTx, TraceTxRequest,
+    WatchId, WatchTarget, REORG_WINDOW_BLOCKS,
+};
 use crate::fiber::channel::{TxCollaborationCommand, TxUpdateCommand};
 use crate::fiber::types::TxSignatures;
+use crate::signer::Signer;
+use crate::store::StoreError;
 use crate::{unwrap_or_return, Error};
 
 pub const FIBER_PROTOCOL_ID: ProtocolId = ProtocolId::new(42);
@@ -59,6 +76,14 @@ const ASSUME_CHAIN_ACTOR_ALWAYS_ALIVE_FOR_NOW: &str =
 
 const ASSUME_NETWORK_MYSELF_ALIVE: &str = "network actor myself alive";
 
+// Committed transactions are watched for chain reorganizations for at most this long, which
+// is well beyond the time to produce `REORG_WINDOW_BLOCKS` blocks.
+const TX_REORG_WATCH_TIMEOUT: Duration = Duration::from_secs(REORG_WINDOW_BLOCKS as u64 * 30);
+
+// The estimated fee rates are refreshed at this interval, the chain actor caches the fee rate
+// statistics for the same time.
+const FEE_RATE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub struct OpenChannelResponse {
     pub channel_id: Hash256,
@@ -98,6 +123,21 @@ pub enum NetworkActorCommand {
     ControlFiberChannel(ChannelCommandWithId),
     UpdateChannelFunding(Hash256, Transaction, FundingRequest),
     SignTx(PeerId, Hash256, Transaction, Option<Vec<Vec<u8>>>),
+    // Send pings to all connected peers and disconnect the ones that have not
+    // answered the previous ping in time. Triggered periodically by a timer.
+    PingPeers,
+    // Get the round-trip latency of all connected peers.
+    ListPeerLatencies(RpcReplyPort<Result<Vec<PeerLatency>, String>>),
+    // Get the information of all connected peers.
+    ListPeers(RpcReplyPort<Result<Vec<PeerInfo>, String>>),
+    // Get the information of this node.
+    NodeInfo(RpcReplyPort<Result<NodeInfo, String>>),
+    // Estimate the fee rate for a kind of transaction, used as the default when
+    // the fee rate is not specified.
+    EstimateFeeRate(FeeRateTarget, RpcReplyPort<Result<u64, String>>),
+    // Estimate the fee rates used by the network actor off the actor, so that a slow chain
+    // backend doesn't block it. Triggered periodically by a timer.
+    RefreshFeeRates,
 }
 
 #[derive(Debug)]
@@ -109,24 +149,72 @@ pub struct OpenChannelCommand {
     pub funding_fee_rate: Option<u64>,
     pub max_tlc_value_in_flight: Option<u128>,
     pub max_num_of_accept_tlcs: Option<u64>,
+    // Overrides the configured `tx_confirmations` for this channel.
+    pub tx_confirmations: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct AcceptChannelCommand {
     pub temp_channel_id: Hash256,
     pub funding_amount: u128,
+    // Overrides the configured `tx_confirmations` for this channel.
+    pub tx_confirmations: Option<u64>,
 }
 
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    // Whether the peer connected to us (true) or we connected to the peer (false).
+    pub is_inbound: bool,
+    // Number of channels with this peer which are currently managed by a channel actor.
+    pub channel_count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub version: String,
+    pub node_name: String,
+    pub peer_id: PeerId,
+    pub listening_addr: Multiaddr,
+    pub announced_addrs: Vec<String>,
+    pub chain_network: Option<CkbNetwork>,
+    pub peer_count: usize,
+    pub channel_count: usize,
+    // Channels opened by peers which are still waiting for us to accept.
+    pub pending_channel_count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerLatency {
+    pub peer_id: PeerId,
+    // Round-trip time of the last ping answered by the peer.
+    pub round_trip_time: Option<Duration>,
+    // The time (milliseconds since UNIX epoch) we received the last pong from the peer.
+    pub last_pong_at: Option<u64>,
+}
+
+// Build the messages with these functions, which count the messages in the mailbox of the
+// network actor, see `crate::metrics::actor_message_sent`.
 impl NetworkActorMessage {
     pub fn new_event(event: NetworkActorEvent) -> Self {
+        crate::metrics::actor_message_sent("network");
         Self::Event(event)
     }
 
     pub fn new_command(command: NetworkActorCommand) -> Self {
+        crate::metrics::actor_message_sent("network");
         Self::Command(command)
     }
 }
 
+/// A peer is connected or disconnected, see `ChannelSubscribers::peer_subscribers`.
+#[derive(Clone, Debug)]
+pub enum PeerNotification {
+    Connected(PeerId, Multiaddr),
+    Disconnected(PeerId, Multiaddr),
+}
+
 #[derive(Debug)]
 pub enum NetworkServiceEvent {
     ServiceError(ServiceError),
@@ -142,6 +230,10 @@ pub enum NetworkServiceEvent {
     // and both parties sent ChannelReady messages).
     ChannelReady(PeerId, Hash256),
     ChannelClosed(PeerId, Hash256, Byte32),
+    // The confirmed closing transaction of a closed channel has left the chain
+    // because of a chain reorganization. It is confirmed again with another
+    // `ChannelClosed` event.
+    ClosingTransactionOrphaned(PeerId, Hash256, Byte32),
     // We should sign a commitment transaction and send it to the other party.
     CommitmentSignaturePending(PeerId, Hash256, u64),
     // We have signed a commitment transaction and sent it to the other party.
@@ -214,6 +306,12 @@ pub enum NetworkActorEvent {
     /// A funding transaction has been confirmed.
     FundingTransactionFailed(OutPoint),
 
+    /// A confirmed funding transaction has left the chain because of a chain reorganization.
+    FundingTransactionOrphaned(OutPoint, Hash256),
+
+    /// The funding of a channel is aborted before the funding transaction is signed by us.
+    FundingTransactionAborted(Hash256, Transaction),
+
     /// A commitment transaction is signed by us and has sent to the other party.
     LocalCommitmentSigned(PeerId, Hash256, u64, TransactionView, Vec<u8>),
 
@@ -232,6 +330,19 @@ pub enum NetworkActorEvent {
     /// A closing transaction has failed (either because of invalid transaction or timeout)
     ClosingTransactionFailed(PeerId, Hash256, Byte32),
 
+    /// A confirmed closing transaction has left the chain because of a chain reorganization.
+    ClosingTransactionOrphaned(PeerId, Hash256, Byte32),
+
+    /// The funding cell of a channel recovered from the static channel backup is spent by
+    /// the transaction.
+    RecoveredChannelClosed(Hash256, Byte32),
+
+    /// The funding cell of a funded channel is spent by the transaction.
+    FundingCellSpent(Hash256, Byte32),
+
+    /// The fee rates are estimated by the chain actor.
+    FeeRatesEstimated(Vec<(FeeRateTarget, u64)>),
+
     /// Network service events to be sent to outside observers.
     /// These events may be both present at `NetworkActorEvent` and
     /// this branch of `NetworkActorEvent`. This is because some events
@@ -328,6 +439,7 @@ where
                                 temp_channel_id,
                                 funding_amount: state.auto_accept_channel_ckb_funding_amount
                                     as u128,
+                                tx_confirmations: None,
                             };
                             state
                                 .create_inbound_channel(accept_channel, self.store.clone())
@@ -340,10 +452,36 @@ where
                 }
             }
 
-            _ => state.send_message_to_channel_actor(
-                message.get_channel_id(),
-                ChannelActorMessage::PeerMessage(message),
-            ),
+            FiberMessage::Ping(Ping { nonce }) => {
+                state
+                    .send_message_to_peer(&peer_id, FiberMessage::Pong(Pong { nonce }))
+                    .await?;
+            }
+            FiberMessage::Pong(pong) => state.on_pong(&peer_id, pong),
+
+            // We have lost the state of the channels being recovered, the peer will force close
+            // them after receiving our ReestablishChannel message.
+            FiberMessage::ReestablishChannel(reestablish_channel)
+                if state
+                    .recovering_channels
+                    .contains_key(&reestablish_channel.channel_id) =>
+            {
+                debug!(
+                    "Peer {:?} reestablishes channel {:?} which is being recovered, waiting for it to be closed",
+                    &peer_id, &reestablish_channel.channel_id
+                );
+            }
+
+            _ => match message.get_channel_id() {
+                Some(channel_id) => state.send_message_to_channel_actor(
+                    channel_id,
+                    ChannelActorMessage::new_peer_message(peer_id, message),
+                ),
+                None => warn!(
+                    "Received unexpected message from peer {:?}: {:?}",
+                    &peer_id, &message
+                ),
+            },
         };
         Ok(())
     }
@@ -451,6 +589,7 @@ where
                     "Channel ({:?}) to peer {:?} is now ready",
                     channel_id, peer_id
                 );
+                state.update_channel_backup(&self.store, None);
                 // Notify outside observers.
                 myself
                     .send_message(NetworkActorMessage::new_event(
@@ -464,22 +603,38 @@ where
                 self.handle_peer_message(state, peer_id, message).await?
             }
             NetworkActorEvent::FundingTransactionPending(transaction, outpoint, channel_id) => {
+                let confirmations = state.get_tx_confirmations(&self.store, &channel_id);
                 state
-                    .on_funding_transaction_pending(transaction, outpoint.clone(), channel_id)
+                    .on_funding_transaction_pending(
+                        transaction,
+                        outpoint.clone(),
+                        channel_id,
+                        confirmations,
+                    )
                     .await;
             }
             NetworkActorEvent::FundingTransactionConfirmed(outpoint) => {
                 state.on_funding_transaction_confirmed(outpoint).await;
             }
             NetworkActorEvent::CommitmentTransactionPending(transaction, channel_id) => {
+                let confirmations = state.get_tx_confirmations(&self.store, &channel_id);
                 state
-                    .on_commitment_transaction_pending(transaction, channel_id)
+                    .on_commitment_transaction_pending(transaction, channel_id, confirmations)
                     .await;
             }
             NetworkActorEvent::CommitmentTransactionConfirmed(tx_hash, channel_id) => {
+                state
+                    .archive_closed_channel(
+                        &self.store,
+                        channel_id,
+                        ChannelCloseType::LocalForce,
+                        tx_hash.into(),
+                    )
+                    .await;
                 state
                     .on_commitment_transaction_confirmed(tx_hash, channel_id)
                     .await;
+                state.update_channel_backup(&self.store, Some(channel_id));
             }
             NetworkActorEvent::CommitmentTransactionFailed(tx_hash, channel_id) => {
                 error!(
@@ -490,15 +645,39 @@ where
             NetworkActorEvent::FundingTransactionFailed(outpoint) => {
                 error!("Funding transaction failed: {:?}", outpoint);
             }
+            NetworkActorEvent::FundingTransactionOrphaned(outpoint, channel_id) => {
+                state
+                    .on_funding_transaction_orphaned(outpoint, channel_id)
+                    .await;
+            }
+            NetworkActorEvent::FundingTransactionAborted(channel_id, transaction) => {
+                warn!("Funding of channel {:?} is aborted", &channel_id);
+                state.release_funding_cells(&transaction.into_view());
+            }
             NetworkActorEvent::ClosingTransactionPending(channel_id, peer_id, tx) => {
+                let confirmations = state.get_tx_confirmations(&self.store, &channel_id);
                 state
-                    .on_closing_transaction_pending(channel_id, peer_id.clone(), tx.clone())
+                    .on_closing_transaction_pending(
+                        channel_id,
+                        peer_id.clone(),
+                        tx.clone(),
+                        confirmations,
+                    )
                     .await;
             }
             NetworkActorEvent::ClosingTransactionConfirmed(peer_id, channel_id, tx_hash) => {
+                state
+                    .archive_closed_channel(
+                        &self.store,
+                        channel_id,
+                        ChannelCloseType::Cooperative,
+                        tx_hash.clone(),
+                    )
+                    .await;
                 state
                     .on_closing_transaction_confirmed(&peer_id, &channel_id, tx_hash)
                     .await;
+                state.update_channel_backup(&self.store, Some(channel_id));
             }
             NetworkActorEvent::ClosingTransactionFailed(peer_id, tx_hash, channel_id) => {
                 error!(
@@ -506,6 +685,34 @@ where
                     &channel_id, &tx_hash, &peer_id
                 );
             }
+            NetworkActorEvent::ClosingTransactionOrphaned(peer_id, channel_id, tx_hash) => {
+                error!(
+                    "Closing transaction {:?} of channel {:?} is orphaned by a chain reorganization, waiting for it to be confirmed again",
+                    &tx_hash, &channel_id
+                );
+                // Notify outside observers.
+                myself
+                    .send_message(NetworkActorMessage::new_event(
+                        NetworkActorEvent::NetworkServiceEvent(
+                            NetworkServiceEvent::ClosingTransactionOrphaned(
+                                peer_id, channel_id, tx_hash,
+                            ),
+                        ),
+                    ))
+                    .expect(ASSUME_NETWORK_MYSELF_ALIVE);
+            }
+            NetworkActorEvent::RecoveredChannelClosed(channel_id, tx_hash) => {
+                state.on_recovered_channel_closed(channel_id, tx_hash).await;
+                state.update_channel_backup(&self.store, None);
+            }
+            NetworkActorEvent::FeeRatesEstimated(fee_rates) => {
+                state.fee_rates.extend(fee_rates);
+            }
+            NetworkActorEvent::FundingCellSpent(channel_id, tx_hash) => {
+                state
+                    .on_funding_cell_spent(&self.store, channel_id, tx_hash)
+                    .await;
+            }
             NetworkActorEvent::LocalCommitmentSigned(
                 peer_id,
                 channel_id,
@@ -626,7 +833,7 @@ where
                     }
                 };
                 debug!("Funding transaction updated on our part: {:?}", tx);
-                state
+                if let Err(err) = state
                     .send_command_to_channel(
                         channel_id,
                         ChannelCommand::TxCollaborationCommand(TxCollaborationCommand::TxUpdate(
@@ -635,7 +842,12 @@ where
                             },
                         )),
                     )
-                    .await?
+                    .await
+                {
+                    // The channel is gone, it won't abort the funding with our new inputs.
+                    state.release_funding_cells(&tx);
+                    return Err(err.into());
+                }
             }
             NetworkActorCommand::SignTx(
                 ref peer_id,
@@ -738,6 +950,44 @@ where
                     ))
                     .expect("network actor alive");
             }
+            NetworkActorCommand::PingPeers => {
+                state.ping_peers().await;
+            }
+            NetworkActorCommand::ListPeerLatencies(reply) => {
+                let _ = reply.send(Ok(state.get_peer_latencies()));
+            }
+            NetworkActorCommand::ListPeers(reply) => {
+                let _ = reply.send(Ok(state.get_peers()));
+            }
+            NetworkActorCommand::NodeInfo(reply) => {
+                let _ = reply.send(Ok(state.get_node_info()));
+            }
+            NetworkActorCommand::EstimateFeeRate(target, reply) => {
+                let chain_actor = state.chain_actor.clone();
+                ractor::concurrency::tokio_primatives::spawn(async move {
+                    let _ = reply.send(estimate_fee_rate(&chain_actor, target).await);
+                });
+            }
+            NetworkActorCommand::RefreshFeeRates => {
+                let (chain_actor, network) = (state.chain_actor.clone(), state.network.clone());
+                ractor::concurrency::tokio_primatives::spawn(async move {
+                    let mut fee_rates = vec![];
+                    for target in [
+                        FeeRateTarget::Funding,
+                        FeeRateTarget::Commitment,
+                        FeeRateTarget::Closing,
+                    ] {
+                        if let Ok(fee_rate) = estimate_fee_rate(&chain_actor, target).await {
+                            fee_rates.push((target, fee_rate));
+                        }
+                    }
+                    network
+                        .send_message(NetworkActorMessage::new_event(
+                            NetworkActorEvent::FeeRatesEstimated(fee_rates),
+                        ))
+                        .expect(ASSUME_NETWORK_MYSELF_ALIVE);
+                });
+            }
         };
         Ok(())
     }
@@ -753,7 +1003,15 @@ pub struct NetworkActorState {
     // This immutable attribute is placed here because we need to create it in
     // the pre_start function.
     control: ServiceAsyncControl,
+    // The address tentacle is actually listening on.
+    listening_addr: Multiaddr,
+    // Node name, addresses and network from the config, used to answer node info queries.
+    announced_node_name: String,
+    announced_addrs: Vec<String>,
+    chain_network: Option<CkbNetwork>,
     peer_session_map: HashMap<PeerId, SessionId>,
+    // Remote addresses and directions of connected peers.
+    peer_addresses: HashMap<PeerId, (Multiaddr, SessionType)>,
     session_channels_map: HashMap<SessionId, HashSet<Hash256>>,
     channels: HashMap<Hash256, ActorRef<ChannelActorMessage>>,
     // Channels in this hashmap are pending for acceptance. The user needs to
@@ -768,6 +1026,33 @@ pub struct NetworkActorState {
     // Tha default amount of CKB to be funded when auto accepting a channel.
     auto_accept_channel_ckb_funding_amount: u64,
     channel_subscribers: ChannelSubscribers,
+    // Liveness information of the connected peers, updated by ping/pong messages.
+    peer_pings: HashMap<PeerId, PeerPingState>,
+    ping_interval: Duration,
+    // Peers that have not answered a ping within this duration are disconnected.
+    ping_timeout: Duration,
+    commitment_batch_config: CommitmentBatchConfig,
+    // Funding and closing transactions are considered confirmed once they are this many blocks deep.
+    tx_confirmations: u64,
+    // Holds the keys of the channels.
+    signer: Arc<dyn Signer>,
+    channel_backup: ChannelBackupFile,
+    // Channels in the static channel backup to recover from, which are not in the store.
+    // They are removed once the peers close them and our outputs are swept.
+    recovering_channels: HashMap<Hash256, ChannelBackup>,
+    // The watches of the funding cells of the funded channels, to find out the channels
+    // closed by the peers.
+    funding_cell_watches: HashMap<Hash256, WatchId>,
+    // The fee rates estimated by the chain actor, see `NetworkActorCommand::RefreshFeeRates`.
+    fee_rates: HashMap<FeeRateTarget, u64>,
+}
+
+#[derive(Debug, Default)]
+struct PeerPingState {
+    // The nonce and the sending time of the ping which is still waiting for a pong.
+    pending_ping: Option<(u64, Instant)>,
+    round_trip_time: Option<Duration>,
+    last_pong_at: Option<u64>,
 }
 
 static CHANNEL_ACTOR_NAME_PREFIX: AtomicU64 = AtomicU64::new(0u64);
@@ -809,6 +1094,7 @@ impl NetworkActorState {
             funding_fee_rate,
             max_tlc_value_in_flight,
             max_num_of_accept_tlcs,
+            tx_confirmations,
         } = open_channel;
         if let Some(udt_type_script) = funding_udt_type_script.as_ref() {
             if !check_udt_script(udt_type_script) {
@@ -820,6 +1106,12 @@ impl NetworkActorState {
         // NOTE: here we only check the amount is valid, we will also check more in the `pre_start` from channel creation
         let (_funding_amount, _reserved_ckb_amount) =
             self.get_funding_and_reserved_amount(funding_amount, &funding_udt_type_script)?;
+        // Use the estimated fee rates if they are not specified. The channel falls back
+        // to the default fee rates if they are not estimated yet.
+        let funding_fee_rate =
+            funding_fee_rate.or_else(|| self.fee_rates.get(&FeeRateTarget::Funding).copied());
+        let commitment_fee_rate =
+            commitment_fee_rate.or_else(|| self.fee_rates.get(&FeeRateTarget::Commitment).copied());
         let seed = self.generate_channel_seed();
         let (tx, rx) = oneshot::channel::<Hash256>();
         let channel = Actor::spawn_linked(
@@ -829,6 +1121,8 @@ impl NetworkActorState {
                 network.clone(),
                 store,
                 self.channel_subscribers.clone(),
+                self.commitment_batch_config,
+                self.signer.clone(),
             ),
             ChannelInitializationParameter::OpenChannel(OpenChannelParameter {
                 funding_amount,
@@ -839,6 +1133,7 @@ impl NetworkActorState {
                 funding_fee_rate,
                 max_tlc_value_in_flight,
                 max_num_of_accept_tlcs,
+                tx_confirmations,
             }),
             network.clone().get_cell(),
         )
@@ -856,6 +1151,7 @@ impl NetworkActorState {
         let AcceptChannelCommand {
             temp_channel_id,
             funding_amount,
+            tx_confirmations,
         } = accept_channel;
 
         let (peer_id, open_channel) = self
@@ -887,6 +1183,8 @@ impl NetworkActorState {
                 network.clone(),
                 store,
                 self.channel_subscribers.clone(),
+                self.commitment_batch_config,
+                self.signer.clone(),
             ),
             ChannelInitializationParameter::AcceptChannel(AcceptChannelParameter {
                 funding_amount,
@@ -894,6 +1192,7 @@ impl NetworkActorState {
                 seed,
                 open_channel,
                 channel_id_sender: Some(tx),
+                tx_confirmations,
             }),
             network.clone().get_cell(),
         )
@@ -903,14 +1202,19 @@ impl NetworkActorState {
         Ok((channel, temp_channel_id, new_id))
     }
 
-    async fn broadcast_tx_with_callback<F>(&self, transaction: TransactionView, callback: F)
-    where
-        F: Send + 'static + FnOnce(Result<Status, RactorErr<CkbChainMessage>>),
+    /// Broadcast the transaction and call `callback` with its final status once it is
+    /// `confirmations` blocks deep, see [`Self::trace_tx_with_callback`].
+    async fn broadcast_tx_with_callback<F>(
+        &self,
+        transaction: TransactionView,
+        confirmations: u64,
+        callback: F,
+    ) where
+        F: Send + 'static + FnMut(Result<Status, RactorErr<CkbChainMessage>>),
     {
         debug!("Trying to broadcast transaction {:?}", &transaction);
-        let chain = self.chain_actor.clone();
         call_t!(
-            &chain,
+            &self.chain_actor,
             CkbChainMessage::SendTx,
             DEFAULT_CHAIN_ACTOR_TIMEOUT,
             transaction.clone()
@@ -920,27 +1224,115 @@ impl NetworkActorState {
 
         let tx_hash = transaction.hash();
         info!("Transactoin sent to the network: {}", tx_hash);
+        self.trace_tx_with_callback(tx_hash, confirmations, false, callback);
+    }
 
-        // TODO: make number of confirmation to transaction configurable.
-        const NUM_CONFIRMATIONS: u64 = 4;
+    /// Call `callback` with the final status of the transaction once it is `confirmations`
+    /// blocks deep, unless it is already known to be `committed`. Once the transaction is
+    /// committed, it is tracked through chain reorganizations for at most
+    /// `TX_REORG_WATCH_TIMEOUT`: if the block committing it is detached, `callback` is called
+    /// with `Status::Pending` (the transaction is back in the pool), and then with the final
+    /// status again.
+    fn trace_tx_with_callback<F>(
+        &self,
+        tx_hash: Byte32,
+        confirmations: u64,
+        mut committed: bool,
+        mut callback: F,
+    ) where
+        F: Send + 'static + FnMut(Result<Status, RactorErr<CkbChainMessage>>),
+    {
+        let chain = self.chain_actor.clone();
         let request = TraceTxRequest {
             tx_hash: tx_hash.clone(),
-            confirmations: NUM_CONFIRMATIONS,
+            confirmations,
         };
 
         // Spawn a new task to avoid blocking current actor message processing.
         ractor::concurrency::tokio_primatives::spawn(async move {
-            debug!("Tracing transaction status {:?}", &request.tx_hash);
-            let result = call_t!(
+            // The watch is registered before tracing, so that a block detached between the
+            // confirmation and the registration is not missed. The chain actor closes the
+            // watch once the transaction is too deep to be reorganized. The watch may never be
+            // closed if the transaction is already out of the reorganization window, or if the
+            // backend doesn't follow the blocks, so it is also removed after a timeout.
+            let (sender, mut receiver) = mpsc::unbounded_channel();
+            let watch_id = call!(
                 chain,
-                CkbChainMessage::TraceTx,
-                DEFAULT_CHAIN_ACTOR_TIMEOUT,
-                request.clone()
-            );
-            callback(result);
+                CkbChainMessage::Watch,
+                WatchTarget::Transaction(tx_hash.clone()),
+                sender
+            )
+            .ok();
+            loop {
+                if !committed {
+                    debug!("Tracing transaction status {:?}", &request.tx_hash);
+                    let result = call_t!(
+                        chain,
+                        CkbChainMessage::TraceTx,
+                        DEFAULT_CHAIN_ACTOR_TIMEOUT,
+                        request.clone()
+                    );
+                    committed = matches!(result, Ok(Status::Committed));
+                    callback(result);
+                    if !committed {
+                        break;
+                    }
+                }
+                if watch_id.is_none() {
+                    break;
+                }
+
+                // Only the last event received while tracing tells whether the transaction is
+                // still in the chain.
+                let mut detached = false;
+                while let Ok(event) = receiver.try_recv() {
+                    detached = matches!(event, ChainWatchEvent::Detached(_));
+                }
+                if !detached {
+                    let deadline = tokio::time::Instant::now() + TX_REORG_WATCH_TIMEOUT;
+                    detached = loop {
+                        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                            Ok(Some(ChainWatchEvent::Detached(_))) => break true,
+                            Ok(Some(ChainWatchEvent::Attached(_))) => {}
+                            Ok(None) | Err(_) => break false,
+                        }
+                    };
+                }
+                if !detached {
+                    debug!(
+                        "Stop watching transaction {:?} for chain reorganizations",
+                        &tx_hash
+                    );
+                    break;
+                }
+                warn!(
+                    "Transaction {:?} is orphaned by a chain reorganization",
+                    &tx_hash
+                );
+                callback(Ok(Status::Pending));
+                committed = false;
+            }
+            if let Some(watch_id) = watch_id {
+                let _ = chain.send_message(CkbChainMessage::Unwatch(watch_id));
+            }
         });
     }
 
+    /// The confirmations required by the channel for its funding and closing transactions,
+    /// or the configured default.
+    fn get_tx_confirmations<S: ChannelActorStateStore>(
+        &self,
+        store: &S,
+        channel_id: &Hash256,
+    ) -> u64 {
+        store
+            .get_channel_actor_state(channel_id)
+            .ok()
+            .flatten()
+            .and_then(|state| state.tx_confirmations)
+            .unwrap_or(self.tx_confirmations)
+    }
+
     fn get_peer_session(&self, peer_id: &PeerId) -> Option<SessionId> {
         self.peer_session_map.get(peer_id).cloned()
     }
@@ -1070,7 +1462,10 @@ impl NetworkActorState {
     ) -> crate::Result<()> {
         match self.channels.get(&channel_id) {
             Some(actor) => {
-                actor.send_message(ChannelActorMessage::Command(command))?;
+                if let Err(err) = actor.send_message(ChannelActorMessage::new_command(command)) {
+                    crate::metrics::actor_message_dropped("channel");
+                    return Err(err.into());
+                }
                 Ok(())
             }
             None => Err(Error::ChannelNotFound(channel_id)),
@@ -1084,8 +1479,20 @@ impl NetworkActorState {
         store: S,
     ) {
         self.peer_session_map.insert(peer_id.clone(), session.id);
+        self.peer_addresses
+            .insert(peer_id.clone(), (session.address.clone(), session.ty));
+        self.peer_pings.insert(peer_id.clone(), Default::default());
 
-        for channel_id in store.get_active_channel_ids_by_peer(peer_id) {
+        let channel_ids = store
+            .get_active_channel_ids_by_peer(peer_id)
+            .unwrap_or_else(|err| {
+                error!(
+                    "Failed to get the channels of peer {:?} from the store: {}",
+                    peer_id, err
+                );
+                vec![]
+            });
+        for channel_id in channel_ids {
             debug!("Reestablishing channel {:x}", &channel_id);
             if let Ok((channel, _)) = Actor::spawn_linked(
                 Some(generate_channel_actor_name(&self.peer_id, peer_id)),
@@ -1094,6 +1501,8 @@ impl NetworkActorState {
                     self.network.clone(),
                     store.clone(),
                     self.channel_subscribers.clone(),
+                    self.commitment_batch_config,
+                    self.signer.clone(),
                 ),
                 ChannelInitializationParameter::ReestablishChannel(channel_id),
                 self.network.get_cell(),
@@ -1103,22 +1512,176 @@ impl NetworkActorState {
                 self.on_channel_created(channel_id, peer_id, channel);
             }
         }
+
+        // Zero commitment numbers tell the peer that we have lost the channel state.
+        let reestablish_channels: Vec<_> = self
+            .recovering_channels
+            .values()
+            .filter(|channel| &channel.peer_id == peer_id)
+            .map(|channel| ReestablishChannel {
+                channel_id: channel.channel_id,
+                local_commitment_number: 0,
+                remote_commitment_number: 0,
+            })
+            .collect();
+        for reestablish_channel in reestablish_channels {
+            debug!(
+                "Asking peer {:?} to close channel {:?} which is being recovered",
+                peer_id, &reestablish_channel.channel_id
+            );
+            if let Err(err) = self
+                .send_message_to_peer(
+                    peer_id,
+                    FiberMessage::ReestablishChannel(reestablish_channel),
+                )
+                .await
+            {
+                error!("Failed to send ReestablishChannel message: {}", err);
+            }
+        }
     }
 
     fn on_peer_disconnected(&mut self, id: &PeerId) {
+        self.peer_pings.remove(id);
+        self.peer_addresses.remove(id);
         if let Some(session) = self.peer_session_map.remove(id) {
             if let Some(channel_ids) = self.session_channels_map.remove(&session) {
                 for channel_id in channel_ids {
                     if let Some(channel) = self.channels.remove(&channel_id) {
-                        let _ = channel.send_message(ChannelActorMessage::Event(
-                            ChannelEvent::PeerDisconnected,
-                        ));
+                        // The channel actor may have stopped by itself.
+                        if channel
+                            .send_message(ChannelActorMessage::new_event(
+                                ChannelEvent::PeerDisconnected,
+                            ))
+                            .is_err()
+                        {
+                            crate::metrics::actor_message_dropped("channel");
+                        }
                     }
                 }
             }
         }
     }
 
+    async fn ping_peers(&mut self) {
+        let now = Instant::now();
+        let mut unresponsive_peers = vec![];
+        let mut pings = vec![];
+        for (peer_id, ping_state) in self.peer_pings.iter_mut() {
+            match ping_state.pending_ping {
+                Some((_, sent_at)) if now.duration_since(sent_at) > self.ping_timeout => {
+                    unresponsive_peers.push(peer_id.clone());
+                }
+                Some(_) => {}
+                None => {
+                    let nonce = rand::random::<u64>();
+                    ping_state.pending_ping = Some((nonce, now));
+                    pings.push((peer_id.clone(), nonce));
+                }
+            }
+        }
+
+        for peer_id in unresponsive_peers {
+            warn!(
+                "Peer {:?} did not answer ping within {:?}, disconnecting it",
+                &peer_id, self.ping_timeout
+            );
+            if let Some(session) = self.get_peer_session(&peer_id) {
+                if let Err(err) = self.control.disconnect(session).await {
+                    error!("Failed to disconnect peer {:?}: {}", &peer_id, err);
+                }
+            }
+            // Don't wait for tentacle to notice the broken session, channels with this
+            // peer should be notified as soon as possible.
+            self.on_peer_disconnected(&peer_id);
+        }
+
+        for (peer_id, nonce) in pings {
+            if let Err(err) = self
+                .send_message_to_peer(&peer_id, FiberMessage::Ping(Ping { nonce }))
+                .await
+            {
+                warn!("Failed to send ping to peer {:?}: {}", &peer_id, err);
+            }
+        }
+    }
+
+    fn on_pong(&mut self, peer_id: &PeerId, pong: Pong) {
+        let ping_state = match self.peer_pings.get_mut(peer_id) {
+            Some(ping_state) => ping_state,
+            None => {
+                warn!("Received pong from unknown peer {:?}", peer_id);
+                return;
+            }
+        };
+        match ping_state.pending_ping {
+            Some((nonce, sent_at)) if nonce == pong.nonce => {
+                let round_trip_time = sent_at.elapsed();
+                debug!(
+                    "Received pong from peer {:?}, round trip time: {:?}",
+                    peer_id, round_trip_time
+                );
+                ping_state.pending_ping = None;
+                ping_state.round_trip_time = Some(round_trip_time);
+                ping_state.last_pong_at = Some(
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .expect("SystemTime::now() should after UNIX_EPOCH")
+                        .as_millis() as u64,
+                );
+            }
+            _ => {
+                warn!(
+                    "Received unexpected pong {:?} from peer {:?}",
+                    &pong, peer_id
+                );
+            }
+        }
+    }
+
+    fn get_peer_latencies(&self) -> Vec<PeerLatency> {
+        self.peer_pings
+            .iter()
+            .map(|(peer_id, ping_state)| PeerLatency {
+                peer_id: peer_id.clone(),
+                round_trip_time: ping_state.round_trip_time,
+                last_pong_at: ping_state.last_pong_at,
+            })
+            .collect()
+    }
+
+    fn get_peers(&self) -> Vec<PeerInfo> {
+        self.peer_session_map
+            .iter()
+            .filter_map(|(peer_id, session)| {
+                let (address, session_type) = self.peer_addresses.get(peer_id)?;
+                Some(PeerInfo {
+                    peer_id: peer_id.clone(),
+                    address: address.clone(),
+                    is_inbound: session_type.is_inbound(),
+                    channel_count: self
+                        .session_channels_map
+                        .get(session)
+                        .map_or(0, |channels| channels.len()),
+                })
+            })
+            .collect()
+    }
+
+    fn get_node_info(&self) -> NodeInfo {
+        NodeInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            node_name: self.announced_node_name.clone(),
+            peer_id: self.peer_id.clone(),
+            listening_addr: self.listening_addr.clone(),
+            announced_addrs: self.announced_addrs.clone(),
+            chain_network: self.chain_network,
+            peer_count: self.peer_session_map.len(),
+            channel_count: self.channels.len(),
+            pending_channel_count: self.to_be_accepted_channels.len(),
+        }
+    }
+
     fn on_channel_created(
         &mut self,
         id: Hash256,
@@ -1139,36 +1702,66 @@ impl NetworkActorState {
         channel_id: Hash256,
         peer_id: PeerId,
         transaction: TransactionView,
+        confirmations: u64,
     ) {
-        let tx_hash: Byte32 = transaction.hash();
         info!(
             "Channel ({:?}) to peer {:?} is closed. Broadcasting closing transaction ({:?}) now.",
-            &channel_id, &peer_id, &tx_hash
+            &channel_id,
+            &peer_id,
+            transaction.hash()
         );
+        let callback = self.closing_tx_callback(channel_id, peer_id, transaction.hash());
+        self.broadcast_tx_with_callback(transaction, confirmations, callback)
+            .await;
+    }
+
+    /// Notify the network actor of the status of the closing transaction of the channel.
+    fn closing_tx_callback(
+        &self,
+        channel_id: Hash256,
+        peer_id: PeerId,
+        tx_hash: Byte32,
+    ) -> impl FnMut(Result<Status, RactorErr<CkbChainMessage>>) + Send + 'static {
         let network: ActorRef<NetworkActorMessage> = self.network.clone();
-        self.broadcast_tx_with_callback(transaction, move |result| {
+        move |result| {
             let message = match result {
                 Ok(Status::Committed) => {
                     info!("Cloisng transaction {:?} confirmed", &tx_hash);
-                    NetworkActorEvent::ClosingTransactionConfirmed(peer_id, channel_id, tx_hash)
+                    NetworkActorEvent::ClosingTransactionConfirmed(
+                        peer_id.clone(),
+                        channel_id,
+                        tx_hash.clone(),
+                    )
                 }
+                Ok(Status::Pending) => NetworkActorEvent::ClosingTransactionOrphaned(
+                    peer_id.clone(),
+                    channel_id,
+                    tx_hash.clone(),
+                ),
                 Ok(status) => {
                     error!(
                         "Closing transaction {:?} failed to be confirmed with final status {:?}",
                         &tx_hash, &status
                     );
-                    NetworkActorEvent::ClosingTransactionFailed(peer_id, channel_id, tx_hash)
+                    NetworkActorEvent::ClosingTransactionFailed(
+                        peer_id.clone(),
+                        channel_id,
+                        tx_hash.clone(),
+                    )
                 }
                 Err(err) => {
                     error!("Failed to trace transaction {:?}: {:?}", &tx_hash, &err);
-                    NetworkActorEvent::ClosingTransactionFailed(peer_id, channel_id, tx_hash)
+                    NetworkActorEvent::ClosingTransactionFailed(
+                        peer_id.clone(),
+                        channel_id,
+                        tx_hash.clone(),
+                    )
                 }
             };
             network
                 .send_message(NetworkActorMessage::new_event(message))
                 .expect(ASSUME_NETWORK_MYSELF_ALIVE);
-        })
-        .await;
+        }
     }
 
     async fn on_closing_transaction_confirmed(
@@ -1185,7 +1778,7 @@ impl NetworkActorState {
         }
         self.send_message_to_channel_actor(
             *channel_id,
-            ChannelActorMessage::Event(ChannelEvent::ClosingTransactionConfirmed),
+            ChannelActorMessage::new_event(ChannelEvent::ClosingTransactionConfirmed),
         );
         // Notify outside observers.
         self.network
@@ -1244,11 +1837,20 @@ impl NetworkActorState {
         Ok(())
     }
 
+    // The cells reserved for the funding transaction can be used by other fundings and the
+    // wallet now.
+    fn release_funding_cells(&self, transaction: &TransactionView) {
+        let _ = self.chain_actor.send_message(CkbChainMessage::ReleaseCells(
+            transaction.input_pts_iter().collect(),
+        ));
+    }
+
     async fn on_funding_transaction_pending(
         &mut self,
         transaction: Transaction,
         outpoint: OutPoint,
         channel_id: Hash256,
+        confirmations: u64,
     ) {
         // Just a sanity check to ensure that no two channels are associated with the same outpoint.
         if let Some(old) = self.pending_channels.remove(&outpoint) {
@@ -1259,43 +1861,151 @@ impl NetworkActorState {
         self.pending_channels.insert(outpoint.clone(), channel_id);
         // TODO: try to broadcast the transaction to the network.
         let transaction = transaction.into_view();
-        let tx_hash: Byte32 = transaction.hash();
         debug!(
             "Funding transaction (outpoint {:?}) for channel {:?} is now ready. Broadcast it {:?} now.",
-            &outpoint, &channel_id, &tx_hash
+            &outpoint,
+            &channel_id,
+            transaction.hash()
         );
+        let callback = self.funding_tx_callback(outpoint, channel_id, &transaction);
+        self.broadcast_tx_with_callback(transaction, confirmations, callback)
+            .await;
+    }
+
+    /// Notify the network actor of the status of the funding transaction of the channel, and
+    /// release the cells it spends if it fails.
+    fn funding_tx_callback(
+        &self,
+        outpoint: OutPoint,
+        channel_id: Hash256,
+        transaction: &TransactionView,
+    ) -> impl FnMut(Result<Status, RactorErr<CkbChainMessage>>) + Send + 'static {
+        let tx_hash: Byte32 = transaction.hash();
         let network = self.network.clone();
-        self.broadcast_tx_with_callback(transaction, move |result| {
+        let chain = self.chain_actor.clone();
+        let inputs: Vec<OutPoint> = transaction.input_pts_iter().collect();
+        move |result| {
             let message = match result {
                 Ok(Status::Committed) => {
                     info!("Funding transaction {:?} confirmed", &tx_hash);
-                    NetworkActorEvent::FundingTransactionConfirmed(outpoint)
+                    NetworkActorEvent::FundingTransactionConfirmed(outpoint.clone())
+                }
+                Ok(Status::Pending) => {
+                    NetworkActorEvent::FundingTransactionOrphaned(outpoint.clone(), channel_id)
                 }
                 Ok(status) => {
                     error!(
                         "Funding transaction {:?} failed to be confirmed with final status {:?}",
                         &tx_hash, &status
                     );
-                    NetworkActorEvent::FundingTransactionFailed(outpoint)
+                    NetworkActorEvent::FundingTransactionFailed(outpoint.clone())
                 }
                 Err(err) => {
                     error!("Failed to trace transaction {:?}: {:?}", &tx_hash, &err);
-                    NetworkActorEvent::FundingTransactionFailed(outpoint)
+                    NetworkActorEvent::FundingTransactionFailed(outpoint.clone())
                 }
             };
+            if let NetworkActorEvent::FundingTransactionFailed(_) = message {
+                // The inputs can be used by other fundings now.
+                let _ = chain.send_message(CkbChainMessage::ReleaseCells(inputs.clone()));
+            }
 
             // Notify outside observers.
             network
                 .send_message(NetworkActorMessage::new_event(message))
                 .expect(ASSUME_NETWORK_MYSELF_ALIVE);
-        })
-        .await;
+        }
+    }
+
+    /// Track the funding and closing transactions of the channels again after a restart: the
+    /// pending ones until they are confirmed, and the confirmed ones through chain
+    /// reorganizations.
+    fn retrace_channel_transactions<S: ChannelActorStateStore>(
+        &mut self,
+        store: &S,
+    ) -> Result<(), StoreError> {
+        let closed_channels: HashMap<Hash256, ClosedChannel> = store
+            .get_closed_channels(None)?
+            .into_iter()
+            .map(|channel| (channel.channel_id, channel))
+            .collect();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("SystemTime::now() should after UNIX_EPOCH")
+            .as_micros() as u64;
+        for (_, channel_id, _) in store.get_channel_states(None)? {
+            let Some(channel) = store.get_channel_actor_state(&channel_id)? else {
+                continue;
+            };
+            let confirmations = channel.tx_confirmations.unwrap_or(self.tx_confirmations);
+            match channel.state {
+                ChannelState::AwaitingChannelReady(_)
+                | ChannelState::ChannelReady()
+                | ChannelState::ShuttingDown(_)
+                    if channel.funding_tx.is_some() =>
+                {
+                    // We only send `channel_ready` once the funding transaction is confirmed.
+                    let committed = match channel.state {
+                        ChannelState::AwaitingChannelReady(flags) => {
+                            flags.contains(AwaitingChannelReadyFlags::OUR_CHANNEL_READY)
+                        }
+                        _ => true,
+                    };
+                    let transaction = channel.get_funding_transaction().clone().into_view();
+                    let outpoint = channel.get_funding_transaction_outpoint();
+                    if !committed {
+                        self.pending_channels.insert(outpoint.clone(), channel_id);
+                    }
+                    let callback = self.funding_tx_callback(outpoint, channel_id, &transaction);
+                    self.trace_tx_with_callback(
+                        transaction.hash(),
+                        confirmations,
+                        committed,
+                        callback,
+                    );
+                }
+                ChannelState::Closed(flags) if flags.contains(CloseFlags::COOPERATIVE) => {
+                    let (tx_hash, committed) = match closed_channels.get(&channel_id) {
+                        Some(closed) => {
+                            if closed.close_type != ChannelCloseType::Cooperative
+                                || now.saturating_sub(closed.closed_at)
+                                    > TX_REORG_WATCH_TIMEOUT.as_micros() as u64
+                            {
+                                continue;
+                            }
+                            (Byte32::from(closed.close_tx_hash), true)
+                        }
+                        // The closing transaction is not confirmed yet, its hash doesn't
+                        // depend on the signatures in the witnesses.
+                        None => match channel.build_shutdown_tx() {
+                            Ok((tx, _)) => (tx.hash(), false),
+                            Err(err) => {
+                                error!(
+                                    "Failed to build the closing transaction of channel {:?}: {}",
+                                    &channel_id, err
+                                );
+                                continue;
+                            }
+                        },
+                    };
+                    let callback = self.closing_tx_callback(
+                        channel_id,
+                        channel.peer_id.clone(),
+                        tx_hash.clone(),
+                    );
+                    self.trace_tx_with_callback(tx_hash, confirmations, committed, callback);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
     async fn on_commitment_transaction_pending(
         &mut self,
         transaction: Transaction,
         channel_id: Hash256,
+        confirmations: u64,
     ) {
         let transaction = transaction.into_view();
         let tx_hash: Byte32 = transaction.hash();
@@ -1305,25 +2015,36 @@ impl NetworkActorState {
         );
 
         let network = self.network.clone();
-        self.broadcast_tx_with_callback(transaction, move |result| {
+        self.broadcast_tx_with_callback(transaction, confirmations, move |result| {
             let message = match result {
                 Ok(Status::Committed) => {
                     info!("Commitment transaction {:?} confirmed", tx_hash,);
-                    NetworkActorEvent::CommitmentTransactionConfirmed(tx_hash.into(), channel_id)
+                    NetworkActorEvent::CommitmentTransactionConfirmed(
+                        tx_hash.clone().into(),
+                        channel_id,
+                    )
+                }
+                Ok(Status::Pending) => {
+                    // The channel is already closed, there is nothing to roll back.
+                    error!(
+                        "Commitment transaction {:?} of channel {:?} is orphaned by a chain reorganization",
+                        &tx_hash, &channel_id
+                    );
+                    return;
                 }
                 Ok(status) => {
                     error!(
                         "Commitment transaction {:?} failed to be confirmed with final status {:?}",
                         &tx_hash, &status
                     );
-                    NetworkActorEvent::CommitmentTransactionFailed(channel_id, tx_hash)
+                    NetworkActorEvent::CommitmentTransactionFailed(channel_id, tx_hash.clone())
                 }
                 Err(err) => {
                     error!(
                         "Failed to trace commitment transaction {:?}: {:?}",
                         &tx_hash, &err
                     );
-                    NetworkActorEvent::CommitmentTransactionFailed(channel_id, tx_hash)
+                    NetworkActorEvent::CommitmentTransactionFailed(channel_id, tx_hash.clone())
                 }
             };
             network
@@ -1344,9 +2065,141 @@ impl NetworkActorState {
                 return;
             }
         };
+        self.watch_funding_cell(channel_id, outpoint).await;
+        self.send_message_to_channel_actor(
+            channel_id,
+            ChannelActorMessage::new_event(ChannelEvent::FundingTransactionConfirmed),
+        );
+    }
+
+    /// Watch the funding cell of a funded channel, the channel is closed by the transaction
+    /// spending it.
+    async fn watch_funding_cell(&mut self, channel_id: Hash256, funding_out_point: OutPoint) {
+        if self.funding_cell_watches.contains_key(&channel_id) {
+            return;
+        }
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let watch_id = match call!(
+            self.chain_actor,
+            CkbChainMessage::Watch,
+            WatchTarget::OutPoint(funding_out_point),
+            sender
+        ) {
+            Ok(watch_id) => watch_id,
+            Err(err) => {
+                error!(
+                    "Failed to watch the funding cell of channel {:?}: {}",
+                    &channel_id, err
+                );
+                return;
+            }
+        };
+        self.funding_cell_watches.insert(channel_id, watch_id);
+        let network = self.network.clone();
+        ractor::concurrency::tokio_primatives::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if let ChainWatchEvent::Attached(watch_match) = event {
+                    network
+                        .send_message(NetworkActorMessage::new_event(
+                            NetworkActorEvent::FundingCellSpent(channel_id, watch_match.tx_hash),
+                        ))
+                        .expect(ASSUME_NETWORK_MYSELF_ALIVE);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Archive the channel closed by the peer, the cooperative closes and our force closes are
+    /// archived once our transactions are confirmed.
+    async fn on_funding_cell_spent<S: ChannelActorStateStore>(
+        &mut self,
+        store: &S,
+        channel_id: Hash256,
+        tx_hash: Byte32,
+    ) {
+        if let Some(watch_id) = self.funding_cell_watches.remove(&channel_id) {
+            let _ = self
+                .chain_actor
+                .send_message(CkbChainMessage::Unwatch(watch_id));
+        }
+        let mut state = match store.get_channel_actor_state(&channel_id) {
+            Ok(Some(state)) if !state.is_closed() => state,
+            Ok(_) => return,
+            Err(err) => {
+                error!(
+                    "Failed to get the state of channel {:?}: {}",
+                    &channel_id, err
+                );
+                return;
+            }
+        };
+        let close_tx = match call_t!(
+            self.chain_actor,
+            CkbChainMessage::GetTransaction,
+            DEFAULT_CHAIN_ACTOR_TIMEOUT,
+            tx_hash.clone()
+        ) {
+            Ok(Ok(tx)) => tx,
+            Ok(Err(err)) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+            Err(err) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+        };
+        let close_type = state.get_close_type(&close_tx);
+        match close_type {
+            ChannelCloseType::Cooperative | ChannelCloseType::LocalForce => return,
+            ChannelCloseType::RemoteForce => info!(
+                "Channel {:?} is force closed by the peer with transaction {:?}",
+                &channel_id, &tx_hash
+            ),
+            ChannelCloseType::Revoked => warn!(
+                "Channel {:?} is closed by the peer with revoked commitment transaction {:?}",
+                &channel_id, &tx_hash
+            ),
+        }
+        self.archive_closed_channel(store, channel_id, close_type, tx_hash)
+            .await;
+        if self.channels.contains_key(&channel_id) {
+            self.send_message_to_channel_actor(
+                channel_id,
+                ChannelActorMessage::new_event(ChannelEvent::ClosedByPeer),
+            );
+        } else {
+            state.state = ChannelState::Closed(CloseFlags::UNCOOPERATIVE);
+            let notification = ChannelStateNotification {
+                channel_id,
+                peer_id: state.peer_id.clone(),
+                state: state.state,
+            };
+            match store.insert_channel_actor_state(state) {
+                Ok(()) => self
+                    .channel_subscribers
+                    .channel_state_subscribers
+                    .send(notification),
+                Err(err) => error!(
+                    "Failed to save the closed state of channel {:?}: {}",
+                    &channel_id, err
+                ),
+            }
+        }
+        self.update_channel_backup(store, Some(channel_id));
+    }
+
+    async fn on_funding_transaction_orphaned(&mut self, outpoint: OutPoint, channel_id: Hash256) {
+        warn!(
+            "Funding transaction (outpoint {:?}) of channel {:?} is orphaned by a chain reorganization",
+            &outpoint, &channel_id
+        );
+        // Wait for the funding transaction to be confirmed again.
+        self.pending_channels.insert(outpoint, channel_id);
         self.send_message_to_channel_actor(
             channel_id,
-            ChannelActorMessage::Event(ChannelEvent::FundingTransactionConfirmed),
+            ChannelActorMessage::new_event(ChannelEvent::FundingTransactionOrphaned),
         );
     }
 
@@ -1354,10 +2207,314 @@ impl NetworkActorState {
         debug!("Commitment transaction is confirmed: {:?}", tx_hash);
         self.send_message_to_channel_actor(
             channel_id,
-            ChannelActorMessage::Event(ChannelEvent::CommitmentTransactionConfirmed),
+            ChannelActorMessage::new_event(ChannelEvent::CommitmentTransactionConfirmed),
         );
     }
 
+    /// Save the archive record of a channel closed by the confirmed transaction.
+    async fn archive_closed_channel<S: ChannelActorStateStore>(
+        &self,
+        store: &S,
+        channel_id: Hash256,
+        close_type: ChannelCloseType,
+        tx_hash: Byte32,
+    ) {
+        let state = match store.get_channel_actor_state(&channel_id) {
+            Ok(Some(state)) => state,
+            Ok(None) => {
+                warn!("Closed channel {:?} is not found in the store", &channel_id);
+                return;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to archive closed channel {:?}: {}",
+                    &channel_id, err
+                );
+                return;
+            }
+        };
+        let close_tx = match call_t!(
+            self.chain_actor,
+            CkbChainMessage::GetTransaction,
+            DEFAULT_CHAIN_ACTOR_TIMEOUT,
+            tx_hash.clone()
+        ) {
+            Ok(Ok(tx)) => tx,
+            Ok(Err(err)) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+            Err(err) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+        };
+        let funding_fee = match &state.funding_tx {
+            Some(funding_tx) => self
+                .get_funding_fee_share(&funding_tx.clone().into_view())
+                .await
+                .unwrap_or_default(),
+            None => 0,
+        };
+        let Some(closed_channel) = state.to_closed_channel(close_type, &close_tx, funding_fee)
+        else {
+            warn!("Closed channel {:?} is not funded", &channel_id);
+            return;
+        };
+        if let Err(err) = store.insert_closed_channel(closed_channel) {
+            error!(
+                "Failed to archive closed channel {:?}: {}",
+                &channel_id, err
+            );
+        }
+    }
+
+    /// Our share of the fee of the funding transaction, in proportion to the capacity of the
+    /// inputs we put in it. `None` if any of the cells it spends can't be found.
+    async fn get_funding_fee_share(&self, tx: &TransactionView) -> Option<u64> {
+        let funding_pubkey = match tokio::task::block_in_place(|| self.signer.funding_pubkey()) {
+            Ok(pubkey) => pubkey,
+            Err(err) => {
+                error!("Failed to get the funding pubkey: {}", err);
+                return None;
+            }
+        };
+        let funding_lock_script = get_script_by_contract(
+            Contract::Secp256k1Lock,
+            &blake2b_256(funding_pubkey.serialize())[0..20],
+        );
+        let mut inputs_capacity = 0u64;
+        let mut local_inputs_capacity = 0u64;
+        for out_point in tx.input_pts_iter() {
+            let previous_tx = match call_t!(
+                self.chain_actor,
+                CkbChainMessage::GetTransaction,
+                DEFAULT_CHAIN_ACTOR_TIMEOUT,
+                out_point.tx_hash()
+            ) {
+                Ok(Ok(tx)) => tx,
+                Ok(Err(err)) => {
+                    error!(
+                        "Failed to get transaction {:?}: {}",
+                        out_point.tx_hash(),
+                        err
+                    );
+                    return None;
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to get transaction {:?}: {}",
+                        out_point.tx_hash(),
+                        err
+                    );
+                    return None;
+                }
+            };
+            let index: u32 = out_point.index().unpack();
+            let output = previous_tx.outputs().get(index as usize)?;
+            let capacity: u64 = output.capacity().unpack();
+            inputs_capacity += capacity;
+            if output.lock() == funding_lock_script {
+                local_inputs_capacity += capacity;
+            }
+        }
+        let outputs_capacity = tx.outputs_capacity().ok()?.as_u64();
+        let fee = inputs_capacity.saturating_sub(outputs_capacity);
+        if inputs_capacity == 0 {
+            return Some(0);
+        }
+        Some((fee as u128 * local_inputs_capacity as u128 / inputs_capacity as u128) as u64)
+    }
+
+    /// Rewrite the static channel backup with the funded channels in the store. The channel
+    /// `closed_channel` is left out, its state may not be saved as closed yet.
+    fn update_channel_backup<S: ChannelActorStateStore>(
+        &mut self,
+        store: &S,
+        closed_channel: Option<Hash256>,
+    ) {
+        let channel_states = match store.get_active_channel_states(None) {
+            Ok(channel_states) => channel_states,
+            Err(err) => {
+                error!("Failed to update the channel backup: {}", err);
+                return;
+            }
+        };
+        let mut channels = vec![];
+        for (peer_id, channel_id, state) in channel_states {
+            if Some(channel_id) == closed_channel
+                || !matches!(
+                    state,
+                    ChannelState::AwaitingChannelReady(_)
+                        | ChannelState::ChannelReady()
+                        | ChannelState::ShuttingDown(_)
+                )
+            {
+                continue;
+            }
+            // Don't drop a channel from the backup because it can't be read now.
+            let state = match store.get_channel_actor_state(&channel_id) {
+                Ok(Some(state)) => state,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Failed to update the channel backup: {}", err);
+                    return;
+                }
+            };
+            let (Some(key_id), Some(funding_tx)) = (state.signer.key_id(), &state.funding_tx)
+            else {
+                continue;
+            };
+            let peer_address = self
+                .peer_addresses
+                .get(&peer_id)
+                .filter(|(_, session_type)| session_type.is_outbound())
+                .map(|(address, _)| address.clone());
+            channels.push(ChannelBackup {
+                channel_id,
+                peer_id,
+                peer_address,
+                funding_out_point: OutPoint::new(funding_tx.calc_tx_hash(), 0),
+                funding_udt_type_script: state.funding_udt_type_script.clone(),
+                key_id,
+            });
+        }
+        // Keep the channels being recovered until our outputs are swept.
+        channels.extend(self.recovering_channels.values().cloned());
+        if let Err(err) = self.channel_backup.write(channels) {
+            error!("Failed to write the channel backup: {}", err);
+        }
+    }
+
+    /// Watch the funding cell of a channel recovered from the static channel backup, and
+    /// connect to the peer, which is asked to force close the channel once connected.
+    async fn start_recovering_channel(&self, channel: ChannelBackup) {
+        info!(
+            "Recovering channel {:?} with peer {:?} from the channel backup",
+            &channel.channel_id, &channel.peer_id
+        );
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        if let Err(err) = call!(
+            self.chain_actor,
+            CkbChainMessage::Watch,
+            WatchTarget::OutPoint(channel.funding_out_point.clone()),
+            sender
+        ) {
+            error!(
+                "Failed to watch the funding cell of channel {:?}: {}",
+                &channel.channel_id, err
+            );
+            return;
+        }
+        let network = self.network.clone();
+        let channel_id = channel.channel_id;
+        ractor::concurrency::tokio_primatives::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if let ChainWatchEvent::Attached(watch_match) = event {
+                    network
+                        .send_message(NetworkActorMessage::new_event(
+                            NetworkActorEvent::RecoveredChannelClosed(
+                                channel_id,
+                                watch_match.tx_hash,
+                            ),
+                        ))
+                        .expect(ASSUME_NETWORK_MYSELF_ALIVE);
+                    return;
+                }
+            }
+        });
+
+        match channel.peer_address {
+            Some(address) => self
+                .network
+                .send_message(NetworkActorMessage::new_command(
+                    NetworkActorCommand::ConnectPeer(address),
+                ))
+                .expect(ASSUME_NETWORK_MYSELF_ALIVE),
+            None => warn!(
+                "No address of peer {:?} in the channel backup, waiting for it to connect",
+                &channel.peer_id
+            ),
+        }
+    }
+
+    async fn on_recovered_channel_closed(&mut self, channel_id: Hash256, tx_hash: Byte32) {
+        let channel = match self.recovering_channels.get(&channel_id) {
+            Some(channel) => channel.clone(),
+            None => return,
+        };
+        info!(
+            "Channel {:?} being recovered is closed by transaction {:?}",
+            &channel_id, &tx_hash
+        );
+        let commitment_tx = match call_t!(
+            self.chain_actor,
+            CkbChainMessage::GetTransaction,
+            DEFAULT_CHAIN_ACTOR_TIMEOUT,
+            tx_hash.clone()
+        ) {
+            Ok(Ok(tx)) => tx,
+            Ok(Err(err)) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+            Err(err) => {
+                error!("Failed to get transaction {:?}: {}", &tx_hash, err);
+                return;
+            }
+        };
+        let fee_rate = self
+            .fee_rates
+            .get(&FeeRateTarget::Closing)
+            .copied()
+            .unwrap_or(DEFAULT_FEE_RATE);
+        let signer = self.signer.clone();
+        let result = tokio::task::block_in_place(|| {
+            build_sweep_transaction(signer.as_ref(), &channel, &commitment_tx, fee_rate)
+        });
+        let sweep_tx = match result {
+            Ok(Some(sweep_tx)) => sweep_tx,
+            Ok(None) => {
+                warn!(
+                    "No output of transaction {:?} closing channel {:?} can be swept",
+                    &tx_hash, &channel_id
+                );
+                self.recovering_channels.remove(&channel_id);
+                return;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to sign the sweep transaction of channel {:?}: {}",
+                    &channel_id, err
+                );
+                return;
+            }
+        };
+        match call_t!(
+            self.chain_actor,
+            CkbChainMessage::SendTx,
+            DEFAULT_CHAIN_ACTOR_TIMEOUT,
+            sweep_tx.clone()
+        ) {
+            Ok(Ok(())) => {
+                info!(
+                    "Sent transaction {:?} sweeping our output of channel {:?}",
+                    sweep_tx.hash(),
+                    &channel_id
+                );
+                self.recovering_channels.remove(&channel_id);
+            }
+            Ok(Err(err)) => error!(
+                "Failed to send the sweep transaction of channel {:?}: {:?}",
+                &channel_id, err
+            ),
+            Err(err) => error!(
+                "Failed to send the sweep transaction of channel {:?}: {}",
+                &channel_id, err
+            ),
+        }
+    }
+
     fn send_message_to_channel_actor(&self, channel_id: Hash256, message: ChannelActorMessage) {
         match self.channels.get(&channel_id) {
             None => {
@@ -1365,18 +2522,42 @@ impl NetworkActorState {
                     "Failed to send message to channel actor: channel {:?} not found",
                     &channel_id
                 );
+                crate::metrics::actor_message_dropped("channel");
             }
             Some(actor) => {
-                actor.send_message(message).expect("channel actor alive");
+                if let Err(err) = actor.send_message(message) {
+                    error!(
+                        "Failed to send message to channel actor {:?}: {}",
+                        &channel_id, err
+                    );
+                    crate::metrics::actor_message_dropped("channel");
+                }
             }
         }
     }
 }
 
+async fn estimate_fee_rate(
+    chain_actor: &ActorRef<CkbChainMessage>,
+    target: FeeRateTarget,
+) -> Result<u64, String> {
+    call_t!(
+        chain_actor,
+        CkbChainMessage::EstimateFeeRate,
+        DEFAULT_CHAIN_ACTOR_TIMEOUT,
+        target
+    )
+    .map_err(|err| {
+        error!("Failed to estimate {:?} fee rate: {}", target, err);
+        err.to_string()
+    })
+}
+
 pub struct NetworkActorStartArguments {
     pub config: FiberConfig,
     pub tracker: TaskTracker,
     pub channel_subscribers: ChannelSubscribers,
+    pub signer: Arc<dyn Signer>,
 }
 
 #[rasync_trait]
@@ -1397,6 +2578,7 @@ where
             config,
             tracker,
             channel_subscribers,
+            signer,
         } = args;
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -1410,6 +2592,20 @@ where
                 .as_slice(),
             b"FIBER_NETWORK_ENTROPY",
         );
+        let channel_backup =
+            ChannelBackupFile::open(config.channel_backup_path(), channel_backup_key(&kp));
+        let mut recovering_channels = HashMap::new();
+        if let Some(path) = config.recover_channel_backup.as_ref() {
+            for channel in read_channel_backup(path, channel_backup.key())? {
+                if self
+                    .store
+                    .get_channel_actor_state(&channel.channel_id)?
+                    .is_none()
+                {
+                    recovering_channels.insert(channel.channel_id, channel);
+                }
+            }
+        }
         let secio_kp = SecioKeyPair::from(kp);
         let secio_pk = secio_kp.public_key();
         let handle = Handle::new(myself.clone());
@@ -1439,7 +2635,7 @@ where
             .send_message(NetworkActorMessage::new_event(
                 NetworkActorEvent::NetworkServiceEvent(NetworkServiceEvent::NetworkStarted(
                     my_peer_id.clone(),
-                    listen_addr,
+                    listen_addr.clone(),
                 )),
             ))
             .expect(ASSUME_NETWORK_MYSELF_ALIVE);
@@ -1454,7 +2650,12 @@ where
             entropy,
             network: myself,
             control,
+            listening_addr: listen_addr,
+            announced_node_name: config.announced_node_name.clone(),
+            announced_addrs: config.announced_listen_addrs.clone(),
+            chain_network: config.network,
             peer_session_map: Default::default(),
+            peer_addresses: Default::default(),
             session_channels_map: Default::default(),
             channels: Default::default(),
             to_be_accepted_channels: Default::default(),
@@ -1464,15 +2665,73 @@ where
                 .open_channel_auto_accept_min_ckb_funding_amount(),
             auto_accept_channel_ckb_funding_amount: config.auto_accept_channel_ckb_funding_amount(),
             channel_subscribers,
+            peer_pings: Default::default(),
+            ping_interval: config.ping_interval(),
+            ping_timeout: config.ping_timeout(),
+            commitment_batch_config: CommitmentBatchConfig {
+                interval: config.commitment_batch_interval(),
+                max_updates: config.commitment_batch_max_updates(),
+            },
+            tx_confirmations: config.tx_confirmations(),
+            signer,
+            channel_backup,
+            recovering_channels,
+            funding_cell_watches: Default::default(),
+            fee_rates: Default::default(),
         })
     }
 
+    async fn post_start(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        // The timer stops by itself when the actor is stopped.
+        myself.send_interval(state.ping_interval, || {
+            NetworkActorMessage::new_command(NetworkActorCommand::PingPeers)
+        });
+        myself
+            .send_message(NetworkActorMessage::new_command(
+                NetworkActorCommand::RefreshFeeRates,
+            ))
+            .expect(ASSUME_NETWORK_MYSELF_ALIVE);
+        myself.send_interval(FEE_RATE_REFRESH_INTERVAL, || {
+            NetworkActorMessage::new_command(NetworkActorCommand::RefreshFeeRates)
+        });
+        state.update_channel_backup(&self.store, None);
+        for (_, channel_id, _) in self.store.get_active_channel_states(None)? {
+            if let Some(channel) = self.store.get_channel_actor_state(&channel_id)? {
+                if channel.funding_tx.is_some()
+                    && matches!(
+                        channel.state,
+                        ChannelState::AwaitingChannelReady(_)
+                            | ChannelState::ChannelReady()
+                            | ChannelState::ShuttingDown(_)
+                    )
+                {
+                    let funding_out_point = channel.get_funding_transaction_outpoint();
+                    state
+                        .watch_funding_cell(channel_id, funding_out_point)
+                        .await;
+                }
+            }
+        }
+        state.retrace_channel_transactions(&self.store)?;
+        let recovering_channels: Vec<_> = state.recovering_channels.values().cloned().collect();
+        for channel in recovering_channels {
+            state.start_recovering_channel(channel).await;
+        }
+        Ok(())
+    }
+
     async fn handle(
         &self,
         myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
+        crate::metrics::actor_message_received("network");
+        let _timer = crate::metrics::actor_message_timer("network");
         match message {
             NetworkActorMessage::Event(event) => {
                 if let Err(err) = self.handle_event(myself, state, event).await {
@@ -1538,7 +2797,7 @@ impl Handle {
     }
 
     fn emit_event(&self, event: NetworkServiceEvent) {
-        self.send_actor_message(NetworkActorMessage::Event(
+        self.send_actor_message(NetworkActorMessage::new_event(
             NetworkActorEvent::NetworkServiceEvent(event),
         ));
     }
@@ -1640,6 +2899,7 @@ pub async fn start_ckb<S: ChannelActorStateStore + Clone + Send + Sync + 'static
     root_actor: ActorCell,
     store: S,
     channel_subscribers: ChannelSubscribers,
+    signer: Arc<dyn Signer>,
 ) -> ActorRef<NetworkActorMessage> {
     let secio_kp: SecioKeyPair = config
         .read_or_generate_secret_key()
@@ -1654,6 +2914,7 @@ pub async fn start_ckb<S: ChannelActorStateStore + Clone + Send + Sync + 'static
             config,
             tracker,
             channel_subscribers,
+            signer,
         },
         root_actor,
     )
@@ -1,5 +1,8 @@
 use molecule::prelude::Entity;
-use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use serde::{
+    de::{Error, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serializer,
+};
 use serde_with::{serde_conv, DeserializeAs, SerializeAs};
 
 pub fn from_hex<'de, D, E>(deserializer: D) -> Result<E, D::Error>
@@ -8,6 +11,15 @@ where
     E: TryFrom<Vec<u8>>,
     E::Error: core::fmt::Debug,
 {
+    if !deserializer.is_human_readable() {
+        return deserializer
+            .deserialize_byte_buf(BytesVisitor)
+            .and_then(|vec| {
+                vec.try_into().map_err(|err| {
+                    Error::custom(format!("failed to convert vector into type: {:?}", err))
+                })
+            });
+    }
     String::deserialize(deserializer)
         .and_then(|string| {
             if string.len() < 2 || &string[..2].to_lowercase() != "0x" {
@@ -28,9 +40,39 @@ where
     E: AsRef<[u8]>,
     S: Serializer,
 {
+    // Binary formats such as the store encoding keep the raw bytes.
+    if !serializer.is_human_readable() {
+        return serializer.serialize_bytes(e.as_ref());
+    }
     serializer.serialize_str(&format!("0x{}", &hex::encode(e.as_ref())))
 }
 
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("bytes")
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
 pub struct SliceHex;
 
 impl<T> SerializeAs<T> for SliceHex
@@ -143,5 +185,10 @@ mod tests {
         let json = r#"{"slice":"0x01020304","enity":"0x3500000010000000300000003100000000000000000000000000000000000000000000000000000000000000000000000000000000","bar_128":"0xdeadbeef","bar_64":"0x123"}"#;
         assert_eq!(serde_json::to_string(&foo).unwrap(), json);
         assert_eq!(serde_json::from_str::<Foo>(json).unwrap(), foo);
+
+        // Binary formats keep the raw bytes.
+        let bin = bincode::serialize(&foo).unwrap();
+        assert_eq!(&bin[..12], &[4, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(bincode::deserialize::<Foo>(&bin).unwrap(), foo);
     }
 }
@@ -24,13 +24,15 @@ use crate::{
     actors::{RootActor, RootActorMessage},
     ckb::{submit_tx, trace_tx, trace_tx_hash, CkbChainMessage, MockChainActor},
     fiber::network::NetworkActorStartArguments,
+    signer::LocalSigner,
+    store::StoreError,
     tasks::{new_tokio_cancellation_token, new_tokio_task_tracker},
     FiberConfig, NetworkServiceEvent,
 };
 
 use super::{
-    channel::{ChannelActorState, ChannelActorStateStore, ChannelState},
-    types::Hash256,
+    channel::{ChannelActorState, ChannelActorStateStore, ChannelState, ClosedChannel, TlcRecord},
+    types::{Hash256, Privkey},
     NetworkActor, NetworkActorCommand, NetworkActorMessage,
 };
 
@@ -95,15 +97,23 @@ pub struct NetworkNode {
     pub chain_actor: ActorRef<CkbChainMessage>,
     pub peer_id: PeerId,
     pub event_emitter: mpsc::Receiver<NetworkServiceEvent>,
+    pub store: MemoryStore,
 }
 
 impl NetworkNode {
     pub async fn new() -> Self {
+        Self::new_with_config(Default::default()).await
+    }
+
+    pub async fn new_with_config(config: FiberConfig) -> Self {
         let base_dir = TempDir::new("fnn-test");
         let fiber_config = FiberConfig {
             base_dir: Some(PathBuf::from(base_dir.as_ref())),
-            auto_accept_channel_ckb_funding_amount: Some(0), // Disable auto accept for unit tests
-            ..Default::default()
+            // Disable auto accept for unit tests
+            auto_accept_channel_ckb_funding_amount: config
+                .auto_accept_channel_ckb_funding_amount
+                .or(Some(0)),
+            ..config
         };
 
         let root = ROOT_ACTOR.get_or_init(get_test_root_actor).await.clone();
@@ -114,13 +124,19 @@ impl NetworkNode {
             .expect("start mock chain actor")
             .0;
 
+        let store = MemoryStore::default();
+        let signer = Arc::new(LocalSigner::new(
+            Privkey::from(&rand::random::<[u8; 32]>()).into(),
+            rand::random(),
+        ));
         let network_actor = Actor::spawn_linked(
             Some(format!("network actor at {:?}", base_dir.as_ref())),
-            NetworkActor::new(event_sender, chain_actor.clone(), MemoryStore::default()),
+            NetworkActor::new(event_sender, chain_actor.clone(), store.clone()),
             NetworkActorStartArguments {
                 config: fiber_config,
                 tracker: new_tokio_task_tracker(),
                 channel_subscribers: Default::default(),
+                signer,
             },
             root.get_cell(),
         )
@@ -153,6 +169,7 @@ impl NetworkNode {
             chain_actor,
             peer_id,
             event_emitter: event_receiver,
+            store,
         }
     }
 
@@ -232,33 +249,42 @@ impl NetworkNode {
     }
 }
 
-#[derive(Clone, Default)]
-struct MemoryStore {
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
     channel_actor_state_map: Arc<RwLock<HashMap<Hash256, ChannelActorState>>>,
+    closed_channel_map: Arc<RwLock<HashMap<Hash256, ClosedChannel>>>,
+    tlc_records: Arc<RwLock<Vec<TlcRecord>>>,
 }
 
 impl ChannelActorStateStore for MemoryStore {
-    fn get_channel_actor_state(&self, id: &Hash256) -> Option<ChannelActorState> {
-        self.channel_actor_state_map
+    fn get_channel_actor_state(
+        &self,
+        id: &Hash256,
+    ) -> Result<Option<ChannelActorState>, StoreError> {
+        Ok(self
+            .channel_actor_state_map
             .read()
             .unwrap()
             .get(id)
-            .cloned()
+            .cloned())
     }
 
-    fn insert_channel_actor_state(&self, state: ChannelActorState) {
+    fn insert_channel_actor_state(&self, state: ChannelActorState) -> Result<(), StoreError> {
         self.channel_actor_state_map
             .write()
             .unwrap()
             .insert(state.id, state);
+        Ok(())
     }
 
-    fn delete_channel_actor_state(&self, id: &Hash256) {
+    fn delete_channel_actor_state(&self, id: &Hash256) -> Result<(), StoreError> {
         self.channel_actor_state_map.write().unwrap().remove(id);
+        Ok(())
     }
 
-    fn get_channel_ids_by_peer(&self, peer_id: &PeerId) -> Vec<Hash256> {
-        self.channel_actor_state_map
+    fn get_channel_ids_by_peer(&self, peer_id: &PeerId) -> Result<Vec<Hash256>, StoreError> {
+        Ok(self
+            .channel_actor_state_map
             .read()
             .unwrap()
             .values()
@@ -269,13 +295,16 @@ impl ChannelActorStateStore for MemoryStore {
                     None
                 }
             })
-            .collect()
+            .collect())
     }
 
-    fn get_channel_states(&self, peer_id: Option<PeerId>) -> Vec<(PeerId, Hash256, ChannelState)> {
+    fn get_channel_states(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<(PeerId, Hash256, ChannelState)>, StoreError> {
         let map = self.channel_actor_state_map.read().unwrap();
         let values = map.values();
-        match peer_id {
+        Ok(match peer_id {
             Some(peer_id) => values
                 .filter_map(|state| {
                     if peer_id == state.peer_id {
@@ -288,13 +317,61 @@ impl ChannelActorStateStore for MemoryStore {
             None => values
                 .map(|state| (state.peer_id.clone(), state.id, state.state))
                 .collect(),
-        }
+        })
+    }
+
+    fn insert_closed_channel(&self, channel: ClosedChannel) -> Result<(), StoreError> {
+        self.closed_channel_map
+            .write()
+            .unwrap()
+            .insert(channel.channel_id, channel);
+        Ok(())
+    }
+
+    fn get_closed_channels(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<ClosedChannel>, StoreError> {
+        Ok(self
+            .closed_channel_map
+            .read()
+            .unwrap()
+            .values()
+            .filter(|channel| {
+                peer_id
+                    .as_ref()
+                    .map_or(true, |peer_id| &channel.peer_id == peer_id)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn insert_tlc_record(&self, record: TlcRecord) -> Result<(), StoreError> {
+        let mut records = self.tlc_records.write().unwrap();
+        records.retain(|r| {
+            (r.channel_id, r.direction, r.tlc_id)
+                != (record.channel_id, record.direction, record.tlc_id)
+        });
+        records.push(record);
+        Ok(())
+    }
+
+    fn get_tlc_records(&self, channel_id: Option<Hash256>) -> Result<Vec<TlcRecord>, StoreError> {
+        Ok(self
+            .tlc_records
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|record| channel_id.map_or(true, |id| record.channel_id == id))
+            .cloned()
+            .collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::NetworkNode;
+    use crate::{FiberConfig, NetworkServiceEvent};
 
     #[tokio::test]
     async fn test_start_network_node() {
@@ -314,4 +391,25 @@ mod tests {
     async fn test_create_two_interconnected_nodes() {
         let _two_nodes = NetworkNode::new_n_interconnected_nodes(2).await;
     }
+
+    #[tokio::test]
+    async fn test_disconnect_peer_not_answering_ping() {
+        let mut node_a = NetworkNode::new_with_config(FiberConfig {
+            ping_interval_seconds: Some(1),
+            ping_timeout_seconds: Some(1),
+            ..Default::default()
+        })
+        .await;
+        let node_b = NetworkNode::new().await;
+        node_a.connect_to(&node_b).await;
+
+        // The tentacle service of a killed network actor keeps the session open (`post_stop`
+        // is not called), but nobody answers the pings any more.
+        node_b.network_actor.kill();
+        node_a
+            .expect_event(|event| {
+                matches!(event, NetworkServiceEvent::PeerDisConnected(id, _) if id == &node_b.peer_id)
+            })
+            .await;
+    }
 }
@@ -1167,6 +1167,78 @@ impl TryFrom<molecule_fiber::ReestablishChannel> for ReestablishChannel {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping {
+    pub nonce: u64,
+}
+
+impl From<Ping> for molecule_fiber::Ping {
+    fn from(ping: Ping) -> Self {
+        molecule_fiber::Ping::new_builder()
+            .nonce(ping.nonce.pack())
+            .build()
+    }
+}
+
+impl TryFrom<molecule_fiber::Ping> for Ping {
+    type Error = Error;
+
+    fn try_from(ping: molecule_fiber::Ping) -> Result<Self, Self::Error> {
+        Ok(Ping {
+            nonce: ping.nonce().unpack(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pong {
+    pub nonce: u64,
+}
+
+impl From<Pong> for molecule_fiber::Pong {
+    fn from(pong: Pong) -> Self {
+        molecule_fiber::Pong::new_builder()
+            .nonce(pong.nonce.pack())
+            .build()
+    }
+}
+
+impl TryFrom<molecule_fiber::Pong> for Pong {
+    type Error = Error;
+
+    fn try_from(pong: molecule_fiber::Pong) -> Result<Self, Self::Error> {
+        Ok(Pong {
+            nonce: pong.nonce().unpack(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateFee {
+    pub channel_id: Hash256,
+    pub fee_rate: u64,
+}
+
+impl From<UpdateFee> for molecule_fiber::UpdateFee {
+    fn from(update_fee: UpdateFee) -> Self {
+        molecule_fiber::UpdateFee::new_builder()
+            .channel_id(update_fee.channel_id.into())
+            .fee_rate(update_fee.fee_rate.pack())
+            .build()
+    }
+}
+
+impl TryFrom<molecule_fiber::UpdateFee> for UpdateFee {
+    type Error = Error;
+
+    fn try_from(update_fee: molecule_fiber::UpdateFee) -> Result<Self, Self::Error> {
+        Ok(UpdateFee {
+            channel_id: update_fee.channel_id().into(),
+            fee_rate: update_fee.fee_rate().unpack(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum FiberMessage {
     OpenChannel(OpenChannel),
@@ -1185,11 +1257,16 @@ pub enum FiberMessage {
     RevokeAndAck(RevokeAndAck),
     RemoveTlc(RemoveTlc),
     ReestablishChannel(ReestablishChannel),
+    Ping(Ping),
+    Pong(Pong),
+    UpdateFee(UpdateFee),
 }
 
 impl FiberMessage {
-    pub fn get_channel_id(&self) -> Hash256 {
-        match &self {
+    /// Returns the id of the channel this message belongs to, or `None` for
+    /// messages that are not bound to any channel (e.g. `Ping` and `Pong`).
+    pub fn get_channel_id(&self) -> Option<Hash256> {
+        Some(match &self {
             FiberMessage::OpenChannel(open_channel) => open_channel.channel_id,
             FiberMessage::AcceptChannel(accept_channel) => accept_channel.channel_id,
             FiberMessage::CommitmentSigned(commitment_signed) => commitment_signed.channel_id,
@@ -1206,7 +1283,9 @@ impl FiberMessage {
             FiberMessage::RevokeAndAck(revoke_and_ack) => revoke_and_ack.channel_id,
             FiberMessage::RemoveTlc(remove_tlc) => remove_tlc.channel_id,
             FiberMessage::ReestablishChannel(reestablish_channel) => reestablish_channel.channel_id,
-        }
+            FiberMessage::UpdateFee(update_fee) => update_fee.channel_id,
+            FiberMessage::Ping(_) | FiberMessage::Pong(_) => return None,
+        })
     }
 }
 
@@ -1261,6 +1340,11 @@ impl From<FiberMessage> for molecule_fiber::FiberMessageUnion {
             FiberMessage::ReestablishChannel(reestablish_channel) => {
                 molecule_fiber::FiberMessageUnion::ReestablishChannel(reestablish_channel.into())
             }
+            FiberMessage::Ping(ping) => molecule_fiber::FiberMessageUnion::Ping(ping.into()),
+            FiberMessage::Pong(pong) => molecule_fiber::FiberMessageUnion::Pong(pong.into()),
+            FiberMessage::UpdateFee(update_fee) => {
+                molecule_fiber::FiberMessageUnion::UpdateFee(update_fee.into())
+            }
         }
     }
 }
@@ -1326,6 +1410,11 @@ impl TryFrom<molecule_fiber::FiberMessage> for FiberMessage {
             molecule_fiber::FiberMessageUnion::ReestablishChannel(reestablish_channel) => {
                 FiberMessage::ReestablishChannel(reestablish_channel.try_into()?)
             }
+            molecule_fiber::FiberMessageUnion::Ping(ping) => FiberMessage::Ping(ping.try_into()?),
+            molecule_fiber::FiberMessageUnion::Pong(pong) => FiberMessage::Pong(pong.try_into()?),
+            molecule_fiber::FiberMessageUnion::UpdateFee(update_fee) => {
+                FiberMessage::UpdateFee(update_fee.try_into()?)
+            }
         })
     }
 }
@@ -1383,4 +1472,17 @@ mod tests {
         let add_tlc2 = add_tlc_mol.try_into().expect("decode");
         assert_eq!(add_tlc, add_tlc2);
     }
+
+    #[test]
+    fn test_ping_pong_serialization() {
+        let ping = super::FiberMessage::Ping(super::Ping { nonce: 42 });
+        let bytes = ping.to_molecule_bytes();
+        match super::FiberMessage::from_molecule_slice(&bytes).expect("decode") {
+            super::FiberMessage::Ping(ping) => assert_eq!(ping.nonce, 42),
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert!(super::FiberMessage::Pong(super::Pong { nonce: 42 })
+            .get_channel_id()
+            .is_none());
+    }
 }
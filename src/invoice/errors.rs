@@ -2,6 +2,8 @@ use std::fmt::Display;
 use std::num::ParseIntError;
 use thiserror::Error;
 
+use crate::store::StoreError;
+
 #[derive(Error, Debug)]
 pub struct VerificationError(pub molecule::error::VerificationError);
 
@@ -55,4 +57,6 @@ pub enum InvoiceError {
     HexDecodeError(#[from] hex::FromHexError),
     #[error("Duplicated inovice found: {0}")]
     DuplicatedInvoice(String),
+    #[error("Store error: {0}")]
+    StoreError(#[from] StoreError),
 }
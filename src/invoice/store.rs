@@ -1,8 +1,8 @@
-use crate::{fiber::types::Hash256, invoice::CkbInvoice};
+use crate::{fiber::types::Hash256, invoice::CkbInvoice, store::StoreError};
 
 use super::InvoiceError;
 
 pub trait InvoiceStore {
-    fn get_invoice(&self, id: &Hash256) -> Option<CkbInvoice>;
+    fn get_invoice(&self, id: &Hash256) -> Result<Option<CkbInvoice>, StoreError>;
     fn insert_invoice(&self, invoice: CkbInvoice) -> Result<(), InvoiceError>;
 }
@@ -0,0 +1,389 @@
+//! Encrypted storage of the secret keys of the node.
+//!
+//! A key file is either a plaintext key or a keystore JSON in the layout of ckb-cli keystore
+//! files (version 3): the key is encrypted with aes-128-ctr by a key derived from the
+//! passphrase with scrypt, and authenticated by blake2b of the derived key and the ciphertext.
+//!
+//! The passphrase is read from the environment variable `FIBER_SECRET_KEY_PASSWORD`, or
+//! prompted on the terminal when an encrypted key is read, and forgotten once the keys are
+//! loaded on startup. Plaintext keys are encrypted in place once a passphrase is set.
+
+use std::{
+    env, fs,
+    io::{self, ErrorKind, IsTerminal, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use aes::Aes128;
+use ckb_hash::blake2b_256;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use thiserror::Error;
+use tracing::warn;
+use zeroize::Zeroizing;
+
+pub const PASSPHRASE_ENV_NAME: &str = "FIBER_SECRET_KEY_PASSWORD";
+
+const KEYSTORE_VERSION: u32 = 3;
+const CIPHER: &str = "aes-128-ctr";
+const KDF: &str = "scrypt";
+// The parameters used by ckb-cli, scrypt takes 256 MiB of memory with them.
+const SCRYPT_LOG_N: u8 = 18;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const DERIVED_KEY_LEN: usize = 32;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("Unsupported keystore: {0}")]
+    Unsupported(String),
+    #[error("Invalid keystore: {0}")]
+    InvalidData(String),
+}
+
+impl From<KeystoreError> for io::Error {
+    fn from(err: KeystoreError) -> Self {
+        let kind = match err {
+            KeystoreError::WrongPassphrase => ErrorKind::PermissionDenied,
+            _ => ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keystore {
+    pub crypto: KeystoreCrypto,
+    pub id: String,
+    pub version: u32,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub ciphertext: Vec<u8>,
+    pub kdf: String,
+    pub kdfparams: ScryptParams,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub mac: Vec<u8>,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CipherParams {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub iv: Vec<u8>,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScryptParams {
+    pub dklen: usize,
+    pub n: u64,
+    pub p: u32,
+    pub r: u32,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub salt: Vec<u8>,
+}
+
+impl Keystore {
+    pub fn encrypt(secret: &[u8; 32], passphrase: &[u8]) -> Self {
+        Self::encrypt_with_log_n(secret, passphrase, SCRYPT_LOG_N)
+    }
+
+    fn encrypt_with_log_n(secret: &[u8; 32], passphrase: &[u8], log_n: u8) -> Self {
+        let mut rng = thread_rng();
+        let salt: [u8; 32] = rng.gen();
+        let iv: [u8; 16] = rng.gen();
+        let kdfparams = ScryptParams {
+            dklen: DERIVED_KEY_LEN,
+            n: 1 << log_n,
+            p: SCRYPT_P,
+            r: SCRYPT_R,
+            salt: salt.to_vec(),
+        };
+        let derived_key = derive_key(passphrase, &kdfparams).expect("valid scrypt params");
+
+        let mut ciphertext = secret.to_vec();
+        Aes128Ctr::new(derived_key[0..16].into(), iv.as_slice().into())
+            .apply_keystream(&mut ciphertext);
+        let mac = compute_mac(&derived_key, &ciphertext);
+
+        let id: [u8; 16] = rng.gen();
+        Self {
+            crypto: KeystoreCrypto {
+                cipher: CIPHER.to_string(),
+                cipherparams: CipherParams { iv: iv.to_vec() },
+                ciphertext,
+                kdf: KDF.to_string(),
+                kdfparams,
+                mac: mac.to_vec(),
+            },
+            id: format_uuid(id),
+            version: KEYSTORE_VERSION,
+        }
+    }
+
+    pub fn decrypt(&self, passphrase: &[u8]) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+        let crypto = &self.crypto;
+        if crypto.cipher != CIPHER {
+            return Err(KeystoreError::Unsupported(format!(
+                "cipher {}",
+                crypto.cipher
+            )));
+        }
+        if crypto.kdf != KDF {
+            return Err(KeystoreError::Unsupported(format!("kdf {}", crypto.kdf)));
+        }
+        if crypto.cipherparams.iv.len() != 16 {
+            return Err(KeystoreError::InvalidData(
+                "iv must be 16 bytes".to_string(),
+            ));
+        }
+        // ckb-cli encrypts the extended private key, which is the private key followed by the
+        // chain code.
+        if crypto.ciphertext.len() != 32 && crypto.ciphertext.len() != 64 {
+            return Err(KeystoreError::InvalidData(
+                "ciphertext must be 32 or 64 bytes".to_string(),
+            ));
+        }
+
+        let derived_key = derive_key(passphrase, &crypto.kdfparams)?;
+        if compute_mac(&derived_key, &crypto.ciphertext).as_slice() != crypto.mac.as_slice() {
+            return Err(KeystoreError::WrongPassphrase);
+        }
+        let mut plaintext = Zeroizing::new(crypto.ciphertext.clone());
+        Aes128Ctr::new(
+            derived_key[0..16].into(),
+            crypto.cipherparams.iv.as_slice().into(),
+        )
+        .apply_keystream(&mut plaintext[..]);
+        let mut secret = Zeroizing::new([0u8; 32]);
+        secret.copy_from_slice(&plaintext[0..32]);
+        Ok(secret)
+    }
+}
+
+fn derive_key(
+    passphrase: &[u8],
+    params: &ScryptParams,
+) -> Result<Zeroizing<[u8; DERIVED_KEY_LEN]>, KeystoreError> {
+    if params.dklen != DERIVED_KEY_LEN {
+        return Err(KeystoreError::Unsupported(format!(
+            "derived key length {}",
+            params.dklen
+        )));
+    }
+    if !params.n.is_power_of_two() || params.n < 2 {
+        return Err(KeystoreError::InvalidData(format!(
+            "scrypt n {} is not a power of 2",
+            params.n
+        )));
+    }
+    let log_n = params.n.trailing_zeros() as u8;
+    let scrypt_params = scrypt::Params::new(log_n, params.r, params.p, DERIVED_KEY_LEN)
+        .map_err(|err| KeystoreError::InvalidData(err.to_string()))?;
+    let mut derived_key = Zeroizing::new([0u8; DERIVED_KEY_LEN]);
+    scrypt::scrypt(
+        passphrase,
+        &params.salt,
+        &scrypt_params,
+        &mut derived_key[..],
+    )
+    .map_err(|err| KeystoreError::InvalidData(err.to_string()))?;
+    Ok(derived_key)
+}
+
+fn compute_mac(derived_key: &[u8; DERIVED_KEY_LEN], ciphertext: &[u8]) -> [u8; 32] {
+    blake2b_256([&derived_key[16..32], ciphertext].concat())
+}
+
+// A random (version 4) uuid.
+fn format_uuid(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// Cleared by `clear_passphrase` once the keys are loaded, so that it doesn't stay in memory
+// for the lifetime of the node.
+static PASSPHRASE: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
+
+/// Read the passphrase of the node keys from the environment variable and remove the variable,
+/// so that it is not leaked to the child processes. Removing an environment variable is not
+/// thread safe, this must be called before any other thread is started, e.g. the tokio runtime.
+pub fn read_passphrase_from_env() {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV_NAME) {
+        env::remove_var(PASSPHRASE_ENV_NAME);
+        *PASSPHRASE.lock().expect("lock passphrase") = Some(Zeroizing::new(passphrase));
+    }
+}
+
+/// Forget the passphrase after all the key files are read on startup. The key files read or
+/// created after that are not encrypted.
+pub fn clear_passphrase() {
+    PASSPHRASE.lock().expect("lock passphrase").take();
+}
+
+/// The passphrase of the node keys, see `read_passphrase_from_env`. If it is not set and
+/// `prompt` is true, ask for it on the terminal.
+fn passphrase(prompt: bool) -> io::Result<Option<Zeroizing<String>>> {
+    if let Some(passphrase) = PASSPHRASE.lock().expect("lock passphrase").as_ref() {
+        return Ok(Some(passphrase.clone()));
+    }
+    if !(prompt && io::stdin().is_terminal()) {
+        return Ok(None);
+    }
+    let passphrase = Zeroizing::new(rpassword::prompt_password(
+        "Passphrase to unlock the keys of the node: ",
+    )?);
+    *PASSPHRASE.lock().expect("lock passphrase") = Some(passphrase.clone());
+    Ok(Some(passphrase))
+}
+
+fn is_keystore(content: &[u8]) -> bool {
+    content.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+}
+
+/// Decode the content of a key file, which is either a keystore or a plaintext key decoded by
+/// `decode_plaintext`. A plaintext key is encrypted in place if a passphrase is set.
+pub fn decode_key_file(
+    path: &Path,
+    content: &[u8],
+    decode_plaintext: impl FnOnce(&[u8]) -> io::Result<Zeroizing<[u8; 32]>>,
+) -> io::Result<Zeroizing<[u8; 32]>> {
+    if is_keystore(content) {
+        let keystore: Keystore = serde_json::from_slice(content)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        let passphrase = passphrase(true)?.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "key file {:?} is encrypted, set the passphrase by the environment variable {}",
+                    path, PASSPHRASE_ENV_NAME
+                ),
+            )
+        })?;
+        return Ok(keystore.decrypt(passphrase.as_bytes())?);
+    }
+
+    let secret = decode_plaintext(content)?;
+    match passphrase(false)? {
+        Some(passphrase) => {
+            // The file is overwritten, tools reading the plaintext key can't use it any more.
+            warn!(
+                "Replacing the plaintext key file {:?} with a keystore encrypted by the passphrase in {}",
+                path, PASSPHRASE_ENV_NAME
+            );
+            save_keystore(path, &Keystore::encrypt(&secret, passphrase.as_bytes()))?;
+        }
+        None => warn!(
+            "Key file {:?} is not encrypted, set the environment variable {} to encrypt it",
+            path, PASSPHRASE_ENV_NAME
+        ),
+    }
+    Ok(secret)
+}
+
+/// Write the key to a new key file, encrypted if a passphrase is set, otherwise write the
+/// plaintext content.
+pub fn write_key_file(path: &Path, secret: &[u8; 32], plaintext: &[u8]) -> io::Result<()> {
+    match passphrase(false)? {
+        Some(passphrase) => save_keystore(path, &Keystore::encrypt(secret, passphrase.as_bytes())),
+        None => write_private_file(path, plaintext),
+    }
+}
+
+fn save_keystore(path: &Path, keystore: &Keystore) -> io::Result<()> {
+    let content = serde_json::to_vec(keystore).expect("serialize keystore");
+    write_private_file(path, &content)
+}
+
+// Write to a temporary file first, so that the key is never lost if the write fails.
+pub(crate) fn write_private_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    // A temporary file left by a failed write may be read-only.
+    match fs::remove_file(&tmp_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Never let the key be readable by others, not even before the permissions are set.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o400))?;
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = fs::metadata(&tmp_path)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&tmp_path, permissions)?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use ctr::cipher::{KeyIvInit, StreamCipher};
+
+    use super::{compute_mac, derive_key, Aes128Ctr, Keystore, KeystoreError};
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let secret = [42u8; 32];
+        let keystore = Keystore::encrypt_with_log_n(&secret, b"passphrase", 10);
+        let json = serde_json::to_string(&keystore).expect("serialize keystore");
+        let keystore: Keystore = serde_json::from_str(&json).expect("deserialize keystore");
+        assert_eq!(keystore.crypto.kdfparams.n, 1024);
+        assert_eq!(*keystore.decrypt(b"passphrase").unwrap(), secret);
+        assert!(matches!(
+            keystore.decrypt(b"wrong passphrase"),
+            Err(KeystoreError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_keystore_extended_key() {
+        // ckb-cli keystores hold the private key and the chain code.
+        let mut keystore = Keystore::encrypt_with_log_n(&[0u8; 32], b"passphrase", 10);
+        let derived_key = derive_key(b"passphrase", &keystore.crypto.kdfparams).unwrap();
+        let mut ciphertext = [[42u8; 32], [7u8; 32]].concat();
+        Aes128Ctr::new(
+            derived_key[0..16].into(),
+            keystore.crypto.cipherparams.iv.as_slice().into(),
+        )
+        .apply_keystream(&mut ciphertext);
+        keystore.crypto.mac = compute_mac(&derived_key, &ciphertext).to_vec();
+        keystore.crypto.ciphertext = ciphertext;
+        assert_eq!(*keystore.decrypt(b"passphrase").unwrap(), [42u8; 32]);
+    }
+}
@@ -12,6 +12,9 @@ pub use cch::{start_cch, CchActor, CchConfig};
 pub mod rpc;
 pub use rpc::{start_rpc, RpcConfig};
 pub mod invoice;
+pub mod keystore;
+pub mod mnemonic;
+pub mod signer;
 pub mod store;
 
 mod errors;
@@ -21,6 +24,8 @@ pub mod actors;
 
 pub mod tasks;
 
+pub mod metrics;
+
 pub fn get_node_prefix() -> &'static str {
     static INSTANCE: once_cell::sync::OnceCell<String> = once_cell::sync::OnceCell::new();
     INSTANCE.get_or_init(|| std::env::var("LOG_PREFIX").unwrap_or_else(|_| "".to_string()))
@@ -1,28 +1,45 @@
 use fnn::cch::CchMessage;
 use fnn::ckb::contracts::init_contracts_context;
+use fnn::signer::new_signer;
 use fnn::store::Store;
-use ractor::Actor;
+use ractor::{Actor, ActorRef, OutputPort};
 use tentacle::multiaddr::Multiaddr;
 use tokio::sync::mpsc;
 use tokio::{select, signal};
-use tracing::{debug, error, info, info_span, trace};
+use tracing::{debug, error, info, info_span, trace, warn};
 use tracing_subscriber::field::MakeExt;
 use tracing_subscriber::{fmt, EnvFilter};
 
 use std::str::FromStr;
+use std::sync::Arc;
 
 use core::default::Default;
 use fnn::actors::RootActor;
-use fnn::ckb::CkbChainActor;
-use fnn::fiber::{channel::ChannelSubscribers, NetworkActorCommand, NetworkActorMessage};
+use fnn::ckb::{CkbChainActor, CkbChainMessage};
+use fnn::fiber::{
+    channel::ChannelSubscribers, network::PeerNotification, NetworkActorCommand,
+    NetworkActorMessage, NetworkServiceEvent,
+};
+use fnn::metrics::{start_metrics_server, NETWORK_EVENT_QUEUE_SIZE, PEERS_CONNECTED};
+use fnn::rpc::start_subscription_actor;
 use fnn::tasks::{
     cancel_tasks_and_wait_for_completion, new_tokio_cancellation_token, new_tokio_task_tracker,
 };
 use fnn::{start_cch, start_ckb, start_ldk, start_rpc, Config};
 use tracing_subscriber::fmt::format;
 
-#[tokio::main]
-pub async fn main() {
+pub fn main() {
+    // The passphrase environment variable is removed, which must happen before the runtime
+    // starts its threads.
+    fnn::keystore::read_passphrase_from_env();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("build tokio runtime")
+        .block_on(run());
+}
+
+async fn run() {
     // ractor will set "id" for each actor:
     // https://github.com/slawlor/ractor/blob/67d657e4cdcb8884a9ccc9b758704cbb447ac163/ractor/src/actor/mod.rs#L701
     // here we map it with the node prefix
@@ -61,10 +78,23 @@ pub async fn main() {
     let token = new_tokio_cancellation_token();
     let root_actor = RootActor::start(tracker, token).await;
 
-    let store = Store::new(config.fiber.as_ref().unwrap().store_path());
+    let fiber_config = config.fiber.as_ref().unwrap();
+    if let Some(checkpoint) = &fiber_config.restore_store_checkpoint {
+        info!("Restoring the store from checkpoint {:?}", checkpoint);
+        if Store::restore_checkpoint(checkpoint, fiber_config.store_path())
+            .expect("restore store checkpoint")
+        {
+            warn!(
+                "RESTORED THE STORE FROM CHECKPOINT {:?}. The channel states may be stale: if the peers have newer commitments, broadcasting ours loses the channel funds. Ask the peers to force close the channels instead of closing them ourselves.",
+                checkpoint
+            );
+        }
+    }
+    let store = Store::new(fiber_config.store_path()).expect("open store");
     let subscribers = ChannelSubscribers::default();
+    let cch_order_subscribers = Arc::new(OutputPort::default());
 
-    let fiber_command_sender = match config.fiber {
+    let (fiber_command_sender, ckb_chain_actor) = match config.fiber {
         Some(fiber_config) => {
             // TODO: this is not a super user friendly error message which has actionable information
             // for the user to fix the error and start the node.
@@ -73,9 +103,11 @@ pub async fn main() {
 
             let _ = init_contracts_context(fiber_config.network, Some(&ckb_config));
 
-            let ckb_actor = Actor::spawn_linked(
+            let signer = new_signer(&fiber_config, &ckb_config).expect("create signer");
+
+            let ckb_actor: ActorRef<CkbChainMessage> = Actor::spawn_linked(
                 Some("ckb".to_string()),
-                CkbChainActor {},
+                CkbChainActor::new(signer.clone()),
                 ckb_config,
                 root_actor.get_cell(),
             )
@@ -85,18 +117,27 @@ pub async fn main() {
 
             const CHANNEL_SIZE: usize = 4000;
             let (event_sender, mut event_receiver) = mpsc::channel(CHANNEL_SIZE);
+            // Don't hold a sender, the event processing service stops when all senders are dropped.
+            let event_queue = event_sender.downgrade();
+
+            if let Some(addr) = &fiber_config.metrics_listening_addr {
+                start_metrics_server(addr, store.clone())
+                    .await
+                    .expect("start metrics server");
+            }
 
             let bootnodes = fiber_config.bootnode_addrs.clone();
 
             info!("Starting fiber");
             let network_actor = start_ckb(
                 fiber_config,
-                ckb_actor,
+                ckb_actor.clone(),
                 event_sender,
                 new_tokio_task_tracker(),
                 root_actor.get_cell(),
                 store.clone(),
                 subscribers.clone(),
+                signer,
             )
             .await;
 
@@ -108,6 +149,7 @@ pub async fn main() {
                     .expect("ckb actor alive")
             }
 
+            let peer_subscribers = subscribers.peer_subscribers.clone();
             new_tokio_task_tracker().spawn(async move {
                 let token = new_tokio_cancellation_token();
                 loop {
@@ -120,6 +162,20 @@ pub async fn main() {
                                 }
                                 Some(event) => {
                                     trace!("Received event from ckb service: {:?}", event);
+                                    if let Some(sender) = event_queue.upgrade() {
+                                        NETWORK_EVENT_QUEUE_SIZE.set((sender.max_capacity() - sender.capacity()) as i64);
+                                    }
+                                    match event {
+                                        NetworkServiceEvent::PeerConnected(peer_id, addr) => {
+                                            PEERS_CONNECTED.inc();
+                                            peer_subscribers.send(PeerNotification::Connected(peer_id, addr));
+                                        }
+                                        NetworkServiceEvent::PeerDisConnected(peer_id, addr) => {
+                                            PEERS_CONNECTED.dec();
+                                            peer_subscribers.send(PeerNotification::Disconnected(peer_id, addr));
+                                        }
+                                        _ => {}
+                                    }
                                 }
                             }
                         }
@@ -132,10 +188,12 @@ pub async fn main() {
                 debug!("Event processing service exited");
             });
 
-            Some(network_actor)
+            (Some(network_actor), Some(ckb_actor))
         }
-        None => None,
+        None => (None, None),
     };
+    // All the keys are loaded by now.
+    fnn::keystore::clear_passphrase();
 
     let cch_actor = match config.cch {
         Some(cch_config) => {
@@ -147,6 +205,7 @@ pub async fn main() {
                 new_tokio_cancellation_token(),
                 root_actor.get_cell(),
                 fiber_command_sender.clone(),
+                cch_order_subscribers.clone(),
             )
             .await
             {
@@ -189,7 +248,22 @@ pub async fn main() {
             }
 
             info!("Starting rpc");
-            let handle = start_rpc(rpc_config, fiber_command_sender, cch_actor, store).await;
+            let subscription_actor = start_subscription_actor(
+                store.clone(),
+                &subscribers,
+                &cch_order_subscribers,
+                root_actor.get_cell(),
+            )
+            .await;
+            let handle = start_rpc(
+                rpc_config,
+                fiber_command_sender,
+                cch_actor,
+                ckb_chain_actor,
+                subscription_actor,
+                store,
+            )
+            .await;
             Some(handle)
         }
         None => None,
@@ -0,0 +1,345 @@
+//! Prometheus metrics of the node, served on `/metrics` of `fiber.metrics_listening_addr`.
+//!
+//! The counters and histograms are updated where the events happen, while the gauges of the
+//! channels are computed from the store on a blocking thread, at most once per
+//! `CHANNEL_METRICS_MAX_AGE`.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use ckb_types::{packed::Script, prelude::Entity};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, GaugeVec, HistogramTimer, HistogramVec, IntCounterVec,
+    IntGauge, IntGaugeVec, TextEncoder,
+};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::{
+    cch::CchOrderStatus,
+    fiber::channel::{ChannelActorStateStore, ChannelState},
+    tasks::{new_tokio_cancellation_token, new_tokio_task_tracker},
+};
+
+pub static PEERS_CONNECTED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("fiber_peers_connected", "Number of connected peers").unwrap()
+});
+
+static CHANNELS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("fiber_channels", "Number of channels by state", &["state"]).unwrap()
+});
+
+/// The channel metrics computed by a scrape are reused by the scrapes within this duration.
+const CHANNEL_METRICS_MAX_AGE: Duration = Duration::from_secs(10);
+
+// The balances are in shannons for ckb and in the smallest unit for udt. Prometheus samples are
+// f64, which represent the amounts exactly only up to 2^53, about 90 million CKB in shannons,
+// larger amounts are rounded.
+static LOCAL_BALANCE: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "fiber_channel_local_balance",
+        "Local balance of the open channels by asset, rounded above 2^53",
+        &["asset"]
+    )
+    .unwrap()
+});
+
+static REMOTE_BALANCE: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "fiber_channel_remote_balance",
+        "Remote balance of the open channels by asset, rounded above 2^53",
+        &["asset"]
+    )
+    .unwrap()
+});
+
+static TLCS_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fiber_tlcs_in_flight",
+        "Number of TLCs which are not removed yet",
+        &["direction"]
+    )
+    .unwrap()
+});
+
+pub static COMMITMENT_UPDATES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "fiber_commitment_updates_total",
+        "Number of commitment number increments",
+        &["party"]
+    )
+    .unwrap()
+});
+
+static CCH_ORDERS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fiber_cch_orders",
+        "Number of cross chain hub orders by status",
+        &["status"]
+    )
+    .unwrap()
+});
+
+static CKB_CHAIN_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "fiber_ckb_chain_request_duration_seconds",
+        "Time to handle the requests of the ckb chain actor, mostly spent on the ckb rpc",
+        &["request"]
+    )
+    .unwrap()
+});
+
+static ACTOR_MESSAGE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "fiber_actor_message_duration_seconds",
+        "Time to handle a message by the actors, the count is the number of handled messages",
+        &["actor"]
+    )
+    .unwrap()
+});
+
+static ACTOR_MAILBOX_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fiber_actor_mailbox_size",
+        "Number of messages sent to the actors and not handled yet",
+        &["actor"]
+    )
+    .unwrap()
+});
+
+pub static NETWORK_EVENT_QUEUE_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "fiber_network_event_queue_size",
+        "Number of network service events waiting to be processed"
+    )
+    .unwrap()
+});
+
+/// Time the handling of a message by the actor, until the timer is dropped.
+pub fn actor_message_timer(actor: &str) -> HistogramTimer {
+    ACTOR_MESSAGE_DURATION
+        .with_label_values(&[actor])
+        .start_timer()
+}
+
+/// Count a message built for the actor. ractor doesn't expose the length of the mailboxes, so
+/// they are counted by the constructors of the messages, e.g. `NetworkActorMessage::new_event`,
+/// and by `actor_message_received` when the actor starts handling them.
+pub fn actor_message_sent(actor: &str) {
+    ACTOR_MAILBOX_SIZE.with_label_values(&[actor]).inc();
+}
+
+pub fn actor_message_received(actor: &str) {
+    ACTOR_MAILBOX_SIZE.with_label_values(&[actor]).dec();
+}
+
+/// Uncount a message which never reaches the actor, e.g. the channel is unknown or the actor
+/// has stopped.
+pub fn actor_message_dropped(actor: &str) {
+    ACTOR_MAILBOX_SIZE.with_label_values(&[actor]).dec();
+}
+
+pub fn ckb_chain_request_timer(request: &str) -> HistogramTimer {
+    CKB_CHAIN_REQUEST_DURATION
+        .with_label_values(&[request])
+        .start_timer()
+}
+
+pub fn update_cch_order_status(status: CchOrderStatus, previous_status: Option<CchOrderStatus>) {
+    if let Some(previous_status) = previous_status {
+        CCH_ORDERS
+            .with_label_values(&[cch_order_status_label(previous_status)])
+            .dec();
+    }
+    CCH_ORDERS
+        .with_label_values(&[cch_order_status_label(status)])
+        .inc();
+}
+
+fn cch_order_status_label(status: CchOrderStatus) -> &'static str {
+    match status {
+        CchOrderStatus::Pending => "pending",
+        CchOrderStatus::Accepted => "accepted",
+        CchOrderStatus::InFlight => "in_flight",
+        CchOrderStatus::Succeeded => "succeeded",
+        CchOrderStatus::Failed => "failed",
+    }
+}
+
+fn channel_state_label(state: &ChannelState) -> &'static str {
+    match state {
+        ChannelState::NegotiatingFunding(_) => "negotiating_funding",
+        ChannelState::CollaboratingFundingTx(_) => "collaborating_funding_tx",
+        ChannelState::SigningCommitment(_) => "signing_commitment",
+        ChannelState::AwaitingTxSignatures(_) => "awaiting_tx_signatures",
+        ChannelState::AwaitingChannelReady(_) => "awaiting_channel_ready",
+        ChannelState::ChannelReady() => "channel_ready",
+        ChannelState::ShuttingDown(_) => "shutting_down",
+        ChannelState::Closed(_) => "closed",
+    }
+}
+
+/// `ckb` for the native asset, or the hash of the udt type script.
+fn asset_label(udt_type_script: &Option<Script>) -> String {
+    match udt_type_script {
+        Some(script) => format!("0x{}", hex::encode(script.calc_script_hash().as_slice())),
+        None => "ckb".to_string(),
+    }
+}
+
+fn update_channel_metrics<S: ChannelActorStateStore>(store: &S) -> Result<(), String> {
+    let mut channels = HashMap::new();
+    let mut local_balances: HashMap<String, u128> = HashMap::new();
+    let mut remote_balances: HashMap<String, u128> = HashMap::new();
+    let (mut offered_tlcs, mut received_tlcs) = (0, 0);
+    for (_, channel_id, state) in store.get_channel_states(None).map_err(|e| e.to_string())? {
+        *channels.entry(channel_state_label(&state)).or_insert(0) += 1;
+        if matches!(state, ChannelState::Closed(_)) {
+            continue;
+        }
+        let Some(state) = store
+            .get_channel_actor_state(&channel_id)
+            .map_err(|e| e.to_string())?
+        else {
+            continue;
+        };
+        let asset = asset_label(&state.funding_udt_type_script);
+        *local_balances.entry(asset.clone()).or_default() += state.to_local_amount;
+        *remote_balances.entry(asset).or_default() += state.to_remote_amount;
+        for tlc in state.get_tlcs_in_flight() {
+            if tlc.is_offered() {
+                offered_tlcs += 1;
+            } else {
+                received_tlcs += 1;
+            }
+        }
+    }
+
+    // Reset the gauges so that the states and assets without channels are not left behind.
+    CHANNELS.reset();
+    for (state, count) in channels {
+        CHANNELS.with_label_values(&[state]).set(count);
+    }
+    LOCAL_BALANCE.reset();
+    for (asset, balance) in local_balances {
+        LOCAL_BALANCE
+            .with_label_values(&[&asset])
+            .set(balance as f64);
+    }
+    REMOTE_BALANCE.reset();
+    for (asset, balance) in remote_balances {
+        REMOTE_BALANCE
+            .with_label_values(&[&asset])
+            .set(balance as f64);
+    }
+    TLCS_IN_FLIGHT
+        .with_label_values(&["offered"])
+        .set(offered_tlcs);
+    TLCS_IN_FLIGHT
+        .with_label_values(&["received"])
+        .set(received_tlcs);
+    Ok(())
+}
+
+#[derive(Clone)]
+struct MetricsState<S> {
+    store: S,
+    // When the channel metrics were computed, the lock also keeps the concurrent scrapes from
+    // scanning the store at the same time.
+    channel_metrics_updated_at: Arc<Mutex<Option<Instant>>>,
+}
+
+async fn render<S>(state: &MetricsState<S>) -> Result<String, String>
+where
+    S: ChannelActorStateStore + Clone + Send + Sync + 'static,
+{
+    let mut updated_at = state.channel_metrics_updated_at.lock().await;
+    if !updated_at.is_some_and(|at| at.elapsed() < CHANNEL_METRICS_MAX_AGE) {
+        let store = state.store.clone();
+        tokio::task::spawn_blocking(move || update_channel_metrics(&store))
+            .await
+            .map_err(|e| e.to_string())??;
+        *updated_at = Some(Instant::now());
+    }
+    drop(updated_at);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(buffer).map_err(|e| e.to_string())
+}
+
+async fn metrics<S>(State(state): State<MetricsState<S>>) -> Result<String, (StatusCode, String)>
+where
+    S: ChannelActorStateStore + Clone + Send + Sync + 'static,
+{
+    render(&state).await.map_err(|err| {
+        error!("Failed to render metrics: {}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err)
+    })
+}
+
+pub async fn start_metrics_server<S>(listening_addr: &str, store: S) -> std::io::Result<()>
+where
+    S: ChannelActorStateStore + Clone + Send + Sync + 'static,
+{
+    let app = Router::new()
+        .route("/metrics", get(metrics::<S>))
+        .with_state(MetricsState {
+            store,
+            channel_metrics_updated_at: Default::default(),
+        });
+    let listener = tokio::net::TcpListener::bind(listening_addr).await?;
+    info!("Serving metrics on {}", listener.local_addr()?);
+    let token = new_tokio_cancellation_token();
+    new_tokio_task_tracker().spawn(async move {
+        if let Err(err) = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { token.cancelled().await })
+            .await
+        {
+            error!("Metrics server exited: {}", err);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render, MetricsState, CCH_ORDERS};
+    use crate::{cch::CchOrderStatus, store::Store};
+
+    #[tokio::test]
+    async fn test_render_metrics() {
+        // Other tests may update the cch orders concurrently, so only check the differences.
+        let accepted = CCH_ORDERS.with_label_values(&["accepted"]).get();
+        super::update_cch_order_status(CchOrderStatus::Pending, None);
+        super::update_cch_order_status(CchOrderStatus::Accepted, Some(CchOrderStatus::Pending));
+        assert_eq!(
+            CCH_ORDERS.with_label_values(&["accepted"]).get(),
+            accepted + 1
+        );
+
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let store = Store::new(dir.path()).expect("open store");
+        let state = MetricsState {
+            store,
+            channel_metrics_updated_at: Default::default(),
+        };
+        let text = render(&state).await.unwrap();
+        assert!(text.contains("fiber_cch_orders{status=\"accepted\"}"));
+        assert!(text.contains("fiber_tlcs_in_flight{direction=\"offered\"} 0"));
+        let updated_at = state.channel_metrics_updated_at.lock().await.unwrap();
+        render(&state).await.unwrap();
+        assert_eq!(
+            *state.channel_metrics_updated_at.lock().await,
+            Some(updated_at)
+        );
+    }
+}
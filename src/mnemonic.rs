@@ -0,0 +1,126 @@
+//! Derive all the secrets of the node from a single BIP39 mnemonic.
+//!
+//! The mnemonic file contains the 24 words of the mnemonic, or a keystore of its entropy if a
+//! passphrase is set, see [`crate::keystore`]. The keys are derived from the BIP39 seed with
+//! BIP32, the funding key uses the same path as the first address of ckb-cli and Neuron, so
+//! the funds are also accessible from these wallets.
+
+use std::{io, path::Path, str::FromStr};
+
+use bip39::Mnemonic;
+use bitcoin::{
+    bip32::{DerivationPath, ExtendedPrivKey},
+    secp256k1::Secp256k1,
+    Network,
+};
+use rand::{thread_rng, Rng};
+use secp256k1::SecretKey;
+use tracing::info;
+use zeroize::Zeroizing;
+
+use crate::keystore;
+
+const MNEMONIC_ENTROPY_LEN: usize = 32;
+const FUNDING_KEY_PATH: &str = "m/44'/309'/0'/0/0";
+const NODE_KEY_PATH: &str = "m/1017'/309'/0'";
+const CHANNEL_MASTER_KEY_PATH: &str = "m/1017'/309'/1'";
+
+/// The BIP39 seed of the node mnemonic.
+pub struct NodeSeed(Zeroizing<[u8; 64]>);
+
+impl NodeSeed {
+    pub fn from_mnemonic(mnemonic: &Mnemonic) -> Self {
+        Self(Zeroizing::new(mnemonic.to_seed("")))
+    }
+
+    fn derive(&self, path: &str) -> Zeroizing<[u8; 32]> {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str(path).expect("valid derivation path");
+        let key = ExtendedPrivKey::new_master(Network::Bitcoin, self.0.as_slice())
+            .and_then(|master| master.derive_priv(&secp, &path))
+            .expect("derive key from seed");
+        Zeroizing::new(key.private_key.secret_bytes())
+    }
+
+    /// The secret key of the p2p network identity.
+    pub fn node_key(&self) -> Zeroizing<[u8; 32]> {
+        self.derive(NODE_KEY_PATH)
+    }
+
+    /// The secret key of the secp256k1 lock script which funds the channels.
+    pub fn funding_key(&self) -> SecretKey {
+        SecretKey::from_slice(self.derive(FUNDING_KEY_PATH).as_slice())
+            .expect("derived key is valid")
+    }
+
+    /// The master key of the channel keys, see [`crate::signer::LocalSigner`].
+    pub fn channel_master_key(&self) -> Zeroizing<[u8; 32]> {
+        self.derive(CHANNEL_MASTER_KEY_PATH)
+    }
+}
+
+fn parse_mnemonic(content: &[u8]) -> io::Result<Zeroizing<[u8; MNEMONIC_ENTROPY_LEN]>> {
+    let words = std::str::from_utf8(content)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mnemonic = Mnemonic::parse(words.trim())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let entropy = Zeroizing::new(mnemonic.to_entropy());
+    let mut result = Zeroizing::new([0u8; MNEMONIC_ENTROPY_LEN]);
+    if entropy.len() != MNEMONIC_ENTROPY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "mnemonic must have 24 words",
+        ));
+    }
+    result.copy_from_slice(&entropy);
+    Ok(result)
+}
+
+/// Read the mnemonic from the file. If the file doesn't exist, a new mnemonic is generated
+/// and saved when `generate` is true, otherwise returns `None`.
+pub fn read_or_generate(path: &Path, generate: bool) -> io::Result<Option<NodeSeed>> {
+    let entropy = match std::fs::read(path) {
+        Ok(content) => {
+            let content = Zeroizing::new(content);
+            keystore::decode_key_file(path, &content, parse_mnemonic)?
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound && generate => {
+            let entropy = Zeroizing::new(thread_rng().gen::<[u8; MNEMONIC_ENTROPY_LEN]>());
+            let mnemonic = Mnemonic::from_entropy(entropy.as_slice()).expect("valid entropy");
+            let words = Zeroizing::new(mnemonic.to_string());
+            keystore::write_key_file(path, &entropy, words.as_bytes())?;
+            info!(
+                "Generated a new mnemonic in {:?}, write it down as the backup of the node",
+                path
+            );
+            entropy
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mnemonic = Mnemonic::from_entropy(entropy.as_slice()).expect("valid entropy");
+    Ok(Some(NodeSeed::from_mnemonic(&mnemonic)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_mnemonic, read_or_generate};
+
+    #[test]
+    fn test_keys_derived_from_mnemonic_file() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("mnemonic");
+        assert!(read_or_generate(&path, false).unwrap().is_none());
+
+        let seed = read_or_generate(&path, true).unwrap().expect("generated");
+        let content = std::fs::read(&path).expect("read mnemonic");
+        assert_eq!(String::from_utf8_lossy(&content).split(' ').count(), 24);
+        parse_mnemonic(&content).expect("valid mnemonic");
+
+        let restored = read_or_generate(&path, false).unwrap().expect("restored");
+        assert_eq!(*seed.node_key(), *restored.node_key());
+        assert_eq!(seed.funding_key(), restored.funding_key());
+        assert_eq!(*seed.channel_master_key(), *restored.channel_master_key());
+        assert_ne!(*seed.node_key(), *seed.channel_master_key());
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use crate::log_and_error;
+use crate::store::AdminStore;
+use jsonrpsee::{
+    core::async_trait,
+    proc_macros::rpc,
+    types::{error::CALL_EXECUTION_FAILED_CODE, ErrorObjectOwned},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateCheckpointParams {
+    // The directory to write the checkpoint to, which must not exist yet.
+    pub path: PathBuf,
+}
+
+#[rpc(server)]
+pub trait AdminRpc {
+    #[method(name = "create_checkpoint")]
+    async fn create_checkpoint(
+        &self,
+        params: CreateCheckpointParams,
+    ) -> Result<(), ErrorObjectOwned>;
+
+    #[method(name = "compact_store")]
+    async fn compact_store(&self) -> Result<(), ErrorObjectOwned>;
+}
+
+pub struct AdminRpcServerImpl<S> {
+    store: S,
+}
+
+impl<S> AdminRpcServerImpl<S> {
+    pub fn new(store: S) -> Self {
+        AdminRpcServerImpl { store }
+    }
+}
+
+#[async_trait]
+impl<S> AdminRpcServer for AdminRpcServerImpl<S>
+where
+    S: AdminStore + Send + Sync + 'static,
+{
+    async fn create_checkpoint(
+        &self,
+        params: CreateCheckpointParams,
+    ) -> Result<(), ErrorObjectOwned> {
+        // Checkpoints hard link the database files, so this is quick unless the store is on
+        // another filesystem.
+        match tokio::task::block_in_place(|| self.store.create_checkpoint(&params.path)) {
+            Ok(()) => Ok(()),
+            Err(err) => log_and_error!(params, err.to_string()),
+        }
+    }
+
+    async fn compact_store(&self) -> Result<(), ErrorObjectOwned> {
+        match tokio::task::block_in_place(|| self.store.compact()) {
+            Ok(()) => Ok(()),
+            Err(err) => log_and_error!((), err.to_string()),
+        }
+    }
+}
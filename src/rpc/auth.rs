@@ -0,0 +1,359 @@
+//! Token authentication of the rpc service.
+//!
+//! Each token is granted a [`RpcScope`]. The tokens are sent in the `Authorization: Bearer`
+//! header, and the methods of the JSON-RPC requests, including the batched ones, are checked
+//! against the scope of the token before the requests reach the jsonrpsee server.
+//!
+//! The messages of a WebSocket connection don't pass this layer, so the scope of the token
+//! the connection is opened with is kept for the connection, and the method calls over it are
+//! checked by the rpc middleware [`ScopeLayer`].
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+
+use hyper::{
+    body::HttpBody as _,
+    header::{AUTHORIZATION, UPGRADE},
+    Body, Request, Response, StatusCode,
+};
+use jsonrpsee::{
+    server::{middleware::rpc::RpcServiceT, MethodResponse},
+    types::{ErrorObject, Request as RpcRequest},
+};
+use serde_json::Value;
+use tower::{Layer, Service};
+
+// The same as the default max request body size of the jsonrpsee server.
+const MAX_REQUEST_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+// The error code of the calls out of the scope of the token over a WebSocket connection.
+const FORBIDDEN_ERROR_CODE: i32 = -32403;
+
+const READ_ONLY_METHODS: &[&str] = &[
+    "node_info",
+    "list_peers",
+    "list_peer_latencies",
+    "list_channels",
+    "list_closed_channels",
+    "accounting_report",
+    "get_balance",
+    "list_unspent",
+    "new_address",
+    "parse_invoice",
+    "get_receive_btc_order",
+    "subscribe_channel_states",
+    "unsubscribe_channel_states",
+    "subscribe_tlcs",
+    "unsubscribe_tlcs",
+    "subscribe_invoices",
+    "unsubscribe_invoices",
+    "subscribe_peers",
+    "unsubscribe_peers",
+    "subscribe_cch_orders",
+    "unsubscribe_cch_orders",
+];
+
+const INVOICE_METHODS: &[&str] = &[
+    "new_invoice",
+    "parse_invoice",
+    "subscribe_invoices",
+    "unsubscribe_invoices",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RpcScope {
+    /// The methods which don't change the node or move funds.
+    ReadOnly,
+    /// Creating and parsing invoices.
+    Invoice,
+    /// All the methods.
+    Admin,
+}
+
+impl RpcScope {
+    pub fn allows(&self, method: &str) -> bool {
+        match self {
+            RpcScope::ReadOnly => READ_ONLY_METHODS.contains(&method),
+            RpcScope::Invoice => INVOICE_METHODS.contains(&method),
+            RpcScope::Admin => true,
+        }
+    }
+}
+
+impl FromStr for RpcScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(RpcScope::ReadOnly),
+            "invoice" => Ok(RpcScope::Invoice),
+            "admin" => Ok(RpcScope::Admin),
+            _ => Err(format!("unknown rpc scope {}", s)),
+        }
+    }
+}
+
+/// Parse the tokens in the form of `<scope>:<token>`.
+pub fn parse_auth_tokens(tokens: &[String]) -> Result<HashMap<String, RpcScope>, String> {
+    tokens
+        .iter()
+        .map(|entry| {
+            let (scope, token) = entry
+                .split_once(':')
+                .ok_or_else(|| "rpc auth token should be <scope>:<token>".to_string())?;
+            if token.is_empty() {
+                return Err(format!("empty rpc auth token for scope {}", scope));
+            }
+            Ok((token.to_string(), scope.parse()?))
+        })
+        .collect()
+}
+
+/// The scope of the token a WebSocket connection is opened with, shared by the layers of
+/// the connection. It is not set for the connections without WebSocket upgrades.
+pub type ConnectionScope = Arc<OnceLock<RpcScope>>;
+
+/// Reject the requests without a token allowing all their methods. All the requests are
+/// passed through if there are no tokens.
+#[derive(Clone)]
+pub struct AuthLayer {
+    tokens: Option<Arc<HashMap<String, RpcScope>>>,
+    connection_scope: ConnectionScope,
+}
+
+impl AuthLayer {
+    pub fn new(tokens: HashMap<String, RpcScope>) -> Self {
+        Self {
+            tokens: (!tokens.is_empty()).then(|| Arc::new(tokens)),
+            connection_scope: Default::default(),
+        }
+    }
+
+    /// The layer of a connection, which records the scope of the WebSocket upgrade request
+    /// in `connection_scope`.
+    pub fn with_connection_scope(&self, connection_scope: ConnectionScope) -> Self {
+        Self {
+            tokens: self.tokens.clone(),
+            connection_scope,
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            tokens: self.tokens.clone(),
+            connection_scope: self.connection_scope.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    tokens: Option<Arc<HashMap<String, RpcScope>>>,
+    connection_scope: ConnectionScope,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message.to_string()))
+        .expect("valid response")
+}
+
+fn request_scope(request: &Request<Body>, tokens: &HashMap<String, RpcScope>) -> Option<RpcScope> {
+    let header = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ")?;
+    tokens.get(token.trim()).copied()
+}
+
+/// The methods of a single or batched JSON-RPC request, or None if the body is not a request.
+fn requested_methods(body: &[u8]) -> Option<Vec<String>> {
+    let method = |call: &Value| call.get("method")?.as_str().map(ToString::to_string);
+    match serde_json::from_slice(body).ok()? {
+        Value::Array(calls) => calls.iter().map(method).collect(),
+        call => method(&call).map(|method| vec![method]),
+    }
+}
+
+impl<S> Service<Request<Body>> for AuthService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // Take the service which is ready, see the docs of `Service::call`.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let Some(tokens) = self.tokens.clone() else {
+            return Box::pin(inner.call(request));
+        };
+        let connection_scope = self.connection_scope.clone();
+        Box::pin(async move {
+            let Some(scope) = request_scope(&request, &tokens) else {
+                return Ok(error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "missing or invalid rpc auth token",
+                ));
+            };
+            if request.headers().contains_key(UPGRADE) {
+                // The connection is taken over by the WebSocket, whose calls are checked
+                // by `ScopeService`.
+                if connection_scope.set(scope).is_err() {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "connection is already upgraded",
+                    ));
+                }
+                return inner.call(request).await;
+            }
+
+            let (parts, mut body) = request.into_parts();
+            let mut bytes = Vec::new();
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) if bytes.len() + chunk.len() <= MAX_REQUEST_BODY_SIZE => {
+                        bytes.extend_from_slice(&chunk)
+                    }
+                    Ok(_) => {
+                        return Ok(error_response(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "request body is too large",
+                        ))
+                    }
+                    Err(_) => {
+                        return Ok(error_response(
+                            StatusCode::BAD_REQUEST,
+                            "failed to read request body",
+                        ))
+                    }
+                }
+            }
+            match requested_methods(&bytes) {
+                Some(methods) if methods.iter().all(|method| scope.allows(method)) => {
+                    inner
+                        .call(Request::from_parts(parts, Body::from(bytes)))
+                        .await
+                }
+                _ => Ok(error_response(
+                    StatusCode::FORBIDDEN,
+                    "rpc auth token is not allowed to call the method",
+                )),
+            }
+        })
+    }
+}
+
+/// The rpc middleware rejecting the calls out of the scope of the connection, see
+/// [`ConnectionScope`]. The calls are passed through if the scope is not set.
+#[derive(Clone)]
+pub struct ScopeLayer {
+    connection_scope: ConnectionScope,
+}
+
+impl ScopeLayer {
+    pub fn new(connection_scope: ConnectionScope) -> Self {
+        Self { connection_scope }
+    }
+}
+
+impl<S> Layer<S> for ScopeLayer {
+    type Service = ScopeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ScopeService {
+            inner,
+            connection_scope: self.connection_scope.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ScopeService<S> {
+    inner: S,
+    connection_scope: ConnectionScope,
+}
+
+impl<'a, S> RpcServiceT<'a> for ScopeService<S>
+where
+    S: RpcServiceT<'a> + Send + Sync + Clone + 'static,
+{
+    type Future = BoxFuture<'a, MethodResponse>;
+
+    fn call(&self, request: RpcRequest<'a>) -> Self::Future {
+        match self.connection_scope.get() {
+            Some(scope) if !scope.allows(request.method_name()) => {
+                let response = MethodResponse::error(
+                    request.id,
+                    ErrorObject::owned(
+                        FORBIDDEN_ERROR_CODE,
+                        "rpc auth token is not allowed to call the method",
+                        None::<()>,
+                    ),
+                );
+                Box::pin(async move { response })
+            }
+            _ => Box::pin(self.inner.call(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_auth_tokens, requested_methods, RpcScope};
+
+    #[test]
+    fn test_parse_auth_tokens() {
+        let tokens =
+            parse_auth_tokens(&["read_only:abc".to_string(), "admin:a:b".to_string()]).unwrap();
+        assert_eq!(tokens["abc"], RpcScope::ReadOnly);
+        assert_eq!(tokens["a:b"], RpcScope::Admin);
+        assert!(parse_auth_tokens(&["owner:abc".to_string()]).is_err());
+        assert!(parse_auth_tokens(&["abc".to_string()]).is_err());
+        assert!(parse_auth_tokens(&["admin:".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_scope_of_batched_requests() {
+        let batch = br#"[
+            {"jsonrpc": "2.0", "id": 1, "method": "list_channels", "params": [{}]},
+            {"jsonrpc": "2.0", "id": 2, "method": "shutdown_channel", "params": [{}]}
+        ]"#;
+        let methods = requested_methods(batch).unwrap();
+        assert_eq!(methods, vec!["list_channels", "shutdown_channel"]);
+        assert!(!methods.iter().all(|m| RpcScope::ReadOnly.allows(m)));
+        assert!(methods.iter().all(|m| RpcScope::Admin.allows(m)));
+
+        let invoice = br#"{"jsonrpc": "2.0", "id": 1, "method": "new_invoice", "params": []}"#;
+        let methods = requested_methods(invoice).unwrap();
+        assert!(methods.iter().all(|m| RpcScope::Invoice.allows(m)));
+        assert!(!methods.iter().all(|m| RpcScope::ReadOnly.allows(m)));
+
+        assert!(RpcScope::ReadOnly.allows("subscribe_channel_states"));
+        assert!(RpcScope::Invoice.allows("subscribe_invoices"));
+        assert!(!RpcScope::Invoice.allows("subscribe_tlcs"));
+
+        assert_eq!(requested_methods(b"[{\"id\": 1}]"), None);
+        assert_eq!(requested_methods(b"not json"), None);
+    }
+}
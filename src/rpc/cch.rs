@@ -100,7 +100,7 @@ pub struct ReceiveBTCResponse {
     pub status: CchOrderStatus,
 }
 
-#[rpc(server)]
+#[rpc(server, client)]
 pub trait CchRpc {
     #[method(name = "send_btc")]
     async fn send_btc(&self, params: SendBtcParams) -> Result<SendBTCResponse, ErrorObjectOwned>;
@@ -1,9 +1,11 @@
-use std::cmp::Reverse;
+use std::{cmp::Reverse, collections::HashMap};
 
+use crate::ckb::FeeRateTarget;
 use crate::fiber::{
     channel::{
-        AddTlcCommand, ChannelActorStateStore, ChannelCommand, ChannelCommandWithId, ChannelState,
-        RemoveTlcCommand, ShutdownCommand,
+        AddTlcCommand, ChannelActorStateStore, ChannelCloseType, ChannelCommand,
+        ChannelCommandWithId, ChannelState, ClosedChannel, RemoveTlcCommand, ShutdownCommand,
+        TlcDirection, TlcRecord, TlcStatus, UpdateCommitmentFeeCommand,
     },
     hash_algorithm::HashAlgorithm,
     network::{AcceptChannelCommand, OpenChannelCommand},
@@ -40,9 +42,11 @@ pub struct OpenChannelParams {
     pub max_tlc_value_in_flight: Option<u128>,
     #[serde_as(as = "Option<U64Hex>")]
     pub max_num_of_accept_tlcs: Option<u64>,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub tx_confirmations: Option<u64>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OpenChannelResult {
     pub temporary_channel_id: Hash256,
 }
@@ -53,38 +57,43 @@ pub struct AcceptChannelParams {
     pub temporary_channel_id: Hash256,
     #[serde_as(as = "U128Hex")]
     pub funding_amount: u128,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub tx_confirmations: Option<u64>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AcceptChannelResult {
     pub channel_id: Hash256,
 }
 
-// TODO @quake remove this unnecessary struct and rpc after refactoring
+// Pending channel updates are signed automatically in batches, this rpc is optional
+// and only used to sign the pending updates immediately.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CommitmentSignedParams {
     pub channel_id: Hash256,
 }
 
 #[serde_as]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ListChannelsParams {
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub peer_id: Option<PeerId>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ListChannelsResult {
     pub channels: Vec<Channel>,
 }
 
 #[serde_as]
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Channel {
     pub channel_id: Hash256,
     #[serde_as(as = "DisplayFromStr")]
     pub peer_id: PeerId,
     pub state: ChannelState,
+    // None for CKB.
+    pub funding_udt_type_script: Option<Script>,
     #[serde_as(as = "U128Hex")]
     pub local_balance: u128,
     #[serde_as(as = "U128Hex")]
@@ -97,6 +106,178 @@ pub struct Channel {
     pub created_at: u64,
 }
 
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListClosedChannelsParams {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub peer_id: Option<PeerId>,
+    // Only list the channels closed in this time range, in microseconds since the UNIX epoch.
+    #[serde_as(as = "Option<U64Hex>")]
+    pub closed_after: Option<u64>,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub closed_before: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ListClosedChannelsResult {
+    pub channels: Vec<ClosedChannelInfo>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClosedChannelInfo {
+    pub channel_id: Hash256,
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    pub funding_udt_type_script: Option<Script>,
+    pub funding_tx_hash: Hash256,
+    pub close_tx_hash: Hash256,
+    pub close_type: ChannelCloseType,
+    #[serde_as(as = "U128Hex")]
+    pub local_balance: u128,
+    #[serde_as(as = "U128Hex")]
+    pub remote_balance: u128,
+    #[serde_as(as = "U64Hex")]
+    pub close_fee: u64,
+    #[serde_as(as = "U64Hex")]
+    pub funding_fee: u64,
+    #[serde_as(as = "U64Hex")]
+    pub created_at: u64,
+    #[serde_as(as = "U64Hex")]
+    pub closed_at: u64,
+}
+
+impl From<ClosedChannel> for ClosedChannelInfo {
+    fn from(channel: ClosedChannel) -> Self {
+        ClosedChannelInfo {
+            channel_id: channel.channel_id,
+            peer_id: channel.peer_id,
+            funding_udt_type_script: channel.funding_udt_type_script.map(Into::into),
+            funding_tx_hash: channel.funding_tx_hash,
+            close_tx_hash: channel.close_tx_hash,
+            close_type: channel.close_type,
+            local_balance: channel.local_balance,
+            remote_balance: channel.remote_balance,
+            close_fee: channel.close_fee,
+            funding_fee: channel.funding_fee,
+            created_at: channel.created_at,
+            closed_at: channel.closed_at,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountingReportParams {
+    // Only report the channel with this id.
+    pub channel_id: Option<Hash256>,
+    // Only count the tlcs removed and the channels closed in this time range, in
+    // microseconds since the UNIX epoch.
+    #[serde_as(as = "Option<U64Hex>")]
+    pub after: Option<u64>,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub before: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AccountingReportResult {
+    pub channels: Vec<ChannelAccounting>,
+    pub assets: Vec<AssetAccounting>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChannelAccounting {
+    pub channel_id: Hash256,
+    pub udt_type_script: Option<Script>,
+    #[serde(flatten)]
+    pub summary: AccountingSummary,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssetAccounting {
+    // None for CKB.
+    pub udt_type_script: Option<Script>,
+    #[serde(flatten)]
+    pub summary: AccountingSummary,
+}
+
+#[serde_as]
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AccountingSummary {
+    // The amounts of the settled incoming and outgoing tlcs, in CKB shannons or UDT amount.
+    #[serde_as(as = "U128Hex")]
+    pub received_amount: u128,
+    #[serde_as(as = "U128Hex")]
+    pub sent_amount: u128,
+    #[serde_as(as = "U64Hex")]
+    pub settled_tlcs: u64,
+    #[serde_as(as = "U64Hex")]
+    pub failed_tlcs: u64,
+    // The fees earned by forwarding payments, i.e. the settled incoming tlcs minus the settled
+    // outgoing tlcs of the same payment hashes, counted in the incoming channels.
+    #[serde_as(as = "U128Hex")]
+    pub routing_fee: u128,
+    // The fees we paid for the funding, closing and commitment transactions, always in CKB
+    // shannons.
+    #[serde_as(as = "U64Hex")]
+    pub on_chain_fee: u64,
+}
+
+impl AccountingSummary {
+    fn add_tlc_record(&mut self, record: &TlcRecord) {
+        match (record.status, record.direction) {
+            (TlcStatus::Failed, _) => self.failed_tlcs += 1,
+            (TlcStatus::Settled, TlcDirection::Incoming) => {
+                self.settled_tlcs += 1;
+                self.received_amount += record.amount;
+            }
+            (TlcStatus::Settled, TlcDirection::Outgoing) => {
+                self.settled_tlcs += 1;
+                self.sent_amount += record.amount;
+            }
+        }
+    }
+
+    fn add_closed_channel(&mut self, channel: &ClosedChannel) {
+        // The commitment transactions broadcast by the peer are paid by the peer.
+        let close_fee = match channel.close_type {
+            ChannelCloseType::Cooperative | ChannelCloseType::LocalForce => channel.close_fee,
+            ChannelCloseType::RemoteForce | ChannelCloseType::Revoked => 0,
+        };
+        self.on_chain_fee += close_fee + channel.funding_fee;
+    }
+}
+
+// Get the summaries of the channel and its asset, adding them if missing.
+fn accounting_summaries<'a>(
+    channels: &'a mut HashMap<Hash256, ChannelAccounting>,
+    assets: &'a mut Vec<AssetAccounting>,
+    channel_id: Hash256,
+    udt_type_script: Option<Script>,
+) -> (&'a mut AccountingSummary, &'a mut AccountingSummary) {
+    let index = match assets
+        .iter()
+        .position(|asset| asset.udt_type_script == udt_type_script)
+    {
+        Some(index) => index,
+        None => {
+            assets.push(AssetAccounting {
+                udt_type_script: udt_type_script.clone(),
+                summary: Default::default(),
+            });
+            assets.len() - 1
+        }
+    };
+    let channel = channels
+        .entry(channel_id)
+        .or_insert_with(|| ChannelAccounting {
+            channel_id,
+            udt_type_script,
+            summary: Default::default(),
+        });
+    (&mut channel.summary, &mut assets[index].summary)
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AddTlcParams {
@@ -109,7 +290,7 @@ pub struct AddTlcParams {
 }
 
 #[serde_as]
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AddTlcResult {
     #[serde_as(as = "U64Hex")]
     pub tlc_id: u64,
@@ -143,11 +324,19 @@ pub struct ShutdownChannelParams {
     pub channel_id: Hash256,
     pub close_script: Script,
     pub force: Option<bool>,
-    #[serde_as(as = "U64Hex")]
-    pub fee_rate: u64,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub fee_rate: Option<u64>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateCommitmentFeeParams {
+    pub channel_id: Hash256,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub fee_rate: Option<u64>,
 }
 
-#[rpc(server)]
+#[rpc(server, client)]
 pub trait ChannelRpc {
     #[method(name = "open_channel")]
     async fn open_channel(
@@ -167,6 +356,18 @@ pub trait ChannelRpc {
         params: ListChannelsParams,
     ) -> Result<ListChannelsResult, ErrorObjectOwned>;
 
+    #[method(name = "list_closed_channels")]
+    async fn list_closed_channels(
+        &self,
+        params: ListClosedChannelsParams,
+    ) -> Result<ListClosedChannelsResult, ErrorObjectOwned>;
+
+    #[method(name = "accounting_report")]
+    async fn accounting_report(
+        &self,
+        params: AccountingReportParams,
+    ) -> Result<AccountingReportResult, ErrorObjectOwned>;
+
     #[method(name = "commitment_signed")]
     async fn commitment_signed(
         &self,
@@ -182,6 +383,12 @@ pub trait ChannelRpc {
     #[method(name = "shutdown_channel")]
     async fn shutdown_channel(&self, params: ShutdownChannelParams)
         -> Result<(), ErrorObjectOwned>;
+
+    #[method(name = "update_commitment_fee")]
+    async fn update_commitment_fee(
+        &self,
+        params: UpdateCommitmentFeeParams,
+    ) -> Result<(), ErrorObjectOwned>;
 }
 
 pub struct ChannelRpcServerImpl<S> {
@@ -193,6 +400,19 @@ impl<S> ChannelRpcServerImpl<S> {
     pub fn new(actor: ActorRef<NetworkActorMessage>, store: S) -> Self {
         ChannelRpcServerImpl { actor, store }
     }
+
+    async fn estimate_fee_rate<P: Serialize + std::fmt::Debug>(
+        &self,
+        target: FeeRateTarget,
+        params: &P,
+    ) -> Result<u64, ErrorObjectOwned> {
+        let message = |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::EstimateFeeRate(
+                target, rpc_reply,
+            ))
+        };
+        handle_actor_call!(self.actor, message, params)
+    }
 }
 
 #[async_trait]
@@ -205,7 +425,7 @@ where
         params: OpenChannelParams,
     ) -> Result<OpenChannelResult, ErrorObjectOwned> {
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::OpenChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::OpenChannel(
                 OpenChannelCommand {
                     peer_id: params.peer_id.clone(),
                     funding_amount: params.funding_amount,
@@ -217,6 +437,7 @@ where
                     funding_fee_rate: params.funding_fee_rate,
                     max_tlc_value_in_flight: params.max_tlc_value_in_flight,
                     max_num_of_accept_tlcs: params.max_num_of_accept_tlcs,
+                    tx_confirmations: params.tx_confirmations,
                 },
                 rpc_reply,
             ))
@@ -231,10 +452,11 @@ where
         params: AcceptChannelParams,
     ) -> Result<AcceptChannelResult, ErrorObjectOwned> {
         let message = |rpc_reply| {
-            NetworkActorMessage::Command(NetworkActorCommand::AcceptChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::AcceptChannel(
                 AcceptChannelCommand {
                     temp_channel_id: params.temporary_channel_id,
                     funding_amount: params.funding_amount,
+                    tx_confirmations: params.tx_confirmations,
                 },
                 rpc_reply,
             ))
@@ -249,35 +471,144 @@ where
         &self,
         params: ListChannelsParams,
     ) -> Result<ListChannelsResult, ErrorObjectOwned> {
-        let mut channels: Vec<_> = self
-            .store
-            .get_active_channel_states(params.peer_id)
-            .into_iter()
-            .filter_map(|(peer_id, channel_id, _state)| {
-                self.store
-                    .get_channel_actor_state(&channel_id)
-                    .map(|state| Channel {
-                        channel_id,
-                        peer_id,
-                        state: state.state,
-                        local_balance: state.get_local_balance(),
-                        remote_balance: state.get_remote_balance(),
-                        sent_tlc_balance: state.get_sent_tlc_balance(),
-                        received_tlc_balance: state.get_received_tlc_balance(),
-                        created_at: state.get_created_at_in_microseconds(),
-                    })
-            })
-            .collect();
+        let channel_states = match self.store.get_active_channel_states(params.peer_id.clone()) {
+            Ok(channel_states) => channel_states,
+            Err(err) => return log_and_error!(params, err.to_string()),
+        };
+        let mut channels = Vec::with_capacity(channel_states.len());
+        for (peer_id, channel_id, _state) in channel_states {
+            let state = match self.store.get_channel_actor_state(&channel_id) {
+                Ok(Some(state)) => state,
+                Ok(None) => continue,
+                Err(err) => return log_and_error!(params, err.to_string()),
+            };
+            channels.push(Channel {
+                channel_id,
+                peer_id,
+                state: state.state,
+                funding_udt_type_script: state.funding_udt_type_script.clone().map(Into::into),
+                local_balance: state.get_local_balance(),
+                remote_balance: state.get_remote_balance(),
+                sent_tlc_balance: state.get_sent_tlc_balance(),
+                received_tlc_balance: state.get_received_tlc_balance(),
+                created_at: state.get_created_at_in_microseconds(),
+            });
+        }
         // Sort by created_at in descending order
         channels.sort_by_key(|channel| Reverse(channel.created_at));
         Ok(ListChannelsResult { channels })
     }
 
+    async fn list_closed_channels(
+        &self,
+        params: ListClosedChannelsParams,
+    ) -> Result<ListClosedChannelsResult, ErrorObjectOwned> {
+        let mut channels: Vec<ClosedChannelInfo> =
+            match self.store.get_closed_channels(params.peer_id.clone()) {
+                Ok(channels) => channels
+                    .into_iter()
+                    .filter(|channel| {
+                        params
+                            .closed_after
+                            .map_or(true, |after| channel.closed_at >= after)
+                            && params
+                                .closed_before
+                                .map_or(true, |before| channel.closed_at < before)
+                    })
+                    .map(Into::into)
+                    .collect(),
+                Err(err) => return log_and_error!(params, err.to_string()),
+            };
+        // Sort by closed_at in descending order
+        channels.sort_by_key(|channel| Reverse(channel.closed_at));
+        Ok(ListClosedChannelsResult { channels })
+    }
+
+    async fn accounting_report(
+        &self,
+        params: AccountingReportParams,
+    ) -> Result<AccountingReportResult, ErrorObjectOwned> {
+        // The records of all the channels are needed to find the forwarded payments.
+        let tlc_records = match self.store.get_tlc_records(None) {
+            Ok(records) => records,
+            Err(err) => return log_and_error!(params, err.to_string()),
+        };
+        let closed_channels = match self.store.get_closed_channels(None) {
+            Ok(channels) => channels,
+            Err(err) => return log_and_error!(params, err.to_string()),
+        };
+        let in_range = |time: u64| {
+            params.after.map_or(true, |after| time >= after)
+                && params.before.map_or(true, |before| time < before)
+        };
+
+        let mut channels = HashMap::new();
+        let mut assets = vec![];
+        // The settled incoming and outgoing amounts of each payment hash, a payment may be
+        // split into several tlcs.
+        let mut settled_amounts: HashMap<_, (u128, u128)> = HashMap::new();
+        for r in tlc_records
+            .iter()
+            .filter(|r| r.status == TlcStatus::Settled)
+        {
+            let amounts = settled_amounts
+                .entry((r.payment_hash, r.udt_type_script.clone()))
+                .or_default();
+            match r.direction {
+                TlcDirection::Incoming => amounts.0 += r.amount,
+                TlcDirection::Outgoing => amounts.1 += r.amount,
+            }
+        }
+        for record in tlc_records.iter().filter(|r| {
+            in_range(r.removed_at) && params.channel_id.map_or(true, |id| r.channel_id == id)
+        }) {
+            let (channel, asset) = accounting_summaries(
+                &mut channels,
+                &mut assets,
+                record.channel_id,
+                record.udt_type_script.clone().map(Into::into),
+            );
+            channel.add_tlc_record(record);
+            asset.add_tlc_record(record);
+            if record.status == TlcStatus::Settled && record.direction == TlcDirection::Incoming {
+                let key = (record.payment_hash, record.udt_type_script.clone());
+                match settled_amounts.get(&key) {
+                    Some(&(incoming_amount, outgoing_amount)) if outgoing_amount > 0 => {
+                        // The fee of the payment is split among its incoming tlcs.
+                        let fee = record
+                            .amount
+                            .saturating_mul(incoming_amount.saturating_sub(outgoing_amount))
+                            / incoming_amount;
+                        channel.routing_fee += fee;
+                        asset.routing_fee += fee;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for closed in closed_channels.iter().filter(|c| {
+            in_range(c.closed_at) && params.channel_id.map_or(true, |id| c.channel_id == id)
+        }) {
+            let (channel, asset) = accounting_summaries(
+                &mut channels,
+                &mut assets,
+                closed.channel_id,
+                closed.funding_udt_type_script.clone().map(Into::into),
+            );
+            channel.add_closed_channel(closed);
+            asset.add_closed_channel(closed);
+        }
+
+        let mut channels: Vec<ChannelAccounting> = channels.into_values().collect();
+        channels.sort_by(|a, b| a.channel_id.as_ref().cmp(b.channel_id.as_ref()));
+        Ok(AccountingReportResult { channels, assets })
+    }
+
     async fn commitment_signed(
         &self,
         params: CommitmentSignedParams,
     ) -> Result<(), ErrorObjectOwned> {
-        let message = NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+        let message = NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
             ChannelCommandWithId {
                 channel_id: params.channel_id,
                 command: ChannelCommand::CommitmentSigned(),
@@ -288,7 +619,7 @@ where
 
     async fn add_tlc(&self, params: AddTlcParams) -> Result<AddTlcResult, ErrorObjectOwned> {
         let message = |rpc_reply| -> NetworkActorMessage {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: params.channel_id,
                     command: ChannelCommand::AddTlc(
@@ -311,7 +642,7 @@ where
 
     async fn remove_tlc(&self, params: RemoveTlcParams) -> Result<(), ErrorObjectOwned> {
         let message = |rpc_reply| -> NetworkActorMessage {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: params.channel_id,
                     command: ChannelCommand::RemoveTlc(
@@ -343,14 +674,29 @@ where
         &self,
         params: ShutdownChannelParams,
     ) -> Result<(), ErrorObjectOwned> {
+        let fee_rate = match params.fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => {
+                let fee_rate = self
+                    .estimate_fee_rate(FeeRateTarget::Closing, &params)
+                    .await?;
+                // The closing fee rate can't be lower than the commitment fee rate.
+                match self.store.get_channel_actor_state(&params.channel_id) {
+                    Ok(state) => {
+                        state.map_or(fee_rate, |state| fee_rate.max(state.commitment_fee_rate))
+                    }
+                    Err(err) => return log_and_error!(params, err.to_string()),
+                }
+            }
+        };
         let message = |rpc_reply| -> NetworkActorMessage {
-            NetworkActorMessage::Command(NetworkActorCommand::ControlFiberChannel(
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
                 ChannelCommandWithId {
                     channel_id: params.channel_id,
                     command: ChannelCommand::Shutdown(
                         ShutdownCommand {
                             close_script: params.close_script.clone().into(),
-                            fee_rate: FeeRate::from_u64(params.fee_rate),
+                            fee_rate: FeeRate::from_u64(fee_rate),
                             force: params.force.unwrap_or(false),
                         },
                         rpc_reply,
@@ -360,4 +706,29 @@ where
         };
         handle_actor_call!(self.actor, message, params)
     }
+
+    async fn update_commitment_fee(
+        &self,
+        params: UpdateCommitmentFeeParams,
+    ) -> Result<(), ErrorObjectOwned> {
+        let fee_rate = match params.fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => {
+                self.estimate_fee_rate(FeeRateTarget::Commitment, &params)
+                    .await?
+            }
+        };
+        let message = |rpc_reply| -> NetworkActorMessage {
+            NetworkActorMessage::new_command(NetworkActorCommand::ControlFiberChannel(
+                ChannelCommandWithId {
+                    channel_id: params.channel_id,
+                    command: ChannelCommand::UpdateCommitmentFee(
+                        UpdateCommitmentFeeCommand { fee_rate },
+                        rpc_reply,
+                    ),
+                },
+            ))
+        };
+        handle_actor_call!(self.actor, message, params)
+    }
 }
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap_serde_derive::ClapSerde;
 
 #[derive(ClapSerde, Debug, Clone)]
@@ -6,4 +8,20 @@ pub struct RpcConfig {
     /// listening port for rpc service
     #[arg(name = "RPC_LISTENING_ADDR", long = "rpc-listening-addr", env)]
     pub listening_addr: Option<String>,
+
+    /// tokens to authenticate the rpc requests, in the form of `<scope>:<token>` (separated by `,`), the scope is one of `read_only`, `invoice` and `admin`; the rpc service is open to everyone if not set
+    #[arg(name = "RPC_AUTH_TOKENS", long = "rpc-auth-tokens", env, value_parser, num_args = 0.., value_delimiter = ',')]
+    pub auth_tokens: Vec<String>,
+
+    /// path to the PEM certificate chain, the rpc service is served over TLS if both the certificate and key are set
+    #[arg(name = "RPC_TLS_CERT_PATH", long = "rpc-tls-cert-path", env)]
+    pub tls_cert_path: Option<PathBuf>,
+
+    /// path to the PEM private key of the TLS certificate
+    #[arg(name = "RPC_TLS_KEY_PATH", long = "rpc-tls-key-path", env)]
+    pub tls_key_path: Option<PathBuf>,
+
+    /// path of the unix domain socket for local admin access, which doesn't require auth tokens; the tcp listener is not started if only the unix socket is set
+    #[arg(name = "RPC_UNIX_SOCKET_PATH", long = "rpc-unix-socket-path", env)]
+    pub unix_socket_path: Option<PathBuf>,
 }
@@ -0,0 +1,72 @@
+use crate::fiber::{
+    config::CkbNetwork, network::NodeInfo, serde_utils::U64Hex, NetworkActorCommand,
+    NetworkActorMessage,
+};
+use crate::{handle_actor_call, log_and_error};
+use jsonrpsee::{
+    core::async_trait, proc_macros::rpc, types::error::CALL_EXECUTION_FAILED_CODE,
+    types::ErrorObjectOwned,
+};
+use ractor::{call, ActorRef};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tentacle::{multiaddr::MultiAddr, secio::PeerId};
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct NodeInfoResult {
+    pub version: String,
+    pub node_name: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    pub listening_addr: MultiAddr,
+    pub announced_addrs: Vec<String>,
+    pub chain_network: Option<CkbNetwork>,
+    #[serde_as(as = "U64Hex")]
+    pub peer_count: u64,
+    #[serde_as(as = "U64Hex")]
+    pub channel_count: u64,
+    #[serde_as(as = "U64Hex")]
+    pub pending_channel_count: u64,
+}
+
+impl From<NodeInfo> for NodeInfoResult {
+    fn from(info: NodeInfo) -> Self {
+        NodeInfoResult {
+            version: info.version,
+            node_name: info.node_name,
+            peer_id: info.peer_id,
+            listening_addr: info.listening_addr,
+            announced_addrs: info.announced_addrs,
+            chain_network: info.chain_network,
+            peer_count: info.peer_count as u64,
+            channel_count: info.channel_count as u64,
+            pending_channel_count: info.pending_channel_count as u64,
+        }
+    }
+}
+
+#[rpc(server)]
+pub trait InfoRpc {
+    #[method(name = "node_info")]
+    async fn node_info(&self) -> Result<NodeInfoResult, ErrorObjectOwned>;
+}
+
+pub struct InfoRpcServerImpl {
+    actor: ActorRef<NetworkActorMessage>,
+}
+
+impl InfoRpcServerImpl {
+    pub fn new(actor: ActorRef<NetworkActorMessage>) -> Self {
+        InfoRpcServerImpl { actor }
+    }
+}
+
+#[async_trait]
+impl InfoRpcServer for InfoRpcServerImpl {
+    async fn node_info(&self) -> Result<NodeInfoResult, ErrorObjectOwned> {
+        let message =
+            |rpc_reply| NetworkActorMessage::new_command(NetworkActorCommand::NodeInfo(rpc_reply));
+        handle_actor_call!(self.actor, message, ()).map(NodeInfoResult::from)
+    }
+}
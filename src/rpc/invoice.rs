@@ -45,7 +45,7 @@ pub struct ParseInvoiceResult {
     pub invoice: CkbInvoice,
 }
 
-#[rpc(server)]
+#[rpc(server, client)]
 pub trait InvoiceRpc {
     #[method(name = "new_invoice")]
     async fn new_invoice(
@@ -1,71 +1,136 @@
-mod cch;
-mod channel;
+mod admin;
+mod auth;
+pub mod cch;
+pub mod channel;
 mod config;
-mod invoice;
-mod peer;
+mod info;
+pub mod invoice;
+pub mod peer;
+mod subscription;
+mod transport;
 mod utils;
+mod wallet;
+
+use std::collections::HashMap;
 
 use crate::{
     cch::CchMessage,
+    ckb::CkbChainMessage,
     fiber::{channel::ChannelActorStateStore, NetworkActorMessage},
     invoice::{InvoiceCommand, InvoiceStore},
+    store::AdminStore,
 };
+use admin::{AdminRpcServer, AdminRpcServerImpl};
+use auth::{parse_auth_tokens, AuthLayer, ConnectionScope, ScopeLayer};
 use cch::{CchRpcServer, CchRpcServerImpl};
 use channel::{ChannelRpcServer, ChannelRpcServerImpl};
 pub use config::RpcConfig;
+use info::{InfoRpcServer, InfoRpcServerImpl};
 use invoice::{InvoiceRpcServer, InvoiceRpcServerImpl};
-use jsonrpsee::server::{Server, ServerHandle};
+use jsonrpsee::{
+    server::{middleware::rpc::RpcServiceBuilder, stop_channel, Server, ServerHandle},
+    Methods,
+};
 use peer::{PeerRpcServer, PeerRpcServerImpl};
 use ractor::ActorRef;
+pub use subscription::{start_subscription_actor, SubscriptionActorMessage};
+use subscription::{SubscriptionRpcServer, SubscriptionRpcServerImpl};
 use tokio::sync::mpsc::Sender;
+use tower::ServiceBuilder;
+use wallet::{WalletRpcServer, WalletRpcServerImpl};
 
 pub type InvoiceCommandWithReply = (InvoiceCommand, Sender<crate::Result<String>>);
 
-fn build_server(addr: &str) -> Server {
-    #[cfg(not(release))]
-    {
-        // Use socket2 to set reuse address and reuse port,
-        // so that we can restart the server without waiting for the port to be released.
-        // it will avoid the error: "Address already in use" in CI.
-        use socket2::{Domain, Socket, Type};
-        let addr = addr.parse().unwrap();
-        let domain = Domain::for_address(addr);
-        let socket = Socket::new(domain, Type::STREAM, None).unwrap();
-        socket.set_nonblocking(true).unwrap();
-        socket.set_reuse_address(true).expect("set reuse address");
-        socket.set_reuse_port(true).expect("set reuse port");
-
-        socket.bind(&addr.into()).unwrap();
-        socket.listen(4096).unwrap();
-
-        jsonrpsee::server::Server::builder()
-            .build_from_tcp(socket)
-            .unwrap()
-    }
-    #[cfg(release)]
-    {
-        Server::builder().build(addr).await.unwrap()
-    }
-}
-
-pub async fn start_rpc<S: ChannelActorStateStore + InvoiceStore + Clone + Send + Sync + 'static>(
+pub async fn start_rpc<
+    S: ChannelActorStateStore + InvoiceStore + AdminStore + Clone + Send + Sync + 'static,
+>(
     config: RpcConfig,
     network_actor: Option<ActorRef<NetworkActorMessage>>,
     cch_actor: Option<ActorRef<CchMessage>>,
+    ckb_chain_actor: Option<ActorRef<CkbChainMessage>>,
+    subscription_actor: ActorRef<SubscriptionActorMessage>,
     store: S,
 ) -> ServerHandle {
-    let listening_addr = config.listening_addr.as_deref().unwrap_or("[::]:0");
-    let server = build_server(listening_addr);
     let mut methods = InvoiceRpcServerImpl::new(store.clone()).into_rpc();
+    methods
+        .merge(AdminRpcServerImpl::new(store.clone()).into_rpc())
+        .unwrap();
+    // Subscriptions are only served over websocket connections, on the same address.
+    methods
+        .merge(SubscriptionRpcServerImpl::new(subscription_actor).into_rpc())
+        .unwrap();
     if let Some(network_actor) = network_actor {
+        let info = InfoRpcServerImpl::new(network_actor.clone());
         let peer = PeerRpcServerImpl::new(network_actor.clone());
         let channel = ChannelRpcServerImpl::new(network_actor, store);
+        methods.merge(info.into_rpc()).unwrap();
         methods.merge(peer.into_rpc()).unwrap();
         methods.merge(channel.into_rpc()).unwrap();
     }
+    if let Some(ckb_chain_actor) = ckb_chain_actor {
+        let wallet = WalletRpcServerImpl::new(ckb_chain_actor);
+        methods.merge(wallet.into_rpc()).unwrap();
+    }
     if let Some(cch_actor) = cch_actor {
         let cch = CchRpcServerImpl::new(cch_actor);
         methods.merge(cch.into_rpc()).unwrap();
     }
-    server.start(methods)
+    let methods: Methods = methods.into();
+
+    let auth_tokens = parse_auth_tokens(&config.auth_tokens).expect("valid rpc auth tokens");
+    let (stop_handle, server_handle) = stop_channel();
+    if config.listening_addr.is_some() || config.unix_socket_path.is_none() {
+        let listening_addr = config.listening_addr.as_deref().unwrap_or("[::]:0");
+        let listener = transport::bind_tcp(listening_addr)
+            .await
+            .expect("bind rpc listening addr");
+        let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(
+                transport::load_tls_acceptor(cert_path, key_path).expect("valid rpc tls config"),
+            ),
+            (None, None) => None,
+            _ => panic!("both rpc tls cert path and key path should be set"),
+        };
+        let auth = AuthLayer::new(auth_tokens);
+        let (methods, stop) = (methods.clone(), stop_handle.clone());
+        transport::serve_tcp(
+            listener,
+            tls,
+            move || {
+                // The scope of the token is kept for the WebSocket connection.
+                let connection_scope = ConnectionScope::default();
+                Server::builder()
+                    .set_http_middleware(
+                        ServiceBuilder::new()
+                            .layer(auth.with_connection_scope(connection_scope.clone())),
+                    )
+                    .set_rpc_middleware(
+                        RpcServiceBuilder::new().layer(ScopeLayer::new(connection_scope)),
+                    )
+                    .to_service_builder()
+                    .build(methods.clone(), stop.clone())
+            },
+            stop_handle.clone(),
+        );
+    }
+    #[cfg(not(unix))]
+    if config.unix_socket_path.is_some() {
+        panic!("rpc unix socket is only supported on unix");
+    }
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket_path {
+        // The socket is only accessible by the user running the node, so the auth tokens
+        // are not required.
+        let listener = transport::bind_unix(path).expect("bind rpc unix socket");
+        let builder = Server::builder()
+            .set_http_middleware(ServiceBuilder::new().layer(AuthLayer::new(HashMap::new())))
+            .to_service_builder();
+        let stop = stop_handle.clone();
+        transport::serve_unix(
+            listener,
+            move || builder.build(methods.clone(), stop.clone()),
+            stop_handle,
+        );
+    }
+    server_handle
 }
@@ -1,10 +1,14 @@
-use crate::fiber::{NetworkActorCommand, NetworkActorMessage};
-use crate::log_and_error;
+use crate::fiber::{
+    network::{PeerInfo, PeerLatency},
+    serde_utils::U64Hex,
+    NetworkActorCommand, NetworkActorMessage,
+};
+use crate::{handle_actor_call, log_and_error};
 use jsonrpsee::{
     core::async_trait, proc_macros::rpc, types::error::CALL_EXECUTION_FAILED_CODE,
     types::ErrorObjectOwned,
 };
-use ractor::ActorRef;
+use ractor::{call, ActorRef};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use tentacle::{multiaddr::MultiAddr, secio::PeerId};
@@ -21,13 +25,72 @@ pub struct DisconnectPeerParams {
     pub peer_id: PeerId,
 }
 
-#[rpc(server)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ListPeersResult {
+    pub peers: Vec<Peer>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Peer {
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    pub address: MultiAddr,
+    pub is_inbound: bool,
+    #[serde_as(as = "U64Hex")]
+    pub channel_count: u64,
+}
+
+impl From<PeerInfo> for Peer {
+    fn from(info: PeerInfo) -> Self {
+        Peer {
+            peer_id: info.peer_id,
+            address: info.address,
+            is_inbound: info.is_inbound,
+            channel_count: info.channel_count as u64,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ListPeerLatenciesResult {
+    pub peers: Vec<PeerLatencyInfo>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerLatencyInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub round_trip_time_ms: Option<u64>,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub last_pong_at: Option<u64>,
+}
+
+impl From<PeerLatency> for PeerLatencyInfo {
+    fn from(latency: PeerLatency) -> Self {
+        PeerLatencyInfo {
+            peer_id: latency.peer_id,
+            round_trip_time_ms: latency.round_trip_time.map(|rtt| rtt.as_millis() as u64),
+            last_pong_at: latency.last_pong_at,
+        }
+    }
+}
+
+#[rpc(server, client)]
 pub trait PeerRpc {
     #[method(name = "connect_peer")]
     async fn connect_peer(&self, params: ConnectPeerParams) -> Result<(), ErrorObjectOwned>;
 
     #[method(name = "disconnect_peer")]
     async fn disconnect_peer(&self, params: DisconnectPeerParams) -> Result<(), ErrorObjectOwned>;
+
+    #[method(name = "list_peers")]
+    async fn list_peers(&self) -> Result<ListPeersResult, ErrorObjectOwned>;
+
+    #[method(name = "list_peer_latencies")]
+    async fn list_peer_latencies(&self) -> Result<ListPeerLatenciesResult, ErrorObjectOwned>;
 }
 
 pub struct PeerRpcServerImpl {
@@ -43,15 +106,35 @@ impl PeerRpcServerImpl {
 #[async_trait]
 impl PeerRpcServer for PeerRpcServerImpl {
     async fn connect_peer(&self, params: ConnectPeerParams) -> Result<(), ErrorObjectOwned> {
-        let message =
-            NetworkActorMessage::Command(NetworkActorCommand::ConnectPeer(params.address.clone()));
+        let message = NetworkActorMessage::new_command(NetworkActorCommand::ConnectPeer(
+            params.address.clone(),
+        ));
         crate::handle_actor_cast!(self.actor, message, params)
     }
 
     async fn disconnect_peer(&self, params: DisconnectPeerParams) -> Result<(), ErrorObjectOwned> {
-        let message = NetworkActorMessage::Command(NetworkActorCommand::DisconnectPeer(
+        let message = NetworkActorMessage::new_command(NetworkActorCommand::DisconnectPeer(
             params.peer_id.clone(),
         ));
         crate::handle_actor_cast!(self.actor, message, params)
     }
+
+    async fn list_peers(&self) -> Result<ListPeersResult, ErrorObjectOwned> {
+        let message =
+            |rpc_reply| NetworkActorMessage::new_command(NetworkActorCommand::ListPeers(rpc_reply));
+        handle_actor_call!(self.actor, message, ()).map(|peers: Vec<PeerInfo>| ListPeersResult {
+            peers: peers.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    async fn list_peer_latencies(&self) -> Result<ListPeerLatenciesResult, ErrorObjectOwned> {
+        let message = |rpc_reply| {
+            NetworkActorMessage::new_command(NetworkActorCommand::ListPeerLatencies(rpc_reply))
+        };
+        handle_actor_call!(self.actor, message, ()).map(|latencies: Vec<PeerLatency>| {
+            ListPeerLatenciesResult {
+                peers: latencies.into_iter().map(Into::into).collect(),
+            }
+        })
+    }
 }
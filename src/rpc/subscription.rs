@@ -0,0 +1,411 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::cch::{CchOrderNotification, CchOrderStatus, CchOrderType};
+use crate::fiber::{
+    channel::{
+        AddedTlcNotification, ChannelState, ChannelStateNotification, ChannelSubscribers,
+        TlcDirection, TlcRecord, TlcStatus,
+    },
+    network::PeerNotification,
+    serde_utils::{U128Hex, U64Hex},
+    types::Hash256,
+};
+use crate::invoice::InvoiceStore;
+use ckb_jsonrpc_types::Script;
+use jsonrpsee::{
+    core::{async_trait, SubscriptionResult},
+    proc_macros::rpc,
+    PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink,
+};
+use ractor::{Actor, ActorCell, ActorProcessingErr, ActorRef, OutputPort};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tentacle::{multiaddr::MultiAddr, secio::PeerId};
+use tracing::{error, warn};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SubscriptionTopic {
+    ChannelStates,
+    Tlcs,
+    Invoices,
+    Peers,
+    CchOrders,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct ChannelStateEvent {
+    pub channel_id: Hash256,
+    #[serde_as(as = "DisplayFromStr")]
+    pub peer_id: PeerId,
+    pub state: ChannelState,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TlcEvent {
+    Added {
+        channel_id: Hash256,
+        #[serde_as(as = "U64Hex")]
+        tlc_id: u64,
+        direction: TlcDirection,
+        payment_hash: Hash256,
+        #[serde_as(as = "U128Hex")]
+        amount: u128,
+        udt_type_script: Option<Script>,
+    },
+    Removed {
+        channel_id: Hash256,
+        #[serde_as(as = "U64Hex")]
+        tlc_id: u64,
+        direction: TlcDirection,
+        status: TlcStatus,
+        payment_hash: Hash256,
+        #[serde_as(as = "U128Hex")]
+        amount: u128,
+        udt_type_script: Option<Script>,
+        #[serde_as(as = "U64Hex")]
+        removed_at: u64,
+    },
+}
+
+impl From<AddedTlcNotification> for TlcEvent {
+    fn from(notification: AddedTlcNotification) -> Self {
+        let tlc = notification.tlc;
+        TlcEvent::Added {
+            channel_id: notification.channel_id,
+            tlc_id: tlc.id.into(),
+            direction: if tlc.is_received() {
+                TlcDirection::Incoming
+            } else {
+                TlcDirection::Outgoing
+            },
+            payment_hash: tlc.payment_hash,
+            amount: tlc.amount,
+            udt_type_script: notification.udt_type_script.map(Into::into),
+        }
+    }
+}
+
+impl From<TlcRecord> for TlcEvent {
+    fn from(record: TlcRecord) -> Self {
+        TlcEvent::Removed {
+            channel_id: record.channel_id,
+            tlc_id: record.tlc_id,
+            direction: record.direction,
+            status: record.status,
+            payment_hash: record.payment_hash,
+            amount: record.amount,
+            udt_type_script: record.udt_type_script.map(Into::into),
+            removed_at: record.removed_at,
+        }
+    }
+}
+
+/// An incoming tlc paying an invoice of this node is settled, and the removal of the tlc is
+/// committed by both parties.
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct InvoicePaidEvent {
+    pub payment_hash: Hash256,
+    pub channel_id: Hash256,
+    #[serde_as(as = "U64Hex")]
+    pub tlc_id: u64,
+    #[serde_as(as = "U128Hex")]
+    pub amount: u128,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeerEvent {
+    Connected {
+        #[serde_as(as = "DisplayFromStr")]
+        peer_id: PeerId,
+        address: MultiAddr,
+    },
+    Disconnected {
+        #[serde_as(as = "DisplayFromStr")]
+        peer_id: PeerId,
+        address: MultiAddr,
+    },
+}
+
+impl From<PeerNotification> for PeerEvent {
+    fn from(notification: PeerNotification) -> Self {
+        match notification {
+            PeerNotification::Connected(peer_id, address) => {
+                PeerEvent::Connected { peer_id, address }
+            }
+            PeerNotification::Disconnected(peer_id, address) => {
+                PeerEvent::Disconnected { peer_id, address }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct CchOrderEvent {
+    pub payment_hash: String,
+    pub order_type: CchOrderType,
+    pub status: CchOrderStatus,
+}
+
+pub enum SubscriptionActorMessage {
+    Subscribe(SubscriptionTopic, SubscriptionSink),
+    // A subscription of the topic is closed by the client.
+    SubscriptionClosed(SubscriptionTopic),
+    ChannelState(ChannelStateNotification),
+    AddedTlc(AddedTlcNotification),
+    RemovedTlc(TlcRecord),
+    Peer(PeerNotification),
+    CchOrder(CchOrderNotification),
+}
+
+/// Sends the notifications of the subscriber ports to the websocket subscriptions.
+pub struct SubscriptionActor<S> {
+    store: S,
+}
+
+#[derive(Default)]
+pub struct SubscriptionState {
+    sinks: HashMap<SubscriptionTopic, Vec<SubscriptionSink>>,
+}
+
+impl SubscriptionState {
+    fn publish<T: Serialize>(&mut self, topic: SubscriptionTopic, event: &T) {
+        let Some(sinks) = self.sinks.get_mut(&topic) else {
+            return;
+        };
+        let message = match SubscriptionMessage::from_json(event) {
+            Ok(message) => message,
+            Err(err) => {
+                error!("Failed to serialize {:?} event: {}", topic, err);
+                return;
+            }
+        };
+        sinks.retain(|sink| {
+            if sink.is_closed() {
+                return false;
+            }
+            // Don't block the other subscriptions on a client which doesn't read its messages.
+            if sink.try_send(message.clone()).is_err() {
+                warn!(
+                    "Dropping {:?} event for slow subscription {:?}",
+                    topic,
+                    sink.subscription_id()
+                );
+            }
+            true
+        });
+    }
+}
+
+#[ractor::async_trait]
+impl<S> Actor for SubscriptionActor<S>
+where
+    S: InvoiceStore + Send + Sync + 'static,
+{
+    type Msg = SubscriptionActorMessage;
+    type State = SubscriptionState;
+    type Arguments = ();
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        _args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(Default::default())
+    }
+
+    async fn handle(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            SubscriptionActorMessage::Subscribe(topic, sink) => {
+                let closed_sink = sink.clone();
+                tokio::spawn(async move {
+                    closed_sink.closed().await;
+                    let _ =
+                        myself.send_message(SubscriptionActorMessage::SubscriptionClosed(topic));
+                });
+                state.sinks.entry(topic).or_default().push(sink);
+            }
+            SubscriptionActorMessage::SubscriptionClosed(topic) => {
+                if let Some(sinks) = state.sinks.get_mut(&topic) {
+                    sinks.retain(|sink| !sink.is_closed());
+                }
+            }
+            SubscriptionActorMessage::ChannelState(notification) => {
+                let event = ChannelStateEvent {
+                    channel_id: notification.channel_id,
+                    peer_id: notification.peer_id,
+                    state: notification.state,
+                };
+                state.publish(SubscriptionTopic::ChannelStates, &event);
+            }
+            SubscriptionActorMessage::AddedTlc(notification) => {
+                state.publish(SubscriptionTopic::Tlcs, &TlcEvent::from(notification));
+            }
+            SubscriptionActorMessage::RemovedTlc(record) => {
+                if record.direction == TlcDirection::Incoming && record.status == TlcStatus::Settled
+                {
+                    match self.store.get_invoice(&record.payment_hash) {
+                        Ok(Some(_)) => {
+                            let event = InvoicePaidEvent {
+                                payment_hash: record.payment_hash,
+                                channel_id: record.channel_id,
+                                tlc_id: record.tlc_id,
+                                amount: record.amount,
+                            };
+                            state.publish(SubscriptionTopic::Invoices, &event);
+                        }
+                        Ok(None) => {}
+                        Err(err) => error!(
+                            "Failed to get the invoice of payment hash {:?}: {}",
+                            record.payment_hash, err
+                        ),
+                    }
+                }
+                state.publish(SubscriptionTopic::Tlcs, &TlcEvent::from(record));
+            }
+            SubscriptionActorMessage::Peer(notification) => {
+                state.publish(SubscriptionTopic::Peers, &PeerEvent::from(notification));
+            }
+            SubscriptionActorMessage::CchOrder(notification) => {
+                let event = CchOrderEvent {
+                    payment_hash: notification.payment_hash,
+                    order_type: notification.order_type,
+                    status: notification.status,
+                };
+                state.publish(SubscriptionTopic::CchOrders, &event);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Start the subscription actor and subscribe it to the ports of the channels, the peers and
+/// the cross-chain hub orders.
+pub async fn start_subscription_actor<S: InvoiceStore + Send + Sync + 'static>(
+    store: S,
+    channel_subscribers: &ChannelSubscribers,
+    cch_order_subscribers: &Arc<OutputPort<CchOrderNotification>>,
+    root_actor: ActorCell,
+) -> ActorRef<SubscriptionActorMessage> {
+    let (actor, _handle) = Actor::spawn_linked(
+        Some("subscription actor".to_string()),
+        SubscriptionActor { store },
+        (),
+        root_actor,
+    )
+    .await
+    .expect("start subscription actor");
+    channel_subscribers
+        .channel_state_subscribers
+        .subscribe(actor.clone(), |notification| {
+            Some(SubscriptionActorMessage::ChannelState(notification))
+        });
+    channel_subscribers
+        .added_tlcs_subscribers
+        .subscribe(actor.clone(), |notification| {
+            Some(SubscriptionActorMessage::AddedTlc(notification))
+        });
+    channel_subscribers
+        .removed_tlcs_subscribers
+        .subscribe(actor.clone(), |record| {
+            Some(SubscriptionActorMessage::RemovedTlc(record))
+        });
+    channel_subscribers
+        .peer_subscribers
+        .subscribe(actor.clone(), |notification| {
+            Some(SubscriptionActorMessage::Peer(notification))
+        });
+    cch_order_subscribers.subscribe(actor.clone(), |notification| {
+        Some(SubscriptionActorMessage::CchOrder(notification))
+    });
+    actor
+}
+
+#[rpc(server)]
+pub trait SubscriptionRpc {
+    #[subscription(
+        name = "subscribe_channel_states",
+        unsubscribe = "unsubscribe_channel_states",
+        item = ChannelStateEvent
+    )]
+    async fn subscribe_channel_states(&self) -> SubscriptionResult;
+
+    #[subscription(name = "subscribe_tlcs", unsubscribe = "unsubscribe_tlcs", item = TlcEvent)]
+    async fn subscribe_tlcs(&self) -> SubscriptionResult;
+
+    #[subscription(
+        name = "subscribe_invoices",
+        unsubscribe = "unsubscribe_invoices",
+        item = InvoicePaidEvent
+    )]
+    async fn subscribe_invoices(&self) -> SubscriptionResult;
+
+    #[subscription(name = "subscribe_peers", unsubscribe = "unsubscribe_peers", item = PeerEvent)]
+    async fn subscribe_peers(&self) -> SubscriptionResult;
+
+    #[subscription(
+        name = "subscribe_cch_orders",
+        unsubscribe = "unsubscribe_cch_orders",
+        item = CchOrderEvent
+    )]
+    async fn subscribe_cch_orders(&self) -> SubscriptionResult;
+}
+
+pub struct SubscriptionRpcServerImpl {
+    actor: ActorRef<SubscriptionActorMessage>,
+}
+
+impl SubscriptionRpcServerImpl {
+    pub fn new(actor: ActorRef<SubscriptionActorMessage>) -> Self {
+        SubscriptionRpcServerImpl { actor }
+    }
+
+    async fn subscribe(
+        &self,
+        pending: PendingSubscriptionSink,
+        topic: SubscriptionTopic,
+    ) -> SubscriptionResult {
+        let sink = pending.accept().await?;
+        self.actor
+            .send_message(SubscriptionActorMessage::Subscribe(topic, sink))
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SubscriptionRpcServer for SubscriptionRpcServerImpl {
+    async fn subscribe_channel_states(
+        &self,
+        pending: PendingSubscriptionSink,
+    ) -> SubscriptionResult {
+        self.subscribe(pending, SubscriptionTopic::ChannelStates)
+            .await
+    }
+
+    async fn subscribe_tlcs(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        self.subscribe(pending, SubscriptionTopic::Tlcs).await
+    }
+
+    async fn subscribe_invoices(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        self.subscribe(pending, SubscriptionTopic::Invoices).await
+    }
+
+    async fn subscribe_peers(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        self.subscribe(pending, SubscriptionTopic::Peers).await
+    }
+
+    async fn subscribe_cch_orders(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        self.subscribe(pending, SubscriptionTopic::CchOrders).await
+    }
+}
@@ -0,0 +1,222 @@
+//! The listeners of the rpc service. The connections are served by the tower service of
+//! jsonrpsee, so all the transports share the same methods, middleware and stop handle.
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use hyper::{server::conn::Http, service::Service, Body, Request, Response};
+use jsonrpsee::server::StopHandle;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+use tracing::{debug, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Accepting fails when the process runs out of file descriptors, retrying immediately
+// would spin until some connections are closed.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+// Connections which don't complete the TLS handshake in time are dropped, so that they
+// can't hold the file descriptors forever.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn bind_tcp(addr: &str) -> Result<TcpListener> {
+    #[cfg(not(release))]
+    {
+        // Use socket2 to set reuse address and reuse port,
+        // so that we can restart the server without waiting for the port to be released.
+        // it will avoid the error: "Address already in use" in CI.
+        use socket2::{Domain, Socket, Type};
+        let addr: std::net::SocketAddr = addr.parse()?;
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(4096)?;
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+    #[cfg(release)]
+    {
+        Ok(TcpListener::bind(addr).await?)
+    }
+}
+
+/// Bind the unix socket, which is only accessible by the user running the node.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> Result<UnixListener> {
+    // Remove the socket left by the last run, but never another kind of file.
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+        }
+        Ok(_) => return Err(anyhow!("{} exists and is not a socket", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("stat {}", path.display())),
+    }
+    // The socket is bound in a directory only accessible by us, and moved to the path once
+    // its permissions are restricted, so that no one can connect to it in between.
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file path", path.display()))?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&private_dir);
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("create {}", private_dir.display()))?;
+    let private_path = private_dir.join(file_name);
+    let result = UnixListener::bind(&private_path)
+        .with_context(|| format!("bind {}", path.display()))
+        .and_then(|listener| {
+            fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&private_path, path)
+                .with_context(|| format!("move the socket to {}", path.display()))?;
+            Ok(listener)
+        });
+    let _ = fs::remove_dir_all(&private_dir);
+    result
+}
+
+pub fn load_tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert_path).with_context(|| format!("open {}", cert_path.display()))?,
+    ))?
+    .into_iter()
+    .map(Certificate)
+    .collect();
+    let key = rustls_pemfile::read_all(&mut BufReader::new(
+        File::open(key_path).with_context(|| format!("open {}", key_path.display()))?,
+    ))?
+    .into_iter()
+    .find_map(|item| match item {
+        rustls_pemfile::Item::PKCS8Key(key)
+        | rustls_pemfile::Item::RSAKey(key)
+        | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+        _ => None,
+    })
+    .ok_or_else(|| anyhow!("no private key in {}", key_path.display()))?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn serve_connection<IO, S>(io: IO, service: S, stop_handle: StopHandle)
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    // Upgrades are required by the websocket connections.
+    let connection = Http::new().serve_connection(io, service).with_upgrades();
+    tokio::pin!(connection);
+    tokio::select! {
+        result = &mut connection => {
+            if let Err(err) = result {
+                debug!("Rpc connection closed with error: {}", err);
+            }
+        }
+        _ = stop_handle.shutdown() => {
+            connection.as_mut().graceful_shutdown();
+            let _ = connection.await;
+        }
+    }
+}
+
+/// Serve the connections of the tcp listener, over TLS if the acceptor is given.
+pub fn serve_tcp<F, S>(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    make_service: F,
+    stop_handle: StopHandle,
+) where
+    F: Fn() -> S + Send + 'static,
+    S: Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept rpc connection: {}", err);
+                        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                },
+                _ = stop_handle.clone().shutdown() => break,
+            };
+            let (service, stop_handle) = (make_service(), stop_handle.clone());
+            match tls.clone() {
+                Some(acceptor) => {
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => serve_connection(stream, service, stop_handle).await,
+                            Ok(Err(err)) => debug!("Rpc TLS handshake failed: {}", err),
+                            Err(_) => debug!("Rpc TLS handshake timed out"),
+                        }
+                    });
+                }
+                None => {
+                    tokio::spawn(serve_connection(stream, service, stop_handle));
+                }
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+pub fn serve_unix<F, S>(listener: UnixListener, make_service: F, stop_handle: StopHandle)
+where
+    F: Fn() -> S + Send + 'static,
+    S: Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept rpc connection: {}", err);
+                        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                },
+                _ = stop_handle.clone().shutdown() => break,
+            };
+            tokio::spawn(serve_connection(
+                stream,
+                make_service(),
+                stop_handle.clone(),
+            ));
+        }
+    });
+}
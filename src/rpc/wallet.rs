@@ -0,0 +1,170 @@
+use crate::ckb::{CkbChainMessage, SendToAddressRequest, UdtBalance, WalletBalance};
+use crate::fiber::{
+    channel::DEFAULT_FEE_RATE,
+    serde_utils::{U128Hex, U64Hex},
+    types::Hash256,
+};
+use crate::{handle_actor_call, log_and_error};
+use ckb_jsonrpc_types::{JsonBytes, OutPoint, Script};
+use ckb_types::prelude::Unpack;
+use jsonrpsee::{
+    core::async_trait,
+    proc_macros::rpc,
+    types::{error::CALL_EXECUTION_FAILED_CODE, ErrorObjectOwned},
+};
+use ractor::{call, ActorRef};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct GetBalanceResult {
+    /// Capacity of the cells without type script and data, in shannons.
+    #[serde_as(as = "U64Hex")]
+    pub ckb: u64,
+    pub udts: Vec<UdtBalanceResult>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct UdtBalanceResult {
+    pub name: String,
+    pub type_script: Script,
+    #[serde_as(as = "U128Hex")]
+    pub amount: u128,
+    #[serde_as(as = "U64Hex")]
+    pub capacity: u64,
+}
+
+impl From<WalletBalance> for GetBalanceResult {
+    fn from(balance: WalletBalance) -> Self {
+        GetBalanceResult {
+            ckb: balance.ckb,
+            udts: balance
+                .udts
+                .into_iter()
+                .map(|udt: UdtBalance| UdtBalanceResult {
+                    name: udt.name,
+                    type_script: udt.type_script.into(),
+                    amount: udt.amount,
+                    capacity: udt.capacity,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct ListUnspentResult {
+    pub cells: Vec<UnspentCell>,
+}
+
+#[serde_as]
+#[derive(Clone, Serialize)]
+pub struct UnspentCell {
+    pub out_point: OutPoint,
+    #[serde_as(as = "U64Hex")]
+    pub capacity: u64,
+    pub type_script: Option<Script>,
+    pub output_data: JsonBytes,
+    #[serde_as(as = "U64Hex")]
+    pub block_number: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct NewAddressResult {
+    pub address: String,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SendToAddressParams {
+    pub address: String,
+    /// Capacity sent to the address, in shannons.
+    #[serde_as(as = "U64Hex")]
+    pub amount: u64,
+    #[serde_as(as = "Option<U64Hex>")]
+    pub fee_rate: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SendToAddressResult {
+    pub tx_hash: Hash256,
+}
+
+#[rpc(server)]
+pub trait WalletRpc {
+    #[method(name = "get_balance")]
+    async fn get_balance(&self) -> Result<GetBalanceResult, ErrorObjectOwned>;
+
+    #[method(name = "list_unspent")]
+    async fn list_unspent(&self) -> Result<ListUnspentResult, ErrorObjectOwned>;
+
+    #[method(name = "new_address")]
+    async fn new_address(&self) -> Result<NewAddressResult, ErrorObjectOwned>;
+
+    #[method(name = "send_to_address")]
+    async fn send_to_address(
+        &self,
+        params: SendToAddressParams,
+    ) -> Result<SendToAddressResult, ErrorObjectOwned>;
+}
+
+pub struct WalletRpcServerImpl {
+    actor: ActorRef<CkbChainMessage>,
+}
+
+impl WalletRpcServerImpl {
+    pub fn new(actor: ActorRef<CkbChainMessage>) -> Self {
+        WalletRpcServerImpl { actor }
+    }
+}
+
+#[async_trait]
+impl WalletRpcServer for WalletRpcServerImpl {
+    async fn get_balance(&self) -> Result<GetBalanceResult, ErrorObjectOwned> {
+        let message = CkbChainMessage::GetBalance;
+        handle_actor_call!(self.actor, message, ()).map(GetBalanceResult::from)
+    }
+
+    async fn list_unspent(&self) -> Result<ListUnspentResult, ErrorObjectOwned> {
+        let message = CkbChainMessage::ListUnspent;
+        handle_actor_call!(self.actor, message, ()).map(|cells| ListUnspentResult {
+            cells: cells
+                .into_iter()
+                .map(|cell| UnspentCell {
+                    out_point: cell.out_point.into(),
+                    capacity: cell.output.capacity().unpack(),
+                    type_script: cell.output.type_().to_opt().map(Into::into),
+                    output_data: JsonBytes::from_bytes(cell.output_data),
+                    block_number: cell.block_number,
+                })
+                .collect(),
+        })
+    }
+
+    async fn new_address(&self) -> Result<NewAddressResult, ErrorObjectOwned> {
+        // The node has a single funding key, so the address is always the same.
+        let message = CkbChainMessage::GetAddress;
+        handle_actor_call!(self.actor, message, ()).map(|address| NewAddressResult { address })
+    }
+
+    async fn send_to_address(
+        &self,
+        params: SendToAddressParams,
+    ) -> Result<SendToAddressResult, ErrorObjectOwned> {
+        let message = |rpc_reply| {
+            CkbChainMessage::SendToAddress(
+                SendToAddressRequest {
+                    address: params.address.clone(),
+                    amount: params.amount,
+                    fee_rate: params.fee_rate.unwrap_or(DEFAULT_FEE_RATE),
+                },
+                rpc_reply,
+            )
+        };
+        handle_actor_call!(self.actor, message, params).map(|tx_hash| SendToAddressResult {
+            tx_hash: tx_hash.into(),
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use ckb_sdk::traits::{Signer as CkbSigner, SignerError as CkbSignerError};
+use ckb_types::{bytes::Bytes, core::TransactionView};
+
+use super::Signer;
+
+/// Sign the transactions spending the cells of the funding lock script with the funding key
+/// held by the node [`Signer`], so that ckb-sdk never sees the secret key.
+#[derive(Clone, Debug)]
+pub struct FundingKeySigner {
+    signer: Arc<dyn Signer>,
+    // The args of the funding lock script, i.e. blake160 of the funding pubkey.
+    pubkey_hash: [u8; 20],
+}
+
+impl FundingKeySigner {
+    pub fn new(signer: Arc<dyn Signer>, pubkey_hash: [u8; 20]) -> Self {
+        Self {
+            signer,
+            pubkey_hash,
+        }
+    }
+}
+
+impl CkbSigner for FundingKeySigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id == self.pubkey_hash.as_slice()
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        _tx: &TransactionView,
+    ) -> Result<Bytes, CkbSignerError> {
+        if !self.match_id(id) {
+            return Err(CkbSignerError::IdNotFound);
+        }
+        let message: [u8; 32] = message.try_into().map_err(|_| {
+            CkbSignerError::InvalidMessage(format!("expect 32 bytes, got {}", message.len()))
+        })?;
+        let signature = self
+            .signer
+            .sign_funding_message(message)
+            .map_err(|err| CkbSignerError::Other(err.into()))?;
+        // The recoverable signature is the compact signature followed by the recovery id.
+        let signature = if recoverable {
+            &signature[..]
+        } else {
+            &signature[..64]
+        };
+        Ok(Bytes::from(signature.to_vec()))
+    }
+}
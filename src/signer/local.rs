@@ -0,0 +1,134 @@
+use std::fmt;
+
+use ckb_types::core::TransactionView;
+use musig2::{sign_partial, AggNonce, KeyAggContext, PartialSignature, PubNonce};
+use secp256k1::SecretKey;
+use zeroize::Zeroize;
+
+use crate::fiber::{
+    backup::{check_sweep_transaction, ChannelBackup},
+    channel::{ChannelBasePublicKeys, InMemorySigner},
+    types::{Hash256, Privkey, Pubkey},
+};
+
+use super::{Signer, SignerError};
+
+/// A signer which keeps the funding key and the master key of the channel keys in memory.
+/// The keys of a channel are derived from the master key and the key id.
+pub struct LocalSigner {
+    funding_key: Privkey,
+    channel_master_key: [u8; 32],
+}
+
+impl LocalSigner {
+    pub fn new(funding_key: SecretKey, channel_master_key: [u8; 32]) -> Self {
+        Self {
+            funding_key: funding_key.into(),
+            channel_master_key,
+        }
+    }
+
+    fn channel_keys(&self, key_id: &Hash256) -> InMemorySigner {
+        InMemorySigner::generate_from_seed(
+            &[self.channel_master_key.as_slice(), key_id.as_ref()].concat(),
+        )
+    }
+}
+
+impl Drop for LocalSigner {
+    fn drop(&mut self) {
+        self.funding_key.0.non_secure_erase();
+        self.channel_master_key.zeroize();
+    }
+}
+
+// Never print the secrets.
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSigner")
+            .field("funding_pubkey", &self.funding_key.pubkey())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer for LocalSigner {
+    fn funding_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.funding_key.pubkey())
+    }
+
+    fn sign_funding_message(&self, message: [u8; 32]) -> Result<[u8; 65], SignerError> {
+        Ok(self.funding_key.sign_ecdsa_recoverable(&message))
+    }
+
+    fn channel_base_public_keys(
+        &self,
+        key_id: Hash256,
+    ) -> Result<ChannelBasePublicKeys, SignerError> {
+        Ok(self.channel_keys(&key_id).get_base_public_keys())
+    }
+
+    fn commitment_point(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<Pubkey, SignerError> {
+        Ok(self
+            .channel_keys(&key_id)
+            .get_commitment_point(commitment_number))
+    }
+
+    fn commitment_secret(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<[u8; 32], SignerError> {
+        Ok(self
+            .channel_keys(&key_id)
+            .get_commitment_secret(commitment_number))
+    }
+
+    fn musig2_pubnonce(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<PubNonce, SignerError> {
+        Ok(self
+            .channel_keys(&key_id)
+            .derive_musig2_nonce(commitment_number)
+            .public_nonce())
+    }
+
+    fn sign_sweep_transaction(
+        &self,
+        channel: &ChannelBackup,
+        commitment_tx: &TransactionView,
+        sweep_tx: &TransactionView,
+    ) -> Result<[u8; 65], SignerError> {
+        let (commitment_number, message) =
+            check_sweep_transaction(self, channel, commitment_tx, sweep_tx)?;
+        Ok(self
+            .channel_keys(&channel.key_id)
+            .derive_payment_key(commitment_number)
+            .sign_ecdsa_recoverable(&message))
+    }
+
+    fn musig2_partial_sign(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        message: &[u8],
+    ) -> Result<PartialSignature, SignerError> {
+        let keys = self.channel_keys(&key_id);
+        let key_agg_ctx = KeyAggContext::new(pubkeys)?;
+        let secnonce = keys.derive_musig2_nonce(commitment_number);
+        Ok(sign_partial(
+            &key_agg_ctx,
+            keys.funding_key,
+            secnonce,
+            &agg_nonce,
+            message,
+        )?)
+    }
+}
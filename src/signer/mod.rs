@@ -0,0 +1,146 @@
+use std::{fmt::Debug, sync::Arc};
+
+use ckb_types::core::TransactionView;
+use musig2::{
+    errors::{KeyAggError, SigningError},
+    AggNonce, PartialSignature, PubNonce,
+};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::{
+    ckb::CkbConfig,
+    fiber::{
+        backup::ChannelBackup,
+        channel::ChannelBasePublicKeys,
+        types::{Hash256, Pubkey},
+    },
+    FiberConfig,
+};
+
+mod funding;
+pub use funding::FundingKeySigner;
+
+mod local;
+pub use local::LocalSigner;
+
+#[cfg(unix)]
+mod remote;
+#[cfg(unix)]
+pub use remote::{serve, RemoteSigner};
+
+#[derive(Error, Debug)]
+pub enum SignerError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid response from the signer: {0}")]
+    InvalidResponse(String),
+    #[error("The signer refused the request: {0}")]
+    Refused(String),
+    #[error("Musig2 KeyAggError: {0}")]
+    Musig2KeyAggError(#[from] KeyAggError),
+    #[error("Musig2 SigningError: {0}")]
+    Musig2SigningError(#[from] SigningError),
+}
+
+/// Holds the secrets of the node: the funding key which owns the cells used to fund channels,
+/// and the keys of the channels. The keys of a channel are identified by a key id chosen when
+/// the channel is created, only the key id is saved in the channel state.
+///
+/// All the signatures and commitment secrets are derived deterministically from the key id
+/// and the commitment number, so the signer doesn't need to keep any state for the channels.
+/// A signing process serving remote signers keeps a record of the nonce uses and the revealed
+/// secrets though, see [`serve`].
+pub trait Signer: Debug + Send + Sync {
+    /// The public key of the secp256k1 lock script which owns the funding cells.
+    fn funding_pubkey(&self) -> Result<Pubkey, SignerError>;
+
+    /// Sign the message with the funding key, returns a recoverable signature, i.e. 64 bytes
+    /// compact signature followed by the recovery id.
+    fn sign_funding_message(&self, message: [u8; 32]) -> Result<[u8; 65], SignerError>;
+
+    fn channel_base_public_keys(
+        &self,
+        key_id: Hash256,
+    ) -> Result<ChannelBasePublicKeys, SignerError>;
+
+    fn commitment_point(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<Pubkey, SignerError>;
+
+    /// The secret is revealed to the counterparty once the commitment is revoked.
+    fn commitment_secret(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<[u8; 32], SignerError>;
+
+    fn musig2_pubnonce(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<PubNonce, SignerError>;
+
+    /// Sign the transaction which sweeps our immediately spendable balance on the commitment
+    /// transaction broadcast by the peer of a backed up channel. The output is locked by a
+    /// payment key of the channel, the transaction is only signed if it passes
+    /// [`crate::fiber::backup::check_sweep_transaction`]. Returns a recoverable signature like
+    /// [`Signer::sign_funding_message`].
+    fn sign_sweep_transaction(
+        &self,
+        channel: &ChannelBackup,
+        commitment_tx: &TransactionView,
+        sweep_tx: &TransactionView,
+    ) -> Result<[u8; 65], SignerError>;
+
+    /// Sign the message with the channel funding key and the nonce of the commitment number,
+    /// `pubkeys` are the funding pubkeys of both parties in the order of key aggregation.
+    fn musig2_partial_sign(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        message: &[u8],
+    ) -> Result<PartialSignature, SignerError>;
+}
+
+/// Connect to the external signer if a signer socket is configured, otherwise keep the keys
+/// in memory. The local signer derives the funding key and the channel keys from the node
+/// mnemonic, an existing key file of the ckb service still takes precedence as the funding key.
+/// Nodes created before the mnemonic derive the channel keys from the node key file.
+pub fn new_signer(
+    fiber_config: &FiberConfig,
+    ckb_config: &CkbConfig,
+) -> crate::Result<Arc<dyn Signer>> {
+    #[cfg(unix)]
+    if let Some(path) = fiber_config.signer_socket.as_ref() {
+        tracing::info!("Using external signer at {:?}", path);
+        return Ok(Arc::new(RemoteSigner::new(path.clone())));
+    }
+
+    let seed = fiber_config.read_or_generate_node_seed()?;
+    let funding_key = match seed.as_ref() {
+        Some(seed) if !ckb_config.secret_key_exists() => seed.funding_key(),
+        Some(_) => {
+            tracing::warn!(
+                "Using the funding key in {:?}, it can't be restored from the mnemonic",
+                ckb_config.base_dir().join("key")
+            );
+            ckb_config.read_secret_key()?
+        }
+        None => ckb_config.read_secret_key()?,
+    };
+    let channel_master_key = match seed {
+        Some(seed) => *seed.channel_master_key(),
+        None => {
+            let node_key = fiber_config.read_or_generate_secret_key()?;
+            ckb_hash::blake2b_256(Zeroizing::new(
+                [node_key.as_ref(), b"FIBER_CHANNEL_MASTER_KEY"].concat(),
+            ))
+        }
+    };
+    Ok(Arc::new(LocalSigner::new(funding_key, channel_master_key)))
+}
@@ -0,0 +1,594 @@
+//! Talk to an external signer over a unix domain socket.
+//!
+//! Each request is a line of JSON like `{"method":"commitment_point","params":{...}}`, the signer
+//! answers with a line of either `{"result":...}` or `{"error":"reason"}`. Byte strings are
+//! encoded as 0x-prefixed hex strings.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types::{JsonBytes, Transaction};
+use ckb_types::{core::TransactionView, packed, prelude::IntoTransactionView};
+use musig2::{AggNonce, PartialSignature, PubNonce};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::serde_as;
+use tracing::{debug, warn};
+
+use crate::{
+    fiber::{
+        backup::ChannelBackup,
+        channel::{ChannelBasePublicKeys, INITIAL_COMMITMENT_NUMBER, MAX_MUSIG2_NONCE_USES},
+        serde_utils::SliceHex,
+        types::{Hash256, Pubkey},
+    },
+    keystore::write_private_file,
+};
+
+use super::{Signer, SignerError};
+
+/// Give up if the signer doesn't answer within this time.
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum SignerRequest {
+    FundingPubkey,
+    SignFundingMessage {
+        #[serde_as(as = "SliceHex")]
+        message: [u8; 32],
+    },
+    ChannelBasePublicKeys {
+        key_id: Hash256,
+    },
+    CommitmentPoint {
+        key_id: Hash256,
+        commitment_number: u64,
+    },
+    CommitmentSecret {
+        key_id: Hash256,
+        commitment_number: u64,
+    },
+    Musig2Pubnonce {
+        key_id: Hash256,
+        commitment_number: u64,
+    },
+    SignSweepTransaction {
+        channel: ChannelBackup,
+        commitment_tx: Transaction,
+        sweep_tx: Transaction,
+    },
+    Musig2PartialSign {
+        key_id: Hash256,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        #[serde_as(as = "SliceHex")]
+        message: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SignerResponse<T> {
+    Result(T),
+    Error(String),
+}
+
+/// A signer running in another process, which listens on a unix domain socket.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    path: PathBuf,
+    // Connected on the first request, and dropped on errors so that the next request reconnects.
+    connection: Mutex<Option<BufReader<UnixStream>>>,
+}
+
+impl RemoteSigner {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, request: SignerRequest) -> Result<T, SignerError> {
+        let mut connection = self.connection.lock().expect("lock signer connection");
+        let response = match self.round_trip(&mut connection, &request) {
+            Ok(response) => response,
+            Err(err) => {
+                // The response of this request may still arrive later, don't reuse the stream.
+                *connection = None;
+                return Err(err);
+            }
+        };
+        match serde_json::from_str::<SignerResponse<T>>(&response) {
+            Ok(SignerResponse::Result(result)) => Ok(result),
+            Ok(SignerResponse::Error(reason)) => Err(SignerError::Refused(reason)),
+            Err(err) => Err(SignerError::InvalidResponse(err.to_string())),
+        }
+    }
+
+    fn round_trip(
+        &self,
+        connection: &mut Option<BufReader<UnixStream>>,
+        request: &SignerRequest,
+    ) -> Result<String, SignerError> {
+        if connection.is_none() {
+            let stream = UnixStream::connect(&self.path)?;
+            stream.set_read_timeout(Some(SIGNER_TIMEOUT))?;
+            stream.set_write_timeout(Some(SIGNER_TIMEOUT))?;
+            *connection = Some(BufReader::new(stream));
+        }
+        let reader = connection.as_mut().expect("signer connected");
+
+        let mut line = serde_json::to_string(request).expect("serialize signer request");
+        line.push('\n');
+        reader.get_mut().write_all(line.as_bytes())?;
+
+        let mut response = String::new();
+        if reader.read_line(&mut response)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(response)
+    }
+}
+
+fn parse_bytes<const N: usize>(bytes: JsonBytes) -> Result<[u8; N], SignerError> {
+    let bytes = bytes.into_bytes();
+    bytes.as_ref().try_into().map_err(|_| {
+        SignerError::InvalidResponse(format!("expect {} bytes, got {}", N, bytes.len()))
+    })
+}
+
+impl Signer for RemoteSigner {
+    fn funding_pubkey(&self) -> Result<Pubkey, SignerError> {
+        self.call(SignerRequest::FundingPubkey)
+    }
+
+    fn sign_funding_message(&self, message: [u8; 32]) -> Result<[u8; 65], SignerError> {
+        parse_bytes(self.call(SignerRequest::SignFundingMessage { message })?)
+    }
+
+    fn channel_base_public_keys(
+        &self,
+        key_id: Hash256,
+    ) -> Result<ChannelBasePublicKeys, SignerError> {
+        self.call(SignerRequest::ChannelBasePublicKeys { key_id })
+    }
+
+    fn commitment_point(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<Pubkey, SignerError> {
+        self.call(SignerRequest::CommitmentPoint {
+            key_id,
+            commitment_number,
+        })
+    }
+
+    fn commitment_secret(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<[u8; 32], SignerError> {
+        parse_bytes(self.call(SignerRequest::CommitmentSecret {
+            key_id,
+            commitment_number,
+        })?)
+    }
+
+    fn musig2_pubnonce(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<PubNonce, SignerError> {
+        self.call(SignerRequest::Musig2Pubnonce {
+            key_id,
+            commitment_number,
+        })
+    }
+
+    fn sign_sweep_transaction(
+        &self,
+        channel: &ChannelBackup,
+        commitment_tx: &TransactionView,
+        sweep_tx: &TransactionView,
+    ) -> Result<[u8; 65], SignerError> {
+        parse_bytes(self.call(SignerRequest::SignSweepTransaction {
+            channel: channel.clone(),
+            commitment_tx: commitment_tx.data().into(),
+            sweep_tx: sweep_tx.data().into(),
+        })?)
+    }
+
+    fn musig2_partial_sign(
+        &self,
+        key_id: Hash256,
+        commitment_number: u64,
+        pubkeys: [Pubkey; 2],
+        agg_nonce: AggNonce,
+        message: &[u8],
+    ) -> Result<PartialSignature, SignerError> {
+        self.call(SignerRequest::Musig2PartialSign {
+            key_id,
+            commitment_number,
+            pubkeys,
+            agg_nonce,
+            message: message.to_vec(),
+        })
+    }
+}
+
+/// What the signing process has done with the keys of a channel.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ChannelRecord {
+    key_id: Hash256,
+    // The latest commitment number whose nonce has signed messages, and the hashes of the
+    // aggregated nonces and the messages signed with it.
+    nonce_commitment_number: u64,
+    nonce_digests: Vec<Hash256>,
+    // The commitment number of the last revealed secret, i.e. the last revoked commitment.
+    revoked_commitment_number: Option<u64>,
+}
+
+/// The record of the nonce uses and the revealed commitment secrets of all channels, which is
+/// saved before the signature or the secret is returned. The node keeps the same record with
+/// the channel state, this one makes sure that a compromised node, or a node restored from an
+/// old database, can't get a nonce reused or the secret of an unrevoked commitment revealed.
+#[derive(Debug)]
+struct SignerRecord {
+    path: PathBuf,
+    channels: HashMap<Hash256, ChannelRecord>,
+}
+
+impl SignerRecord {
+    fn load(path: PathBuf) -> io::Result<Self> {
+        let channels = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice::<Vec<ChannelRecord>>(&content)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            channels: channels
+                .into_iter()
+                .map(|record| (record.key_id, record))
+                .collect(),
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let channels = self.channels.values().collect::<Vec<_>>();
+        let content = serde_json::to_vec(&channels).expect("serialize signer record");
+        write_private_file(&self.path, &content)
+    }
+
+    fn channel(&mut self, key_id: Hash256) -> &mut ChannelRecord {
+        self.channels
+            .entry(key_id)
+            .or_insert_with(|| ChannelRecord {
+                key_id,
+                ..Default::default()
+            })
+    }
+
+    /// Secrets are revealed in the order of the commitments, revealing the secret of the
+    /// commitment next to the last revoked one revokes it.
+    fn reveal_commitment_secret(
+        &mut self,
+        key_id: Hash256,
+        commitment_number: u64,
+    ) -> Result<(), SignerError> {
+        let channel = self.channel(key_id);
+        let next = channel
+            .revoked_commitment_number
+            .map_or(INITIAL_COMMITMENT_NUMBER, |number| number + 1);
+        if commitment_number > next {
+            return Err(SignerError::Refused(format!(
+                "the commitment number {} is ahead of the last revoked commitment {:?}",
+                commitment_number, channel.revoked_commitment_number
+            )));
+        }
+        if commitment_number == next {
+            channel.revoked_commitment_number = Some(commitment_number);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`crate::fiber::channel::ChannelSigner::sign_musig2_partial`], a nonce never
+    /// signs more than [`MAX_MUSIG2_NONCE_USES`] different messages, and the nonces of the
+    /// previous commitment numbers are never used again.
+    fn use_musig2_nonce(
+        &mut self,
+        key_id: Hash256,
+        commitment_number: u64,
+        agg_nonce: &AggNonce,
+        message: &[u8],
+    ) -> Result<(), SignerError> {
+        let digest = blake2b_256([agg_nonce.serialize().as_slice(), message].concat()).into();
+        let channel = self.channel(key_id);
+        if commitment_number < channel.nonce_commitment_number {
+            return Err(SignerError::Refused(format!(
+                "the nonce of commitment number {} is older than the latest used nonce {}",
+                commitment_number, channel.nonce_commitment_number
+            )));
+        }
+        if commitment_number > channel.nonce_commitment_number {
+            channel.nonce_commitment_number = commitment_number;
+            channel.nonce_digests.clear();
+        }
+        if channel.nonce_digests.contains(&digest) {
+            return Ok(());
+        }
+        if channel.nonce_digests.len() >= MAX_MUSIG2_NONCE_USES {
+            return Err(SignerError::Refused(format!(
+                "the nonce of commitment number {} has already signed {} messages",
+                commitment_number,
+                channel.nonce_digests.len()
+            )));
+        }
+        channel.nonce_digests.push(digest);
+        self.save()?;
+        Ok(())
+    }
+}
+
+/// Serve the requests of remote signers with the given signer, this is what a signing process
+/// runs. Each connection is handled in its own thread. The nonce uses and the revealed secrets
+/// are recorded in the file at `record_path`, which must be kept as long as the channels are
+/// open. This function only returns if the record can't be loaded.
+pub fn serve(
+    listener: UnixListener,
+    signer: Arc<dyn Signer>,
+    record_path: PathBuf,
+) -> io::Result<()> {
+    let record = Arc::new(Mutex::new(SignerRecord::load(record_path)?));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let signer = signer.clone();
+                let record = record.clone();
+                thread::spawn(move || {
+                    if let Err(err) = serve_connection(stream, signer.as_ref(), &record) {
+                        debug!("Signer connection closed: {}", err);
+                    }
+                });
+            }
+            Err(err) => warn!("Failed to accept signer connection: {}", err),
+        }
+    }
+    Ok(())
+}
+
+fn serve_connection(
+    stream: UnixStream,
+    signer: &dyn Signer,
+    record: &Mutex<SignerRecord>,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let response = match serde_json::from_str::<SignerRequest>(&line) {
+            Ok(request) => match handle_request(signer, record, request) {
+                Ok(result) => SignerResponse::Result(result),
+                Err(err) => SignerResponse::Error(err.to_string()),
+            },
+            Err(err) => SignerResponse::Error(format!("invalid request: {}", err)),
+        };
+        let mut response = serde_json::to_string(&response).expect("serialize signer response");
+        response.push('\n');
+        writer.write_all(response.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn handle_request(
+    signer: &dyn Signer,
+    record: &Mutex<SignerRecord>,
+    request: SignerRequest,
+) -> Result<serde_json::Value, SignerError> {
+    let result = match request {
+        SignerRequest::FundingPubkey => serde_json::to_value(signer.funding_pubkey()?),
+        SignerRequest::SignFundingMessage { message } => serde_json::to_value(JsonBytes::from_vec(
+            signer.sign_funding_message(message)?.to_vec(),
+        )),
+        SignerRequest::ChannelBasePublicKeys { key_id } => {
+            serde_json::to_value(signer.channel_base_public_keys(key_id)?)
+        }
+        SignerRequest::CommitmentPoint {
+            key_id,
+            commitment_number,
+        } => serde_json::to_value(signer.commitment_point(key_id, commitment_number)?),
+        SignerRequest::CommitmentSecret {
+            key_id,
+            commitment_number,
+        } => {
+            record
+                .lock()
+                .expect("lock signer record")
+                .reveal_commitment_secret(key_id, commitment_number)?;
+            serde_json::to_value(JsonBytes::from_vec(
+                signer
+                    .commitment_secret(key_id, commitment_number)?
+                    .to_vec(),
+            ))
+        }
+        SignerRequest::Musig2Pubnonce {
+            key_id,
+            commitment_number,
+        } => serde_json::to_value(signer.musig2_pubnonce(key_id, commitment_number)?),
+        SignerRequest::SignSweepTransaction {
+            channel,
+            commitment_tx,
+            sweep_tx,
+        } => serde_json::to_value(JsonBytes::from_vec(
+            signer
+                .sign_sweep_transaction(
+                    &channel,
+                    &packed::Transaction::from(commitment_tx).into_view(),
+                    &packed::Transaction::from(sweep_tx).into_view(),
+                )?
+                .to_vec(),
+        )),
+        SignerRequest::Musig2PartialSign {
+            key_id,
+            commitment_number,
+            pubkeys,
+            agg_nonce,
+            message,
+        } => {
+            record
+                .lock()
+                .expect("lock signer record")
+                .use_musig2_nonce(key_id, commitment_number, &agg_nonce, &message)?;
+            serde_json::to_value(signer.musig2_partial_sign(
+                key_id,
+                commitment_number,
+                pubkeys,
+                agg_nonce,
+                &message,
+            )?)
+        }
+    };
+    Ok(result.expect("serialize signer result"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{os::unix::net::UnixListener, path::Path, sync::Arc, thread};
+
+    use musig2::{verify_partial, AggNonce, KeyAggContext, SecNonce};
+
+    use crate::fiber::types::{Hash256, Privkey, Pubkey};
+
+    use super::{super::LocalSigner, RemoteSigner, Signer, SignerError};
+
+    fn start_signer(dir: &Path, name: &str) -> (Arc<LocalSigner>, RemoteSigner) {
+        let path = dir.join(name);
+        let local = Arc::new(LocalSigner::new(
+            Privkey::from(&[1u8; 32]).into(),
+            [2u8; 32],
+        ));
+        let listener = UnixListener::bind(&path).expect("bind signer socket");
+        let server = local.clone();
+        let record_path = dir.join("record.json");
+        thread::spawn(move || super::serve(listener, server, record_path));
+        (local, RemoteSigner::new(path))
+    }
+
+    fn partial_sign(
+        local: &LocalSigner,
+        remote: &RemoteSigner,
+        key_id: Hash256,
+        commitment_number: u64,
+        message: &[u8],
+    ) -> Result<(), SignerError> {
+        let local_pubkey = local
+            .channel_base_public_keys(key_id)
+            .unwrap()
+            .funding_pubkey;
+        let pubkeys: [Pubkey; 2] = [local_pubkey, Privkey::from(&[6u8; 32]).pubkey()];
+        let agg_nonce = AggNonce::sum([
+            local.musig2_pubnonce(key_id, commitment_number).unwrap(),
+            SecNonce::build([7u8; 32]).build().public_nonce(),
+        ]);
+        remote
+            .musig2_partial_sign(key_id, commitment_number, pubkeys, agg_nonce, message)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn test_remote_signer_matches_local_signer() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let (local, remote) = start_signer(dir.path(), "signer.sock");
+        let key_id = Hash256::from([3u8; 32]);
+        assert_eq!(
+            remote.funding_pubkey().unwrap(),
+            local.funding_pubkey().unwrap()
+        );
+        assert_eq!(
+            remote.sign_funding_message([4u8; 32]).unwrap(),
+            local.sign_funding_message([4u8; 32]).unwrap()
+        );
+        assert_eq!(
+            remote.channel_base_public_keys(key_id).unwrap(),
+            local.channel_base_public_keys(key_id).unwrap()
+        );
+        assert_eq!(
+            remote.commitment_point(key_id, 5).unwrap(),
+            local.commitment_point(key_id, 5).unwrap()
+        );
+        assert_eq!(
+            remote.commitment_secret(key_id, 0).unwrap(),
+            local.commitment_secret(key_id, 0).unwrap()
+        );
+        let pubnonce = remote.musig2_pubnonce(key_id, 5).unwrap();
+        assert_eq!(pubnonce, local.musig2_pubnonce(key_id, 5).unwrap());
+
+        let local_pubkey = local
+            .channel_base_public_keys(key_id)
+            .unwrap()
+            .funding_pubkey;
+        let remote_key = Privkey::from(&[6u8; 32]);
+        let pubkeys = [local_pubkey, remote_key.pubkey()];
+        let remote_nonce = SecNonce::build([7u8; 32]).build().public_nonce();
+        let agg_nonce = AggNonce::sum([pubnonce, remote_nonce]);
+        let signature = remote
+            .musig2_partial_sign(key_id, 5, pubkeys, agg_nonce.clone(), b"message")
+            .unwrap();
+        assert_eq!(
+            signature,
+            local
+                .musig2_partial_sign(key_id, 5, pubkeys, agg_nonce.clone(), b"message")
+                .unwrap()
+        );
+        let key_agg_ctx = KeyAggContext::new(pubkeys).unwrap();
+        assert!(verify_partial(
+            &key_agg_ctx,
+            signature,
+            &agg_nonce,
+            local_pubkey,
+            &local.musig2_pubnonce(key_id, 5).unwrap(),
+            b"message",
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_remote_signer_record() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let (local, remote) = start_signer(dir.path(), "signer.sock");
+        let key_id = Hash256::from([3u8; 32]);
+
+        assert!(remote.commitment_secret(key_id, 1).is_err());
+        assert!(remote.commitment_secret(key_id, 0).is_ok());
+        assert!(remote.commitment_secret(key_id, 1).is_ok());
+        assert!(remote.commitment_secret(key_id, 1).is_ok());
+        assert!(remote.commitment_secret(key_id, 3).is_err());
+
+        assert!(partial_sign(&local, &remote, key_id, 5, b"message").is_ok());
+        assert!(partial_sign(&local, &remote, key_id, 5, b"message").is_ok());
+        assert!(partial_sign(&local, &remote, key_id, 5, b"another message").is_ok());
+        assert!(partial_sign(&local, &remote, key_id, 5, b"third message").is_err());
+
+        // A restarted signer still refuses the requests.
+        let (local, remote) = start_signer(dir.path(), "restarted.sock");
+        assert!(remote.commitment_secret(key_id, 3).is_err());
+        assert!(remote.commitment_secret(key_id, 2).is_ok());
+        assert!(partial_sign(&local, &remote, key_id, 5, b"third message").is_err());
+        assert!(partial_sign(&local, &remote, key_id, 4, b"message").is_err());
+        assert!(partial_sign(&local, &remote, key_id, 6, b"message").is_ok());
+    }
+}
@@ -0,0 +1,350 @@
+//! Upgrade the records in the store to the latest schema on startup.
+//!
+//! The schema version is saved under [`SCHEMA_VERSION_KEY`]. A store without the key is
+//! either new, which starts at [`LATEST_SCHEMA_VERSION`], or created before the versioning,
+//! which is version 0. Each [`Migration`] upgrades the store by one version, and is written
+//! in the same batch as the new version, so an interrupted migration is run again on the
+//! next start.
+//!
+//! To change the layout of a stored type, bump [`LATEST_SCHEMA_VERSION`] and add a
+//! migration which decodes the records with a copy of the old type definition.
+//!
+//! Before version 2, all the records were saved in the default column family, with a
+//! single-byte key prefix for each type.
+
+use rocksdb::{prelude::*, IteratorMode, WriteBatch, DB};
+use thiserror::Error;
+use tracing::info;
+
+use crate::{
+    fiber::channel::{ChannelActorState, ChannelState},
+    invoice::CkbInvoice,
+};
+
+use super::{
+    cf_handle, serialize_to_vec, COLUMN_CHANNEL_ACTOR_STATE, COLUMN_INVOICE, COLUMN_PEER_CHANNEL,
+    SCHEMA_VERSION_KEY,
+};
+
+pub const LATEST_SCHEMA_VERSION: u32 = 2;
+
+const CHANNEL_ACTOR_STATE_PREFIX: u8 = 0;
+const CKB_INVOICE_PREFIX: u8 = 32;
+const PEER_ID_CHANNEL_ID_PREFIX: u8 = 64;
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error(
+        "The store schema version {0} is newer than {LATEST_SCHEMA_VERSION} supported by this node"
+    )]
+    UnsupportedVersion(u32),
+    #[error("Invalid schema version record")]
+    InvalidVersion,
+    #[error("Failed to migrate record {key}: {reason}")]
+    InvalidRecord { key: String, reason: String },
+    #[error("RocksDB error: {0}")]
+    DB(#[from] rocksdb::Error),
+}
+
+pub trait Migration {
+    /// The schema version of the store after this migration.
+    fn version(&self) -> u32;
+
+    fn description(&self) -> &'static str;
+
+    /// Add the upgraded records of the store to the batch.
+    fn migrate(&self, db: &DB, batch: &mut WriteBatch) -> Result<(), MigrationError>;
+}
+
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![Box::new(JsonToBinary), Box::new(SplitColumnFamilies)]
+}
+
+fn read_schema_version(db: &DB) -> Result<Option<u32>, MigrationError> {
+    match db.get(SCHEMA_VERSION_KEY)? {
+        Some(value) => {
+            let bytes: [u8; 4] = value
+                .as_ref()
+                .try_into()
+                .map_err(|_| MigrationError::InvalidVersion)?;
+            Ok(Some(u32::from_le_bytes(bytes)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_empty(db: &DB) -> bool {
+    db.iterator(IteratorMode::Start).next().is_none()
+}
+
+/// Run the migrations newer than the schema version of the store.
+pub fn migrate(db: &DB) -> Result<(), MigrationError> {
+    let version = match read_schema_version(db)? {
+        Some(version) => version,
+        None if is_empty(db) => {
+            db.put(SCHEMA_VERSION_KEY, LATEST_SCHEMA_VERSION.to_le_bytes())?;
+            return Ok(());
+        }
+        None => 0,
+    };
+    if version > LATEST_SCHEMA_VERSION {
+        return Err(MigrationError::UnsupportedVersion(version));
+    }
+    for migration in migrations()
+        .into_iter()
+        .filter(|migration| migration.version() > version)
+    {
+        info!(
+            "Migrating the store to schema version {}: {}",
+            migration.version(),
+            migration.description()
+        );
+        let mut batch = WriteBatch::default();
+        migration.migrate(db, &mut batch)?;
+        batch.put(SCHEMA_VERSION_KEY, migration.version().to_le_bytes())?;
+        db.write(&batch)?;
+    }
+    Ok(())
+}
+
+fn prefix_records(db: &DB, prefix: u8) -> impl Iterator<Item = (Box<[u8]>, Box<[u8]>)> + '_ {
+    db.prefix_iterator([prefix])
+        .take_while(move |(key, _)| key.first() == Some(&prefix))
+}
+
+fn reencode<T>(
+    db: &DB,
+    batch: &mut WriteBatch,
+    prefix: u8,
+    type_name: &'static str,
+    upgrade: impl Fn(T) -> T,
+) -> Result<(), MigrationError>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    for (key, value) in prefix_records(db, prefix) {
+        let invalid_record = |reason: String| MigrationError::InvalidRecord {
+            key: hex::encode(&key),
+            reason,
+        };
+        let record: T =
+            serde_json::from_slice(&value).map_err(|err| invalid_record(err.to_string()))?;
+        let value = serialize_to_vec(&upgrade(record), type_name)
+            .map_err(|err| invalid_record(err.to_string()))?;
+        batch.put(&key, value)?;
+    }
+    Ok(())
+}
+
+/// Version 1: the JSON records are encoded with bincode.
+struct JsonToBinary;
+
+impl Migration for JsonToBinary {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn description(&self) -> &'static str {
+        "encode the records with bincode instead of JSON"
+    }
+
+    fn migrate(&self, db: &DB, batch: &mut WriteBatch) -> Result<(), MigrationError> {
+        reencode(
+            db,
+            batch,
+            CHANNEL_ACTOR_STATE_PREFIX,
+            "ChannelActorState",
+            |mut state: ChannelActorState| {
+                // Release builds didn't save the total amount.
+                state.total_amount = state.to_local_amount + state.to_remote_amount;
+                state
+            },
+        )?;
+        reencode(
+            db,
+            batch,
+            CKB_INVOICE_PREFIX,
+            "CkbInvoice",
+            |invoice: CkbInvoice| invoice,
+        )?;
+        reencode(
+            db,
+            batch,
+            PEER_ID_CHANNEL_ID_PREFIX,
+            "ChannelState",
+            |state: ChannelState| state,
+        )?;
+        Ok(())
+    }
+}
+
+/// Version 2: the records are moved from the default column family to a column family per
+/// type, without the key prefixes.
+struct SplitColumnFamilies;
+
+impl Migration for SplitColumnFamilies {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn description(&self) -> &'static str {
+        "move the records to a column family per type"
+    }
+
+    fn migrate(&self, db: &DB, batch: &mut WriteBatch) -> Result<(), MigrationError> {
+        for (prefix, column) in [
+            (CHANNEL_ACTOR_STATE_PREFIX, COLUMN_CHANNEL_ACTOR_STATE),
+            (CKB_INVOICE_PREFIX, COLUMN_INVOICE),
+            (PEER_ID_CHANNEL_ID_PREFIX, COLUMN_PEER_CHANNEL),
+        ] {
+            for (key, value) in prefix_records(db, prefix) {
+                batch.put_cf(cf_handle(db, column), &key[1..], value)?;
+                batch.delete(&key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rocksdb::{prelude::*, DB};
+
+    use super::{
+        migrate, read_schema_version, CHANNEL_ACTOR_STATE_PREFIX, CKB_INVOICE_PREFIX,
+        LATEST_SCHEMA_VERSION, PEER_ID_CHANNEL_ID_PREFIX,
+    };
+    use crate::{
+        fiber::{
+            channel::{
+                ChannelActorState, ChannelActorStateStore, ChannelSigner, ChannelState,
+                InMemorySigner,
+            },
+            types::{Hash256, LockTime, Privkey},
+        },
+        invoice::{Currency, InvoiceBuilder, InvoiceStore},
+        signer::LocalSigner,
+        store::{Store, SCHEMA_VERSION_KEY},
+    };
+
+    // The JSON layout of a channel state saved by the release builds: the keys of the channel
+    // are inlined in `signer` without a key id, and there are no `total_amount`, which was
+    // only saved by debug builds, and the fields added since then.
+    fn release_channel_state_json() -> (Hash256, Vec<u8>) {
+        let signer = LocalSigner::new(Privkey::from(&[6u8; 32]).into(), [7u8; 32]);
+        let mut state = ChannelActorState::new_outbound_channel(
+            ChannelSigner::new(Hash256::from([8u8; 32]), Arc::new(signer)),
+            tentacle::secio::PeerId::random(),
+            1000,
+            100,
+            1000,
+            1000,
+            None,
+            u128::MAX,
+            10,
+            LockTime::new(100),
+        )
+        .unwrap();
+        state.to_remote_amount = 500;
+        let mut json = serde_json::to_value(&state).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("total_amount");
+        fields.remove("pending_commitment_fee_rate");
+        fields.remove("pending_commitment_fee_rate_commitments");
+        fields.remove("tx_confirmations");
+        fields.remove("pending_commitment_updates");
+        fields.insert(
+            "signer".to_string(),
+            serde_json::to_value(InMemorySigner::generate_from_seed(b"seed")).unwrap(),
+        );
+        (state.id, serde_json::to_vec(&json).unwrap())
+    }
+
+    #[test]
+    fn test_migrate_release_channel_state() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let (channel_id, json) = release_channel_state_json();
+        {
+            let db = DB::open_default(dir.path()).expect("open db");
+            db.put(
+                [&[CHANNEL_ACTOR_STATE_PREFIX], channel_id.as_ref()].concat(),
+                json,
+            )
+            .unwrap();
+        }
+
+        let store = Store::new(dir.path()).expect("migrate store");
+        let state = store
+            .get_channel_actor_state(&channel_id)
+            .unwrap()
+            .expect("migrated channel state");
+        assert_eq!(state.total_amount, 1500);
+        assert_eq!(state.pending_commitment_fee_rate, None);
+        // The keys are kept in the state.
+        assert_eq!(state.signer.key_id(), None);
+    }
+
+    #[test]
+    fn test_migrate_json_records() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let invoice = InvoiceBuilder::new(Currency::Fibb)
+            .amount(Some(1280))
+            .payment_preimage([1u8; 32].into())
+            .build()
+            .expect("build invoice");
+        let peer_id = tentacle::secio::PeerId::random();
+        let channel_id = Hash256::from([2u8; 32]);
+        let invoice_key = [&[CKB_INVOICE_PREFIX], invoice.payment_hash().as_ref()].concat();
+        {
+            let db = DB::open_default(dir.path()).expect("open db");
+            db.put(&invoice_key, serde_json::to_vec(&invoice).unwrap())
+                .unwrap();
+            db.put(
+                [
+                    &[PEER_ID_CHANNEL_ID_PREFIX],
+                    peer_id.as_bytes(),
+                    channel_id.as_ref(),
+                ]
+                .concat(),
+                serde_json::to_vec(&ChannelState::ChannelReady()).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let store = Store::new(dir.path()).expect("migrate store");
+        assert_eq!(
+            read_schema_version(&store.db).unwrap(),
+            Some(LATEST_SCHEMA_VERSION)
+        );
+        assert_eq!(
+            store.get_invoice(invoice.payment_hash()),
+            Ok(Some(invoice.clone()))
+        );
+        assert!(matches!(
+            store.get_channel_states(Some(peer_id.clone())).unwrap()[..],
+            [(_, id, ChannelState::ChannelReady())] if id == channel_id
+        ));
+        // The records are moved out of the default column family.
+        assert!(store.db.get(&invoice_key).unwrap().is_none());
+
+        // Migrations are not run again.
+        migrate(&store.db).expect("migrate again");
+        assert_eq!(store.get_invoice(invoice.payment_hash()), Ok(Some(invoice)));
+    }
+
+    #[test]
+    fn test_reject_newer_schema_version() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        {
+            let db = DB::open_default(dir.path()).expect("open db");
+            db.put(
+                SCHEMA_VERSION_KEY,
+                (LATEST_SCHEMA_VERSION + 1).to_le_bytes(),
+            )
+            .unwrap();
+        }
+        assert!(Store::new(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,616 @@
+mod migration;
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::Options as BincodeOptions;
+use rocksdb::{prelude::*, ColumnFamily, Options, WriteBatch, DB};
+use serde::{de::DeserializeOwned, Serialize};
+use tentacle::secio::PeerId;
+use thiserror::Error;
+use tracing::info;
+
+use crate::{
+    fiber::{
+        channel::{
+            ChannelActorState, ChannelActorStateStore, ChannelState, ClosedChannel, TlcDirection,
+            TlcRecord,
+        },
+        types::Hash256,
+    },
+    invoice::{CkbInvoice, InvoiceError, InvoiceStore},
+};
+
+pub use migration::{Migration, MigrationError, LATEST_SCHEMA_VERSION};
+
+const COLUMN_CHANNEL_ACTOR_STATE: &str = "channel_actor_state";
+const COLUMN_INVOICE: &str = "invoice";
+const COLUMN_PEER_CHANNEL: &str = "peer_channel";
+const COLUMN_CLOSED_CHANNEL: &str = "closed_channel";
+const COLUMN_TLC_RECORD: &str = "tlc_record";
+const COLUMNS: [&str; 5] = [
+    COLUMN_CHANNEL_ACTOR_STATE,
+    COLUMN_INVOICE,
+    COLUMN_PEER_CHANNEL,
+    COLUMN_CLOSED_CHANNEL,
+    COLUMN_TLC_RECORD,
+];
+// Saved in the default column family.
+const SCHEMA_VERSION_KEY: [u8; 1] = [255];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum StoreError {
+    #[error("RocksDB error: {0}")]
+    DB(String),
+    #[error("Failed to serialize {0}: {1}")]
+    Serialize(&'static str, String),
+    #[error("Failed to deserialize {0}: {1}")]
+    Deserialize(&'static str, String),
+    #[error("Failed to migrate the store: {0}")]
+    Migration(String),
+    #[error("IO error: {0}")]
+    IO(String),
+    #[error("Failed to restore the checkpoint: {0}")]
+    Restore(String),
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError::IO(err.to_string())
+    }
+}
+
+impl From<rocksdb::Error> for StoreError {
+    fn from(err: rocksdb::Error) -> Self {
+        StoreError::DB(err.to_string())
+    }
+}
+
+impl From<MigrationError> for StoreError {
+    fn from(err: MigrationError) -> Self {
+        StoreError::Migration(err.to_string())
+    }
+}
+
+fn bincode_options() -> impl BincodeOptions {
+    bincode::DefaultOptions::new()
+}
+
+fn serialize_to_vec<T: Serialize>(
+    value: &T,
+    type_name: &'static str,
+) -> Result<Vec<u8>, StoreError> {
+    bincode_options()
+        .serialize(value)
+        .map_err(|err| StoreError::Serialize(type_name, err.to_string()))
+}
+
+fn deserialize_from<T: DeserializeOwned>(
+    slice: &[u8],
+    type_name: &'static str,
+) -> Result<T, StoreError> {
+    bincode_options()
+        .deserialize(slice)
+        .map_err(|err| StoreError::Deserialize(type_name, err.to_string()))
+}
+
+/// Find an open channel of the store which is missing from the checkpoint or has signed newer
+/// commitments than the checkpoint.
+fn find_channel_ahead(store: &Store, checkpoint: &Store) -> Result<Option<Hash256>, StoreError> {
+    for (_, channel_id, state) in store.get_channel_states(None)? {
+        if matches!(state, ChannelState::Closed(_)) {
+            continue;
+        }
+        let Some(state) = store.get_channel_actor_state(&channel_id)? else {
+            continue;
+        };
+        let numbers = state.get_current_commitment_numbers();
+        match checkpoint.get_channel_actor_state(&channel_id)? {
+            Some(old) => {
+                let old_numbers = old.get_current_commitment_numbers();
+                if numbers.get_local() > old_numbers.get_local()
+                    || numbers.get_remote() > old_numbers.get_remote()
+                {
+                    return Ok(Some(channel_id));
+                }
+            }
+            None => return Ok(Some(channel_id)),
+        }
+    }
+    Ok(None)
+}
+
+fn cf_handle<'a>(db: &'a DB, column: &str) -> &'a ColumnFamily {
+    db.cf_handle(column)
+        .expect("column families are created when opening the store")
+}
+
+#[derive(Clone)]
+pub struct Store {
+    pub(crate) db: Arc<DB>,
+}
+
+impl Store {
+    /// Open the store and upgrade its records to the latest schema version.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = Arc::new(DB::open_cf(&options, path, COLUMNS)?);
+        migration::migrate(&db)?;
+        Ok(Self { db })
+    }
+
+    /// Replace the store at `path` with a copy of the checkpoint. The checkpoint is copied and
+    /// opened next to the store first, so a broken checkpoint leaves the store untouched. The
+    /// replaced store is kept as `<path>.<unix seconds>.bak`.
+    ///
+    /// The checkpoint is renamed to `<checkpoint>.restored` afterwards, so it is only restored
+    /// once, returns `false` if it has already been restored. The restore is refused if a
+    /// channel in the store is ahead of the checkpoint.
+    pub fn restore_checkpoint<P: AsRef<Path>, Q: AsRef<Path>>(
+        checkpoint: P,
+        path: Q,
+    ) -> Result<bool, StoreError> {
+        let (checkpoint, path) = (checkpoint.as_ref(), path.as_ref());
+        let restored_checkpoint = {
+            let mut name = OsString::from(checkpoint.as_os_str());
+            name.push(".restored");
+            PathBuf::from(name)
+        };
+        if !checkpoint.exists() && restored_checkpoint.exists() {
+            info!(
+                "The checkpoint {:?} has already been restored to {:?}",
+                checkpoint, path
+            );
+            return Ok(false);
+        }
+
+        let restoring = path.with_extension("restoring");
+        if restoring.exists() {
+            fs::remove_dir_all(&restoring)?;
+        }
+        fs::create_dir_all(&restoring)?;
+        // A checkpoint is a flat directory of hard-linked or copied files.
+        for entry in fs::read_dir(checkpoint)? {
+            let entry = entry?;
+            fs::copy(entry.path(), restoring.join(entry.file_name()))?;
+        }
+        let restored = Self::new(&restoring)?;
+        if path.exists() {
+            if let Some(channel_id) = find_channel_ahead(&Self::new(path)?, &restored)? {
+                drop(restored);
+                fs::remove_dir_all(&restoring)?;
+                return Err(StoreError::Restore(format!(
+                    "channel {:?} in the store {:?} is ahead of the checkpoint, restoring it would go back to revoked commitments. Move the store away to restore the checkpoint anyway",
+                    channel_id, path
+                )));
+            }
+        }
+        drop(restored);
+
+        if path.exists() {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let backup = path.with_extension(format!("{}.bak", seconds));
+            info!("Moving the store {:?} to {:?}", path, backup);
+            fs::rename(path, backup)?;
+        }
+        fs::rename(&restoring, path)?;
+        fs::rename(checkpoint, &restored_checkpoint)?;
+        info!(
+            "Restored the store {:?} from checkpoint {:?}, which is renamed to {:?}",
+            path, checkpoint, restored_checkpoint
+        );
+        Ok(true)
+    }
+
+    // The iterators of RocksDB continue past the prefix without a prefix extractor.
+    fn prefix_iterator<'a>(
+        &'a self,
+        column: &str,
+        prefix: &'a [u8],
+    ) -> Result<impl Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a, StoreError> {
+        Ok(self
+            .db
+            .prefix_iterator_cf(cf_handle(&self.db, column), prefix)?
+            .take_while(move |(key, _)| key.starts_with(prefix)))
+    }
+
+    fn get<K: AsRef<[u8]>>(&self, column: &str, key: K) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self
+            .db
+            .get_cf(cf_handle(&self.db, column), key.as_ref())?
+            .map(|v| v.to_vec()))
+    }
+
+    fn batch(&self) -> Batch {
+        Batch {
+            db: Arc::clone(&self.db),
+            wb: WriteBatch::default(),
+        }
+    }
+}
+
+pub struct Batch {
+    db: Arc<DB>,
+    wb: WriteBatch,
+}
+
+impl Batch {
+    fn put_kv(&mut self, key_value: KeyValue) -> Result<(), StoreError> {
+        match key_value {
+            KeyValue::ChannelActorState(id, state) => self.put(
+                COLUMN_CHANNEL_ACTOR_STATE,
+                id,
+                serialize_to_vec(&state, "ChannelActorState")?,
+            ),
+            KeyValue::CkbInvoice(id, invoice) => self.put(
+                COLUMN_INVOICE,
+                id,
+                serialize_to_vec(&invoice, "CkbInvoice")?,
+            ),
+            KeyValue::PeerIdChannelId((peer_id, channel_id), state) => self.put(
+                COLUMN_PEER_CHANNEL,
+                [peer_id.as_bytes(), channel_id.as_ref()].concat(),
+                serialize_to_vec(&state, "ChannelState")?,
+            ),
+            KeyValue::ClosedChannel(id, channel) => self.put(
+                COLUMN_CLOSED_CHANNEL,
+                id,
+                serialize_to_vec(&channel, "ClosedChannel")?,
+            ),
+            KeyValue::TlcRecord(record) => self.put(
+                COLUMN_TLC_RECORD,
+                tlc_record_key(&record),
+                serialize_to_vec(&record, "TlcRecord")?,
+            ),
+        }
+    }
+
+    fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        column: &str,
+        key: K,
+        value: V,
+    ) -> Result<(), StoreError> {
+        Ok(self.wb.put_cf(cf_handle(&self.db, column), key, value)?)
+    }
+
+    fn delete<K: AsRef<[u8]>>(&mut self, column: &str, key: K) -> Result<(), StoreError> {
+        Ok(self
+            .wb
+            .delete_cf(cf_handle(&self.db, column), key.as_ref())?)
+    }
+
+    fn commit(self) -> Result<(), StoreError> {
+        Ok(self.db.write(&self.wb)?)
+    }
+}
+
+///
+/// +---------------------+--------------------+--------------------------+
+/// | Column::            | Key::              | Value::                  |
+/// +---------------------+--------------------+--------------------------+
+/// | channel_actor_state | Hash256            | ChannelActorState        |
+/// | invoice             | Hash256            | CkbInvoice               |
+/// | peer_channel        | PeerId | Hash256   | ChannelState             |
+/// | closed_channel      | Hash256            | ClosedChannel            |
+/// | tlc_record          | Hash256 | u8 | u64 | TlcRecord                |
+/// | default             | 255                | Schema version (u32 LE)  |
+/// +---------------------+--------------------+--------------------------+
+///
+/// The values are encoded with bincode, see [`migration`] for the schema versions.
+///
+
+enum KeyValue {
+    ChannelActorState(Hash256, ChannelActorState),
+    CkbInvoice(Hash256, CkbInvoice),
+    PeerIdChannelId((PeerId, Hash256), ChannelState),
+    ClosedChannel(Hash256, ClosedChannel),
+    TlcRecord(TlcRecord),
+}
+
+// The channel id, the direction and the big-endian tlc id, so the records of a channel
+// are listed in order.
+fn tlc_record_key(record: &TlcRecord) -> Vec<u8> {
+    let direction = match record.direction {
+        TlcDirection::Incoming => 0u8,
+        TlcDirection::Outgoing => 1u8,
+    };
+    [
+        record.channel_id.as_ref(),
+        &[direction],
+        &record.tlc_id.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn parse_channel_id(key: &[u8]) -> Result<Hash256, StoreError> {
+    let channel_id: [u8; 32] = key
+        .try_into()
+        .map_err(|_| StoreError::Deserialize("Hash256", "channel id should be 32 bytes".into()))?;
+    Ok(channel_id.into())
+}
+
+impl ChannelActorStateStore for Store {
+    fn get_channel_actor_state(
+        &self,
+        id: &Hash256,
+    ) -> Result<Option<ChannelActorState>, StoreError> {
+        self.get(COLUMN_CHANNEL_ACTOR_STATE, id)?
+            .map(|v| deserialize_from(v.as_ref(), "ChannelActorState"))
+            .transpose()
+    }
+
+    fn insert_channel_actor_state(&self, state: ChannelActorState) -> Result<(), StoreError> {
+        let mut batch = self.batch();
+        batch.put_kv(KeyValue::PeerIdChannelId(
+            (state.peer_id.clone(), state.id),
+            state.state,
+        ))?;
+        batch.put_kv(KeyValue::ChannelActorState(state.id, state))?;
+        batch.commit()
+    }
+
+    fn delete_channel_actor_state(&self, id: &Hash256) -> Result<(), StoreError> {
+        if let Some(state) = self.get_channel_actor_state(id)? {
+            let mut batch = self.batch();
+            batch.delete(COLUMN_CHANNEL_ACTOR_STATE, id)?;
+            batch.delete(
+                COLUMN_PEER_CHANNEL,
+                [state.peer_id.as_bytes(), id.as_ref()].concat(),
+            )?;
+            batch.commit()?;
+        }
+        Ok(())
+    }
+
+    fn get_channel_ids_by_peer(&self, peer_id: &PeerId) -> Result<Vec<Hash256>, StoreError> {
+        let prefix = peer_id.as_bytes();
+        self.prefix_iterator(COLUMN_PEER_CHANNEL, prefix)?
+            .map(|(key, _)| parse_channel_id(&key[prefix.len()..]))
+            .collect()
+    }
+
+    fn get_channel_states(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<(PeerId, Hash256, ChannelState)>, StoreError> {
+        let prefix = peer_id
+            .map(|peer_id| peer_id.as_bytes().to_vec())
+            .unwrap_or_default();
+        self.prefix_iterator(COLUMN_PEER_CHANNEL, &prefix)?
+            .map(|(key, value)| {
+                if key.len() < 32 {
+                    return Err(StoreError::Deserialize(
+                        "PeerId",
+                        "key should end with a channel id".into(),
+                    ));
+                }
+                let (peer_id, channel_id) = key.split_at(key.len() - 32);
+                let peer_id = PeerId::from_bytes(peer_id.to_vec())
+                    .map_err(|err| StoreError::Deserialize("PeerId", format!("{:?}", err)))?;
+                let state = deserialize_from(value.as_ref(), "ChannelState")?;
+                Ok((peer_id, parse_channel_id(channel_id)?, state))
+            })
+            .collect()
+    }
+
+    fn insert_closed_channel(&self, channel: ClosedChannel) -> Result<(), StoreError> {
+        let mut batch = self.batch();
+        batch.put_kv(KeyValue::ClosedChannel(channel.channel_id, channel))?;
+        batch.commit()
+    }
+
+    fn get_closed_channels(
+        &self,
+        peer_id: Option<PeerId>,
+    ) -> Result<Vec<ClosedChannel>, StoreError> {
+        let mut channels = vec![];
+        for (_, value) in self.prefix_iterator(COLUMN_CLOSED_CHANNEL, &[])? {
+            let channel: ClosedChannel = deserialize_from(value.as_ref(), "ClosedChannel")?;
+            if peer_id
+                .as_ref()
+                .map_or(true, |peer_id| &channel.peer_id == peer_id)
+            {
+                channels.push(channel);
+            }
+        }
+        Ok(channels)
+    }
+
+    fn insert_tlc_record(&self, record: TlcRecord) -> Result<(), StoreError> {
+        let mut batch = self.batch();
+        batch.put_kv(KeyValue::TlcRecord(record))?;
+        batch.commit()
+    }
+
+    fn get_tlc_records(&self, channel_id: Option<Hash256>) -> Result<Vec<TlcRecord>, StoreError> {
+        let prefix = channel_id
+            .map(|channel_id| channel_id.as_ref().to_vec())
+            .unwrap_or_default();
+        self.prefix_iterator(COLUMN_TLC_RECORD, &prefix)?
+            .map(|(_, value)| deserialize_from(value.as_ref(), "TlcRecord"))
+            .collect()
+    }
+}
+
+/// Maintenance of the database files, used by the admin rpc.
+pub trait AdminStore {
+    /// Write a consistent snapshot of the store to `path`, which must not exist yet.
+    fn create_checkpoint(&self, path: &Path) -> Result<(), StoreError>;
+    /// Compact all the column families.
+    fn compact(&self) -> Result<(), StoreError>;
+}
+
+impl AdminStore for Store {
+    fn create_checkpoint(&self, path: &Path) -> Result<(), StoreError> {
+        Ok(self
+            .db
+            .create_checkpoint_object()?
+            .create_checkpoint(path)?)
+    }
+
+    fn compact(&self) -> Result<(), StoreError> {
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>)?;
+        for column in COLUMNS {
+            self.db
+                .compact_range_cf(cf_handle(&self.db, column), None, None)?;
+        }
+        Ok(())
+    }
+}
+
+impl InvoiceStore for Store {
+    fn get_invoice(&self, id: &Hash256) -> Result<Option<CkbInvoice>, StoreError> {
+        self.get(COLUMN_INVOICE, id)?
+            .map(|v| deserialize_from(v.as_ref(), "CkbInvoice"))
+            .transpose()
+    }
+
+    fn insert_invoice(&self, invoice: CkbInvoice) -> Result<(), InvoiceError> {
+        let mut batch = self.batch();
+        let hash = invoice.payment_hash();
+        if self.get_invoice(hash)?.is_some() {
+            return Err(InvoiceError::DuplicatedInvoice(hash.to_string()));
+        }
+        batch.put_kv(KeyValue::CkbInvoice(*invoice.payment_hash(), invoice))?;
+        batch.commit()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tentacle::secio::PeerId;
+
+    use super::{AdminStore, Store, StoreError};
+    use crate::{
+        fiber::{
+            channel::{
+                ChannelActorState, ChannelActorStateStore, ChannelCloseType, ChannelSigner,
+                ClosedChannel,
+            },
+            types::{Hash256, LockTime, Privkey},
+        },
+        signer::LocalSigner,
+    };
+
+    fn closed_channel(id: u8, peer_id: &PeerId) -> ClosedChannel {
+        ClosedChannel {
+            channel_id: Hash256::from([id; 32]),
+            peer_id: peer_id.clone(),
+            funding_udt_type_script: None,
+            funding_tx_hash: Hash256::from([id + 1; 32]),
+            close_tx_hash: Hash256::from([id + 2; 32]),
+            close_type: ChannelCloseType::Cooperative,
+            local_balance: 100,
+            remote_balance: 200,
+            close_fee: 1000,
+            funding_fee: 1000,
+            created_at: 1,
+            closed_at: 2,
+        }
+    }
+
+    #[test]
+    fn test_closed_channels_by_peer() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let store = Store::new(dir.path()).expect("open store");
+        let (peer1, peer2) = (PeerId::random(), PeerId::random());
+        store
+            .insert_closed_channel(closed_channel(1, &peer1))
+            .unwrap();
+        store
+            .insert_closed_channel(closed_channel(10, &peer2))
+            .unwrap();
+
+        assert_eq!(store.get_closed_channels(None).unwrap().len(), 2);
+        let channels = store.get_closed_channels(Some(peer2.clone())).unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel_id, Hash256::from([10; 32]));
+        assert_eq!(channels[0].close_tx_hash, Hash256::from([12; 32]));
+        assert_eq!(channels[0].close_type, ChannelCloseType::Cooperative);
+    }
+
+    #[test]
+    fn test_restore_checkpoint() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let (path, checkpoint) = (dir.path().join("store"), dir.path().join("checkpoint"));
+        let peer_id = PeerId::random();
+        {
+            let store = Store::new(&path).expect("open store");
+            store
+                .insert_closed_channel(closed_channel(1, &peer_id))
+                .unwrap();
+            store.create_checkpoint(&checkpoint).unwrap();
+            store
+                .insert_closed_channel(closed_channel(10, &peer_id))
+                .unwrap();
+            store.compact().unwrap();
+        }
+
+        assert!(Store::restore_checkpoint(&checkpoint, &path).expect("restore checkpoint"));
+        {
+            let store = Store::new(&path).expect("open restored store");
+            let channels = store.get_closed_channels(None).unwrap();
+            assert_eq!(channels.len(), 1);
+            assert_eq!(channels[0].channel_id, Hash256::from([1; 32]));
+            store
+                .insert_closed_channel(closed_channel(10, &peer_id))
+                .unwrap();
+        }
+        // The replaced store and the renamed checkpoint are kept.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+        assert!(!checkpoint.exists());
+
+        // The checkpoint is not restored again on the next start.
+        assert!(!Store::restore_checkpoint(&checkpoint, &path).unwrap());
+        let store = Store::new(&path).expect("open store");
+        assert_eq!(store.get_closed_channels(None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_refuse_to_restore_checkpoint_behind_the_store() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let (path, checkpoint) = (dir.path().join("store"), dir.path().join("checkpoint"));
+        {
+            let store = Store::new(&path).expect("open store");
+            let signer = LocalSigner::new(Privkey::from(&[1u8; 32]).into(), [2u8; 32]);
+            let mut state = ChannelActorState::new_outbound_channel(
+                ChannelSigner::new(Hash256::from([3u8; 32]), Arc::new(signer)),
+                PeerId::random(),
+                1000,
+                100,
+                1000,
+                1000,
+                None,
+                u128::MAX,
+                10,
+                LockTime::new(100),
+            )
+            .unwrap();
+            store.insert_channel_actor_state(state.clone()).unwrap();
+            store.create_checkpoint(&checkpoint).unwrap();
+            state.increment_local_commitment_number();
+            store.insert_channel_actor_state(state).unwrap();
+        }
+
+        assert!(matches!(
+            Store::restore_checkpoint(&checkpoint, &path),
+            Err(StoreError::Restore(_))
+        ));
+        assert!(checkpoint.exists());
+        assert!(!path.with_extension("restoring").exists());
+    }
+}